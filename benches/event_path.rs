@@ -0,0 +1,237 @@
+//! Benchmarks of the path of events from a device to clients: serializing reports into batches,
+//! broadcasting the batches to every connection and converting them into frames for each client.
+//! Run with `cargo bench`; the events come from [`SyntheticDevice`], so no hardware is needed.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use remote_input::broadcast::Broadcast;
+use remote_input::protocol::{self, Encoding, Framing, InputEventWrapper, Timestamp};
+use std::hint::black_box;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// The number of values each receiver buffers, as on the server.
+const BUS_CAPACITY: usize = 100;
+
+/// The numbers of connected clients measured.
+const CLIENT_COUNTS: [usize; 4] = [1, 10, 100, 500];
+
+/// The number of reports serialized or converted per iteration.
+const REPORTS: usize = 100;
+
+/// Generates the reports of a mouse with a keyboard: a movement on both axes in every report, a
+/// wheel notch in every tenth and a key press or release in every fifth, each report ending with
+/// `EV_SYN`/`SYN_REPORT` a millisecond after the one before.
+#[derive(Default)]
+struct SyntheticDevice {
+    report: u64,
+}
+
+impl Iterator for SyntheticDevice {
+    type Item = Vec<InputEventWrapper>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        const EV_SYN: u16 = 0x00;
+        const EV_KEY: u16 = 0x01;
+        const EV_REL: u16 = 0x02;
+        const KEY_A: u16 = 30;
+        const REL_X: u16 = 0x00;
+        const REL_Y: u16 = 0x01;
+        const REL_WHEEL: u16 = 0x08;
+
+        self.report += 1;
+        let timestamp = Timestamp::from(Duration::from_millis(1_700_000_000_000 + self.report));
+        let event = |event_type, code, value| InputEventWrapper {
+            timestamp,
+            event_type,
+            code,
+            value,
+        };
+        let mut report = vec![event(EV_REL, REL_X, 3), event(EV_REL, REL_Y, -2)];
+        if self.report.is_multiple_of(10) {
+            report.push(event(EV_REL, REL_WHEEL, 1));
+        }
+        if self.report.is_multiple_of(5) {
+            report.push(event(
+                EV_KEY,
+                KEY_A,
+                (self.report.is_multiple_of(10)).into(),
+            ));
+        }
+        report.push(event(EV_SYN, 0, 0));
+        Some(report)
+    }
+}
+
+/// Serialize `report` into a batch as the server does: each event a COBS encoded postcard message.
+fn serialize(report: &[InputEventWrapper], batch: &mut Vec<u8>) {
+    let mut buffer = [0u8; remote_input_wire::MAX_EVENT_FRAME_LEN];
+    for event in report {
+        let serialized_event = remote_input_wire::encode_event(event, &mut buffer).unwrap();
+        batch.extend_from_slice(serialized_event);
+    }
+}
+
+/// The batches of `count` synthetic reports.
+fn batches(count: usize) -> Vec<Arc<Vec<u8>>> {
+    SyntheticDevice::default()
+        .take(count)
+        .map(|report| {
+            let mut batch = Vec::new();
+            serialize(&report, &mut batch);
+            Arc::new(batch)
+        })
+        .collect()
+}
+
+fn event_count(batches: &[Arc<Vec<u8>>]) -> u64 {
+    batches
+        .iter()
+        .map(|batch| protocol::split_batch(batch).count() as u64)
+        .sum()
+}
+
+/// Serializing the reports of a device into batches.
+fn bench_serialize(c: &mut Criterion) {
+    let reports: Vec<_> = SyntheticDevice::default().take(REPORTS).collect();
+    let events = reports.iter().map(Vec::len).sum::<usize>() as u64;
+    let mut group = c.benchmark_group("serialize");
+    group.throughput(Throughput::Elements(events));
+    group.bench_function("postcard_cobs", |b| {
+        let mut batch = Vec::new();
+        b.iter(|| {
+            for report in &reports {
+                batch.clear();
+                serialize(black_box(report), &mut batch);
+                black_box(&batch);
+            }
+        });
+    });
+    group.finish();
+}
+
+/// Converting batches into the frame of a client, per negotiated encoding.
+fn bench_frame(c: &mut Criterion) {
+    let batches = batches(REPORTS);
+    let mut group = c.benchmark_group("frame");
+    group.throughput(Throughput::Elements(event_count(&batches)));
+    for (name, encoding, names) in [
+        ("postcard", Encoding::Postcard, false),
+        ("message_pack", Encoding::MessagePack, false),
+        ("cbor", Encoding::Cbor, false),
+        ("postcard_names", Encoding::Postcard, true),
+    ] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                for (sequence, batch) in batches.iter().enumerate() {
+                    let frame = protocol::reencode_batch(
+                        black_box(batch),
+                        encoding,
+                        Framing::Cobs,
+                        Some(sequence as u64),
+                        None,
+                        names,
+                    )
+                    .unwrap();
+                    black_box(frame);
+                }
+            });
+        });
+    }
+    group.finish();
+}
+
+/// Broadcasting batches to every client and receiving them, in a single thread.
+fn bench_broadcast(c: &mut Criterion) {
+    let batches = batches(REPORTS);
+    let mut group = c.benchmark_group("broadcast");
+    for clients in CLIENT_COUNTS {
+        group.throughput(Throughput::Elements(batches.len() as u64 * clients as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(clients),
+            &clients,
+            |b, &clients| {
+                let bus = Broadcast::new(BUS_CAPACITY);
+                let mut receivers: Vec<_> = (0..clients).map(|_| bus.subscribe()).collect();
+                b.iter(|| {
+                    for batch in &batches {
+                        bus.send(Arc::clone(batch));
+                        for receiver in &mut receivers {
+                            black_box(receiver.try_recv().unwrap());
+                        }
+                    }
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+/// The time from serializing a report until every client converted it into its frame, with a
+/// task per client on a pool of worker threads, as on the server.
+fn bench_latency(c: &mut Criterion) {
+    let reports: Vec<_> = SyntheticDevice::default().take(REPORTS).collect();
+    let mut group = c.benchmark_group("latency");
+    for clients in CLIENT_COUNTS {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(clients),
+            &clients,
+            |b, &clients| {
+                let bus = Broadcast::<Arc<Vec<u8>>>::new(BUS_CAPACITY);
+                let (done, finished) = mpsc::channel();
+                let receivers: Vec<_> = (0..clients).map(|_| bus.subscribe()).collect();
+                let workers = thread::spawn(move || {
+                    let runtime = tokio::runtime::Builder::new_multi_thread().build().unwrap();
+                    runtime.block_on(async move {
+                        let tasks: Vec<_> = receivers
+                            .into_iter()
+                            .map(|mut receiver| {
+                                let done = done.clone();
+                                tokio::spawn(async move {
+                                    while let Some(batch) = receiver.recv().await {
+                                        let frame = protocol::reencode_batch(
+                                            &batch,
+                                            Encoding::Postcard,
+                                            Framing::Cobs,
+                                            None,
+                                            None,
+                                            false,
+                                        )
+                                        .unwrap();
+                                        black_box(frame);
+                                        done.send(()).unwrap();
+                                    }
+                                })
+                            })
+                            .collect();
+                        for task in tasks {
+                            task.await.unwrap();
+                        }
+                    });
+                });
+                let mut reports = reports.iter().cycle();
+                b.iter(|| {
+                    let mut batch = Vec::new();
+                    serialize(reports.next().unwrap(), &mut batch);
+                    bus.send(Arc::new(batch));
+                    for _ in 0..clients {
+                        finished.recv().unwrap();
+                    }
+                });
+                bus.close();
+                workers.join().unwrap();
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_serialize,
+    bench_frame,
+    bench_broadcast,
+    bench_latency
+);
+criterion_main!(benches);
@@ -0,0 +1,23 @@
+fn main() {
+    // Generate the gRPC service from the hand written messages in `src/grpc.rs`.
+    // See `proto/remote_input.proto`.
+    #[cfg(feature = "grpc")]
+    {
+        let stream_events = tonic_build::manual::Method::builder()
+            .name("stream_events")
+            .route_name("StreamEvents")
+            .input_type("super::StreamEventsRequest")
+            .output_type("super::InputEvent")
+            .codec_path("tonic::codec::ProstCodec")
+            .server_streaming()
+            .build();
+        let service = tonic_build::manual::Service::builder()
+            .name("RemoteInput")
+            .package("remote_input")
+            .method(stream_events)
+            .build();
+        tonic_build::manual::Builder::new()
+            .build_client(false)
+            .compile(&[service]);
+    }
+}
@@ -0,0 +1,130 @@
+/*
+ * C bindings for decoding the event stream of a remote input server.
+ *
+ * Every function works on buffers owned by the caller and never allocates.
+ * Frames are COBS encoded postcard messages terminated by a zero byte. Clients
+ * using these functions must not request RI_FEATURE_LENGTH_PREFIXED,
+ * RI_FEATURE_MESSAGE_PACK, RI_FEATURE_ZSTD, RI_FEATURE_CBOR or
+ * RI_FEATURE_MULTIPLEX.
+ *
+ * A connection is used as follows:
+ * 1. Answer the authentication challenge (ri_unframe, then ri_frame).
+ * 2. Decode the ServerHello (ri_decode_server_hello) and send a ClientHello
+ *    (ri_encode_client_hello).
+ * 3. Decode the HandshakeResponse (ri_decode_handshake_response).
+ * 4. Decode every following frame with ri_decode_frame, or ri_decode_batch if
+ *    RI_FEATURE_BATCH was negotiated. ri_frame_length finds the frames in the
+ *    received bytes.
+ */
+
+#ifndef REMOTE_INPUT_H
+#define REMOTE_INPUT_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define RI_PROTOCOL_VERSION 1
+
+/* The features of the protocol (see the protocol module of the crate). */
+#define RI_FEATURE_HEARTBEAT (1u << 0)
+#define RI_FEATURE_HEARTBEAT_PONG (1u << 1)
+#define RI_FEATURE_LENGTH_PREFIXED (1u << 2)
+#define RI_FEATURE_MESSAGE_PACK (1u << 3)
+#define RI_FEATURE_ZSTD (1u << 4)
+#define RI_FEATURE_BATCH (1u << 5)
+#define RI_FEATURE_CONTROL (1u << 6)
+#define RI_FEATURE_SEQUENCE (1u << 7)
+#define RI_FEATURE_FLOW_CONTROL (1u << 8)
+#define RI_FEATURE_CBOR (1u << 9)
+#define RI_FEATURE_SESSION_TOKEN (1u << 10)
+#define RI_FEATURE_DEVICE_INFO (1u << 11)
+#define RI_FEATURE_MULTIPLEX (1u << 12)
+
+/* The frame was decoded. */
+#define RI_OK 0
+/* The frame is empty, i.e., a heartbeat. */
+#define RI_EMPTY 1
+/* The frame is not valid COBS or does not hold the expected message. */
+#define RI_ERROR_INVALID (-1)
+/* The output buffer is too small. */
+#define RI_ERROR_BUFFER_TOO_SMALL (-2)
+/* A required pointer is null. */
+#define RI_ERROR_NULL (-3)
+/* The server rejected the handshake. */
+#define RI_ERROR_REJECTED (-4)
+
+/* An input event with the type, code and value of input-event-codes.h. */
+typedef struct {
+    /* The time of the event since the UNIX epoch. */
+    uint64_t seconds;
+    uint32_t nanoseconds;
+    uint16_t event_type;
+    uint16_t code;
+    int32_t value;
+} RiEvent;
+
+/*
+ * The length of the first complete frame at the start of buffer (including its
+ * zero byte terminator), or 0 if buffer does not hold a complete frame yet.
+ */
+size_t ri_frame_length(const uint8_t *buffer, size_t len);
+
+/*
+ * Decode the frame in place, storing the length of the message (which starts
+ * at frame) in message_len. Used for the authentication challenge.
+ */
+int32_t ri_unframe(uint8_t *frame, size_t len, size_t *message_len);
+
+/*
+ * Encode message (e.g., the response to the authentication challenge) into a
+ * frame with its zero byte terminator in out, storing its length in out_len.
+ */
+int32_t ri_frame(const uint8_t *message, size_t len, uint8_t *out,
+                 size_t capacity, size_t *out_len);
+
+/* Decode the ServerHello in frame in place. */
+int32_t ri_decode_server_hello(uint8_t *frame, size_t len, uint16_t *version,
+                               uint16_t *min_version, uint32_t *features);
+
+/*
+ * Encode a ClientHello requesting version and features into a frame in out,
+ * storing its length in out_len.
+ */
+int32_t ri_encode_client_hello(uint16_t version, uint32_t features,
+                               uint8_t *out, size_t capacity, size_t *out_len);
+
+/*
+ * Decode the HandshakeResponse in frame in place. Returns RI_ERROR_REJECTED if
+ * the server rejected the client, and otherwise stores the negotiated version
+ * and features.
+ */
+int32_t ri_decode_handshake_response(uint8_t *frame, size_t len,
+                                     uint16_t *version, uint32_t *features);
+
+/*
+ * Decode the event frame in place into event. Returns RI_EMPTY for heartbeats.
+ * If sequence is not NULL (required with RI_FEATURE_SEQUENCE), the sequence
+ * number preceding the event is stored in it.
+ */
+int32_t ri_decode_frame(uint8_t *frame, size_t len, RiEvent *event,
+                        uint64_t *sequence);
+
+/*
+ * Decode the batch frame (with RI_FEATURE_BATCH) in place into events, which
+ * holds up to capacity events, storing the number of events in count. Returns
+ * RI_EMPTY for heartbeats and RI_ERROR_BUFFER_TOO_SMALL (with the number of
+ * events in count) if events is too small. sequence is handled like by
+ * ri_decode_frame.
+ */
+int32_t ri_decode_batch(uint8_t *frame, size_t len, RiEvent *events,
+                        size_t capacity, size_t *count, uint64_t *sequence);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
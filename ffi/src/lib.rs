@@ -0,0 +1,322 @@
+//! C bindings for decoding the event stream of a remote input server, declared in
+//! `include/remote_input.h`. Every function works on buffers owned by the caller and never allocates,
+//! so that clients on small boards can decode frames in place.
+//!
+//! Frames are COBS encoded [`postcard`] messages. Clients using these functions must not request
+//! features which change the encoding (`MESSAGE_PACK`, `CBOR`, `ZSTD`, `MULTIPLEX`) or the framing
+//! (`LENGTH_PREFIXED`).
+
+use remote_input_wire::{
+    unframe, Batch, ClientHello, HandshakeResponse, InputEventWrapper, ServerHello,
+};
+use std::slice;
+
+/// The frame was decoded.
+pub const RI_OK: i32 = 0;
+/// The frame is empty, i.e., a heartbeat.
+pub const RI_EMPTY: i32 = 1;
+/// The frame is not valid COBS or does not hold the expected message.
+pub const RI_ERROR_INVALID: i32 = -1;
+/// The output buffer is too small.
+pub const RI_ERROR_BUFFER_TOO_SMALL: i32 = -2;
+/// A required pointer is null.
+pub const RI_ERROR_NULL: i32 = -3;
+/// The server rejected the handshake.
+pub const RI_ERROR_REJECTED: i32 = -4;
+
+/// An input event (see `InputEventWrapper`).
+#[repr(C)]
+pub struct RiEvent {
+    /// The time of the event since the UNIX epoch.
+    pub seconds: u64,
+    pub nanoseconds: u32,
+    /// The event type, code and value of `input-event-codes.h`.
+    pub event_type: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+impl From<InputEventWrapper> for RiEvent {
+    fn from(event: InputEventWrapper) -> Self {
+        Self {
+            seconds: event.timestamp.secs_since_epoch,
+            nanoseconds: event.timestamp.nanos_since_epoch,
+            event_type: event.event_type,
+            code: event.code,
+            value: event.value,
+        }
+    }
+}
+
+/// The mutable buffer `data` of `len` bytes, or `None` if it is null.
+///
+/// # Safety
+///
+/// `data` must be null or valid for reads and writes of `len` bytes.
+unsafe fn buffer<'a>(data: *mut u8, len: usize) -> Option<&'a mut [u8]> {
+    (!data.is_null()).then(|| slice::from_raw_parts_mut(data, len))
+}
+
+/// Read the sequence number preceding the body of `message` into `sequence` unless it is null.
+///
+/// # Safety
+///
+/// `sequence` must be null or valid for writes.
+unsafe fn take_sequence(message: &[u8], sequence: *mut u64) -> Option<&[u8]> {
+    if sequence.is_null() {
+        return Some(message);
+    }
+    let (value, rest) = remote_input_wire::take_sequence(message).ok()?;
+    *sequence = value;
+    Some(rest)
+}
+
+/// The length of the first complete frame at the start of `buffer` (including its zero byte
+/// terminator), or 0 if `buffer` does not hold a complete frame yet.
+///
+/// # Safety
+///
+/// `buffer` must be valid for reads of `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn ri_frame_length(buffer: *const u8, len: usize) -> usize {
+    if buffer.is_null() {
+        return 0;
+    }
+    remote_input_wire::frame_len(slice::from_raw_parts(buffer, len)).unwrap_or(0)
+}
+
+/// Decode the COBS frame `frame` in place, storing the length of the message (which starts at
+/// `frame`) in `message_len`. Used for the authentication challenge.
+///
+/// # Safety
+///
+/// `frame` must be valid for reads and writes of `len` bytes and `message_len` for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ri_unframe(frame: *mut u8, len: usize, message_len: *mut usize) -> i32 {
+    let Some(frame) = buffer(frame, len) else {
+        return RI_ERROR_NULL;
+    };
+    if message_len.is_null() {
+        return RI_ERROR_NULL;
+    }
+    match unframe(frame) {
+        Ok(message) => {
+            *message_len = message.len();
+            RI_OK
+        }
+        Err(_) => RI_ERROR_INVALID,
+    }
+}
+
+/// Encode `message` (e.g., the response to the authentication challenge) into a COBS frame with
+/// its zero byte terminator in `out`, storing its length in `out_len`.
+///
+/// # Safety
+///
+/// `message` must be valid for reads of `len` bytes, `out` for writes of `capacity` bytes and
+/// `out_len` for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ri_frame(
+    message: *const u8,
+    len: usize,
+    out: *mut u8,
+    capacity: usize,
+    out_len: *mut usize,
+) -> i32 {
+    if message.is_null() || out.is_null() || out_len.is_null() {
+        return RI_ERROR_NULL;
+    }
+    let message = slice::from_raw_parts(message, len);
+    match remote_input_wire::frame(message, slice::from_raw_parts_mut(out, capacity)) {
+        Ok(frame) => {
+            *out_len = frame.len();
+            RI_OK
+        }
+        Err(_) => RI_ERROR_BUFFER_TOO_SMALL,
+    }
+}
+
+/// Decode the `ServerHello` in `frame` in place.
+///
+/// # Safety
+///
+/// `frame` must be valid for reads and writes of `len` bytes and the outputs for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ri_decode_server_hello(
+    frame: *mut u8,
+    len: usize,
+    version: *mut u16,
+    min_version: *mut u16,
+    features: *mut u32,
+) -> i32 {
+    let Some(frame) = buffer(frame, len) else {
+        return RI_ERROR_NULL;
+    };
+    if version.is_null() || min_version.is_null() || features.is_null() {
+        return RI_ERROR_NULL;
+    }
+    match unframe(frame)
+        .ok()
+        .and_then(|message| postcard::from_bytes::<ServerHello>(message).ok())
+    {
+        Some(hello) => {
+            *version = hello.version;
+            *min_version = hello.min_version;
+            *features = hello.features;
+            RI_OK
+        }
+        None => RI_ERROR_INVALID,
+    }
+}
+
+/// Encode a `ClientHello` requesting `version` and `features` into a frame in `out`, storing its
+/// length in `out_len`.
+///
+/// # Safety
+///
+/// `out` must be valid for writes of `capacity` bytes and `out_len` for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ri_encode_client_hello(
+    version: u16,
+    features: u32,
+    out: *mut u8,
+    capacity: usize,
+    out_len: *mut usize,
+) -> i32 {
+    let Some(out) = buffer(out, capacity) else {
+        return RI_ERROR_NULL;
+    };
+    if out_len.is_null() {
+        return RI_ERROR_NULL;
+    }
+    match postcard::to_slice_cobs(&ClientHello { version, features }, out) {
+        Ok(frame) => {
+            *out_len = frame.len();
+            RI_OK
+        }
+        Err(_) => RI_ERROR_BUFFER_TOO_SMALL,
+    }
+}
+
+/// Decode the `HandshakeResponse` in `frame` in place. Returns [`RI_ERROR_REJECTED`] if the server
+/// rejected the client, and otherwise stores the negotiated `version` and `features`.
+///
+/// # Safety
+///
+/// `frame` must be valid for reads and writes of `len` bytes and the outputs for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ri_decode_handshake_response(
+    frame: *mut u8,
+    len: usize,
+    version: *mut u16,
+    features: *mut u32,
+) -> i32 {
+    let Some(frame) = buffer(frame, len) else {
+        return RI_ERROR_NULL;
+    };
+    if version.is_null() || features.is_null() {
+        return RI_ERROR_NULL;
+    }
+    let response = unframe(frame)
+        .ok()
+        .and_then(|message| postcard::from_bytes(message).ok());
+    match response {
+        Some(HandshakeResponse::Accepted {
+            version: accepted_version,
+            features: accepted_features,
+        }) => {
+            *version = accepted_version;
+            *features = accepted_features;
+            RI_OK
+        }
+        Some(HandshakeResponse::Rejected { .. }) => RI_ERROR_REJECTED,
+        None => RI_ERROR_INVALID,
+    }
+}
+
+/// Decode the event frame `frame` in place into `event`. Returns [`RI_EMPTY`] for heartbeats.
+/// If `sequence` is not null, the frame is expected to start with the sequence number of the
+/// `SEQUENCE` feature, which is stored in it.
+///
+/// # Safety
+///
+/// `frame` must be valid for reads and writes of `len` bytes, `event` for writes and `sequence`
+/// null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ri_decode_frame(
+    frame: *mut u8,
+    len: usize,
+    event: *mut RiEvent,
+    sequence: *mut u64,
+) -> i32 {
+    let Some(frame) = buffer(frame, len) else {
+        return RI_ERROR_NULL;
+    };
+    if event.is_null() {
+        return RI_ERROR_NULL;
+    }
+    let Ok(message) = unframe(frame) else {
+        return RI_ERROR_INVALID;
+    };
+    if message.is_empty() {
+        return RI_EMPTY;
+    }
+    let decoded = take_sequence(message, sequence)
+        .and_then(|body| postcard::from_bytes::<InputEventWrapper>(body).ok());
+    match decoded {
+        Some(decoded) => {
+            *event = decoded.into();
+            RI_OK
+        }
+        None => RI_ERROR_INVALID,
+    }
+}
+
+/// Decode the batch frame `frame` (with the `BATCH` feature) in place into `events`, which holds
+/// up to `capacity` events, storing the number of events in `count`. Returns [`RI_EMPTY`] for
+/// heartbeats and [`RI_ERROR_BUFFER_TOO_SMALL`] (with the number of events in `count`) if
+/// `events` is too small. `sequence` is handled like by [`ri_decode_frame`].
+///
+/// # Safety
+///
+/// `frame` must be valid for reads and writes of `len` bytes, `events` for writes of `capacity`
+/// events, `count` for writes and `sequence` null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn ri_decode_batch(
+    frame: *mut u8,
+    len: usize,
+    events: *mut RiEvent,
+    capacity: usize,
+    count: *mut usize,
+    sequence: *mut u64,
+) -> i32 {
+    let Some(frame) = buffer(frame, len) else {
+        return RI_ERROR_NULL;
+    };
+    if events.is_null() || count.is_null() {
+        return RI_ERROR_NULL;
+    }
+    let Ok(message) = unframe(frame) else {
+        return RI_ERROR_INVALID;
+    };
+    if message.is_empty() {
+        return RI_EMPTY;
+    }
+    let Some(body) = take_sequence(message, sequence) else {
+        return RI_ERROR_INVALID;
+    };
+    let Ok(batch) = Batch::new(body) else {
+        return RI_ERROR_INVALID;
+    };
+    *count = batch.len();
+    if batch.len() > capacity {
+        return RI_ERROR_BUFFER_TOO_SMALL;
+    }
+    for (index, event) in batch.enumerate() {
+        let Ok(event) = event else {
+            return RI_ERROR_INVALID;
+        };
+        events.add(index).write(event.into());
+    }
+    RI_OK
+}
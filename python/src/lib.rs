@@ -0,0 +1,160 @@
+//! Python bindings for the remote input client, so that test scripts can consume the event stream:
+//! ```python
+//! import remote_input
+//!
+//! client = remote_input.connect("192.168.1.2:8650", "api key", timeout=5.0)
+//! for event in client:
+//!     print(event.name, event.value)
+//! ```
+
+use pyo3::exceptions::{PyTimeoutError, PyValueError};
+use pyo3::prelude::*;
+use remote_input::codes;
+use remote_input::protocol::InputEventWrapper;
+use std::collections::VecDeque;
+use std::io;
+use std::time::Duration;
+
+/// An input event with the type, code and value of `input-event-codes.h`.
+#[pyclass(frozen, module = "remote_input")]
+struct Event {
+    /// The time of the event in seconds since the UNIX epoch.
+    #[pyo3(get)]
+    timestamp: f64,
+    #[pyo3(get, name = "type")]
+    event_type: u16,
+    #[pyo3(get)]
+    code: u16,
+    #[pyo3(get)]
+    value: i32,
+}
+
+#[pymethods]
+impl Event {
+    /// The name of the code (e.g., "KEY_A"), or `None` if it is unknown.
+    #[getter]
+    fn name(&self) -> Option<&'static str> {
+        codes::code_name(self.event_type, self.code)
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Event(timestamp={}, type={}, code={}, value={})",
+            self.timestamp, self.event_type, self.code, self.value
+        )
+    }
+}
+
+impl From<InputEventWrapper> for Event {
+    fn from(event: InputEventWrapper) -> Self {
+        Self {
+            timestamp: event.timestamp.since_epoch().as_secs_f64(),
+            event_type: event.event_type,
+            code: event.code,
+            value: event.value,
+        }
+    }
+}
+
+/// A connection to a server. Iterating over it yields its events until the server disconnects.
+#[pyclass(module = "remote_input")]
+struct Client {
+    client: remote_input::client::Client,
+    /// The received events which have not been yielded yet.
+    events: VecDeque<InputEventWrapper>,
+}
+
+#[pymethods]
+impl Client {
+    /// The names of the devices of the server.
+    #[getter]
+    fn devices(&self) -> Vec<String> {
+        self.client
+            .devices()
+            .iter()
+            .map(|device| device.name.clone())
+            .collect()
+    }
+
+    /// Wait for the next batch of events. Raises `TimeoutError` if the timeout expires.
+    fn receive(&mut self, py: Python<'_>) -> PyResult<Vec<Event>> {
+        let mut events: Vec<Event> = self.events.drain(..).map(Event::from).collect();
+        if events.is_empty() {
+            let client = &mut self.client;
+            events = py
+                .allow_threads(|| client.receive())
+                .map_err(to_py_err)?
+                .into_iter()
+                .map(Event::from)
+                .collect();
+        }
+        Ok(events)
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<Event>> {
+        while self.events.is_empty() {
+            let client = &mut self.client;
+            match py.allow_threads(|| client.receive()) {
+                Ok(events) => self.events.extend(events),
+                Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(error) => return Err(to_py_err(error)),
+            }
+        }
+        Ok(self.events.pop_front().map(Event::from))
+    }
+}
+
+/// Convert `error` to an `OSError`, or a `TimeoutError` if a read timed out.
+fn to_py_err(error: io::Error) -> PyErr {
+    match error.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+            PyTimeoutError::new_err("no events received within the timeout")
+        }
+        _ => error.into(),
+    }
+}
+
+/// Connect to the server at `address` (e.g., "192.168.1.2:8650") and authenticate with `api_key`,
+/// or the `REMOTE_INPUT_API_KEY` environment variable if it is `None`. Receiving events raises
+/// `TimeoutError` if none are received within `timeout` seconds.
+#[pyfunction]
+#[pyo3(signature = (address, api_key = None, timeout = None))]
+fn connect(
+    py: Python<'_>,
+    address: &str,
+    api_key: Option<String>,
+    timeout: Option<f64>,
+) -> PyResult<Client> {
+    let api_key = match api_key {
+        Some(api_key) => api_key,
+        None => std::env::var("REMOTE_INPUT_API_KEY").map_err(|_| {
+            PyValueError::new_err("no api key given and REMOTE_INPUT_API_KEY is not set")
+        })?,
+    };
+    let timeout = timeout
+        .map(Duration::try_from_secs_f64)
+        .transpose()
+        .map_err(|error| PyValueError::new_err(error.to_string()))?;
+    let client = py
+        .allow_threads(|| remote_input::client::Client::connect(address, &api_key))
+        .map_err(to_py_err)?;
+    client.set_read_timeout(timeout).map_err(to_py_err)?;
+    Ok(Client {
+        client,
+        events: VecDeque::new(),
+    })
+}
+
+/// Receive the events of a remote input server.
+#[pymodule]
+#[pyo3(name = "remote_input")]
+fn init(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(connect, module)?)?;
+    module.add_class::<Client>()?;
+    module.add_class::<Event>()?;
+    Ok(())
+}
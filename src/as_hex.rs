@@ -25,3 +25,19 @@ fn inner_hex(x: u8) -> char {
         _ => '?',
     }
 }
+
+/// Parse a string of hexadecimal digit pairs (either case) into bytes.
+/// Returns `None` if the string has an odd length or contains a non-hexadecimal character.
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let high = (pair[0] as char).to_digit(16)?;
+            let low = (pair[1] as char).to_digit(16)?;
+            Some((high << 4 | low) as u8)
+        })
+        .collect()
+}
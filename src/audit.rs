@@ -0,0 +1,80 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Appends security relevant events (connections, authentication, grabbing and pausing the device)
+/// to a file, one line per event: a UTC timestamp, who caused the event (e.g., "Client 127.0.0.1:50000")
+/// and what happened. Records nothing if no file was opened. Clones share the file.
+#[derive(Clone, Default)]
+pub struct AuditLog(Option<Sender<String>>);
+
+impl AuditLog {
+    /// Open the file at `path` for appending, creating it readable only by its owner if it does not exist.
+    pub fn open(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .open(path)?;
+        Ok(Self(Some(spawn_writer(file, "Audit Log"))))
+    }
+
+    /// Append `event` caused by `source` with the current time.
+    pub fn record(&self, source: &str, event: &str) {
+        if let Some(lines) = &self.0 {
+            let _ = lines.send(format!("{} [{source}] {event}\n", timestamp()));
+        }
+    }
+}
+
+/// Append every line sent to the returned sender to `file` in a thread of its own, so that
+/// connection tasks never wait for the disk. The thread stops once every sender is dropped.
+pub(crate) fn spawn_writer(mut file: File, component: &'static str) -> Sender<String> {
+    let (sender, lines) = mpsc::channel::<String>();
+    let _ = thread::spawn(move || {
+        for line in lines {
+            // Write the whole line at once so that lines are never interleaved.
+            if let Err(error) = file.write_all(line.as_bytes()) {
+                warn!(
+                    "[{component}] Failed to write \"{}\": {error}.",
+                    line.trim_end()
+                );
+            }
+        }
+    });
+    sender
+}
+
+/// The current time in RFC 3339 format in UTC with millisecond precision (e.g., "2024-01-31T12:00:00.000Z").
+fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = now.as_secs();
+    let (hour, minute, second) = (secs / 3600 % 24, secs / 60 % 60, secs % 60);
+
+    // Convert days since 1970-01-01 to a civil date (see http://howardhinnant.github.io/date_algorithms.html).
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}.{:03}Z",
+        now.subsec_millis()
+    )
+}
@@ -0,0 +1,195 @@
+use crate::config::ServerConfig;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Failed authentication attempts and verifications of hashed API keys from one address.
+struct Attempts {
+    failures: u32,
+    hash_checks: u32,
+    /// When the first failure or verification counted in `failures` and `hash_checks` happened.
+    since: Instant,
+    banned_until: Option<Instant>,
+}
+
+/// Counts failed authentication attempts per source address and temporarily bans addresses
+/// with more than `max_failed_auth_attempts` failures within `failed_auth_window_secs`
+/// for `auth_ban_secs`. Also limits how many hashed API keys are verified for each address.
+/// Clones share the counts.
+#[derive(Clone, Default)]
+pub struct AuthLimiter(Arc<Mutex<HashMap<IpAddr, Attempts>>>);
+
+impl AuthLimiter {
+    /// Returns `true` if connections from `address` must be rejected before authentication.
+    pub fn is_banned(&self, address: IpAddr) -> bool {
+        let now = Instant::now();
+        self.0
+            .lock()
+            .unwrap()
+            .get(&address.to_canonical())
+            .and_then(|attempts| attempts.banned_until)
+            .is_some_and(|banned_until| banned_until > now)
+    }
+
+    /// Record the result of an authentication attempt from `address`. A success clears earlier
+    /// failures, but not the verifications of hashed keys, which are limited either way.
+    /// Returns `true` if `address` is banned because of this failure.
+    pub fn record(&self, address: IpAddr, success: bool, config: &ServerConfig) -> bool {
+        let address = address.to_canonical();
+        let mut attempts = self.0.lock().unwrap();
+        if success {
+            if let Some(entry) = attempts.get_mut(&address) {
+                entry.failures = 0;
+            }
+            return false;
+        }
+
+        let now = Instant::now();
+        let window = Duration::from_secs(config.failed_auth_window_secs);
+        let entry = current_attempts(&mut attempts, address, now, window);
+        entry.failures += 1;
+        if config.max_failed_auth_attempts > 0 && entry.failures > config.max_failed_auth_attempts {
+            entry.banned_until = Some(now + Duration::from_secs(config.auth_ban_secs));
+            entry.failures = 0;
+            entry.since = now;
+            return true;
+        }
+        false
+    }
+
+    /// Reserve `count` verifications of hashed API keys for a client from `address`, each of which is
+    /// an Argon2 computation. Returns `false` without reserving any if `address` would exceed
+    /// `max_hash_checks` within `failed_auth_window_secs`.
+    pub fn reserve_hash_checks(&self, address: IpAddr, count: u32, config: &ServerConfig) -> bool {
+        let mut attempts = self.0.lock().unwrap();
+        let window = Duration::from_secs(config.failed_auth_window_secs);
+        let entry = current_attempts(
+            &mut attempts,
+            address.to_canonical(),
+            Instant::now(),
+            window,
+        );
+        if entry.hash_checks.saturating_add(count) > config.max_hash_checks {
+            return false;
+        }
+        entry.hash_checks += count;
+        true
+    }
+}
+
+/// The attempts from `address` within the current `window`.
+fn current_attempts(
+    attempts: &mut HashMap<IpAddr, Attempts>,
+    address: IpAddr,
+    now: Instant,
+    window: Duration,
+) -> &mut Attempts {
+    // Forget addresses which are neither banned nor failed recently so that the map stays small.
+    attempts.retain(|_, attempts| {
+        attempts.banned_until.is_some_and(|until| until > now)
+            || now.duration_since(attempts.since) < window
+    });
+    let entry = attempts.entry(address).or_insert(Attempts {
+        failures: 0,
+        hash_checks: 0,
+        since: now,
+        banned_until: None,
+    });
+    if now.duration_since(entry.since) >= window {
+        entry.failures = 0;
+        entry.hash_checks = 0;
+        entry.since = now;
+    }
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    use std::net::Ipv4Addr;
+
+    const ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+    fn server_config() -> ServerConfig {
+        let mut config = test_config().server;
+        config.max_failed_auth_attempts = 3;
+        config.failed_auth_window_secs = 60;
+        config.auth_ban_secs = 60;
+        config.max_hash_checks = 4;
+        config
+    }
+
+    #[test]
+    fn bans_after_too_many_failures() {
+        let config = server_config();
+        let limiter = AuthLimiter::default();
+        for _ in 0..3 {
+            assert!(!limiter.record(ADDRESS, false, &config));
+            assert!(!limiter.is_banned(ADDRESS));
+        }
+        assert!(limiter.record(ADDRESS, false, &config));
+        assert!(limiter.is_banned(ADDRESS));
+        assert!(!limiter.is_banned(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2))));
+    }
+
+    #[test]
+    fn success_clears_failures() {
+        let config = server_config();
+        let limiter = AuthLimiter::default();
+        for _ in 0..3 {
+            limiter.record(ADDRESS, false, &config);
+        }
+        limiter.record(ADDRESS, true, &config);
+        for _ in 0..3 {
+            assert!(!limiter.record(ADDRESS, false, &config));
+        }
+    }
+
+    #[test]
+    fn forgets_failures_after_window() {
+        let mut config = server_config();
+        config.failed_auth_window_secs = 0;
+        let limiter = AuthLimiter::default();
+        for _ in 0..10 {
+            assert!(!limiter.record(ADDRESS, false, &config));
+        }
+        assert!(!limiter.is_banned(ADDRESS));
+    }
+
+    #[test]
+    fn counts_ipv4_mapped_addresses_as_ipv4() {
+        let config = server_config();
+        let limiter = AuthLimiter::default();
+        let IpAddr::V4(address) = ADDRESS else {
+            unreachable!()
+        };
+        let mapped = IpAddr::V6(address.to_ipv6_mapped());
+        for _ in 0..2 {
+            limiter.record(ADDRESS, false, &config);
+            limiter.record(mapped, false, &config);
+        }
+        assert!(limiter.is_banned(ADDRESS));
+        assert!(limiter.is_banned(mapped));
+    }
+
+    #[test]
+    fn limits_hash_checks() {
+        let config = server_config();
+        let limiter = AuthLimiter::default();
+        assert!(limiter.reserve_hash_checks(ADDRESS, 3, &config));
+        assert!(!limiter.reserve_hash_checks(ADDRESS, 2, &config));
+        assert!(limiter.reserve_hash_checks(ADDRESS, 1, &config));
+        assert!(!limiter.reserve_hash_checks(ADDRESS, 1, &config));
+    }
+
+    #[test]
+    fn success_does_not_refill_hash_checks() {
+        let config = server_config();
+        let limiter = AuthLimiter::default();
+        assert!(limiter.reserve_hash_checks(ADDRESS, 4, &config));
+        limiter.record(ADDRESS, true, &config);
+        assert!(!limiter.reserve_hash_checks(ADDRESS, 1, &config));
+    }
+}
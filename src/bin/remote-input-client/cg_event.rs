@@ -0,0 +1,330 @@
+use crate::translation::macos_key;
+use crate::Backend;
+use remote_input::protocol::{DeviceDescriptor, InputEventWrapper};
+use std::collections::HashSet;
+use std::ffi::c_void;
+use std::io;
+use std::time::{Duration, Instant};
+
+const EV_KEY: u16 = 1;
+const EV_REL: u16 = 2;
+const REL_X: u16 = 0x00;
+const REL_Y: u16 = 0x01;
+const REL_HWHEEL: u16 = 0x06;
+const REL_WHEEL: u16 = 0x08;
+const REL_WHEEL_HI_RES: u16 = 0x0b;
+const REL_HWHEEL_HI_RES: u16 = 0x0c;
+const BTN_LEFT: u16 = 0x110;
+const BTN_RIGHT: u16 = 0x111;
+const BTN_MIDDLE: u16 = 0x112;
+const BTN_SIDE: u16 = 0x113;
+const BTN_EXTRA: u16 = 0x114;
+/// The wheel movement of one notch in high-resolution wheel events.
+const WHEEL_NOTCH: i32 = 120;
+/// The pixels scrolled per notch of high-resolution wheel events.
+const PIXELS_PER_NOTCH: i32 = 10;
+/// The longest interval between the clicks of a double click.
+const DOUBLE_CLICK_INTERVAL: Duration = Duration::from_millis(500);
+
+type CGEventRef = *mut c_void;
+type CGEventSourceRef = *mut c_void;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CGPoint {
+    x: f64,
+    y: f64,
+}
+
+// `CGEventSourceStateID`, `CGEventTapLocation`, `CGEventType`, `CGEventField`, `CGEventFlags` and
+// `CGScrollEventUnit` values of `CGEventTypes.h`.
+const HID_SYSTEM_STATE: i32 = 1;
+const HID_EVENT_TAP: u32 = 0;
+const LEFT_MOUSE_DOWN: u32 = 1;
+const LEFT_MOUSE_UP: u32 = 2;
+const RIGHT_MOUSE_DOWN: u32 = 3;
+const RIGHT_MOUSE_UP: u32 = 4;
+const MOUSE_MOVED: u32 = 5;
+const LEFT_MOUSE_DRAGGED: u32 = 6;
+const RIGHT_MOUSE_DRAGGED: u32 = 7;
+const OTHER_MOUSE_DOWN: u32 = 25;
+const OTHER_MOUSE_UP: u32 = 26;
+const OTHER_MOUSE_DRAGGED: u32 = 27;
+const MOUSE_EVENT_CLICK_STATE: u32 = 1;
+const MOUSE_EVENT_DELTA_X: u32 = 4;
+const MOUSE_EVENT_DELTA_Y: u32 = 5;
+const KEYBOARD_EVENT_AUTOREPEAT: u32 = 8;
+const FLAG_ALPHA_SHIFT: u64 = 0x0001_0000;
+const FLAG_SHIFT: u64 = 0x0002_0000;
+const FLAG_CONTROL: u64 = 0x0004_0000;
+const FLAG_ALTERNATE: u64 = 0x0008_0000;
+const FLAG_COMMAND: u64 = 0x0010_0000;
+const FLAG_SECONDARY_FN: u64 = 0x0080_0000;
+const SCROLL_UNIT_PIXEL: u32 = 0;
+const SCROLL_UNIT_LINE: u32 = 1;
+
+#[link(name = "ApplicationServices", kind = "framework")]
+extern "C" {
+    fn AXIsProcessTrusted() -> u8;
+    fn CGEventSourceCreate(state: i32) -> CGEventSourceRef;
+    fn CGEventCreate(source: CGEventSourceRef) -> CGEventRef;
+    fn CGEventGetLocation(event: CGEventRef) -> CGPoint;
+    fn CGEventCreateKeyboardEvent(source: CGEventSourceRef, key: u16, down: bool) -> CGEventRef;
+    fn CGEventCreateMouseEvent(
+        source: CGEventSourceRef,
+        event_type: u32,
+        location: CGPoint,
+        button: u32,
+    ) -> CGEventRef;
+    fn CGEventCreateScrollWheelEvent(
+        source: CGEventSourceRef,
+        units: u32,
+        wheel_count: u32,
+        wheel1: i32,
+        ...
+    ) -> CGEventRef;
+    fn CGEventSetFlags(event: CGEventRef, flags: u64);
+    fn CGEventSetIntegerValueField(event: CGEventRef, field: u32, value: i64);
+    fn CGEventPost(tap: u32, event: CGEventRef);
+}
+
+#[link(name = "CoreFoundation", kind = "framework")]
+extern "C" {
+    fn CFRelease(object: *const c_void);
+}
+
+/// Replays keyboard and mouse events with `CGEventPost`, which requires the accessibility
+/// permission. Keys are pressed by their virtual key code (see [`crate::translation`]).
+/// Absolute axes are not replayed.
+pub struct CgEvent {
+    source: CGEventSourceRef,
+    /// The modifier flags of the pressed modifier keys, set on every event.
+    flags: u64,
+    /// The pressed mouse buttons by their button number, for dragging.
+    buttons: HashSet<u32>,
+    /// The button number, time and click count of the last click, for double clicks.
+    last_click: Option<(u32, Instant, i64)>,
+    /// Whether high-resolution wheel events were received, so that the low-resolution events sent
+    /// along with them are ignored.
+    high_resolution_wheel: bool,
+    /// Key codes without a macOS key which were already reported.
+    unknown_keys: HashSet<u16>,
+}
+
+impl Backend for CgEvent {
+    fn create(_devices: &[DeviceDescriptor]) -> io::Result<Self> {
+        // SAFETY: the functions have no preconditions.
+        if unsafe { AXIsProcessTrusted() } == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "posting events requires the accessibility permission: allow remote-input-client \
+                 (or the terminal running it) in System Settings > Privacy & Security > \
+                 Accessibility and restart it",
+            ));
+        }
+        let source = unsafe { CGEventSourceCreate(HID_SYSTEM_STATE) };
+        if source.is_null() {
+            return Err(io::Error::other("unable to create an event source"));
+        }
+        Ok(Self {
+            source,
+            flags: 0,
+            buttons: HashSet::new(),
+            last_click: None,
+            high_resolution_wheel: false,
+            unknown_keys: HashSet::new(),
+        })
+    }
+
+    /// Post the key presses, button presses and mouse movements of `report`.
+    fn emit(&mut self, report: &[InputEventWrapper]) -> io::Result<()> {
+        let (mut dx, mut dy) = (0, 0);
+        for event in report {
+            match (event.event_type, event.code) {
+                (EV_REL, REL_X) => dx += event.value,
+                (EV_REL, REL_Y) => dy += event.value,
+                (EV_REL, REL_WHEEL_HI_RES) => {
+                    self.high_resolution_wheel = true;
+                    let pixels = event.value * PIXELS_PER_NOTCH / WHEEL_NOTCH;
+                    self.scroll(SCROLL_UNIT_PIXEL, pixels, 0)?;
+                }
+                (EV_REL, REL_HWHEEL_HI_RES) => {
+                    self.high_resolution_wheel = true;
+                    let pixels = event.value * PIXELS_PER_NOTCH / WHEEL_NOTCH;
+                    self.scroll(SCROLL_UNIT_PIXEL, 0, -pixels)?;
+                }
+                (EV_REL, REL_WHEEL) if !self.high_resolution_wheel => {
+                    self.scroll(SCROLL_UNIT_LINE, event.value, 0)?
+                }
+                (EV_REL, REL_HWHEEL) if !self.high_resolution_wheel => {
+                    self.scroll(SCROLL_UNIT_LINE, 0, -event.value)?
+                }
+                (EV_KEY, BTN_LEFT..=BTN_EXTRA) => {
+                    // Movements before the button change move the cursor to where it is clicked.
+                    self.move_cursor(dx, dy)?;
+                    (dx, dy) = (0, 0);
+                    let button = match event.code {
+                        BTN_LEFT => 0,
+                        BTN_RIGHT => 1,
+                        BTN_MIDDLE => 2,
+                        BTN_SIDE => 3,
+                        _ => 4,
+                    };
+                    match event.value {
+                        0 => self.release_button(button)?,
+                        1 => self.press_button(button)?,
+                        _ => {}
+                    }
+                }
+                (EV_KEY, code) => match macos_key(code) {
+                    Some(key) => self.key(key, event.value)?,
+                    None => {
+                        if self.unknown_keys.insert(code) {
+                            tracing::warn!("[CGEvent] Key {code} has no macOS key. Ignoring it.");
+                        }
+                    }
+                },
+                _ => {}
+            }
+        }
+        self.move_cursor(dx, dy)
+    }
+}
+
+impl CgEvent {
+    /// Post a key press (`value` 1), release (0) or autorepeat (2), updating the modifier flags.
+    fn key(&mut self, key: u16, value: i32) -> io::Result<()> {
+        let flag = match key {
+            0x38 | 0x3C => FLAG_SHIFT,
+            0x3B | 0x3E => FLAG_CONTROL,
+            0x3A | 0x3D => FLAG_ALTERNATE,
+            0x37 | 0x36 => FLAG_COMMAND,
+            0x3F => FLAG_SECONDARY_FN,
+            _ => 0,
+        };
+        match value {
+            0 => self.flags &= !flag,
+            1 => self.flags |= flag,
+            _ => {}
+        }
+        // Caps lock toggles on press.
+        if key == 0x39 && value == 1 {
+            self.flags ^= FLAG_ALPHA_SHIFT;
+        }
+        let event = unsafe { CGEventCreateKeyboardEvent(self.source, key, value != 0) };
+        if value == 2 {
+            unsafe { CGEventSetIntegerValueField(event, KEYBOARD_EVENT_AUTOREPEAT, 1) };
+        }
+        self.post(event)
+    }
+
+    /// Move the cursor by `dx` and `dy`, dragging the pressed buttons.
+    fn move_cursor(&mut self, dx: i32, dy: i32) -> io::Result<()> {
+        if dx == 0 && dy == 0 {
+            return Ok(());
+        }
+        let mut location = self.location()?;
+        location.x += dx as f64;
+        location.y += dy as f64;
+        let (event_type, button) = if self.buttons.contains(&0) {
+            (LEFT_MOUSE_DRAGGED, 0)
+        } else if self.buttons.contains(&1) {
+            (RIGHT_MOUSE_DRAGGED, 1)
+        } else if let Some(&button) = self.buttons.iter().next() {
+            (OTHER_MOUSE_DRAGGED, button)
+        } else {
+            (MOUSE_MOVED, 0)
+        };
+        let event = unsafe { CGEventCreateMouseEvent(self.source, event_type, location, button) };
+        // Programs which read relative movements (e.g., games) use the deltas.
+        unsafe {
+            CGEventSetIntegerValueField(event, MOUSE_EVENT_DELTA_X, dx as i64);
+            CGEventSetIntegerValueField(event, MOUSE_EVENT_DELTA_Y, dy as i64);
+        }
+        self.post(event)
+    }
+
+    /// Press the mouse button with the number `button`, counting repeated clicks.
+    fn press_button(&mut self, button: u32) -> io::Result<()> {
+        let now = Instant::now();
+        let clicks = match self.last_click {
+            Some((last, time, clicks)) if last == button && now - time <= DOUBLE_CLICK_INTERVAL => {
+                clicks + 1
+            }
+            _ => 1,
+        };
+        self.last_click = Some((button, now, clicks));
+        self.buttons.insert(button);
+        let event_type = match button {
+            0 => LEFT_MOUSE_DOWN,
+            1 => RIGHT_MOUSE_DOWN,
+            _ => OTHER_MOUSE_DOWN,
+        };
+        self.button_event(event_type, button, clicks)
+    }
+
+    /// Release the mouse button with the number `button`.
+    fn release_button(&mut self, button: u32) -> io::Result<()> {
+        self.buttons.remove(&button);
+        let clicks = match self.last_click {
+            Some((last, _, clicks)) if last == button => clicks,
+            _ => 1,
+        };
+        let event_type = match button {
+            0 => LEFT_MOUSE_UP,
+            1 => RIGHT_MOUSE_UP,
+            _ => OTHER_MOUSE_UP,
+        };
+        self.button_event(event_type, button, clicks)
+    }
+
+    /// Post a button event at the cursor, as the `clicks`th click of a multi-click.
+    fn button_event(&mut self, event_type: u32, button: u32, clicks: i64) -> io::Result<()> {
+        let location = self.location()?;
+        let event = unsafe { CGEventCreateMouseEvent(self.source, event_type, location, button) };
+        unsafe { CGEventSetIntegerValueField(event, MOUSE_EVENT_CLICK_STATE, clicks) };
+        self.post(event)
+    }
+
+    /// Scroll vertically and horizontally (positive is up and left) in `units`.
+    fn scroll(&mut self, units: u32, vertical: i32, horizontal: i32) -> io::Result<()> {
+        let event =
+            unsafe { CGEventCreateScrollWheelEvent(self.source, units, 2, vertical, horizontal) };
+        self.post(event)
+    }
+
+    /// The location of the cursor.
+    fn location(&self) -> io::Result<CGPoint> {
+        let event = unsafe { CGEventCreate(self.source) };
+        if event.is_null() {
+            return Err(io::Error::other("unable to read the cursor location"));
+        }
+        // SAFETY: `event` is a valid event, released after reading its location.
+        unsafe {
+            let location = CGEventGetLocation(event);
+            CFRelease(event);
+            Ok(location)
+        }
+    }
+
+    /// Post `event` with the current modifier flags and release it.
+    fn post(&self, event: CGEventRef) -> io::Result<()> {
+        if event.is_null() {
+            return Err(io::Error::other("unable to create an event"));
+        }
+        // SAFETY: `event` is a valid event, released after posting it.
+        unsafe {
+            CGEventSetFlags(event, self.flags);
+            CGEventPost(HID_EVENT_TAP, event);
+            CFRelease(event);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for CgEvent {
+    fn drop(&mut self) {
+        // SAFETY: `source` was created by `CGEventSourceCreate` and is not used afterwards.
+        unsafe { CFRelease(self.source) };
+    }
+}
@@ -0,0 +1,34 @@
+use remote_input::codes::{code_name, event_type_name};
+use remote_input::protocol::InputEventWrapper;
+
+/// `EV_SYN`, `EV_KEY` and `SYN_REPORT`.
+const EV_SYN: u16 = 0;
+const EV_KEY: u16 = 1;
+const SYN_REPORT: u16 = 0;
+
+/// Print `event` with symbolic names instead of injecting it, e.g.,
+/// `1718000000.123456 KEY_A pressed` or `1718000000.123456 REL_X -3`.
+pub fn print_event(event: &InputEventWrapper) {
+    let timestamp = event.timestamp.since_epoch();
+    let time = format!("{}.{:06}", timestamp.as_secs(), timestamp.subsec_micros());
+    if event.event_type == EV_SYN && event.code == SYN_REPORT {
+        println!("[Dry Run] {time} -------------- SYN_REPORT ------------");
+        return;
+    }
+    let code = match code_name(event.event_type, event.code) {
+        Some(name) => name.to_string(),
+        None => format!(
+            "{} {}",
+            event_type_name(event.event_type)
+                .map_or_else(|| format!("type {}", event.event_type), str::to_string),
+            event.code
+        ),
+    };
+    let value = match (event.event_type, event.value) {
+        (EV_KEY, 0) => "released".to_string(),
+        (EV_KEY, 1) => "pressed".to_string(),
+        (EV_KEY, 2) => "repeated".to_string(),
+        (_, value) => value.to_string(),
+    };
+    println!("[Dry Run] {time} {code} {value}");
+}
@@ -0,0 +1,351 @@
+//! Connects to a remote input server and replays the events it sends, so that the keyboard and mouse
+//! of the server control this machine: on Linux with a virtual (uinput) device or the XTEST extension
+//! of X11 (or, with the `wayland` feature, the virtual keyboard and pointer protocols of Wayland), on Windows with `SendInput` and on macOS with `CGEventPost`.
+//!
+//! Usage: `REMOTE_INPUT_API_KEY=<api key> remote-input-client [options] <address>`, where the address
+//! is, e.g., `192.168.1.2:8650`. Options:
+//! - `--backend <name>` selects the backend (see [`BACKENDS`]) instead of the first one available.
+//! - `--remap <file>` translates keys with a remap table (see [`Remap`]).
+//! - `--dry-run` prints the events with symbolic names instead of injecting them.
+//! - `--record <file>` records the received events (see [`Recorder`]).
+//! - `--play <file>` replays a recording instead of connecting to a server.
+//! - `--stuck-key-timeout <millis>` releases keys held that long without a repeat (see [`Watchdog`]).
+//!
+//! Logs are written to stderr and filtered with `RUST_LOG` (by default, `info`). Invalid options and
+//! errors which stop replaying (e.g., the connection failing) exit with status 2.
+//!
+//! Connections use plain TCP (see [`Client`]); servers which require TLS or TOTP codes are not supported.
+
+use clap::Parser;
+use recording::Recorder;
+use remap::Remap;
+use remote_input::client::Client;
+use remote_input::protocol::{DeviceDescriptor, InputEventWrapper};
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime};
+use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
+use watchdog::Watchdog;
+
+#[cfg(target_os = "macos")]
+mod cg_event;
+mod dry_run;
+mod recording;
+mod remap;
+#[cfg(windows)]
+mod send_input;
+// Each backend only uses the translation for its own system.
+#[allow(dead_code)]
+mod translation;
+#[cfg(target_os = "linux")]
+mod uinput;
+mod watchdog;
+#[cfg(all(target_os = "linux", feature = "wayland"))]
+mod wayland;
+#[cfg(target_os = "linux")]
+mod xtest;
+
+/// Creates a backend for replaying the events of the given devices.
+type Constructor = fn(&[DeviceDescriptor]) -> io::Result<Box<dyn Backend>>;
+
+/// The backends of this system by name, in the order in which they are tried.
+#[cfg(target_os = "macos")]
+const BACKENDS: &[(&str, Constructor)] = &[("cgevent", create::<cg_event::CgEvent>)];
+#[cfg(windows)]
+const BACKENDS: &[(&str, Constructor)] = &[("sendinput", create::<send_input::SendInput>)];
+#[cfg(all(target_os = "linux", not(feature = "wayland")))]
+const BACKENDS: &[(&str, Constructor)] = &[
+    ("uinput", create::<uinput::Uinput>),
+    ("xtest", create::<xtest::Xtest>),
+];
+#[cfg(all(target_os = "linux", feature = "wayland"))]
+const BACKENDS: &[(&str, Constructor)] = &[
+    ("uinput", create::<uinput::Uinput>),
+    ("wayland", create::<wayland::Wayland>),
+    ("xtest", create::<xtest::Xtest>),
+];
+
+/// `EV_SYN` and `SYN_REPORT`, which ends every report.
+const EV_SYN: u16 = 0;
+const SYN_REPORT: u16 = 0;
+
+/// Injects replayed events into the local system.
+trait Backend {
+    /// Prepare to replay the events of the devices described by `devices`, which is empty if the
+    /// server did not describe them.
+    fn create(devices: &[DeviceDescriptor]) -> io::Result<Self>
+    where
+        Self: Sized;
+
+    /// Inject the events of a report, without its `SYN_REPORT`.
+    fn emit(&mut self, report: &[InputEventWrapper]) -> io::Result<()>;
+}
+
+/// Create the backend `B` as a [`Constructor`].
+fn create<B: Backend + 'static>(devices: &[DeviceDescriptor]) -> io::Result<Box<dyn Backend>> {
+    Ok(Box::new(B::create(devices)?))
+}
+
+/// Create the backend named `name`, or the first backend of [`BACKENDS`] which can be created if
+/// it is `None`.
+fn create_backend(
+    name: Option<&str>,
+    devices: &[DeviceDescriptor],
+) -> Result<Box<dyn Backend>, String> {
+    if let Some(name) = name {
+        let Some((_, create)) = BACKENDS.iter().find(|(backend, _)| *backend == name) else {
+            let names: Vec<&str> = BACKENDS.iter().map(|(name, _)| *name).collect();
+            return Err(format!(
+                "unknown backend {name} (available: {})",
+                names.join(", ")
+            ));
+        };
+        let backend = create(devices)
+            .map_err(|error| format!("unable to prepare replaying events: {error}"))?;
+        info!("[Client] Replaying events with {name}.");
+        return Ok(backend);
+    }
+    for (name, create) in BACKENDS {
+        match create(devices) {
+            Ok(backend) => {
+                info!("[Client] Replaying events with {name}.");
+                return Ok(backend);
+            }
+            Err(error) => warn!("[Client] Unable to replay events with {name}: {error}."),
+        }
+    }
+    Err("unable to prepare replaying events".to_string())
+}
+
+/// Connects to a remote input server and replays the events it sends. The API key is read from
+/// `REMOTE_INPUT_API_KEY`.
+#[derive(Parser)]
+#[command(version)]
+struct Options {
+    /// The server, e.g., `192.168.1.2:8650`.
+    #[arg(required_unless_present = "play", conflicts_with = "play")]
+    address: Option<String>,
+    /// The backend replaying events, the first one available by default.
+    #[arg(long, value_name = "NAME")]
+    backend: Option<String>,
+    /// Translate keys with a remap table.
+    #[arg(long, value_name = "FILE")]
+    remap: Option<PathBuf>,
+    /// Print the events with symbolic names instead of injecting them.
+    #[arg(long)]
+    dry_run: bool,
+    /// Record the received events.
+    #[arg(long, value_name = "FILE")]
+    record: Option<PathBuf>,
+    /// Replay a recording instead of connecting to a server.
+    #[arg(long, value_name = "FILE")]
+    play: Option<PathBuf>,
+    /// Release keys held that long without a repeat.
+    #[arg(long, value_name = "MILLIS", value_parser = parse_millis)]
+    stuck_key_timeout: Option<Duration>,
+}
+
+/// Parse a duration in milliseconds.
+fn parse_millis(millis: &str) -> Result<Duration, String> {
+    millis
+        .parse()
+        .map(Duration::from_millis)
+        .map_err(|error| format!("{error}"))
+}
+
+/// Authenticate with the server at `address` with the api key of the environment.
+fn connect(address: &str) -> Result<Client, String> {
+    let api_key = std::env::var("REMOTE_INPUT_API_KEY")
+        .map_err(|_| "REMOTE_INPUT_API_KEY must be set".to_string())?;
+    info!("[Client] Connecting to {address}.");
+    Client::connect(address, &api_key).map_err(|error| format!("unable to connect: {error}"))
+}
+
+/// Handles every received (or played) event: records it, remaps it and injects it, or prints it
+/// in a dry run.
+struct Sink {
+    recorder: Option<Recorder>,
+    remap: Remap,
+    /// Notices injected keys which are stuck, if enabled.
+    watchdog: Option<Watchdog>,
+    /// The backend injecting events, or `None` in a dry run.
+    backend: Option<Box<dyn Backend>>,
+    /// The events of the current report.
+    report: Vec<InputEventWrapper>,
+}
+
+impl Sink {
+    fn handle(&mut self, mut event: InputEventWrapper) {
+        let end_of_report = event.event_type == EV_SYN && event.code == SYN_REPORT;
+        if let Some(recorder) = &mut self.recorder {
+            let result = recorder.record(&event).and_then(|_| {
+                if end_of_report {
+                    recorder.flush()
+                } else {
+                    Ok(())
+                }
+            });
+            if let Err(error) = result {
+                error!("[Client] Unable to record events: {error}.");
+            }
+        }
+        if !self.remap.apply(&mut event) {
+            return;
+        }
+        self.inject(event);
+    }
+
+    /// Inject `event` (or print it in a dry run), emitting the report at its `SYN_REPORT`.
+    fn inject(&mut self, event: InputEventWrapper) {
+        let end_of_report = event.event_type == EV_SYN && event.code == SYN_REPORT;
+        if let Some(watchdog) = &mut self.watchdog {
+            watchdog.observe(&event);
+        }
+        let Some(backend) = &mut self.backend else {
+            dry_run::print_event(&event);
+            return;
+        };
+        if end_of_report {
+            if let Err(error) = backend.emit(&self.report) {
+                error!("[Client] Unable to emit events: {error}.");
+            }
+            self.report.clear();
+        } else {
+            self.report.push(event);
+        }
+    }
+
+    /// Release the keys which the watchdog found stuck, in a report of their own.
+    fn release_stuck_keys(&mut self) {
+        let Some(watchdog) = &mut self.watchdog else {
+            return;
+        };
+        let releases = watchdog.expired();
+        if releases.is_empty() {
+            return;
+        }
+        warn!("[Client] Releasing {} stuck keys.", releases.len());
+        let report = InputEventWrapper {
+            timestamp: SystemTime::now().into(),
+            event_type: EV_SYN,
+            code: SYN_REPORT,
+            value: 0,
+        };
+        for event in releases.into_iter().chain([report]) {
+            self.inject(event);
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    // Log to stderr, so that the events printed in a dry run can be piped.
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal())
+        .init();
+    match run(Options::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            error!("[Client] Error: {message}.");
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// Replay the events received with or played as configured by `options`, until the server shuts
+/// down or the recording ends.
+fn run(options: Options) -> Result<(), String> {
+    let remap = match &options.remap {
+        Some(path) => {
+            let remap = Remap::load(path).map_err(|error| {
+                format!("unable to load remap table {}: {error}", path.display())
+            })?;
+            info!(
+                "[Client] Remapping {} keys as configured in \"{}\".",
+                remap.len(),
+                path.display()
+            );
+            remap
+        }
+        None => Remap::default(),
+    };
+    let recorder = match &options.record {
+        Some(path) => {
+            info!("[Client] Recording events in \"{}\".", path.display());
+            let recorder = Recorder::create(path)
+                .map_err(|error| format!("unable to create recording: {error}"))?;
+            Some(recorder)
+        }
+        None => None,
+    };
+
+    let connection = options.address.as_deref().map(connect).transpose()?;
+    let mut devices = match &connection {
+        Some(client) => client.devices().to_vec(),
+        None => Vec::new(),
+    };
+    for device in &mut devices {
+        info!("[Client] Replicating \"{}\".", device.name);
+        remap.apply_to_descriptor(device);
+    }
+    // A dry run prints the events instead, without access to the input system.
+    let backend = (!options.dry_run)
+        .then(|| create_backend(options.backend.as_deref(), &devices))
+        .transpose()?;
+    if backend.is_none() {
+        info!("[Client] Printing events.");
+    }
+    let mut sink = Sink {
+        recorder,
+        remap,
+        watchdog: options.stuck_key_timeout.map(Watchdog::new),
+        backend,
+        report: Vec::new(),
+    };
+
+    // Replay the recording without connecting.
+    if let Some(path) = &options.play {
+        info!("[Client] Playing \"{}\".", path.display());
+        return recording::play(path, |event| sink.handle(event))
+            .map_err(|error| format!("unable to play recording: {error}"));
+    }
+
+    // Replay every report, releasing stuck keys while no events are received.
+    let Some(mut client) = connection else {
+        unreachable!("an address is required without a recording");
+    };
+    loop {
+        // A timeout of zero is invalid.
+        let timeout = sink
+            .watchdog
+            .as_ref()
+            .and_then(Watchdog::next_due)
+            .map(|timeout| timeout.max(Duration::from_millis(1)));
+        client
+            .set_read_timeout(timeout)
+            .map_err(|error| format!("unable to set read timeout: {error}"))?;
+        match client.receive() {
+            Ok(events) => {
+                for event in events {
+                    sink.handle(event);
+                }
+            }
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(error) if error.kind() == io::ErrorKind::ConnectionAborted => {
+                info!("[Client] The server is shutting down.");
+                return Ok(());
+            }
+            Err(error) => return Err(format!("unable to receive events: {error}")),
+        }
+        sink.release_stuck_keys();
+    }
+}
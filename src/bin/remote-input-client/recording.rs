@@ -0,0 +1,56 @@
+use remote_input::protocol::{InputEventWrapper, Timestamp};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::thread;
+
+/// Records the received events in a file, one JSON object per line with the fields of
+/// [`InputEventWrapper`] (like the JSON lines server), so that they can be replayed with [`play`].
+pub struct Recorder {
+    writer: BufWriter<File>,
+}
+
+impl Recorder {
+    /// Create (or truncate) the recording at `path`.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    /// Append `event` to the recording.
+    pub fn record(&mut self, event: &InputEventWrapper) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, event)?;
+        self.writer.write_all(b"\n")
+    }
+
+    /// Write the recorded events to the file, e.g., at the end of each report.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Pass every event of the recording at `path` to `handle`, waiting between events as long as
+/// between their timestamps.
+pub fn play(path: &Path, mut handle: impl FnMut(InputEventWrapper)) -> io::Result<()> {
+    let mut previous = None;
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event: InputEventWrapper = serde_json::from_str(&line)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        if let Some(delay) = previous.and_then(|previous: Timestamp| {
+            event
+                .timestamp
+                .since_epoch()
+                .checked_sub(previous.since_epoch())
+        }) {
+            thread::sleep(delay);
+        }
+        previous = Some(event.timestamp);
+        handle(event);
+    }
+    Ok(())
+}
@@ -0,0 +1,90 @@
+use remote_input::codes::key_code;
+use remote_input::protocol::{DeviceDescriptor, InputEventWrapper};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// `EV_KEY`, the type of the events which are remapped.
+const EV_KEY: u16 = 1;
+
+/// A key in a remap table: its name (e.g., "KEY_A") or its code.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KeyName {
+    Name(String),
+    Code(u16),
+}
+
+/// The remap table file.
+#[derive(Deserialize)]
+struct RemapFile {
+    /// The key each key is replaced with, by name or code. "KEY_RESERVED" discards the key.
+    #[serde(default)]
+    keys: HashMap<String, KeyName>,
+}
+
+/// Translates the key codes received from the server before they are injected, so that each
+/// target machine can map the same keys differently (e.g., swap caps lock and control).
+#[derive(Default)]
+pub struct Remap {
+    keys: HashMap<u16, u16>,
+}
+
+impl Remap {
+    /// Load the remap table at `path`, e.g.:
+    /// ```toml
+    /// [keys]
+    /// KEY_CAPSLOCK = "KEY_LEFTCTRL"
+    /// KEY_LEFTCTRL = "KEY_CAPSLOCK"
+    /// ```
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = fs::read_to_string(path).map_err(|error| error.to_string())?;
+        let file: RemapFile = toml::from_str(&data).map_err(|error| error.to_string())?;
+        let mut keys = HashMap::new();
+        for (from, to) in file.keys {
+            let from = parse_key(&from)?;
+            let to = match to {
+                KeyName::Name(name) => parse_key(&name)?,
+                KeyName::Code(code) => code,
+            };
+            keys.insert(from, to);
+        }
+        Ok(Self { keys })
+    }
+
+    /// The number of remapped keys.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Remap the key of `event`, if any. Returns `false` if the event is discarded.
+    pub fn apply(&self, event: &mut InputEventWrapper) -> bool {
+        if event.event_type != EV_KEY {
+            return true;
+        }
+        if let Some(&code) = self.keys.get(&event.code) {
+            event.code = code;
+        }
+        event.code != 0
+    }
+
+    /// Add the keys which `device` produces after remapping to its supported keys, so that
+    /// backends replicating the device can emit them.
+    pub fn apply_to_descriptor(&self, device: &mut DeviceDescriptor) {
+        for &code in self.keys.values().filter(|&&code| code != 0) {
+            let index = code as usize / 8;
+            if device.keys.len() <= index {
+                device.keys.resize(index + 1, 0);
+            }
+            device.keys[index] |= 1 << (code % 8);
+        }
+    }
+}
+
+/// The code of the key named `name` (e.g., "KEY_A") or written as a number.
+fn parse_key(name: &str) -> Result<u16, String> {
+    key_code(name)
+        .or_else(|| name.parse().ok())
+        .ok_or_else(|| format!("unknown key {name}"))
+}
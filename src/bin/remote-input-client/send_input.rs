@@ -0,0 +1,173 @@
+use crate::translation::windows_key;
+use crate::Backend;
+use remote_input::protocol::{DeviceDescriptor, InputEventWrapper};
+use std::collections::HashSet;
+use std::io;
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::{
+    SendInput as send_input, INPUT, INPUT_0, INPUT_KEYBOARD, INPUT_MOUSE, KEYBDINPUT,
+    KEYEVENTF_EXTENDEDKEY, KEYEVENTF_KEYUP, KEYEVENTF_SCANCODE, MOUSEEVENTF_HWHEEL,
+    MOUSEEVENTF_LEFTDOWN, MOUSEEVENTF_LEFTUP, MOUSEEVENTF_MIDDLEDOWN, MOUSEEVENTF_MIDDLEUP,
+    MOUSEEVENTF_MOVE, MOUSEEVENTF_RIGHTDOWN, MOUSEEVENTF_RIGHTUP, MOUSEEVENTF_WHEEL,
+    MOUSEEVENTF_XDOWN, MOUSEEVENTF_XUP, MOUSEINPUT,
+};
+
+const EV_KEY: u16 = 1;
+const EV_REL: u16 = 2;
+const REL_X: u16 = 0x00;
+const REL_Y: u16 = 0x01;
+const REL_HWHEEL: u16 = 0x06;
+const REL_WHEEL: u16 = 0x08;
+const REL_WHEEL_HI_RES: u16 = 0x0b;
+const REL_HWHEEL_HI_RES: u16 = 0x0c;
+const BTN_LEFT: u16 = 0x110;
+const BTN_RIGHT: u16 = 0x111;
+const BTN_MIDDLE: u16 = 0x112;
+const BTN_SIDE: u16 = 0x113;
+const BTN_EXTRA: u16 = 0x114;
+/// The wheel movement of one notch (`WHEEL_DELTA`), which is also the unit of high-resolution wheel events.
+const WHEEL_DELTA: i32 = 120;
+/// `XBUTTON1` and `XBUTTON2`, the side buttons of a mouse.
+const XBUTTON1: u32 = 1;
+const XBUTTON2: u32 = 2;
+
+/// Replays keyboard and mouse events with `SendInput`. Keys are pressed by their virtual-key code
+/// (see [`crate::translation`]) or, if they have none, by their scan code. Absolute axes are not replayed.
+pub struct SendInput {
+    /// Whether high-resolution wheel events were received, so that the low-resolution events sent
+    /// along with them are ignored.
+    high_resolution_wheel: bool,
+    /// Key codes without a Windows key which were already reported.
+    unknown_keys: HashSet<u16>,
+}
+
+impl Backend for SendInput {
+    fn create(_devices: &[DeviceDescriptor]) -> io::Result<Self> {
+        Ok(Self {
+            high_resolution_wheel: false,
+            unknown_keys: HashSet::new(),
+        })
+    }
+
+    /// Send the key presses, button presses and mouse movements of `report` as one batch of inputs.
+    fn emit(&mut self, report: &[InputEventWrapper]) -> io::Result<()> {
+        let (mut dx, mut dy) = (0, 0);
+        let mut inputs = Vec::new();
+        for event in report {
+            match (event.event_type, event.code) {
+                (EV_REL, REL_X) => dx += event.value,
+                (EV_REL, REL_Y) => dy += event.value,
+                (EV_REL, REL_WHEEL_HI_RES) => {
+                    self.high_resolution_wheel = true;
+                    inputs.push(mouse_input(MOUSEEVENTF_WHEEL, event.value as u32));
+                }
+                (EV_REL, REL_HWHEEL_HI_RES) => {
+                    self.high_resolution_wheel = true;
+                    inputs.push(mouse_input(MOUSEEVENTF_HWHEEL, event.value as u32));
+                }
+                (EV_REL, REL_WHEEL) if !self.high_resolution_wheel => inputs.push(mouse_input(
+                    MOUSEEVENTF_WHEEL,
+                    (event.value * WHEEL_DELTA) as u32,
+                )),
+                (EV_REL, REL_HWHEEL) if !self.high_resolution_wheel => inputs.push(mouse_input(
+                    MOUSEEVENTF_HWHEEL,
+                    (event.value * WHEEL_DELTA) as u32,
+                )),
+                (EV_KEY, BTN_LEFT..=BTN_EXTRA) => {
+                    let pressed = event.value != 0;
+                    let (down, up, data) = match event.code {
+                        BTN_LEFT => (MOUSEEVENTF_LEFTDOWN, MOUSEEVENTF_LEFTUP, 0),
+                        BTN_RIGHT => (MOUSEEVENTF_RIGHTDOWN, MOUSEEVENTF_RIGHTUP, 0),
+                        BTN_MIDDLE => (MOUSEEVENTF_MIDDLEDOWN, MOUSEEVENTF_MIDDLEUP, 0),
+                        BTN_SIDE => (MOUSEEVENTF_XDOWN, MOUSEEVENTF_XUP, XBUTTON1),
+                        _ => (MOUSEEVENTF_XDOWN, MOUSEEVENTF_XUP, XBUTTON2),
+                    };
+                    inputs.push(mouse_input(if pressed { down } else { up }, data));
+                }
+                (EV_KEY, code) => match windows_key(code) {
+                    // Autorepeat events (value 2) are sent as further presses, like a held key.
+                    Some(key) => inputs.push(keyboard_input(
+                        key.virtual_key,
+                        key.scan_code,
+                        key.extended,
+                        event.value == 0,
+                    )),
+                    None => {
+                        if self.unknown_keys.insert(code) {
+                            tracing::warn!(
+                                "[Send Input] Key {code} has no Windows key. Ignoring it."
+                            );
+                        }
+                    }
+                },
+                _ => {}
+            }
+        }
+        if dx != 0 || dy != 0 {
+            let mut input = mouse_input(MOUSEEVENTF_MOVE, 0);
+            input.Anonymous.mi.dx = dx;
+            input.Anonymous.mi.dy = dy;
+            inputs.insert(0, input);
+        }
+        if inputs.is_empty() {
+            return Ok(());
+        }
+        // SAFETY: `inputs` is a valid array of `INPUT` of the given length and element size.
+        let sent = unsafe {
+            send_input(
+                inputs.len() as u32,
+                inputs.as_ptr(),
+                std::mem::size_of::<INPUT>() as i32,
+            )
+        };
+        if sent as usize != inputs.len() {
+            // Input is blocked, e.g., by User Interface Privilege Isolation (UIPI) if the focused
+            // window belongs to a process with a higher integrity level.
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// A mouse input with `flags` and `data` (the wheel movement or the side button).
+fn mouse_input(flags: u32, data: u32) -> INPUT {
+    INPUT {
+        r#type: INPUT_MOUSE,
+        Anonymous: INPUT_0 {
+            mi: MOUSEINPUT {
+                dx: 0,
+                dy: 0,
+                mouseData: data,
+                dwFlags: flags,
+                time: 0,
+                dwExtraInfo: 0,
+            },
+        },
+    }
+}
+
+/// A keyboard input pressing or releasing (`up`) a key by its virtual-key code, or by its scan code
+/// if it has none.
+fn keyboard_input(virtual_key: Option<u16>, scan_code: u16, extended: bool, up: bool) -> INPUT {
+    let mut flags = 0;
+    if virtual_key.is_none() {
+        flags |= KEYEVENTF_SCANCODE;
+    }
+    if extended {
+        flags |= KEYEVENTF_EXTENDEDKEY;
+    }
+    if up {
+        flags |= KEYEVENTF_KEYUP;
+    }
+    INPUT {
+        r#type: INPUT_KEYBOARD,
+        Anonymous: INPUT_0 {
+            ki: KEYBDINPUT {
+                wVk: virtual_key.unwrap_or(0),
+                wScan: scan_code,
+                dwFlags: flags,
+                time: 0,
+                dwExtraInfo: 0,
+            },
+        },
+    }
+}
@@ -0,0 +1,303 @@
+//! Translation of Linux key codes (`KEY_*` of `input-event-codes.h`) into the key codes of other
+//! systems, shared by the backends which cannot inject Linux key codes directly.
+
+/// Set in [`WindowsKey::scan_code`] for keys whose PC scan code is prefixed with 0xE0.
+const EXTENDED: u16 = 0xE000;
+
+/// Linux key code, Windows virtual-key code and PC (set 1) scan code, or'ed with [`EXTENDED`]
+/// for extended keys. Keys without a virtual-key code have 0.
+#[rustfmt::skip]
+const WINDOWS_KEYS: &[(u16, u16, u16)] = &[
+    (1, 0x1B, 0x01),             // KEY_ESC, VK_ESCAPE
+    (2, 0x31, 0x02),             // KEY_1
+    (3, 0x32, 0x03),             // KEY_2
+    (4, 0x33, 0x04),             // KEY_3
+    (5, 0x34, 0x05),             // KEY_4
+    (6, 0x35, 0x06),             // KEY_5
+    (7, 0x36, 0x07),             // KEY_6
+    (8, 0x37, 0x08),             // KEY_7
+    (9, 0x38, 0x09),             // KEY_8
+    (10, 0x39, 0x0A),            // KEY_9
+    (11, 0x30, 0x0B),            // KEY_0
+    (12, 0xBD, 0x0C),            // KEY_MINUS, VK_OEM_MINUS
+    (13, 0xBB, 0x0D),            // KEY_EQUAL, VK_OEM_PLUS
+    (14, 0x08, 0x0E),            // KEY_BACKSPACE, VK_BACK
+    (15, 0x09, 0x0F),            // KEY_TAB
+    (16, 0x51, 0x10),            // KEY_Q
+    (17, 0x57, 0x11),            // KEY_W
+    (18, 0x45, 0x12),            // KEY_E
+    (19, 0x52, 0x13),            // KEY_R
+    (20, 0x54, 0x14),            // KEY_T
+    (21, 0x59, 0x15),            // KEY_Y
+    (22, 0x55, 0x16),            // KEY_U
+    (23, 0x49, 0x17),            // KEY_I
+    (24, 0x4F, 0x18),            // KEY_O
+    (25, 0x50, 0x19),            // KEY_P
+    (26, 0xDB, 0x1A),            // KEY_LEFTBRACE, VK_OEM_4
+    (27, 0xDD, 0x1B),            // KEY_RIGHTBRACE, VK_OEM_6
+    (28, 0x0D, 0x1C),            // KEY_ENTER, VK_RETURN
+    (29, 0xA2, 0x1D),            // KEY_LEFTCTRL, VK_LCONTROL
+    (30, 0x41, 0x1E),            // KEY_A
+    (31, 0x53, 0x1F),            // KEY_S
+    (32, 0x44, 0x20),            // KEY_D
+    (33, 0x46, 0x21),            // KEY_F
+    (34, 0x47, 0x22),            // KEY_G
+    (35, 0x48, 0x23),            // KEY_H
+    (36, 0x4A, 0x24),            // KEY_J
+    (37, 0x4B, 0x25),            // KEY_K
+    (38, 0x4C, 0x26),            // KEY_L
+    (39, 0xBA, 0x27),            // KEY_SEMICOLON, VK_OEM_1
+    (40, 0xDE, 0x28),            // KEY_APOSTROPHE, VK_OEM_7
+    (41, 0xC0, 0x29),            // KEY_GRAVE, VK_OEM_3
+    (42, 0xA0, 0x2A),            // KEY_LEFTSHIFT, VK_LSHIFT
+    (43, 0xDC, 0x2B),            // KEY_BACKSLASH, VK_OEM_5
+    (44, 0x5A, 0x2C),            // KEY_Z
+    (45, 0x58, 0x2D),            // KEY_X
+    (46, 0x43, 0x2E),            // KEY_C
+    (47, 0x56, 0x2F),            // KEY_V
+    (48, 0x42, 0x30),            // KEY_B
+    (49, 0x4E, 0x31),            // KEY_N
+    (50, 0x4D, 0x32),            // KEY_M
+    (51, 0xBC, 0x33),            // KEY_COMMA, VK_OEM_COMMA
+    (52, 0xBE, 0x34),            // KEY_DOT, VK_OEM_PERIOD
+    (53, 0xBF, 0x35),            // KEY_SLASH, VK_OEM_2
+    (54, 0xA1, 0x36),            // KEY_RIGHTSHIFT, VK_RSHIFT
+    (55, 0x6A, 0x37),            // KEY_KPASTERISK, VK_MULTIPLY
+    (56, 0xA4, 0x38),            // KEY_LEFTALT, VK_LMENU
+    (57, 0x20, 0x39),            // KEY_SPACE
+    (58, 0x14, 0x3A),            // KEY_CAPSLOCK, VK_CAPITAL
+    (59, 0x70, 0x3B),            // KEY_F1
+    (60, 0x71, 0x3C),            // KEY_F2
+    (61, 0x72, 0x3D),            // KEY_F3
+    (62, 0x73, 0x3E),            // KEY_F4
+    (63, 0x74, 0x3F),            // KEY_F5
+    (64, 0x75, 0x40),            // KEY_F6
+    (65, 0x76, 0x41),            // KEY_F7
+    (66, 0x77, 0x42),            // KEY_F8
+    (67, 0x78, 0x43),            // KEY_F9
+    (68, 0x79, 0x44),            // KEY_F10
+    (69, 0x90, 0x45),            // KEY_NUMLOCK
+    (70, 0x91, 0x46),            // KEY_SCROLLLOCK
+    (71, 0x67, 0x47),            // KEY_KP7, VK_NUMPAD7
+    (72, 0x68, 0x48),            // KEY_KP8
+    (73, 0x69, 0x49),            // KEY_KP9
+    (74, 0x6D, 0x4A),            // KEY_KPMINUS, VK_SUBTRACT
+    (75, 0x64, 0x4B),            // KEY_KP4
+    (76, 0x65, 0x4C),            // KEY_KP5
+    (77, 0x66, 0x4D),            // KEY_KP6
+    (78, 0x6B, 0x4E),            // KEY_KPPLUS, VK_ADD
+    (79, 0x61, 0x4F),            // KEY_KP1
+    (80, 0x62, 0x50),            // KEY_KP2
+    (81, 0x63, 0x51),            // KEY_KP3
+    (82, 0x60, 0x52),            // KEY_KP0
+    (83, 0x6E, 0x53),            // KEY_KPDOT, VK_DECIMAL
+    (86, 0xE2, 0x56),            // KEY_102ND, VK_OEM_102
+    (87, 0x7A, 0x57),            // KEY_F11
+    (88, 0x7B, 0x58),            // KEY_F12
+    (89, 0, 0x73),               // KEY_RO
+    (92, 0x1C, 0x79),            // KEY_HENKAN, VK_CONVERT
+    (93, 0, 0x70),               // KEY_KATAKANAHIRAGANA
+    (94, 0x1D, 0x7B),            // KEY_MUHENKAN, VK_NONCONVERT
+    (96, 0x0D, EXTENDED | 0x1C), // KEY_KPENTER, VK_RETURN
+    (97, 0xA3, EXTENDED | 0x1D), // KEY_RIGHTCTRL, VK_RCONTROL
+    (98, 0x6F, EXTENDED | 0x35), // KEY_KPSLASH, VK_DIVIDE
+    (99, 0x2C, EXTENDED | 0x37), // KEY_SYSRQ, VK_SNAPSHOT
+    (100, 0xA5, EXTENDED | 0x38), // KEY_RIGHTALT, VK_RMENU
+    (102, 0x24, EXTENDED | 0x47), // KEY_HOME
+    (103, 0x26, EXTENDED | 0x48), // KEY_UP
+    (104, 0x21, EXTENDED | 0x49), // KEY_PAGEUP, VK_PRIOR
+    (105, 0x25, EXTENDED | 0x4B), // KEY_LEFT
+    (106, 0x27, EXTENDED | 0x4D), // KEY_RIGHT
+    (107, 0x23, EXTENDED | 0x4F), // KEY_END
+    (108, 0x28, EXTENDED | 0x50), // KEY_DOWN
+    (109, 0x22, EXTENDED | 0x51), // KEY_PAGEDOWN, VK_NEXT
+    (110, 0x2D, EXTENDED | 0x52), // KEY_INSERT
+    (111, 0x2E, EXTENDED | 0x53), // KEY_DELETE
+    (113, 0xAD, EXTENDED | 0x20), // KEY_MUTE, VK_VOLUME_MUTE
+    (114, 0xAE, EXTENDED | 0x2E), // KEY_VOLUMEDOWN
+    (115, 0xAF, EXTENDED | 0x30), // KEY_VOLUMEUP
+    (117, 0, 0x59),              // KEY_KPEQUAL
+    (119, 0x13, 0x45),           // KEY_PAUSE (sent as Ctrl+NumLock by keyboards)
+    (124, 0xDC, 0x7D),           // KEY_YEN, VK_OEM_5
+    (125, 0x5B, EXTENDED | 0x5B), // KEY_LEFTMETA, VK_LWIN
+    (126, 0x5C, EXTENDED | 0x5C), // KEY_RIGHTMETA, VK_RWIN
+    (127, 0x5D, EXTENDED | 0x5D), // KEY_COMPOSE, VK_APPS
+    (140, 0xB7, EXTENDED | 0x21), // KEY_CALC, VK_LAUNCH_APP2
+    (142, 0x5F, EXTENDED | 0x5F), // KEY_SLEEP
+    (155, 0xB4, EXTENDED | 0x6C), // KEY_MAIL, VK_LAUNCH_MAIL
+    (158, 0xA6, EXTENDED | 0x6A), // KEY_BACK, VK_BROWSER_BACK
+    (159, 0xA7, EXTENDED | 0x69), // KEY_FORWARD, VK_BROWSER_FORWARD
+    (163, 0xB0, EXTENDED | 0x19), // KEY_NEXTSONG, VK_MEDIA_NEXT_TRACK
+    (164, 0xB3, EXTENDED | 0x22), // KEY_PLAYPAUSE, VK_MEDIA_PLAY_PAUSE
+    (165, 0xB1, EXTENDED | 0x10), // KEY_PREVIOUSSONG, VK_MEDIA_PREV_TRACK
+    (166, 0xB2, EXTENDED | 0x24), // KEY_STOPCD, VK_MEDIA_STOP
+    (172, 0xAC, EXTENDED | 0x32), // KEY_HOMEPAGE, VK_BROWSER_HOME
+    (173, 0xA8, EXTENDED | 0x67), // KEY_REFRESH, VK_BROWSER_REFRESH
+    (183, 0x7C, 0x64),           // KEY_F13
+    (184, 0x7D, 0x65),           // KEY_F14
+    (185, 0x7E, 0x66),           // KEY_F15
+    (186, 0x7F, 0x67),           // KEY_F16
+    (187, 0x80, 0x68),           // KEY_F17
+    (188, 0x81, 0x69),           // KEY_F18
+    (189, 0x82, 0x6A),           // KEY_F19
+    (190, 0x83, 0x6B),           // KEY_F20
+    (191, 0x84, 0x6C),           // KEY_F21
+    (192, 0x85, 0x6D),           // KEY_F22
+    (193, 0x86, 0x6E),           // KEY_F23
+    (194, 0x87, 0x76),           // KEY_F24
+    (217, 0xAA, EXTENDED | 0x65), // KEY_SEARCH, VK_BROWSER_SEARCH
+];
+
+/// How to press a key on Windows.
+#[derive(Clone, Copy, Debug)]
+pub struct WindowsKey {
+    /// The virtual-key code, or `None` if the key has none and is pressed by its scan code.
+    pub virtual_key: Option<u16>,
+    /// The PC (set 1) scan code without the 0xE0 prefix.
+    pub scan_code: u16,
+    /// Whether the scan code is prefixed with 0xE0 (`KEYEVENTF_EXTENDEDKEY`).
+    pub extended: bool,
+}
+
+/// The Windows key for the Linux key code `code`, or `None` if there is none.
+pub fn windows_key(code: u16) -> Option<WindowsKey> {
+    let &(_, virtual_key, scan_code) = WINDOWS_KEYS.iter().find(|(linux, ..)| *linux == code)?;
+    Some(WindowsKey {
+        virtual_key: (virtual_key != 0).then_some(virtual_key),
+        scan_code: scan_code & !EXTENDED,
+        extended: scan_code & EXTENDED != 0,
+    })
+}
+
+/// Linux key code and macOS virtual key code (`kVK_*` of `Events.h`).
+#[rustfmt::skip]
+const MACOS_KEYS: &[(u16, u16)] = &[
+    (1, 0x35),   // KEY_ESC, kVK_Escape
+    (2, 0x12),   // KEY_1
+    (3, 0x13),   // KEY_2
+    (4, 0x14),   // KEY_3
+    (5, 0x15),   // KEY_4
+    (6, 0x17),   // KEY_5
+    (7, 0x16),   // KEY_6
+    (8, 0x1A),   // KEY_7
+    (9, 0x1C),   // KEY_8
+    (10, 0x19),  // KEY_9
+    (11, 0x1D),  // KEY_0
+    (12, 0x1B),  // KEY_MINUS
+    (13, 0x18),  // KEY_EQUAL
+    (14, 0x33),  // KEY_BACKSPACE, kVK_Delete
+    (15, 0x30),  // KEY_TAB
+    (16, 0x0C),  // KEY_Q
+    (17, 0x0D),  // KEY_W
+    (18, 0x0E),  // KEY_E
+    (19, 0x0F),  // KEY_R
+    (20, 0x11),  // KEY_T
+    (21, 0x10),  // KEY_Y
+    (22, 0x20),  // KEY_U
+    (23, 0x22),  // KEY_I
+    (24, 0x1F),  // KEY_O
+    (25, 0x23),  // KEY_P
+    (26, 0x21),  // KEY_LEFTBRACE
+    (27, 0x1E),  // KEY_RIGHTBRACE
+    (28, 0x24),  // KEY_ENTER, kVK_Return
+    (29, 0x3B),  // KEY_LEFTCTRL, kVK_Control
+    (30, 0x00),  // KEY_A
+    (31, 0x01),  // KEY_S
+    (32, 0x02),  // KEY_D
+    (33, 0x03),  // KEY_F
+    (34, 0x05),  // KEY_G
+    (35, 0x04),  // KEY_H
+    (36, 0x26),  // KEY_J
+    (37, 0x28),  // KEY_K
+    (38, 0x25),  // KEY_L
+    (39, 0x29),  // KEY_SEMICOLON
+    (40, 0x27),  // KEY_APOSTROPHE, kVK_ANSI_Quote
+    (41, 0x32),  // KEY_GRAVE
+    (42, 0x38),  // KEY_LEFTSHIFT, kVK_Shift
+    (43, 0x2A),  // KEY_BACKSLASH
+    (44, 0x06),  // KEY_Z
+    (45, 0x07),  // KEY_X
+    (46, 0x08),  // KEY_C
+    (47, 0x09),  // KEY_V
+    (48, 0x0B),  // KEY_B
+    (49, 0x2D),  // KEY_N
+    (50, 0x2E),  // KEY_M
+    (51, 0x2B),  // KEY_COMMA
+    (52, 0x2F),  // KEY_DOT, kVK_ANSI_Period
+    (53, 0x2C),  // KEY_SLASH
+    (54, 0x3C),  // KEY_RIGHTSHIFT
+    (55, 0x43),  // KEY_KPASTERISK, kVK_ANSI_KeypadMultiply
+    (56, 0x3A),  // KEY_LEFTALT, kVK_Option
+    (57, 0x31),  // KEY_SPACE
+    (58, 0x39),  // KEY_CAPSLOCK
+    (59, 0x7A),  // KEY_F1
+    (60, 0x78),  // KEY_F2
+    (61, 0x63),  // KEY_F3
+    (62, 0x76),  // KEY_F4
+    (63, 0x60),  // KEY_F5
+    (64, 0x61),  // KEY_F6
+    (65, 0x62),  // KEY_F7
+    (66, 0x64),  // KEY_F8
+    (67, 0x65),  // KEY_F9
+    (68, 0x6D),  // KEY_F10
+    (69, 0x47),  // KEY_NUMLOCK, kVK_ANSI_KeypadClear
+    (71, 0x59),  // KEY_KP7
+    (72, 0x5B),  // KEY_KP8
+    (73, 0x5C),  // KEY_KP9
+    (74, 0x4E),  // KEY_KPMINUS
+    (75, 0x56),  // KEY_KP4
+    (76, 0x57),  // KEY_KP5
+    (77, 0x58),  // KEY_KP6
+    (78, 0x45),  // KEY_KPPLUS
+    (79, 0x53),  // KEY_KP1
+    (80, 0x54),  // KEY_KP2
+    (81, 0x55),  // KEY_KP3
+    (82, 0x52),  // KEY_KP0
+    (83, 0x41),  // KEY_KPDOT, kVK_ANSI_KeypadDecimal
+    (86, 0x0A),  // KEY_102ND, kVK_ISO_Section
+    (87, 0x67),  // KEY_F11
+    (88, 0x6F),  // KEY_F12
+    (89, 0x5E),  // KEY_RO, kVK_JIS_Underscore
+    (92, 0x68),  // KEY_HENKAN, kVK_JIS_Kana
+    (94, 0x66),  // KEY_MUHENKAN, kVK_JIS_Eisu
+    (96, 0x4C),  // KEY_KPENTER
+    (97, 0x3E),  // KEY_RIGHTCTRL
+    (98, 0x4B),  // KEY_KPSLASH, kVK_ANSI_KeypadDivide
+    (100, 0x3D), // KEY_RIGHTALT, kVK_RightOption
+    (102, 0x73), // KEY_HOME
+    (103, 0x7E), // KEY_UP
+    (104, 0x74), // KEY_PAGEUP
+    (105, 0x7B), // KEY_LEFT
+    (106, 0x7C), // KEY_RIGHT
+    (107, 0x77), // KEY_END
+    (108, 0x7D), // KEY_DOWN
+    (109, 0x79), // KEY_PAGEDOWN
+    (110, 0x72), // KEY_INSERT, kVK_Help
+    (111, 0x75), // KEY_DELETE, kVK_ForwardDelete
+    (113, 0x4A), // KEY_MUTE
+    (114, 0x49), // KEY_VOLUMEDOWN
+    (115, 0x48), // KEY_VOLUMEUP
+    (117, 0x51), // KEY_KPEQUAL, kVK_ANSI_KeypadEquals
+    (121, 0x5F), // KEY_KPCOMMA, kVK_JIS_KeypadComma
+    (124, 0x5D), // KEY_YEN, kVK_JIS_Yen
+    (125, 0x37), // KEY_LEFTMETA, kVK_Command
+    (126, 0x36), // KEY_RIGHTMETA, kVK_RightCommand
+    (138, 0x72), // KEY_HELP
+    (183, 0x69), // KEY_F13
+    (184, 0x6B), // KEY_F14
+    (185, 0x71), // KEY_F15
+    (186, 0x6A), // KEY_F16
+    (187, 0x40), // KEY_F17
+    (188, 0x4F), // KEY_F18
+    (189, 0x50), // KEY_F19
+    (190, 0x5A), // KEY_F20
+    (464, 0x3F), // KEY_FN, kVK_Function
+];
+
+/// The macOS virtual key code for the Linux key code `code`, or `None` if there is none.
+pub fn macos_key(code: u16) -> Option<u16> {
+    MACOS_KEYS
+        .iter()
+        .find(|(linux, _)| *linux == code)
+        .map(|&(_, key)| key)
+}
@@ -0,0 +1,93 @@
+use crate::Backend;
+use evdev::uinput::{VirtualDevice, VirtualDeviceBuilder};
+use evdev::{
+    AbsInfo, AbsoluteAxisType, AttributeSet, EventType, InputEvent, Key, RelativeAxisType,
+    UinputAbsSetup,
+};
+use remote_input::protocol::{DeviceDescriptor, InputEventWrapper};
+use std::io;
+
+/// The number of key codes (`KEY_CNT` of Linux), used if the server does not describe its devices.
+const KEY_COUNT: u16 = 0x300;
+
+/// Replays events with a virtual (uinput) device, so that key codes need no translation.
+pub struct Uinput {
+    device: VirtualDevice,
+}
+
+impl Backend for Uinput {
+    /// Create a virtual device with the keys and axes of every device in `devices`, or with every key
+    /// and the axes of a mouse if there are none.
+    fn create(devices: &[DeviceDescriptor]) -> io::Result<Self> {
+        let mut keys = AttributeSet::<Key>::new();
+        let mut relative_axes = AttributeSet::<RelativeAxisType>::new();
+        let mut absolute_axes = Vec::new();
+        for device in devices {
+            for (index, byte) in device.keys.iter().enumerate() {
+                for bit in 0..8 {
+                    if byte & (1 << bit) != 0 {
+                        keys.insert(Key::new((index * 8 + bit) as u16));
+                    }
+                }
+            }
+            for code in 0..16 {
+                if device.relative_axes & (1 << code) != 0 {
+                    relative_axes.insert(RelativeAxisType(code));
+                }
+            }
+            absolute_axes.extend(device.axes.iter().map(|axis| {
+                UinputAbsSetup::new(
+                    AbsoluteAxisType(axis.code),
+                    AbsInfo::new(
+                        axis.minimum,
+                        axis.minimum,
+                        axis.maximum,
+                        axis.fuzz,
+                        axis.flat,
+                        axis.resolution,
+                    ),
+                )
+            }));
+        }
+        if devices.is_empty() {
+            for code in 1..KEY_COUNT {
+                keys.insert(Key::new(code));
+            }
+            for axis in [
+                RelativeAxisType::REL_X,
+                RelativeAxisType::REL_Y,
+                RelativeAxisType::REL_WHEEL,
+            ] {
+                relative_axes.insert(axis);
+            }
+        }
+        let mut builder = VirtualDeviceBuilder::new()
+            .map_err(requires_uinput)?
+            .name("remote-input client")
+            .with_keys(&keys)?
+            .with_relative_axes(&relative_axes)?;
+        for axis in &absolute_axes {
+            builder = builder.with_absolute_axis(axis)?;
+        }
+        Ok(Self {
+            device: builder.build()?,
+        })
+    }
+
+    /// Emit the events of `report`, which the virtual device terminates with its own SYN_REPORT.
+    fn emit(&mut self, report: &[InputEventWrapper]) -> io::Result<()> {
+        let events: Vec<InputEvent> = report
+            .iter()
+            .map(|event| InputEvent::new(EventType(event.event_type), event.code, event.value))
+            .collect();
+        self.device.emit(&events)
+    }
+}
+
+/// Explain that `error`, returned when opening /dev/uinput, requires write access to it.
+fn requires_uinput(error: io::Error) -> io::Error {
+    io::Error::new(
+        error.kind(),
+        format!("{error} (requires write access to /dev/uinput)"),
+    )
+}
@@ -0,0 +1,71 @@
+use remote_input::protocol::InputEventWrapper;
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime};
+
+const EV_KEY: u16 = 1;
+/// The buttons of mice, joysticks and gamepads (`BTN_MISC` to `BTN_GEAR_UP`), which are held
+/// without repeating, e.g., while dragging.
+const BUTTONS: std::ops::Range<u16> = 0x100..0x160;
+/// `BTN_TRIGGER_HAPPY1` to `KEY_MAX`.
+const TRIGGER_HAPPY: std::ops::Range<u16> = 0x2c0..0x300;
+
+/// Notices keys which were pressed for longer than a timeout without being repeated or released,
+/// e.g., because their release was lost, so that they can be released instead of staying pressed.
+/// Held keys are repeated by the server (unless its `autorepeat` drops repeats), so only keys
+/// whose release went missing go that long without an event. Buttons are never released.
+pub struct Watchdog {
+    timeout: Duration,
+    /// The pressed keys and their last press or repeat.
+    pressed: HashMap<u16, Instant>,
+}
+
+impl Watchdog {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            pressed: HashMap::new(),
+        }
+    }
+
+    /// Record an injected event.
+    pub fn observe(&mut self, event: &InputEventWrapper) {
+        if event.event_type != EV_KEY
+            || BUTTONS.contains(&event.code)
+            || TRIGGER_HAPPY.contains(&event.code)
+        {
+            return;
+        }
+        if event.value == 0 {
+            self.pressed.remove(&event.code);
+        } else {
+            self.pressed.insert(event.code, Instant::now());
+        }
+    }
+
+    /// Forget the keys pressed for longer than the timeout and return their releases.
+    pub fn expired(&mut self) -> Vec<InputEventWrapper> {
+        let timeout = self.timeout;
+        let mut releases = Vec::new();
+        self.pressed.retain(|&code, pressed| {
+            let expired = pressed.elapsed() >= timeout;
+            if expired {
+                releases.push(InputEventWrapper {
+                    timestamp: SystemTime::now().into(),
+                    event_type: EV_KEY,
+                    code,
+                    value: 0,
+                });
+            }
+            !expired
+        });
+        releases
+    }
+
+    /// How long until the first key exceeds the timeout, or `None` if no key is pressed.
+    pub fn next_due(&self) -> Option<Duration> {
+        self.pressed
+            .values()
+            .map(|pressed| self.timeout.saturating_sub(pressed.elapsed()))
+            .min()
+    }
+}
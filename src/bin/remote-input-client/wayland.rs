@@ -0,0 +1,223 @@
+use crate::Backend;
+use remote_input::protocol::{DeviceDescriptor, InputEventWrapper};
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::fd::{AsFd, FromRawFd};
+use std::time::Instant;
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::wl_pointer::{Axis, AxisSource, ButtonState};
+use wayland_client::protocol::wl_registry::WlRegistry;
+use wayland_client::protocol::wl_seat::WlSeat;
+use wayland_client::{delegate_noop, Connection, Dispatch, EventQueue, QueueHandle};
+use wayland_protocols_misc::zwp_virtual_keyboard_v1::client::zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1;
+use wayland_protocols_misc::zwp_virtual_keyboard_v1::client::zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1;
+use wayland_protocols_wlr::virtual_pointer::v1::client::zwlr_virtual_pointer_manager_v1::ZwlrVirtualPointerManagerV1;
+use wayland_protocols_wlr::virtual_pointer::v1::client::zwlr_virtual_pointer_v1::ZwlrVirtualPointerV1;
+
+const EV_KEY: u16 = 1;
+const EV_REL: u16 = 2;
+const REL_X: u16 = 0x00;
+const REL_Y: u16 = 0x01;
+const REL_HWHEEL: u16 = 0x06;
+const REL_WHEEL: u16 = 0x08;
+const BTN_LEFT: u16 = 0x110;
+const BTN_TASK: u16 = 0x117;
+/// `WL_KEYBOARD_KEYMAP_FORMAT_XKB_V1`.
+const KEYMAP_FORMAT_XKB_V1: u32 = 1;
+/// The keymap of the virtual keyboard, which the compositor compiles: the evdev key codes with a US
+/// layout, so that key codes need no translation.
+const KEYMAP: &str = "xkb_keymap {
+    xkb_keycodes { include \"evdev+aliases(qwerty)\" };
+    xkb_types { include \"complete\" };
+    xkb_compat { include \"complete\" };
+    xkb_symbols { include \"pc+us+inet(evdev)\" };
+};\n";
+/// The scroll distance of one wheel notch, like libinput.
+const WHEEL_STEP: f64 = 15.0;
+
+/// The modifier keys and their masks in [`KEYMAP`]: shift, caps lock (locking), control, alt
+/// (Mod1), num lock (Mod2, locking) and super (Mod4).
+const MODIFIERS: &[(u16, u32)] = &[
+    (42, 1 << 0),
+    (54, 1 << 0),
+    (29, 1 << 2),
+    (97, 1 << 2),
+    (56, 1 << 3),
+    (100, 1 << 3),
+    (125, 1 << 6),
+    (126, 1 << 6),
+];
+const KEY_CAPSLOCK: u16 = 58;
+const KEY_NUMLOCK: u16 = 69;
+const LOCK_MASK: u32 = 1 << 1;
+const NUM_LOCK_MASK: u32 = 1 << 4;
+
+/// Replays keyboard and mouse events with the virtual keyboard (`zwp_virtual_keyboard_v1`) and
+/// virtual pointer (`zwlr_virtual_pointer_v1`) protocols of the Wayland compositor, which need no
+/// access to /dev/uinput. Compositors based on wlroots support both. Absolute axes are not replayed.
+pub struct Wayland {
+    connection: Connection,
+    /// The queue of the virtual keyboard and pointer, which receive no events.
+    _queue: EventQueue<State>,
+    keyboard: ZwpVirtualKeyboardV1,
+    pointer: ZwlrVirtualPointerV1,
+    /// The depressed and locked modifiers, which the compositor does not derive from the keys.
+    depressed: u32,
+    locked: u32,
+    /// The time event timestamps are relative to.
+    start: Instant,
+}
+
+/// The state of the event queue. The compositor sends no events which need handling.
+struct State;
+
+impl Dispatch<WlRegistry, GlobalListContents> for State {
+    fn event(
+        _: &mut Self,
+        _: &WlRegistry,
+        _: <WlRegistry as wayland_client::Proxy>::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+delegate_noop!(State: ignore WlSeat);
+delegate_noop!(State: ZwpVirtualKeyboardManagerV1);
+delegate_noop!(State: ZwpVirtualKeyboardV1);
+delegate_noop!(State: ZwlrVirtualPointerManagerV1);
+delegate_noop!(State: ZwlrVirtualPointerV1);
+
+impl Backend for Wayland {
+    /// Connect to the compositor named by the `WAYLAND_DISPLAY` environment variable and create a
+    /// virtual keyboard and pointer on its first seat.
+    fn create(_devices: &[DeviceDescriptor]) -> io::Result<Self> {
+        let connection = Connection::connect_to_env()
+            .map_err(|error| io::Error::new(io::ErrorKind::NotFound, error))?;
+        let (globals, mut queue) =
+            registry_queue_init::<State>(&connection).map_err(io::Error::other)?;
+        let handle = queue.handle();
+        let unsupported =
+            |error| io::Error::new(io::ErrorKind::Unsupported, format!("compositor: {error}"));
+        let seat: WlSeat = globals.bind(&handle, 1..=1, ()).map_err(unsupported)?;
+        let keyboard_manager: ZwpVirtualKeyboardManagerV1 =
+            globals.bind(&handle, 1..=1, ()).map_err(unsupported)?;
+        let pointer_manager: ZwlrVirtualPointerManagerV1 =
+            globals.bind(&handle, 1..=1, ()).map_err(unsupported)?;
+        let keyboard = keyboard_manager.create_virtual_keyboard(&seat, &handle, ());
+        let pointer = pointer_manager.create_virtual_pointer(Some(&seat), &handle, ());
+
+        let mut keymap = create_memfd()?;
+        keymap.write_all(KEYMAP.as_bytes())?;
+        keymap.write_all(&[0])?;
+        keyboard.keymap(
+            KEYMAP_FORMAT_XKB_V1,
+            keymap.as_fd(),
+            KEYMAP.len() as u32 + 1,
+        );
+        // Fail now if the compositor rejects the client (e.g., if it is not authorized).
+        queue.roundtrip(&mut State).map_err(io::Error::other)?;
+        Ok(Self {
+            connection,
+            _queue: queue,
+            keyboard,
+            pointer,
+            depressed: 0,
+            locked: 0,
+            start: Instant::now(),
+        })
+    }
+
+    /// Send the key presses of `report` and its mouse movement, buttons and wheel as one pointer frame.
+    fn emit(&mut self, report: &[InputEventWrapper]) -> io::Result<()> {
+        let time = self.start.elapsed().as_millis() as u32;
+        let (mut dx, mut dy) = (0, 0);
+        let mut pointer_frame = false;
+        for event in report {
+            match (event.event_type, event.code) {
+                (EV_REL, REL_X) => dx += event.value,
+                (EV_REL, REL_Y) => dy += event.value,
+                // Wheel notches up (or right) are positive, unlike Wayland scroll distances.
+                (EV_REL, REL_WHEEL) => {
+                    self.scroll(time, Axis::VerticalScroll, -event.value);
+                    pointer_frame = true;
+                }
+                (EV_REL, REL_HWHEEL) => {
+                    self.scroll(time, Axis::HorizontalScroll, event.value);
+                    pointer_frame = true;
+                }
+                (EV_KEY, BTN_LEFT..=BTN_TASK) => {
+                    let state = if event.value != 0 {
+                        ButtonState::Pressed
+                    } else {
+                        ButtonState::Released
+                    };
+                    self.pointer.button(time, event.code.into(), state);
+                    pointer_frame = true;
+                }
+                // Clients repeat held keys themselves.
+                (EV_KEY, _) if event.value == 2 => {}
+                (EV_KEY, code) => self.key(time, code, event.value != 0),
+                _ => {}
+            }
+        }
+        if dx != 0 || dy != 0 {
+            self.pointer.motion(time, dx.into(), dy.into());
+            pointer_frame = true;
+        }
+        if pointer_frame {
+            self.pointer.frame();
+        }
+        self.connection.flush().map_err(io::Error::other)
+    }
+}
+
+impl Wayland {
+    /// Press or release the key `code`, updating the modifiers if it is a modifier key.
+    fn key(&mut self, time: u32, code: u16, pressed: bool) {
+        self.keyboard.key(time, code.into(), pressed.into());
+        let (depressed, locked) = (self.depressed, self.locked);
+        if let Some(&(_, mask)) = MODIFIERS.iter().find(|(key, _)| *key == code) {
+            if pressed {
+                self.depressed |= mask;
+            } else {
+                self.depressed &= !mask;
+            }
+        }
+        match (code, pressed) {
+            (KEY_CAPSLOCK, true) => self.locked ^= LOCK_MASK,
+            (KEY_NUMLOCK, true) => self.locked ^= NUM_LOCK_MASK,
+            _ => {}
+        }
+        if (depressed, locked) != (self.depressed, self.locked) {
+            self.keyboard.modifiers(self.depressed, 0, self.locked, 0);
+        }
+    }
+
+    /// Scroll `notches` wheel notches along `axis`.
+    fn scroll(&self, time: u32, axis: Axis, notches: i32) {
+        self.pointer.axis_source(AxisSource::Wheel);
+        self.pointer
+            .axis_discrete(time, axis, f64::from(notches) * WHEEL_STEP, notches);
+    }
+}
+
+impl Drop for Wayland {
+    fn drop(&mut self) {
+        self.keyboard.destroy();
+        self.pointer.destroy();
+        let _ = self.connection.flush();
+    }
+}
+
+/// Create an anonymous file in memory for the keymap.
+fn create_memfd() -> io::Result<File> {
+    // SAFETY: the name is a valid C string.
+    let fd = unsafe { libc::memfd_create(c"remote-input-keymap".as_ptr(), libc::MFD_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `fd` is a new file descriptor owned by nothing else.
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
@@ -0,0 +1,162 @@
+use crate::Backend;
+use remote_input::protocol::{DeviceDescriptor, InputEventWrapper};
+use std::collections::HashSet;
+use std::io;
+use std::ptr;
+use x11_dl::xlib::{CurrentTime, Display, Xlib};
+use x11_dl::xtest::Xf86vmode as Xtst;
+
+const EV_KEY: u16 = 1;
+const EV_REL: u16 = 2;
+const REL_X: u16 = 0x00;
+const REL_Y: u16 = 0x01;
+const REL_HWHEEL: u16 = 0x06;
+const REL_WHEEL: u16 = 0x08;
+const BTN_LEFT: u16 = 0x110;
+const BTN_RIGHT: u16 = 0x111;
+const BTN_MIDDLE: u16 = 0x112;
+const BTN_SIDE: u16 = 0x113;
+const BTN_EXTRA: u16 = 0x114;
+/// The difference between X key codes and Linux key codes with the evdev and libinput drivers.
+const X_KEYCODE_OFFSET: u32 = 8;
+
+/// Replays keyboard and mouse events with the XTEST extension of the X server, for users who cannot
+/// write to /dev/uinput. libX11 and libXtst are loaded at runtime. Keys are pressed by their key code
+/// (see [`X_KEYCODE_OFFSET`]). High-resolution wheel and absolute axes are not replayed.
+pub struct Xtest {
+    xlib: Xlib,
+    xtst: Xtst,
+    display: *mut Display,
+    /// Key codes without an X key code which were already reported.
+    unknown_keys: HashSet<u16>,
+}
+
+impl Backend for Xtest {
+    /// Connect to the X server named by the `DISPLAY` environment variable.
+    fn create(_devices: &[DeviceDescriptor]) -> io::Result<Self> {
+        let xlib = Xlib::open()
+            .map_err(|error| io::Error::new(io::ErrorKind::NotFound, error.to_string()))?;
+        let xtst = Xtst::open()
+            .map_err(|error| io::Error::new(io::ErrorKind::NotFound, error.to_string()))?;
+        // SAFETY: a null name opens the display named by `DISPLAY`.
+        let display = unsafe { (xlib.XOpenDisplay)(ptr::null()) };
+        if display.is_null() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "unable to open the X display (is DISPLAY set?)",
+            ));
+        }
+        // Closes the display if the extension is missing.
+        let xtest = Self {
+            xlib,
+            xtst,
+            display,
+            unknown_keys: HashSet::new(),
+        };
+        let (mut event_base, mut error_base, mut major, mut minor) = (0, 0, 0, 0);
+        // SAFETY: `display` is open and the outputs are valid for writes.
+        let supported = unsafe {
+            (xtest.xtst.XTestQueryExtension)(
+                display,
+                &mut event_base,
+                &mut error_base,
+                &mut major,
+                &mut minor,
+            )
+        };
+        if supported == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the X server does not support XTEST",
+            ));
+        }
+        Ok(xtest)
+    }
+
+    /// Send the mouse movement of `report`, then its button and key presses.
+    fn emit(&mut self, report: &[InputEventWrapper]) -> io::Result<()> {
+        let (mut dx, mut dy) = (0, 0);
+        for event in report.iter().filter(|event| event.event_type == EV_REL) {
+            match event.code {
+                REL_X => dx += event.value,
+                REL_Y => dy += event.value,
+                _ => {}
+            }
+        }
+        if dx != 0 || dy != 0 {
+            // SAFETY: `display` is open until `self` is dropped. The pointer moves on the screen it
+            // is on (-1).
+            unsafe {
+                (self.xtst.XTestFakeRelativeMotionEvent)(self.display, -1, dx, dy, CurrentTime)
+            };
+        }
+        for event in report {
+            match (event.event_type, event.code) {
+                // Wheel notches are clicks of buttons 4 (up), 5 (down), 6 (left) and 7 (right).
+                (EV_REL, REL_WHEEL) => {
+                    let button = if event.value > 0 { 4 } else { 5 };
+                    for _ in 0..event.value.unsigned_abs() {
+                        self.button(button, true);
+                        self.button(button, false);
+                    }
+                }
+                (EV_REL, REL_HWHEEL) => {
+                    let button = if event.value > 0 { 7 } else { 6 };
+                    for _ in 0..event.value.unsigned_abs() {
+                        self.button(button, true);
+                        self.button(button, false);
+                    }
+                }
+                (EV_KEY, BTN_LEFT..=BTN_EXTRA) => {
+                    let button = match event.code {
+                        BTN_LEFT => 1,
+                        BTN_MIDDLE => 2,
+                        BTN_RIGHT => 3,
+                        BTN_SIDE => 8,
+                        _ => 9,
+                    };
+                    self.button(button, event.value != 0);
+                }
+                // The X server repeats held keys itself.
+                (EV_KEY, _) if event.value == 2 => {}
+                (EV_KEY, code) => match u8::try_from(u32::from(code) + X_KEYCODE_OFFSET) {
+                    // SAFETY: `display` is open until `self` is dropped.
+                    Ok(keycode) => unsafe {
+                        (self.xtst.XTestFakeKeyEvent)(
+                            self.display,
+                            keycode.into(),
+                            (event.value != 0).into(),
+                            CurrentTime,
+                        );
+                    },
+                    Err(_) => {
+                        if self.unknown_keys.insert(code) {
+                            tracing::warn!("[XTEST] Key {code} has no X key code. Ignoring it.");
+                        }
+                    }
+                },
+                _ => {}
+            }
+        }
+        // SAFETY: `display` is open until `self` is dropped.
+        unsafe { (self.xlib.XFlush)(self.display) };
+        Ok(())
+    }
+}
+
+impl Xtest {
+    /// Press or release the mouse button `button`.
+    fn button(&self, button: u32, pressed: bool) {
+        // SAFETY: `display` is open until `self` is dropped.
+        unsafe {
+            (self.xtst.XTestFakeButtonEvent)(self.display, button, pressed.into(), CurrentTime)
+        };
+    }
+}
+
+impl Drop for Xtest {
+    fn drop(&mut self) {
+        // SAFETY: `display` is open and not used afterwards.
+        unsafe { (self.xlib.XCloseDisplay)(self.display) };
+    }
+}
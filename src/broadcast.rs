@@ -0,0 +1,111 @@
+use std::sync::RwLock;
+use tokio::sync::broadcast::{self, error::RecvError, error::TryRecvError};
+
+/// A channel delivering a clone of every value sent to each receiver, built on
+/// [`tokio::sync::broadcast`] so that receivers can wait in async tasks as well as in threads.
+/// Values are kept in a ring of `capacity` slots shared by every receiver. A receiver which falls
+/// that far behind misses the oldest values on its own instead of holding up the sender or the
+/// others. Once the bus is closed, receivers are disconnected after receiving the values they hold.
+pub struct Broadcast<T> {
+    /// `None` once closed.
+    sender: RwLock<Option<broadcast::Sender<T>>>,
+}
+
+impl<T: Clone> Broadcast<T> {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self {
+            sender: RwLock::new(Some(sender)),
+        }
+    }
+
+    /// A receiver of the values sent from now on, which is disconnected at once if the bus was
+    /// closed.
+    pub fn subscribe(&self) -> Receiver<T> {
+        let receiver = match &*self.sender.read().unwrap() {
+            Some(sender) => sender.subscribe(),
+            // The sender of a new channel is dropped at once.
+            None => broadcast::channel(1).1,
+        };
+        Receiver { receiver }
+    }
+
+    /// The number of receivers which were not dropped.
+    pub fn receiver_count(&self) -> usize {
+        self.sender
+            .read()
+            .unwrap()
+            .as_ref()
+            .map_or(0, broadcast::Sender::receiver_count)
+    }
+
+    /// Whether any receiver subscribed and was not dropped.
+    pub fn has_receivers(&self) -> bool {
+        self.receiver_count() > 0
+    }
+
+    /// Send a clone of `value` to every receiver. Without receivers, or once the bus is closed,
+    /// `value` is discarded.
+    pub fn send(&self, value: T) {
+        if let Some(sender) = &*self.sender.read().unwrap() {
+            let _ = sender.send(value);
+        }
+    }
+
+    /// Disconnect every receiver, including those subscribing later. Values sent afterwards are
+    /// discarded.
+    pub fn close(&self) {
+        self.sender.write().unwrap().take();
+    }
+}
+
+/// The receiving side of a [`Broadcast`]. Values which it fell too far behind to receive are
+/// skipped.
+pub struct Receiver<T> {
+    receiver: broadcast::Receiver<T>,
+}
+
+impl<T: Clone> Receiver<T> {
+    /// Wait for the next value, or `None` once the bus is closed and every value was received.
+    /// Cancelling the wait loses no value.
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(value) => return Some(value),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// Like [`Receiver::recv`], but blocking the thread. Must not be called in an async task.
+    pub fn blocking_recv(&mut self) -> Option<T> {
+        loop {
+            match self.receiver.blocking_recv() {
+                Ok(value) => return Some(value),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// The next value if one was sent and not yet received.
+    pub fn try_recv(&mut self) -> Option<T> {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(value) => return Some(value),
+                Err(TryRecvError::Lagged(_)) => continue,
+                Err(TryRecvError::Empty | TryRecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+/// Receives values with [`Receiver::blocking_recv`].
+impl<T: Clone> Iterator for Receiver<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.blocking_recv()
+    }
+}
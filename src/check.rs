@@ -0,0 +1,359 @@
+use crate::config::{ApiKey, Config, DeviceConfig, HardwareConfig, LogOutput};
+use crate::filter::EventFilter;
+use crate::noise::{self, NoiseConfig};
+use crate::scaling::RelativeScaling;
+use crate::server::{find_device, load_script};
+use crate::throttle::Throttle;
+use crate::tls;
+use crate::totp::Totp;
+use std::fmt;
+use std::net::{SocketAddr, ToSocketAddrs};
+use tracing_subscriber::EnvFilter;
+
+/// Whether a [`Finding`] keeps the server from starting.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The server starts, but may not behave as intended.
+    Warning,
+    /// The server refuses to start.
+    Error,
+}
+
+/// A problem with a configuration found by [`check`].
+pub struct Finding {
+    pub severity: Severity,
+    /// The key of the value, e.g., `server.websocket_address` or `hardware.devices[0].allow`.
+    pub location: String,
+    /// The line setting the value in the configuration file, if it was found.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+/// Shows the line (if known), severity, location and message, e.g.,
+/// `12: error: server.address: invalid address`.
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "{line}: ")?;
+        }
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{severity}: {}: {}", self.location, self.message)
+    }
+}
+
+/// Collects findings, whose lines are looked up afterwards.
+#[derive(Default)]
+struct Findings {
+    findings: Vec<Finding>,
+}
+
+impl Findings {
+    fn add(&mut self, severity: Severity, location: String, message: String) {
+        self.findings.push(Finding {
+            severity,
+            line: None,
+            location,
+            message,
+        });
+    }
+
+    fn error(&mut self, location: String, message: String) {
+        self.add(Severity::Error, location, message);
+    }
+
+    fn warning(&mut self, location: String, message: String) {
+        self.add(Severity::Warning, location, message);
+    }
+}
+
+/// Check the settings of `config` (parsed from `data`) like [`validate`], with the line setting
+/// each value the findings are about.
+pub fn check(config: &Config, data: &str) -> Vec<Finding> {
+    let mut findings = validate(config);
+    for finding in &mut findings {
+        finding.line = line_of(data, &finding.location);
+    }
+    findings
+}
+
+/// Check the settings of `config` which the parser does not validate: API keys, addresses, files,
+/// the TLS and Noise keys, the tables of each device and whether the devices are present (unless
+/// a recording is replayed instead). The server refuses to start on any error, and every problem
+/// is reported instead of only the first.
+pub fn validate(config: &Config) -> Vec<Finding> {
+    let mut findings = Findings::default();
+    check_server(config, &mut findings);
+    if config.server.replay_file.is_none() {
+        for (index, hardware) in config.hardware.all_devices().iter().enumerate() {
+            check_device(config, index, hardware, &mut findings);
+        }
+    }
+    if let Err(error) = EnvFilter::try_new(&config.log.level) {
+        findings.error("log.level".to_string(), error.to_string());
+    }
+    if config.log.output == LogOutput::File && config.log.file.is_none() {
+        findings.error(
+            "log.file".to_string(),
+            "output = \"file\" requires the log file".to_string(),
+        );
+    }
+    findings.findings
+}
+
+fn check_server(config: &Config, findings: &mut Findings) {
+    let server = &config.server;
+    let api_keys = server.accepted_api_keys();
+    if api_keys.is_empty() {
+        findings.error(
+            "server.api_key".to_string(),
+            "api_key or api_keys must be set".to_string(),
+        );
+    }
+    for api_key in api_keys.iter().filter(|api_key| !api_key.has_valid_hash()) {
+        findings.error(
+            "server.api_keys".to_string(),
+            format!(
+                "key_hash of api key \"{}\" must be an Argon2 hash",
+                api_key.name
+            ),
+        );
+    }
+    let tls = server.tls_certificate.is_some() && server.tls_private_key.is_some();
+    if api_keys.iter().any(ApiKey::is_hashed) && !tls && server.quic_address.is_none() {
+        findings.warning(
+            "server.api_keys".to_string(),
+            "hashed api keys are only accepted over TLS or QUIC, neither of which is configured"
+                .to_string(),
+        );
+    }
+    if !server.require_timestamped_keys
+        && (server.json_lines_address.is_some() || server.grpc_address.is_some())
+    {
+        findings.warning(
+            "server.require_timestamped_keys".to_string(),
+            "is unset, so JSON lines and gRPC clients may send api keys which can be replayed"
+                .to_string(),
+        );
+    }
+    if server.max_connections < server.max_clients {
+        findings.warning(
+            "server.max_connections".to_string(),
+            "is less than max_clients, so max_clients clients cannot connect at once".to_string(),
+        );
+    }
+    if let Some(totp_secret) = &server.totp_secret {
+        if Totp::new(totp_secret).is_none() {
+            findings.error(
+                "server.totp_secret".to_string(),
+                "must be base32 encoded".to_string(),
+            );
+        }
+    }
+
+    for address in server.address.as_slice() {
+        check_address(findings, "address", address);
+    }
+    for (key, address) in [
+        ("websocket_address", &server.websocket_address),
+        ("json_lines_address", &server.json_lines_address),
+        ("noise_address", &server.noise_address),
+        ("encrypted_address", &server.encrypted_address),
+    ] {
+        if let Some(address) = address {
+            check_address(findings, key, address);
+        }
+    }
+    for address in &server.dial_out_addresses {
+        check_address(findings, "dial_out_addresses", address);
+    }
+    for (key, address) in [
+        ("quic_address", &server.quic_address),
+        ("grpc_address", &server.grpc_address),
+    ] {
+        if let Some(Err(error)) = address.as_deref().map(str::parse::<SocketAddr>) {
+            findings.error(format!("server.{key}"), format!("invalid address: {error}"));
+        }
+    }
+    if let Some(address) = &server.multicast_address {
+        if !address
+            .parse::<SocketAddr>()
+            .is_ok_and(|address| address.ip().is_multicast())
+        {
+            findings.error(
+                "server.multicast_address".to_string(),
+                "must be a multicast address:port".to_string(),
+            );
+        }
+        if server.multicast_key.is_none() && server.api_key.is_none() {
+            findings.error(
+                "server.multicast_key".to_string(),
+                "must be set without api_key".to_string(),
+            );
+        }
+    }
+    if let Some(address) = &server.mqtt_address {
+        if address
+            .rsplit_once(':')
+            .is_none_or(|(_, port)| port.parse::<u16>().is_err())
+        {
+            findings.error(
+                "server.mqtt_address".to_string(),
+                "must be host:port".to_string(),
+            );
+        }
+    }
+
+    match (&server.tls_certificate, &server.tls_private_key) {
+        (Some(certificate), Some(private_key)) => {
+            if let Err(error) = tls::load_config(certificate, private_key) {
+                findings.error(
+                    "server.tls_certificate".to_string(),
+                    format!("unable to load \"{certificate}\" and \"{private_key}\": {error}"),
+                );
+            }
+        }
+        (Some(_), None) | (None, Some(_)) => findings.error(
+            "server.tls_certificate".to_string(),
+            "tls_certificate and tls_private_key must be set together".to_string(),
+        ),
+        (None, None) => {
+            if server.quic_address.is_some() {
+                findings.error(
+                    "server.quic_address".to_string(),
+                    "requires tls_certificate and tls_private_key".to_string(),
+                );
+            }
+        }
+    }
+
+    if server.noise_address.is_some() {
+        match &server.noise_private_key {
+            Some(private_key) => {
+                if let Err(error) = NoiseConfig::new(private_key, &server.noise_client_keys) {
+                    findings.error("server.noise_private_key".to_string(), error);
+                }
+            }
+            None => {
+                let (private_key, public_key) = noise::generate_keypair();
+                findings.error(
+                    "server.noise_private_key".to_string(),
+                    format!("must be set when noise_address is set, e.g., to the generated \"{private_key}\", giving clients the public key {public_key}"),
+                );
+            }
+        }
+    }
+}
+
+/// Check that `address` of the server setting `key` resolves to a socket address.
+fn check_address(findings: &mut Findings, key: &str, address: &str) {
+    if let Err(error) = address.to_socket_addrs() {
+        findings.error(
+            format!("server.{key}"),
+            format!("invalid address \"{address}\": {error}"),
+        );
+    }
+}
+
+/// Check the device `index` of [`crate::config::HardwareConfig::all_devices`].
+fn check_device(config: &Config, index: usize, hardware: &HardwareConfig, findings: &mut Findings) {
+    let device = index
+        .checked_sub(1)
+        .map(|index| (index, &config.hardware.devices[index]));
+    // Further devices inherit the settings they do not override from [hardware], which were
+    // checked with the first device, so only the location of the others is returned for them.
+    let location = |key: &str, overridden: fn(&DeviceConfig) -> bool| match device {
+        None => Some(format!("hardware.{key}")),
+        Some((index, device)) => {
+            overridden(device).then(|| format!("hardware.devices[{index}].{key}"))
+        }
+    };
+
+    if let Some(location) = location("name", |_| true) {
+        if find_device(&hardware.name).is_none() {
+            findings.warning(
+                location,
+                format!(
+                    "\"{}\" is not present or cannot be opened; the server will wait for it",
+                    hardware.name
+                ),
+            );
+        }
+    }
+    if let Some(location) = location("relative_scale", |device| device.relative_scale.is_some()) {
+        if let Err(axis) = RelativeScaling::new(&hardware.relative_scale) {
+            findings.error(location, format!("unknown relative axis {axis}"));
+        }
+    }
+    if let Err(name) = EventFilter::new(hardware.allow.as_deref(), &hardware.block) {
+        let location = if hardware.block.contains(&name) {
+            location("block", |device| device.block.is_some())
+        } else {
+            location("allow", |device| device.allow.is_some())
+        };
+        if let Some(location) = location {
+            findings.error(location, format!("unknown key or event type {name}"));
+        }
+    }
+    if let Some(location) = location("max_rate", |device| device.max_rate.is_some()) {
+        if let Err(error) = Throttle::new(&hardware.max_rate) {
+            findings.error(location, error);
+        }
+    }
+    if let Some(location) = location("script", |device| device.script.is_some()) {
+        if let Some(path) = &hardware.script {
+            if let Some(error) = load_script(path, &hardware.name).err() {
+                findings.error(location, error.to_string());
+            }
+        }
+    }
+    if let Some(location) = location("remap", |device| device.remap.is_some()) {
+        // Keys are remapped once, so a key remapped to another remapped key is not remapped again.
+        let mut chains: Vec<_> = hardware
+            .remap
+            .iter()
+            .filter_map(|(from, to)| Some((*from, *to, *hardware.remap.get(to)?)))
+            .filter(|(from, to, _)| from != to)
+            .collect();
+        chains.sort_by_key(|(from, _, _)| from.code());
+        for (from, to, next) in chains {
+            findings.warning(
+                location.clone(),
+                format!("{from:?} is remapped to {to:?}, which is not remapped again to {next:?}"),
+            );
+        }
+    }
+}
+
+/// The number of the line of `data` setting `location`, e.g., `server.address` or
+/// `hardware.devices[0].remap`, if it is set in a table header and `key = value` line.
+fn line_of(data: &str, location: &str) -> Option<usize> {
+    let (table, key) = location.rsplit_once('.')?;
+    let (table, index) = match table
+        .strip_suffix(']')
+        .and_then(|table| table.split_once('['))
+    {
+        Some((table, index)) => (table, index.parse().ok()?),
+        None => (table, 0),
+    };
+    let mut occurrences = 0;
+    let mut inside = false;
+    for (number, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            inside = line.trim_matches(['[', ']']).trim() == table && {
+                occurrences += 1;
+                occurrences == index + 1
+            };
+        } else if inside
+            && line
+                .split_once('=')
+                .is_some_and(|(name, _)| name.trim() == key)
+        {
+            return Some(number + 1);
+        }
+    }
+    None
+}
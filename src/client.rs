@@ -0,0 +1,171 @@
+use crate::protocol::{
+    self, features, ClientHello, ControlMessage, DeviceDescriptor, DeviceInfo, Framing,
+    HandshakeResponse, InputEventWrapper, ServerHello, StreamFrame,
+};
+use ring::hmac;
+use serde::Deserialize;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, SystemTime};
+
+/// A connection to a remote input server receiving its events over plain TCP.
+/// Servers which require TLS or TOTP codes are not supported.
+pub struct Client {
+    reader: BufReader<TcpStream>,
+    features: u32,
+    devices: Vec<DeviceDescriptor>,
+    /// The part of the next frame received before a read timed out.
+    pending: Vec<u8>,
+}
+
+impl Client {
+    /// Connect to the server at `address`, authenticate with `api_key` and negotiate batches,
+    /// device descriptions and answering pings if the server measures latency.
+    pub fn connect(address: impl ToSocketAddrs, api_key: &str) -> io::Result<Self> {
+        let mut stream = TcpStream::connect(address)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+
+        // Answer the challenge with HMAC-SHA256(api key, nonce).
+        let nonce = Framing::Cobs.unframe(&read_frame(&mut reader)?)?;
+        let key = hmac::Key::new(hmac::HMAC_SHA256, api_key.as_bytes());
+        stream.write_all(&Framing::Cobs.frame(hmac::sign(&key, &nonce).as_ref())?)?;
+
+        // The server closes the connection if the api key is wrong.
+        let server_hello: ServerHello = read_message(&mut reader).map_err(|error| {
+            io::Error::new(
+                error.kind(),
+                format!("unable to receive server hello (is the api key correct?): {error}"),
+            )
+        })?;
+        if server_hello.min_version > protocol::PROTOCOL_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "the server requires protocol version {}",
+                    server_hello.min_version
+                ),
+            ));
+        }
+        let mut requested_features = features::BATCH | features::DEVICE_INFO;
+        // Pings are only sent in multiplexed frames, which are only decoded as batches.
+        if server_hello.features & (features::LATENCY | features::BATCH)
+            == features::LATENCY | features::BATCH
+        {
+            requested_features |= features::MULTIPLEX | features::LATENCY;
+        }
+        let client_hello = ClientHello {
+            version: protocol::PROTOCOL_VERSION.min(server_hello.version),
+            features: server_hello.features & requested_features,
+        };
+        stream.write_all(
+            &protocol::encode(&client_hello)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?,
+        )?;
+        let features = match read_message(&mut reader)? {
+            HandshakeResponse::Accepted { features, .. } => features,
+            HandshakeResponse::Rejected { reason } => {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("rejected by the server: {reason}"),
+                ))
+            }
+        };
+
+        let devices = if features & features::DEVICE_INFO != 0 {
+            read_message::<DeviceInfo>(&mut reader)?.devices
+        } else {
+            Vec::new()
+        };
+        Ok(Self {
+            reader,
+            features,
+            devices,
+            pending: Vec::new(),
+        })
+    }
+
+    /// The negotiated features (see [`features`]).
+    pub fn features(&self) -> u32 {
+        self.features
+    }
+
+    /// The devices of the server, or none if it did not describe them.
+    pub fn devices(&self) -> &[DeviceDescriptor] {
+        &self.devices
+    }
+
+    /// Fail [`Client::receive`] with [`io::ErrorKind::WouldBlock`] or [`io::ErrorKind::TimedOut`]
+    /// if no events are received within `timeout`, or wait indefinitely if it is `None`.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.reader.get_ref().set_read_timeout(timeout)
+    }
+
+    /// Wait for the next events: a batch, or a single event if the server does not send batches.
+    /// A frame interrupted by the read timeout is completed by the next call. Pings are answered
+    /// meanwhile. Fails with [`io::ErrorKind::ConnectionAborted`] once the server announces that it
+    /// is shutting down.
+    pub fn receive(&mut self) -> io::Result<Vec<InputEventWrapper>> {
+        loop {
+            self.reader.read_until(0x00, &mut self.pending)?;
+            if self.pending.last() != Some(&0x00) {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed by the server",
+                ));
+            }
+            let frame = std::mem::take(&mut self.pending);
+            let frame = if self.features & features::MULTIPLEX != 0 {
+                protocol::decode(frame)
+            } else if self.features & features::BATCH != 0 {
+                protocol::decode(frame).map(|events| StreamFrame::Events { device: 0, events })
+            } else {
+                protocol::decode(frame).map(|event| StreamFrame::Events {
+                    device: 0,
+                    events: vec![event],
+                })
+            };
+            match frame.map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))? {
+                StreamFrame::Events { events, .. } => return Ok(events),
+                StreamFrame::Ping { id, .. } => self.answer_ping(id)?,
+                StreamFrame::Closing => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        "the server is shutting down",
+                    ))
+                }
+                // The devices are only described once.
+                StreamFrame::Attached { .. } | StreamFrame::Removed { .. } => {}
+            }
+        }
+    }
+
+    /// Answer the ping with the ID `id`, which was just received.
+    fn answer_ping(&self, id: u32) -> io::Result<()> {
+        let pong = ControlMessage::Pong {
+            id,
+            received: SystemTime::now().into(),
+        };
+        let frame = protocol::encode(&pong)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        (&mut self.reader.get_ref()).write_all(&frame)
+    }
+}
+
+/// Read the next COBS frame, including its zero byte terminator.
+fn read_frame(reader: &mut BufReader<TcpStream>) -> io::Result<Vec<u8>> {
+    let mut frame = Vec::new();
+    reader.read_until(0x00, &mut frame)?;
+    if frame.last() != Some(&0x00) {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed by the server",
+        ));
+    }
+    Ok(frame)
+}
+
+/// Decode the [`postcard`] message in the next frame.
+fn read_message<T: for<'de> Deserialize<'de>>(reader: &mut BufReader<TcpStream>) -> io::Result<T> {
+    protocol::decode(read_frame(reader)?)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
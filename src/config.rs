@@ -0,0 +1,1230 @@
+use crate::audit::AuditLog;
+use crate::auth_limiter::AuthLimiter;
+use crate::latency::LatencyLog;
+use crate::protocol::{DeviceDescriptor, DeviceInfo, Keymap, KeysymMapping};
+use crate::{replay, totp};
+use argon2::password_hash::{PasswordHash, PasswordVerifier};
+use argon2::Argon2;
+use evdev::{Key, LedType};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+use tokio_util::task::TaskTracker;
+use tracing::{info, warn};
+
+pub use crate::feedback::FeedbackBackend;
+pub use crate::secrets::{parse_config, set_overrides};
+
+/// Holds configuration values read from config.toml.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Config {
+    pub hardware: HardwareConfig,
+    pub server: ServerConfig,
+    #[serde(default)]
+    pub log: LogConfig,
+}
+
+/// Holds server configuration values read from config.toml.
+/// Configures the device `name` and the defaults of the further devices in `devices`.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct HardwareConfig {
+    pub name: String,
+    pub led_speed_millis: u64,
+    pub led_pattern: Option<Vec<LedFrame>>,
+    pub connect_led_pattern: Option<Vec<LedFrame>>,
+    pub disconnect_led_pattern: Option<Vec<LedFrame>>,
+    pub escape: Chord,
+    pub pause: Key,
+    /// Toggle with the escape and pause keys only when they are tapped twice within this many
+    /// milliseconds, forwarding single taps once it passed (0 toggles with every tap).
+    #[serde(default)]
+    pub double_tap_millis: u64,
+    /// Select the client receiving events with the digit keys while the escape key is held.
+    #[serde(default)]
+    pub kvm: bool,
+    #[serde(default)]
+    pub relative_scale: HashMap<String, f64>,
+    /// The maximum number of events per second of relative axes (e.g., `REL_X = 125`).
+    #[serde(default)]
+    pub max_rate: HashMap<String, f64>,
+    #[serde(default)]
+    pub passthrough: Vec<Key>,
+    /// Keys rewritten to other keys before they are forwarded (e.g., KEY_CAPSLOCK to KEY_LEFTCTRL).
+    #[serde(default)]
+    pub remap: HashMap<Key, Key>,
+    /// The names of the only keys and event types which are forwarded (e.g., "KEY_F13" or "EV_REL").
+    pub allow: Option<Vec<String>>,
+    /// The names of keys and event types which are never forwarded (e.g., "KEY_POWER").
+    #[serde(default)]
+    pub block: Vec<String>,
+    #[serde(default)]
+    pub macros: Vec<MacroConfig>,
+    /// A Rhai script transforming every event (requires the "scripting" feature).
+    pub script: Option<String>,
+    #[serde(default)]
+    pub monitor: bool,
+    #[serde(default)]
+    pub idle_ungrab_minutes: u64,
+    #[serde(default)]
+    pub feedback: Vec<FeedbackBackend>,
+    pub feedback_command: Option<String>,
+    pub keymap: Option<KeymapConfig>,
+    #[serde(default)]
+    pub autorepeat: Autorepeat,
+    #[serde(default)]
+    pub devices: Vec<DeviceConfig>,
+}
+
+/// A further device listed in `hardware.devices`. Unset keys default to those of [`HardwareConfig`].
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DeviceConfig {
+    pub name: String,
+    pub escape: Option<Chord>,
+    pub pause: Option<Key>,
+    pub double_tap_millis: Option<u64>,
+    pub relative_scale: Option<HashMap<String, f64>>,
+    pub max_rate: Option<HashMap<String, f64>>,
+    pub passthrough: Option<Vec<Key>>,
+    pub remap: Option<HashMap<Key, Key>>,
+    pub allow: Option<Vec<String>>,
+    pub block: Option<Vec<String>>,
+    pub macros: Option<Vec<MacroConfig>>,
+    pub script: Option<String>,
+    pub monitor: Option<bool>,
+    pub led_pattern: Option<Vec<LedFrame>>,
+    pub keymap: Option<KeymapConfig>,
+    pub autorepeat: Option<Autorepeat>,
+}
+
+/// A key, or a chord of keys written like "KEY_LEFTCTRL+KEY_LEFTALT+KEY_ESC": the last key pressed
+/// while the others are held.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Chord {
+    /// The keys which must be held.
+    pub modifiers: Vec<Key>,
+    pub key: Key,
+}
+
+impl TryFrom<String> for Chord {
+    type Error = String;
+
+    fn try_from(chord: String) -> Result<Self, String> {
+        let mut keys = chord
+            .split('+')
+            .map(|key| Key::from_str(key.trim()).map_err(|_| format!("unknown key {key}")))
+            .collect::<Result<Vec<_>, _>>()?;
+        let key = keys.pop().ok_or("empty chord")?;
+        Ok(Self {
+            modifiers: keys,
+            key,
+        })
+    }
+}
+
+impl From<Chord> for String {
+    fn from(chord: Chord) -> Self {
+        chord
+            .modifiers
+            .iter()
+            .chain([&chord.key])
+            .map(|key| format!("{key:?}"))
+            .collect::<Vec<_>>()
+            .join("+")
+    }
+}
+
+/// Follows the keys of a [`Chord`] as they are pressed and released.
+pub(crate) struct HeldChord {
+    modifiers: Vec<u16>,
+    key: u16,
+    /// The modifiers which are held.
+    held: HashSet<u16>,
+    /// Whether the key was pressed while every modifier was held and is not released yet.
+    pressed: bool,
+}
+
+impl HeldChord {
+    pub(crate) fn new(chord: &Chord) -> Self {
+        Self {
+            modifiers: chord.modifiers.iter().map(|key| key.code()).collect(),
+            key: chord.key.code(),
+            held: HashSet::new(),
+            pressed: false,
+        }
+    }
+
+    /// Handle a press (value 1), release (0) or repeat (2) of the key `code`. Returns `true` if it
+    /// is an event of the key of the chord pressed while every modifier was held, including its
+    /// release. The events of the modifiers, and of the key pressed without them, are not.
+    pub(crate) fn key(&mut self, code: u16, value: i32) -> bool {
+        if self.modifiers.contains(&code) {
+            if value == 0 {
+                self.held.remove(&code);
+            } else {
+                self.held.insert(code);
+            }
+        }
+        if code != self.key {
+            return false;
+        }
+        if value == 1 {
+            self.pressed = self.modifiers.iter().all(|code| self.held.contains(code));
+        }
+        let chord = self.pressed;
+        if value == 0 {
+            self.pressed = false;
+        }
+        chord
+    }
+
+    /// Returns `true` while the key of the chord is held after it was pressed with every modifier.
+    pub(crate) fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+}
+
+/// A macro: pressing the key `trigger` sends the key events of `steps` instead.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MacroConfig {
+    pub trigger: Key,
+    pub steps: Vec<MacroStep>,
+}
+
+/// An event of a [`MacroConfig`]: wait `delay_millis` milliseconds, then send `key` with `value`
+/// (1 for a press and 0 for a release).
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MacroStep {
+    pub key: Key,
+    pub value: i32,
+    #[serde(default)]
+    pub delay_millis: u64,
+}
+
+/// How the server handles the autorepeat events (value 2) the kernel sends while a key is held.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Autorepeat {
+    /// Forward them unchanged.
+    #[default]
+    Forward,
+    /// Discard them, for clients which repeat held keys themselves.
+    Suppress,
+    /// Forward each as a release followed by a press, for clients which do not understand them.
+    PressRelease,
+}
+
+/// The keyboard layout of a device, sent to clients in its [`DeviceDescriptor`].
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct KeymapConfig {
+    #[serde(default)]
+    pub layout: String,
+    #[serde(default)]
+    pub variant: String,
+    #[serde(default)]
+    pub options: String,
+    /// The X keysym produced by each key, e.g., `KEY_Z = 0x79`.
+    #[serde(default)]
+    pub keysyms: HashMap<Key, u32>,
+}
+
+impl KeymapConfig {
+    pub(crate) fn keymap(&self) -> Keymap {
+        let mut keysyms: Vec<_> = self
+            .keysyms
+            .iter()
+            .map(|(key, &keysym)| KeysymMapping {
+                code: key.code(),
+                keysym,
+            })
+            .collect();
+        keysyms.sort_by_key(|mapping| mapping.code);
+        Keymap {
+            layout: self.layout.clone(),
+            variant: self.variant.clone(),
+            options: self.options.clone(),
+            keysyms,
+        }
+    }
+}
+
+/// A frame of the animation played on the keyboard LEDs: turn the LEDs `on` on and those `off` off,
+/// then wait `millis` milliseconds.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LedFrame {
+    #[serde(default)]
+    pub on: Vec<LedType>,
+    #[serde(default)]
+    pub off: Vec<LedType>,
+    pub millis: u64,
+}
+
+/// A frame of an [`LedFrame`] animation which turns `led` on or off for `millis` milliseconds.
+fn led_frame(led: LedType, on: bool, millis: u64) -> LedFrame {
+    let (on, off) = if on {
+        (vec![led], Vec::new())
+    } else {
+        (Vec::new(), vec![led])
+    };
+    LedFrame { on, off, millis }
+}
+
+impl HardwareConfig {
+    /// The frames of `connect_led_pattern`, by default flashing LED_NUML twice.
+    pub(crate) fn connect_led_frames(&self) -> Vec<LedFrame> {
+        self.connect_led_pattern.clone().unwrap_or_else(|| {
+            vec![
+                led_frame(LedType::LED_NUML, true, 100),
+                led_frame(LedType::LED_NUML, false, 100),
+                led_frame(LedType::LED_NUML, true, 100),
+                led_frame(LedType::LED_NUML, false, 100),
+            ]
+        })
+    }
+
+    /// The frames of `disconnect_led_pattern`, by default flashing LED_NUML once for longer.
+    pub(crate) fn disconnect_led_frames(&self) -> Vec<LedFrame> {
+        self.disconnect_led_pattern.clone().unwrap_or_else(|| {
+            vec![
+                led_frame(LedType::LED_NUML, false, 200),
+                led_frame(LedType::LED_NUML, true, 800),
+                led_frame(LedType::LED_NUML, false, 200),
+            ]
+        })
+    }
+
+    /// The frames flashing LED_NUML once for every client up to the client at `position` in the order
+    /// of connection, or once for longer if every client (0) was selected.
+    pub(crate) fn select_led_frames(&self, position: usize) -> Vec<LedFrame> {
+        if position == 0 {
+            return vec![
+                led_frame(LedType::LED_NUML, false, 200),
+                led_frame(LedType::LED_NUML, true, 600),
+                led_frame(LedType::LED_NUML, false, 200),
+            ];
+        }
+        let mut frames = vec![led_frame(LedType::LED_NUML, false, 200)];
+        for _ in 0..position {
+            frames.push(led_frame(LedType::LED_NUML, true, 150));
+            frames.push(led_frame(LedType::LED_NUML, false, 150));
+        }
+        frames
+    }
+
+    /// The frames of `led_pattern`, by default blinking LED_NUML every `led_speed_millis`.
+    pub(crate) fn led_frames(&self) -> Vec<LedFrame> {
+        self.led_pattern.clone().unwrap_or_else(|| {
+            vec![
+                led_frame(LedType::LED_NUML, false, self.led_speed_millis),
+                led_frame(LedType::LED_NUML, true, self.led_speed_millis),
+            ]
+        })
+    }
+
+    /// The configuration of every device: `name` followed by `devices`, each with its own keys.
+    pub fn all_devices(&self) -> Vec<HardwareConfig> {
+        let first = HardwareConfig {
+            devices: Vec::new(),
+            ..self.clone()
+        };
+        let devices = self.devices.iter().map(|device| HardwareConfig {
+            name: device.name.clone(),
+            led_speed_millis: self.led_speed_millis,
+            led_pattern: device
+                .led_pattern
+                .clone()
+                .or_else(|| self.led_pattern.clone()),
+            connect_led_pattern: self.connect_led_pattern.clone(),
+            disconnect_led_pattern: self.disconnect_led_pattern.clone(),
+            escape: device.escape.clone().unwrap_or_else(|| self.escape.clone()),
+            pause: device.pause.unwrap_or(self.pause),
+            double_tap_millis: device.double_tap_millis.unwrap_or(self.double_tap_millis),
+            kvm: self.kvm,
+            relative_scale: device
+                .relative_scale
+                .clone()
+                .unwrap_or_else(|| self.relative_scale.clone()),
+            max_rate: device
+                .max_rate
+                .clone()
+                .unwrap_or_else(|| self.max_rate.clone()),
+            passthrough: device
+                .passthrough
+                .clone()
+                .unwrap_or_else(|| self.passthrough.clone()),
+            remap: device.remap.clone().unwrap_or_else(|| self.remap.clone()),
+            allow: device.allow.clone().or_else(|| self.allow.clone()),
+            block: device.block.clone().unwrap_or_else(|| self.block.clone()),
+            macros: device.macros.clone().unwrap_or_else(|| self.macros.clone()),
+            script: device.script.clone().or_else(|| self.script.clone()),
+            monitor: device.monitor.unwrap_or(self.monitor),
+            idle_ungrab_minutes: self.idle_ungrab_minutes,
+            feedback: self.feedback.clone(),
+            feedback_command: self.feedback_command.clone(),
+            keymap: device.keymap.clone().or_else(|| self.keymap.clone()),
+            autorepeat: device.autorepeat.unwrap_or(self.autorepeat),
+            devices: Vec::new(),
+        });
+        std::iter::once(first).chain(devices).collect()
+    }
+}
+
+/// Holds logging configuration values read from config.toml.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LogConfig {
+    /// A `tracing` filter, e.g., `info` or `warn,remote_input::session=debug`.
+    #[serde(default = "default_log_level")]
+    pub level: String,
+    #[serde(default)]
+    pub format: LogFormat,
+    #[serde(default)]
+    pub output: LogOutput,
+    /// The file log lines are appended to with [`LogOutput::File`].
+    pub file: Option<String>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: default_log_level(),
+            format: LogFormat::default(),
+            output: LogOutput::default(),
+            file: None,
+        }
+    }
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+/// How log lines are written to standard output.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line, for log collectors.
+    Json,
+}
+
+/// Where log lines are written.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LogOutput {
+    /// Standard output, in the configured [`LogFormat`].
+    #[default]
+    Stdout,
+    /// The file [`LogConfig::file`], in the configured [`LogFormat`].
+    File,
+    /// The systemd journal, with the priority and target of each line as fields.
+    Journald,
+    /// The system logger, with the daemon facility.
+    Syslog,
+}
+
+/// Holds server configuration values read from config.toml.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ServerConfig {
+    pub address: Addresses,
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub api_keys: ApiKeys,
+    pub secrets_file: Option<String>,
+    #[serde(default)]
+    pub disconnect_revoked_clients: bool,
+    #[serde(default)]
+    pub session_token_lifetime_secs: u64,
+    #[serde(default)]
+    pub allowed_networks: Vec<IpNet>,
+    #[serde(default)]
+    pub denied_networks: Vec<IpNet>,
+    pub totp_secret: Option<String>,
+    #[serde(skip)]
+    pub(crate) totp: Option<totp::Totp>,
+    #[serde(default = "default_auth_max_age_secs")]
+    pub auth_max_age_secs: u64,
+    #[serde(default = "default_require_timestamped_keys")]
+    pub require_timestamped_keys: bool,
+    #[serde(skip)]
+    pub(crate) used_keys: replay::ReplayCache,
+    #[serde(default = "default_auth_timeout_millis")]
+    pub auth_timeout_millis: u64,
+    #[serde(default = "default_write_timeout_millis")]
+    pub write_timeout_millis: u64,
+    #[serde(default = "default_max_failed_auth_attempts")]
+    pub max_failed_auth_attempts: u32,
+    #[serde(default = "default_failed_auth_window_secs")]
+    pub failed_auth_window_secs: u64,
+    #[serde(default = "default_auth_ban_secs")]
+    pub auth_ban_secs: u64,
+    #[serde(default = "default_max_hash_checks")]
+    pub max_hash_checks: u32,
+    #[serde(skip)]
+    pub(crate) auth_limiter: AuthLimiter,
+    pub audit_log: Option<String>,
+    /// The file every forwarded event is appended to as a JSON line.
+    pub record_file: Option<String>,
+    /// The PID file of `remote-input serve --daemon`, next to the configuration file if unset.
+    pub pid_file: Option<String>,
+    /// The recording streamed to clients instead of the events of the devices, set by
+    /// `remote-input replay`.
+    #[serde(skip)]
+    pub replay_file: Option<PathBuf>,
+    #[serde(skip)]
+    pub(crate) audit: AuditLog,
+    #[serde(default = "default_max_clients")]
+    pub max_clients: usize,
+    #[serde(skip)]
+    pub(crate) clients: ClientCount,
+    /// The connections handled at once by the TCP, WebSocket, JSON lines, Noise, encrypted, QUIC
+    /// and RFCOMM listeners, including those still authenticating.
+    #[serde(default = "default_max_connections")]
+    pub max_connections: usize,
+    #[serde(skip)]
+    pub(crate) connections: ConnectionCount,
+    /// The descriptor of each device, set by its [`device_listener`] while the device is attached.
+    #[serde(skip)]
+    pub(crate) device_info: Vec<Arc<RwLock<Option<DeviceDescriptor>>>>,
+    pub websocket_address: Option<String>,
+    pub json_lines_address: Option<String>,
+    pub tls_certificate: Option<String>,
+    pub tls_private_key: Option<String>,
+    pub quic_address: Option<String>,
+    pub grpc_address: Option<String>,
+    pub mqtt_address: Option<String>,
+    pub mqtt_topic: Option<String>,
+    pub mqtt_username: Option<String>,
+    pub mqtt_password: Option<String>,
+    pub noise_address: Option<String>,
+    pub encrypted_address: Option<String>,
+    pub noise_private_key: Option<String>,
+    #[serde(default)]
+    pub noise_client_keys: Vec<String>,
+    #[serde(default = "default_heartbeat_interval_millis")]
+    pub heartbeat_interval_millis: u64,
+    #[serde(default = "default_heartbeat_timeout_millis")]
+    pub heartbeat_timeout_millis: u64,
+    /// How often clients which negotiated [`crate::protocol::features::LATENCY`] are pinged (0 if
+    /// they are not).
+    #[serde(default)]
+    pub latency_interval_millis: u64,
+    /// The file the latency of clients is appended to as CSV.
+    pub latency_file: Option<String>,
+    #[serde(skip)]
+    pub(crate) latency_log: LatencyLog,
+    #[serde(default = "default_flow_control_window")]
+    pub flow_control_window: u32,
+    #[serde(default = "default_max_frame_size")]
+    pub max_frame_size: usize,
+    pub serial_port: Option<String>,
+    #[serde(default = "default_serial_baud_rate")]
+    pub serial_baud_rate: u32,
+    pub rfcomm_channel: Option<u8>,
+    pub multicast_address: Option<String>,
+    pub multicast_key: Option<String>,
+    #[serde(default = "default_multicast_ttl")]
+    pub multicast_ttl: u32,
+    #[serde(default)]
+    pub dial_out_addresses: Vec<String>,
+    #[serde(default = "default_dial_out_min_backoff_millis")]
+    pub dial_out_min_backoff_millis: u64,
+    #[serde(default = "default_dial_out_max_backoff_millis")]
+    pub dial_out_max_backoff_millis: u64,
+}
+
+impl ServerConfig {
+    /// Record the result of authenticating `client` from `address` (unless it has no IP address)
+    /// with `auth_limiter`, banning the address after too many failures, and in the audit log.
+    /// `api_key` is the key the client authenticated with or `None` if authentication failed.
+    pub(crate) fn record_authentication(
+        &self,
+        client: &str,
+        address: Option<IpAddr>,
+        api_key: Option<&ApiKey>,
+    ) {
+        match api_key {
+            Some(api_key) => self
+                .audit
+                .record(client, &format!("Authenticated as \"{}\".", api_key.name)),
+            None => self.audit.record(client, "Authentication failed."),
+        }
+        if let Some(address) = address {
+            if self.auth_limiter.record(address, api_key.is_some(), self) {
+                let message = format!(
+                    "Too many failed authentication attempts. Banned for {} seconds.",
+                    self.auth_ban_secs
+                );
+                warn!("[{client}] {message}");
+                self.audit.record(client, &message);
+            }
+        }
+    }
+
+    /// Find the hashed API key which `client_key` is. As the client sends the key itself, hashed keys
+    /// are only accepted on `secure` connections (see [`crate::session::Transport::is_secure`]).
+    /// Each hashed key verified counts towards `max_hash_checks` of `address`, and none are verified
+    /// once the limit is reached or if the client has no IP address. The keys are verified on a
+    /// blocking thread so that the Argon2 computations do not hold up other connections.
+    pub(crate) async fn find_hashed_key(
+        &self,
+        client_key: &[u8],
+        secure: bool,
+        client: &str,
+        address: Option<IpAddr>,
+    ) -> Option<ApiKey> {
+        let hashed = self
+            .api_keys
+            .0
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|api_key| api_key.is_hashed())
+            .count() as u32;
+        let address = address.filter(|_| secure && hashed > 0)?;
+        if !self.auth_limiter.reserve_hash_checks(address, hashed, self) {
+            warn!("[{client}] Too many hashed api key checks. Not verifying hashed keys.");
+            return None;
+        }
+        let (api_keys, client_key) = (self.api_keys.clone(), client_key.to_vec());
+        tokio::task::spawn_blocking(move || {
+            api_keys.find(|api_key| api_key.is_hashed() && api_key.verify(&client_key))
+        })
+        .await
+        .ok()
+        .flatten()
+    }
+
+    /// How long to wait for each read from a client before it is authenticated, or `None` to wait forever.
+    pub(crate) fn auth_timeout(&self) -> Option<Duration> {
+        (self.auth_timeout_millis > 0).then(|| Duration::from_millis(self.auth_timeout_millis))
+    }
+
+    /// How long a send may block before the connection is closed, or `None` to wait forever.
+    pub(crate) fn write_timeout(&self) -> Option<Duration> {
+        (self.write_timeout_millis > 0).then(|| Duration::from_millis(self.write_timeout_millis))
+    }
+
+    /// How long after the challenge its response is accepted. See [`session::authenticate`].
+    pub(crate) fn auth_max_age(&self) -> Duration {
+        Duration::from_secs(self.auth_max_age_secs)
+    }
+
+    /// The descriptors of the attached devices.
+    pub(crate) fn device_info(&self) -> DeviceInfo {
+        let devices = self
+            .device_info
+            .iter()
+            .filter_map(|descriptor| descriptor.read().unwrap().clone())
+            .collect();
+        DeviceInfo { devices }
+    }
+
+    /// Count an authenticated client for as long as the returned slot is kept,
+    /// or return `None` if `max_clients` clients are already being served.
+    pub(crate) fn acquire_client_slot(&self) -> Option<ClientSlot> {
+        self.clients.acquire(self.max_clients)
+    }
+
+    /// Count a connection for as long as the returned slot is kept,
+    /// or return `None` if `max_connections` connections are already being handled.
+    pub(crate) fn acquire_connection_slot(&self) -> Option<ConnectionSlot> {
+        self.connections.acquire(self.max_connections)
+    }
+
+    /// Decide whether to accept a connection from `address` to `endpoint` (e.g., a listening address)
+    /// and record the attempt in the audit log. Returns why the connection must be rejected, if it must.
+    pub(crate) fn check_connection(
+        &self,
+        address: SocketAddr,
+        endpoint: &str,
+    ) -> Option<&'static str> {
+        let reason = if !self.is_allowed_address(address.ip()) {
+            Some("address not allowed")
+        } else if self.auth_limiter.is_banned(address.ip()) {
+            Some("temporarily banned")
+        } else {
+            None
+        };
+        let event = match reason {
+            Some(reason) => format!("Rejected connection to {endpoint}: {reason}."),
+            None => format!("Accepted connection to {endpoint}."),
+        };
+        self.audit.record(&address.to_string(), &event);
+        reason
+    }
+
+    /// Returns `true` if connections from `address` are allowed: it must be in one of
+    /// `allowed_networks` (if any are configured) and in none of `denied_networks`.
+    pub(crate) fn is_allowed_address(&self, address: IpAddr) -> bool {
+        // An IPv4 client of a dual-stack listener has an IPv4-mapped IPv6 address.
+        let address = address.to_canonical();
+        (self.allowed_networks.is_empty()
+            || self
+                .allowed_networks
+                .iter()
+                .any(|network| network.contains(&address)))
+            && !self
+                .denied_networks
+                .iter()
+                .any(|network| network.contains(&address))
+    }
+
+    /// The keys listed in `api_keys` preceded by `api_key`, which is shorthand for a key named
+    /// "default" with every permission.
+    pub(crate) fn accepted_api_keys(&self) -> Vec<ApiKey> {
+        let default_key = self.api_key.as_ref().map(|api_key| ApiKey {
+            name: "default".to_string(),
+            secret: Secret::Plain {
+                key: api_key.clone(),
+            },
+            permissions: Permissions::ALL,
+        });
+        default_key
+            .into_iter()
+            .chain(self.api_keys.0.read().unwrap().iter().cloned())
+            .collect()
+    }
+}
+
+/// A change of the authenticated clients, shown on the keyboard LEDs by [`blink_led`].
+#[derive(Clone, Copy)]
+pub(crate) enum ClientEvent {
+    /// A client was authenticated.
+    Connected,
+    /// The last client left.
+    AllDisconnected,
+    /// Events are only sent to the client at this position in the order of connection, or to
+    /// every client if it is 0.
+    Selected(usize),
+}
+
+/// The receivers of [`ClientEvent`]s.
+pub(crate) type ClientEventSubscribers = Arc<Mutex<Vec<Sender<ClientEvent>>>>;
+
+/// The clients being served in the order of connection and the one selected with [`ClientCount::select`],
+/// which alone receives events, or `None` if every client does.
+#[derive(Default)]
+struct Routing {
+    next_id: u64,
+    clients: Vec<u64>,
+    selected: Option<u64>,
+}
+
+/// Counts the authenticated clients being served and sends [`ClientEvent`]s to its subscribers.
+/// Clones share the count, the subscribers and the selected client.
+#[derive(Clone, Default)]
+pub(crate) struct ClientCount {
+    count: Arc<AtomicUsize>,
+    subscribers: ClientEventSubscribers,
+    routing: Arc<Mutex<Routing>>,
+}
+
+impl ClientCount {
+    /// Count one more client unless there already are `max_clients`.
+    /// The client is counted until the returned slot is dropped.
+    pub(crate) fn acquire(&self, max_clients: usize) -> Option<ClientSlot> {
+        self.count
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |clients| {
+                (clients < max_clients).then_some(clients + 1)
+            })
+            .ok()?;
+        let id = {
+            let mut routing = self.routing.lock().unwrap();
+            let id = routing.next_id;
+            routing.next_id += 1;
+            routing.clients.push(id);
+            id
+        };
+        notify_subscribers(&self.subscribers, ClientEvent::Connected);
+        Some(ClientSlot {
+            id,
+            count: Arc::clone(&self.count),
+            subscribers: Arc::clone(&self.subscribers),
+            routing: Arc::clone(&self.routing),
+        })
+    }
+
+    /// Send events only to the client at `position` (from 1) in the order of connection, or to
+    /// every client if it is 0. Returns `false` if fewer clients are being served.
+    pub(crate) fn select(&self, position: usize) -> bool {
+        {
+            let mut routing = self.routing.lock().unwrap();
+            routing.selected = match position.checked_sub(1) {
+                Some(index) => match routing.clients.get(index) {
+                    Some(&id) => Some(id),
+                    None => return false,
+                },
+                None => None,
+            };
+        }
+        notify_subscribers(&self.subscribers, ClientEvent::Selected(position));
+        true
+    }
+
+    /// The number of clients being served.
+    pub(crate) fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    /// Receive every following [`ClientEvent`].
+    pub(crate) fn subscribe(&self) -> Receiver<ClientEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+}
+
+/// Send `event` to every subscriber which is still receiving.
+fn notify_subscribers(subscribers: &ClientEventSubscribers, event: ClientEvent) {
+    subscribers
+        .lock()
+        .unwrap()
+        .retain(|subscriber| subscriber.send(event).is_ok());
+}
+
+/// One client counted by [`ClientCount`].
+pub(crate) struct ClientSlot {
+    id: u64,
+    count: Arc<AtomicUsize>,
+    subscribers: ClientEventSubscribers,
+    routing: Arc<Mutex<Routing>>,
+}
+
+impl ClientSlot {
+    /// Whether the client receives events: if it was selected or no client was.
+    pub(crate) fn receives(&self) -> bool {
+        self.routing
+            .lock()
+            .unwrap()
+            .selected
+            .is_none_or(|id| id == self.id)
+    }
+}
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        let deselected = {
+            let mut routing = self.routing.lock().unwrap();
+            routing.clients.retain(|&id| id != self.id);
+            let deselected = routing.selected == Some(self.id);
+            if deselected {
+                routing.selected = None;
+            }
+            deselected
+        };
+        if deselected {
+            info!("[KVM] The selected client left. Sending events to every client.");
+            notify_subscribers(&self.subscribers, ClientEvent::Selected(0));
+        }
+        if self.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            notify_subscribers(&self.subscribers, ClientEvent::AllDisconnected);
+        }
+    }
+}
+
+/// Counts the connections being handled, from being accepted until their handler returns, and
+/// tracks the handler tasks so that shutdown can wait for them. Clones share the count.
+#[derive(Clone, Default)]
+pub(crate) struct ConnectionCount {
+    count: Arc<AtomicUsize>,
+    handlers: TaskTracker,
+    /// The runtime handlers run on, or the current one if `None`.
+    workers: Option<Handle>,
+}
+
+impl ConnectionCount {
+    /// Count connections whose handlers run on `workers`.
+    pub(crate) fn new(workers: Handle) -> Self {
+        Self {
+            workers: Some(workers),
+            ..Self::default()
+        }
+    }
+
+    /// Count one more connection unless there already are `max_connections`.
+    /// The connection is counted until the returned slot is dropped.
+    fn acquire(&self, max_connections: usize) -> Option<ConnectionSlot> {
+        self.count
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |connections| {
+                (connections < max_connections).then_some(connections + 1)
+            })
+            .ok()?;
+        Some(ConnectionSlot(self.clone()))
+    }
+
+    /// The number of connections being handled. Connections are rejected while it is at
+    /// `max_connections`, so it is also how saturated the server is.
+    pub(crate) fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    /// Run `task` on the worker runtime, in a task [`ConnectionCount::join`] waits for.
+    pub(crate) fn spawn<F>(&self, task: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        match &self.workers {
+            Some(workers) => self.handlers.spawn_on(task, workers),
+            None => self.handlers.spawn(task),
+        }
+    }
+
+    /// Wait until every task spawned with [`ConnectionCount::spawn`] or [`ConnectionSlot::spawn`]
+    /// returned.
+    pub(crate) async fn join(&self) {
+        self.handlers.close();
+        self.handlers.wait().await;
+    }
+}
+
+/// One connection counted by [`ConnectionCount`].
+pub(crate) struct ConnectionSlot(ConnectionCount);
+
+impl ConnectionSlot {
+    /// Like [`ConnectionCount::spawn`], counting the connection until `task` returns.
+    pub(crate) fn spawn<F>(self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let connections = self.0.clone();
+        connections.spawn(async move {
+            let _slot = self;
+            task.await;
+        });
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.count.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The API keys accepted by the server. Clones share the keys so that they can be replaced
+/// while the server runs.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(from = "Vec<ApiKey>", into = "Vec<ApiKey>")]
+pub struct ApiKeys(pub(crate) Arc<RwLock<Vec<ApiKey>>>);
+
+impl From<Vec<ApiKey>> for ApiKeys {
+    fn from(api_keys: Vec<ApiKey>) -> Self {
+        Self(Arc::new(RwLock::new(api_keys)))
+    }
+}
+
+impl From<ApiKeys> for Vec<ApiKey> {
+    fn from(api_keys: ApiKeys) -> Self {
+        api_keys.0.read().unwrap().clone()
+    }
+}
+
+impl ApiKeys {
+    /// The first key matching `predicate`.
+    pub(crate) fn find(&self, predicate: impl FnMut(&&ApiKey) -> bool) -> Option<ApiKey> {
+        self.0.read().unwrap().iter().find(predicate).cloned()
+    }
+
+    /// Returns `true` if `api_key` (with the same permissions) is still accepted.
+    pub(crate) fn contains(&self, api_key: &ApiKey) -> bool {
+        self.0.read().unwrap().contains(api_key)
+    }
+
+    /// Replace the keys with `api_keys`, returning the previous keys.
+    pub(crate) fn replace(&self, api_keys: Vec<ApiKey>) -> Vec<ApiKey> {
+        std::mem::replace(&mut self.0.write().unwrap(), api_keys)
+    }
+}
+
+/// A named API key and the permissions of clients authenticated with it.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct ApiKey {
+    pub name: String,
+    #[serde(flatten)]
+    pub secret: Secret,
+    #[serde(flatten)]
+    pub permissions: Permissions,
+}
+
+/// An API key as written in `config.toml`: the key itself or an Argon2 hash of it.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Secret {
+    Plain {
+        key: String,
+    },
+    /// A PHC string such as `$argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>`.
+    /// The server cannot compute HMACs with a hashed key, so clients send the key itself,
+    /// which is only accepted over TLS or QUIC.
+    Hashed {
+        key_hash: String,
+    },
+}
+
+impl ApiKey {
+    /// The key for HMACs computed with this API key, or `None` if only its hash is known.
+    pub(crate) fn hmac_key(&self) -> Option<ring::hmac::Key> {
+        match &self.secret {
+            Secret::Plain { key } => Some(ring::hmac::Key::new(
+                ring::hmac::HMAC_SHA256,
+                key.as_bytes(),
+            )),
+            Secret::Hashed { .. } => None,
+        }
+    }
+
+    /// Returns `true` if only the hash of this key is known.
+    pub(crate) fn is_hashed(&self) -> bool {
+        matches!(self.secret, Secret::Hashed { .. })
+    }
+
+    /// Returns `false` if this is a hashed key which cannot be parsed.
+    pub(crate) fn has_valid_hash(&self) -> bool {
+        match &self.secret {
+            Secret::Plain { .. } => true,
+            Secret::Hashed { key_hash } => PasswordHash::new(key_hash).is_ok(),
+        }
+    }
+
+    /// Returns `true` if `client_key` is this API key.
+    /// Plain keys are compared in the same time wherever the keys differ.
+    pub(crate) fn verify(&self, client_key: &[u8]) -> bool {
+        match &self.secret {
+            Secret::Plain { key } => {
+                client_key.len() == key.len()
+                    && client_key
+                        .iter()
+                        .zip(key.as_bytes())
+                        .fold(0, |difference, (a, b)| difference | (a ^ b))
+                        == 0
+            }
+            Secret::Hashed { key_hash } => PasswordHash::new(key_hash).is_ok_and(|key_hash| {
+                Argon2::default()
+                    .verify_password(client_key, &key_hash)
+                    .is_ok()
+            }),
+        }
+    }
+}
+
+/// What an authenticated client may receive and do.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct Permissions {
+    /// The event types (e.g., 1 for EV_KEY) sent to the client, or every type if unset.
+    pub event_types: Option<Vec<u16>>,
+    /// Whether the client may send control messages other than flow control.
+    #[serde(default = "default_control")]
+    pub control: bool,
+}
+
+impl Permissions {
+    /// Every event type and every control message.
+    pub const ALL: Permissions = Permissions {
+        event_types: None,
+        control: true,
+    };
+
+    pub(crate) fn allows_event_type(&self, event_type: u16) -> bool {
+        self.event_types
+            .as_ref()
+            .is_none_or(|event_types| event_types.contains(&event_type))
+    }
+}
+
+fn default_control() -> bool {
+    true
+}
+
+/// One or more bind addresses, written as a string or a list of strings in `config.toml`.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum Addresses {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Addresses {
+    pub(crate) fn as_slice(&self) -> &[String] {
+        match self {
+            Addresses::One(address) => std::slice::from_ref(address),
+            Addresses::Many(addresses) => addresses,
+        }
+    }
+}
+
+fn default_heartbeat_interval_millis() -> u64 {
+    5000
+}
+
+fn default_heartbeat_timeout_millis() -> u64 {
+    15000
+}
+
+fn default_flow_control_window() -> u32 {
+    64
+}
+
+fn default_max_failed_auth_attempts() -> u32 {
+    5
+}
+
+fn default_failed_auth_window_secs() -> u64 {
+    60
+}
+
+fn default_auth_ban_secs() -> u64 {
+    300
+}
+
+fn default_require_timestamped_keys() -> bool {
+    true
+}
+
+fn default_max_hash_checks() -> u32 {
+    10
+}
+
+fn default_auth_max_age_secs() -> u64 {
+    30
+}
+
+fn default_auth_timeout_millis() -> u64 {
+    10000
+}
+
+fn default_write_timeout_millis() -> u64 {
+    5000
+}
+
+fn default_max_clients() -> usize {
+    10
+}
+
+fn default_max_connections() -> usize {
+    64
+}
+
+fn default_max_frame_size() -> usize {
+    4096
+}
+
+fn default_serial_baud_rate() -> u32 {
+    115200
+}
+
+fn default_multicast_ttl() -> u32 {
+    1
+}
+
+fn default_dial_out_min_backoff_millis() -> u64 {
+    1000
+}
+
+fn default_dial_out_max_backoff_millis() -> u64 {
+    60000
+}
+
+/// A configuration reading the device "test" and accepting the API key "secret", for tests.
+#[cfg(test)]
+pub(crate) fn test_config() -> Config {
+    let config = parse_config(
+        r#"
+[hardware]
+name = "test"
+led_speed_millis = 3000
+escape = "KEY_SCROLLLOCK"
+pause = "KEY_PAUSE"
+
+[server]
+address = "127.0.0.1:0"
+api_key = "secret"
+"#,
+    )
+    .unwrap();
+    config
+        .server
+        .api_keys
+        .replace(config.server.accepted_api_keys());
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The events of `keys` as `(key, value)`, and whether each belongs to the completed `chord`.
+    fn play(chord: &mut HeldChord, keys: &[(Key, i32)]) -> Vec<bool> {
+        keys.iter()
+            .map(|&(key, value)| chord.key(key.code(), value))
+            .collect()
+    }
+
+    fn ctrl_alt_esc() -> HeldChord {
+        HeldChord::new(&Chord::try_from("KEY_LEFTCTRL + KEY_LEFTALT+KEY_ESC".to_string()).unwrap())
+    }
+
+    #[test]
+    fn parses_chords() {
+        let chord = Chord::try_from("KEY_LEFTCTRL + KEY_ESC".to_string()).unwrap();
+        assert_eq!(chord.modifiers, [Key::KEY_LEFTCTRL]);
+        assert_eq!(chord.key, Key::KEY_ESC);
+        assert_eq!(String::from(chord), "KEY_LEFTCTRL+KEY_ESC");
+        assert!(Chord::try_from("KEY_LEFTCTRL+KEY_NONE".to_string()).is_err());
+    }
+
+    #[test]
+    fn forwards_keys_of_partial_chord() {
+        let mut chord = ctrl_alt_esc();
+        let events = [
+            (Key::KEY_LEFTCTRL, 1),
+            (Key::KEY_ESC, 1),
+            (Key::KEY_ESC, 2),
+            (Key::KEY_ESC, 0),
+            (Key::KEY_LEFTCTRL, 0),
+        ];
+        assert_eq!(play(&mut chord, &events), [false; 5]);
+        assert!(!chord.is_pressed());
+    }
+
+    #[test]
+    fn completes_chord_with_every_modifier_held() {
+        let mut chord = ctrl_alt_esc();
+        let events = [
+            (Key::KEY_LEFTALT, 1),
+            (Key::KEY_LEFTCTRL, 1),
+            (Key::KEY_ESC, 1),
+            (Key::KEY_ESC, 2),
+        ];
+        assert_eq!(play(&mut chord, &events), [false, false, true, true]);
+        assert!(chord.is_pressed());
+        // Releasing a modifier first still ends the chord with the release of its key.
+        let events = [(Key::KEY_LEFTCTRL, 0), (Key::KEY_ESC, 0)];
+        assert_eq!(play(&mut chord, &events), [false, true]);
+        assert!(!chord.is_pressed());
+        // The chord must be completed again.
+        assert_eq!(play(&mut chord, &[(Key::KEY_ESC, 1)]), [false]);
+    }
+
+    #[test]
+    fn completes_single_key_chord() {
+        let mut chord = HeldChord::new(&Chord::try_from("KEY_SCROLLLOCK".to_string()).unwrap());
+        let events = [
+            (Key::KEY_A, 1),
+            (Key::KEY_SCROLLLOCK, 1),
+            (Key::KEY_SCROLLLOCK, 0),
+        ];
+        assert_eq!(play(&mut chord, &events), [false, true, true]);
+    }
+}
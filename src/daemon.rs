@@ -0,0 +1,127 @@
+use crate::error::{Error, Result};
+use std::fs::{self, File};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long [`stop`] waits for the server to exit after sending it SIGTERM.
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often [`stop`] checks whether the server has exited.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The PID file written by [`daemonize`].
+static PID_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Detach from the terminal and continue in the background, writing the PID of the background
+/// process to `pid_file`. Only the background process returns. Standard input, output and error
+/// are redirected to /dev/null, so logs must be written to a file, journald or syslog.
+/// Must be called before any thread is started.
+pub fn daemonize(pid_file: &Path) -> Result<()> {
+    if let Some(pid) = running(pid_file)? {
+        return Err(Error::AlreadyRunning(pid));
+    }
+    let null = File::options()
+        .read(true)
+        .write(true)
+        .open("/dev/null")
+        .map_err(Error::io("unable to open /dev/null"))?;
+    fork()?;
+    // SAFETY: `setsid` has no preconditions. It only fails if the process leads a process group,
+    // which a forked child never does.
+    if unsafe { libc::setsid() } < 0 {
+        return Err(Error::io("unable to start a session")(
+            io::Error::last_os_error(),
+        ));
+    }
+    // Fork again so that the daemon does not lead its session and never acquires a terminal.
+    fork()?;
+    fs::write(pid_file, format!("{}\n", std::process::id())).map_err(Error::io(format!(
+        "unable to write PID file \"{}\"",
+        pid_file.display()
+    )))?;
+    let _ = PID_FILE.set(pid_file.to_path_buf());
+    for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        // SAFETY: Both file descriptors are open.
+        if unsafe { libc::dup2(null.as_raw_fd(), fd) } < 0 {
+            return Err(Error::io("unable to redirect standard streams")(
+                io::Error::last_os_error(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Fork, exiting in the parent.
+fn fork() -> Result<()> {
+    // SAFETY: No other thread is running, so the child can continue like the parent.
+    match unsafe { libc::fork() } {
+        -1 => Err(Error::io("unable to fork")(io::Error::last_os_error())),
+        0 => Ok(()),
+        _ => std::process::exit(0),
+    }
+}
+
+/// Remove the PID file written by [`daemonize`], if this process is a daemon.
+pub fn remove_pid_file() {
+    if let Some(pid_file) = PID_FILE.get() {
+        let _ = fs::remove_file(pid_file);
+    }
+}
+
+/// The PID in `pid_file`, if the file exists and that process is still running.
+pub fn running(pid_file: &Path) -> Result<Option<libc::pid_t>> {
+    let contents = match fs::read_to_string(pid_file) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => {
+            return Err(Error::io(format!(
+                "unable to read PID file \"{}\"",
+                pid_file.display()
+            ))(error))
+        }
+    };
+    let pid = contents
+        .trim()
+        .parse::<libc::pid_t>()
+        .ok()
+        .filter(|&pid| pid > 0)
+        .ok_or_else(|| {
+            Error::Config(format!(
+                "PID file \"{}\" does not hold a PID",
+                pid_file.display()
+            ))
+        })?;
+    // SAFETY: Signal 0 is never delivered. It only checks whether the process exists.
+    let exists = unsafe { libc::kill(pid, 0) } == 0
+        || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM);
+    Ok(exists.then_some(pid))
+}
+
+/// Ask the process in `pid_file` to shut down with SIGTERM and wait for it to exit.
+/// Returns its PID, or `None` if it was not running.
+pub fn stop(pid_file: &Path) -> Result<Option<libc::pid_t>> {
+    let Some(pid) = running(pid_file)? else {
+        return Ok(None);
+    };
+    // SAFETY: `kill` has no preconditions.
+    if unsafe { libc::kill(pid, libc::SIGTERM) } != 0 {
+        return Err(Error::io(format!("unable to stop PID {pid}"))(
+            io::Error::last_os_error(),
+        ));
+    }
+    let deadline = Instant::now() + STOP_TIMEOUT;
+    while running(pid_file)?.is_some() {
+        if Instant::now() >= deadline {
+            return Err(Error::io(format!("PID {pid} did not stop"))(
+                io::ErrorKind::TimedOut.into(),
+            ));
+        }
+        thread::sleep(STOP_POLL_INTERVAL);
+    }
+    let _ = fs::remove_file(pid_file);
+    Ok(Some(pid))
+}
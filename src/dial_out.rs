@@ -0,0 +1,67 @@
+use crate::config::ServerConfig;
+use crate::protocol::ControlMessage;
+use crate::server::EventBus;
+use crate::tls;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tracing::{info, warn};
+
+/// Connect to the client listening on `address` forever, reconnecting with exponential backoff
+/// between `config.dial_out_min_backoff_millis` and `config.dial_out_max_backoff_millis`.
+/// Each connection is handled exactly like an accepted TCP connection by [`crate::server::handle_connection`],
+/// on the worker runtime of `config.connections`: the server acts as the TLS server if `tls_config`
+/// is set and the client must answer the API key challenge.
+pub async fn connect_forever(
+    address: &str,
+    tls_config: Option<Arc<rustls::ServerConfig>>,
+    config: Arc<ServerConfig>,
+    event_bus: &EventBus,
+    commands: Sender<ControlMessage>,
+) {
+    let min_backoff = Duration::from_millis(config.dial_out_min_backoff_millis);
+    let max_backoff = Duration::from_millis(config.dial_out_max_backoff_millis).max(min_backoff);
+    let mut backoff = min_backoff;
+    loop {
+        info!("[Dial Out {address}] Connecting.");
+        match TcpStream::connect(address).await {
+            Ok(stream) => {
+                backoff = min_backoff;
+                let receiver = event_bus.subscribe();
+                let peer = address.to_string();
+                let tls_config = tls_config.clone();
+                let handler_config = Arc::clone(&config);
+                let commands = commands.clone();
+                let handler = config.connections.spawn(async move {
+                    let auth_timeout = handler_config.auth_timeout();
+                    match tls::Stream::accept(stream, tls_config.as_ref(), auth_timeout).await {
+                        Ok(stream) => {
+                            crate::server::handle_connection(
+                                stream,
+                                &handler_config,
+                                receiver,
+                                &commands,
+                            )
+                            .await
+                        }
+                        Err(error) => {
+                            warn!("[Dial Out {peer}] Unable to start TLS session: {error}.")
+                        }
+                    }
+                });
+                let _ = handler.await;
+                info!("[Dial Out {address}] Disconnected.");
+            }
+            Err(error) => {
+                warn!("[Dial Out {address}] Unable to connect: {error}.");
+            }
+        }
+        info!(
+            "[Dial Out {address}] Reconnecting in {} ms.",
+            backoff.as_millis()
+        );
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(max_backoff);
+    }
+}
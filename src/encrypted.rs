@@ -0,0 +1,293 @@
+//! Encrypted plain TCP for clients which cannot use TLS (see [`handle_connection`]).
+//!
+//! The keys are derived only from the API key and the nonce chosen by the server; the client
+//! contributes nothing. Anyone who learns the API key can therefore decrypt recorded sessions,
+//! since there is no forward secrecy. Use TLS, Noise or QUIC where that matters.
+
+use crate::broadcast::Receiver;
+use crate::config::{Secret, ServerConfig};
+use crate::protocol::ControlMessage;
+use crate::server::EventBatch;
+use crate::session::{self, StreamTransport, TimeoutTransport, Transport, NONCE_LEN};
+use crate::tls;
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305};
+use ring::hkdf;
+use std::io;
+use std::sync::mpsc::Sender;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::warn;
+
+/// HKDF info for the key encrypting frames sent by the server.
+const SERVER_KEY_INFO: &[u8] = b"remote-input server to client";
+
+/// HKDF info for the key encrypting frames sent by the client.
+const CLIENT_KEY_INFO: &[u8] = b"remote-input client to server";
+
+/// The longest encrypted message, limited by its [`u16`] length prefix.
+const MAX_MESSAGE_LEN: usize = u16::MAX as usize;
+
+/// Frames in one direction, encrypted with ChaCha20-Poly1305. The nonce of each message is the number of
+/// messages sent before it as a big endian `u64`, preceded by four zero bytes.
+struct Cipher {
+    key: LessSafeKey,
+    counter: u64,
+}
+
+impl Cipher {
+    /// Derive the key for one direction from `api_key` and the authentication `nonce` with HKDF-SHA256.
+    fn new(api_key: &[u8], nonce: &[u8; NONCE_LEN], info: &[u8]) -> Self {
+        let info = [info];
+        let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, nonce).extract(api_key);
+        let okm = prk
+            .expand(&info, &CHACHA20_POLY1305)
+            .expect("the key length is valid for HKDF-SHA256");
+        Self {
+            key: LessSafeKey::new(UnboundKey::from(okm)),
+            counter: 0,
+        }
+    }
+
+    /// The nonce of the next message. Fails once every nonce was used.
+    fn next_nonce(&mut self) -> io::Result<Nonce> {
+        let mut nonce = [0u8; aead::NONCE_LEN];
+        nonce[4..].copy_from_slice(&self.counter.to_be_bytes());
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| io::Error::other("nonces exhausted"))?;
+        Ok(Nonce::assume_unique_for_key(nonce))
+    }
+}
+
+/// A plain TCP stream carrying each frame in its own encrypted message, prefixed by the length of the
+/// message (the ciphertext and its 16 byte tag) as a big endian [`u16`].
+struct EncryptedTransport {
+    stream: TcpStream,
+    sealing: Cipher,
+    opening: Cipher,
+    /// Bytes received from `stream` that are not yet part of a decrypted message.
+    incoming: Vec<u8>,
+}
+
+impl EncryptedTransport {
+    /// Remove the first complete length prefixed message from `self.incoming` and decrypt it.
+    fn take_message(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.incoming.len() < 2 {
+            return Ok(None);
+        }
+        let len = u16::from_be_bytes([self.incoming[0], self.incoming[1]]) as usize;
+        if self.incoming.len() < 2 + len {
+            return Ok(None);
+        }
+        let mut message: Vec<u8> = self.incoming.drain(..2 + len).skip(2).collect();
+        let nonce = self.opening.next_nonce()?;
+        let frame_len = self
+            .opening
+            .key
+            .open_in_place(nonce, Aad::empty(), &mut message)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "message failed to decrypt"))?
+            .len();
+        message.truncate(frame_len);
+        Ok(Some(message))
+    }
+
+    /// Append bytes from `stream` to `self.incoming`, returning an error at the end of the stream.
+    async fn fill(&mut self) -> io::Result<()> {
+        let mut chunk = [0u8; 4096];
+        match self.stream.read(&mut chunk).await? {
+            0 => Err(io::ErrorKind::UnexpectedEof.into()),
+            len => {
+                self.incoming.extend_from_slice(&chunk[..len]);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Transport for EncryptedTransport {
+    async fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        if frame.len() + CHACHA20_POLY1305.tag_len() > MAX_MESSAGE_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "frame is too long to encrypt",
+            ));
+        }
+        let mut message = frame.to_vec();
+        let nonce = self.sealing.next_nonce()?;
+        self.sealing
+            .key
+            .seal_in_place_append_tag(nonce, Aad::empty(), &mut message)
+            .map_err(|_| io::Error::other("frame failed to encrypt"))?;
+        let mut buffer = Vec::with_capacity(2 + message.len());
+        buffer.extend_from_slice(&(message.len() as u16).to_be_bytes());
+        buffer.extend_from_slice(&message);
+        self.stream.write_all(&buffer).await
+    }
+
+    async fn recv(&mut self) -> io::Result<Vec<u8>> {
+        loop {
+            if let Some(frame) = self.take_message()? {
+                return Ok(frame);
+            }
+            self.fill().await?;
+        }
+    }
+
+    /// The api key challenge is answered before frames are encrypted.
+    fn is_secure(&self) -> bool {
+        false
+    }
+}
+
+/// Handle an encrypted plain TCP connection for clients which cannot use TLS.
+/// The client is authenticated with [`session::authenticate`] using `config.api_keys` exactly like a TCP client.
+/// Afterwards, every frame in either direction is encrypted with ChaCha20-Poly1305 (see [`EncryptedTransport`])
+/// using a key per direction derived with HKDF-SHA256 from the API key, salted with the authentication nonce.
+/// Then perform the handshake and send events with [`session::run`], one encrypted message per frame.
+/// Keys stored as a hash cannot be used because the server does not know the key to derive from.
+pub async fn handle_connection(
+    stream: TcpStream,
+    config: &ServerConfig,
+    receiver: Receiver<EventBatch>,
+    commands: &Sender<ControlMessage>,
+) {
+    let (client, ip_address) =
+        session::connection_established("Encrypted Client", stream.peer_addr());
+
+    let mut transport =
+        TimeoutTransport::new(StreamTransport::new(tls::Stream::Plain(stream)), config);
+    let Some((api_key, nonce)) =
+        session::accept_client(&mut transport, &client, ip_address, config).await
+    else {
+        return;
+    };
+    let Secret::Plain { key } = &api_key.secret else {
+        warn!("[{client}] Keys stored as a hash cannot encrypt frames. Disconnecting.");
+        return;
+    };
+
+    let (tls::Stream::Plain(stream), incoming) = transport.into_inner().into_inner() else {
+        unreachable!("the transport was created with a plain stream");
+    };
+    let mut transport = TimeoutTransport::new(
+        EncryptedTransport {
+            stream,
+            sealing: Cipher::new(key.as_bytes(), &nonce, SERVER_KEY_INFO),
+            opening: Cipher::new(key.as_bytes(), &nonce, CLIENT_KEY_INFO),
+            incoming,
+        },
+        config,
+    );
+    transport.read_timeout = None;
+    session::run(
+        transport,
+        &format!("{client} ({})", api_key.name),
+        Some(&api_key),
+        receiver,
+        commands,
+        config,
+    )
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    const API_KEY: &[u8] = b"secret";
+    const NONCE: [u8; NONCE_LEN] = [7; NONCE_LEN];
+
+    /// A server transport and the client end of its connection.
+    async fn connect() -> (EncryptedTransport, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let transport = EncryptedTransport {
+            stream,
+            sealing: Cipher::new(API_KEY, &NONCE, SERVER_KEY_INFO),
+            opening: Cipher::new(API_KEY, &NONCE, CLIENT_KEY_INFO),
+            incoming: Vec::new(),
+        };
+        (transport, client)
+    }
+
+    /// `frame` as sent by a client encrypting with `cipher`.
+    fn seal(cipher: &mut Cipher, frame: &[u8]) -> Vec<u8> {
+        let mut message = frame.to_vec();
+        let nonce = cipher.next_nonce().unwrap();
+        cipher
+            .key
+            .seal_in_place_append_tag(nonce, Aad::empty(), &mut message)
+            .unwrap();
+        let mut buffer = (message.len() as u16).to_be_bytes().to_vec();
+        buffer.extend_from_slice(&message);
+        buffer
+    }
+
+    #[tokio::test]
+    async fn opens_messages_sealed_by_client() {
+        let (mut transport, mut client) = connect().await;
+        let mut sealing = Cipher::new(API_KEY, &NONCE, CLIENT_KEY_INFO);
+        let mut messages = seal(&mut sealing, b"first");
+        messages.extend(seal(&mut sealing, b""));
+        messages.extend(seal(&mut sealing, b"third"));
+        client.write_all(&messages).await.unwrap();
+        assert_eq!(transport.recv().await.unwrap(), b"first");
+        assert_eq!(transport.recv().await.unwrap(), b"");
+        assert_eq!(transport.recv().await.unwrap(), b"third");
+    }
+
+    #[tokio::test]
+    async fn seals_messages_opened_by_client() {
+        let (mut transport, mut client) = connect().await;
+        transport.send(b"frame").await.unwrap();
+        let mut prefix = [0u8; 2];
+        client.read_exact(&mut prefix).await.unwrap();
+        let mut message = vec![0u8; u16::from_be_bytes(prefix) as usize];
+        client.read_exact(&mut message).await.unwrap();
+        let mut opening = Cipher::new(API_KEY, &NONCE, SERVER_KEY_INFO);
+        let nonce = opening.next_nonce().unwrap();
+        let frame = opening
+            .key
+            .open_in_place(nonce, Aad::empty(), &mut message)
+            .unwrap();
+        assert_eq!(frame, b"frame");
+    }
+
+    #[tokio::test]
+    async fn waits_for_message_split_across_reads() {
+        let (mut transport, mut client) = connect().await;
+        let message = seal(&mut Cipher::new(API_KEY, &NONCE, CLIENT_KEY_INFO), b"frame");
+        for split in [1, 2, message.len() - 1] {
+            transport.incoming = message[..split].to_vec();
+            assert!(transport.take_message().unwrap().is_none());
+        }
+        client
+            .write_all(&message[message.len() - 1..])
+            .await
+            .unwrap();
+        assert_eq!(transport.recv().await.unwrap(), b"frame");
+    }
+
+    #[tokio::test]
+    async fn rejects_tampered_tag() {
+        let (mut transport, _client) = connect().await;
+        let mut message = seal(&mut Cipher::new(API_KEY, &NONCE, CLIENT_KEY_INFO), b"frame");
+        *message.last_mut().unwrap() ^= 1;
+        transport.incoming = message;
+        let error = transport.take_message().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn rejects_message_shorter_than_tag() {
+        let (mut transport, _client) = connect().await;
+        transport.incoming = vec![0, 4, 1, 2, 3, 4];
+        let error = transport.take_message().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,58 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// An error which keeps the server (or one of its listeners) from starting.
+#[derive(thiserror::Error)]
+pub enum Error {
+    /// The configuration file could not be read. A default configuration was installed in its place
+    /// if it did not exist.
+    #[error("unable to read configuration file \"{}\": {source}", path.display())]
+    ReadConfig { path: PathBuf, source: io::Error },
+    /// The configuration file is not valid TOML or does not match [`crate::config::Config`].
+    #[error("unable to load configuration file: {0}")]
+    ParseConfig(String),
+    /// A setting is invalid, e.g., an unknown key or a setting missing another.
+    #[error("{0}")]
+    Config(String),
+    /// A listener could not be bound to `address`.
+    #[error("unable to bind {listener} listener on {address}: {source}")]
+    Bind {
+        listener: &'static str,
+        address: String,
+        source: io::Error,
+    },
+    /// Any other failed I/O, described by `context`.
+    #[error("{context}: {source}")]
+    Io { context: String, source: io::Error },
+    /// Another server started with `serve --daemon` is running.
+    #[error("already running with PID {0}")]
+    AlreadyRunning(i32),
+}
+
+impl Error {
+    /// Wrap an error in [`Error::Io`] with `context`, e.g., `.map_err(Error::io("unable to open audit_log"))`.
+    pub fn io(context: impl Into<String>) -> impl FnOnce(io::Error) -> Self {
+        let context = context.into();
+        move |source| Self::Io { context, source }
+    }
+
+    /// Wrap an error in [`Error::Bind`] for the `listener` on `address`.
+    pub fn bind(listener: &'static str, address: &str) -> impl FnOnce(io::Error) -> Self {
+        let address = address.to_string();
+        move |source| Self::Bind {
+            listener,
+            address,
+            source,
+        }
+    }
+}
+
+/// Shows the message, so that `main` prints it when it returns an error.
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
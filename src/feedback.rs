@@ -0,0 +1,144 @@
+use evdev::{Device, EventType, InputEvent, SoundType};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+
+/// How long the PC speaker beeps.
+const BEEP_DURATION: Duration = Duration::from_millis(100);
+
+/// Ways to show a change of the grab and pause state besides the keyboard LEDs, e.g., for mice.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedbackBackend {
+    /// Write the bell character to the terminal.
+    Bell,
+    /// Beep with the first device which can play tones (`EV_SND`), usually the "PC Speaker".
+    Speaker,
+    /// Show a desktop notification with `notify-send` (libnotify).
+    Notification,
+}
+
+/// A change of the state of a device.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum StateChange {
+    Grabbed,
+    Ungrabbed,
+    Paused,
+    Unpaused,
+}
+
+impl StateChange {
+    /// The name of the state, e.g., "grabbed".
+    fn name(self) -> &'static str {
+        match self {
+            StateChange::Grabbed => "grabbed",
+            StateChange::Ungrabbed => "ungrabbed",
+            StateChange::Paused => "paused",
+            StateChange::Unpaused => "unpaused",
+        }
+    }
+
+    /// The pitch of the beep in Hz, higher when events are forwarded.
+    fn tone(self) -> i32 {
+        match self {
+            StateChange::Grabbed | StateChange::Unpaused => 880,
+            StateChange::Ungrabbed | StateChange::Paused => 440,
+        }
+    }
+}
+
+/// Shows state changes of the device `device_name` with every backend in `backends` and by running `command`.
+pub struct Feedback {
+    device_name: String,
+    backends: Vec<FeedbackBackend>,
+    command: Option<String>,
+    /// The device playing beeps, if [`FeedbackBackend::Speaker`] is used and one was found.
+    speaker: Option<PathBuf>,
+}
+
+impl Feedback {
+    pub fn new(device_name: &str, backends: &[FeedbackBackend], command: Option<&str>) -> Self {
+        let speaker = if backends.contains(&FeedbackBackend::Speaker) {
+            let speaker = evdev::enumerate()
+                .find(|(_, device)| {
+                    device
+                        .supported_sounds()
+                        .is_some_and(|sounds| sounds.contains(SoundType::SND_TONE))
+                })
+                .map(|(path, _)| path);
+            if speaker.is_none() {
+                warn!("[Feedback] No device can play tones. Not beeping.");
+            }
+            speaker
+        } else {
+            None
+        };
+        Self {
+            device_name: device_name.to_string(),
+            backends: backends.to_vec(),
+            command: command.map(str::to_string),
+            speaker,
+        }
+    }
+
+    /// Show `change` without waiting for slow backends.
+    pub fn show(&self, change: StateChange) {
+        for backend in &self.backends {
+            let result = match backend {
+                FeedbackBackend::Bell => io::stdout().write_all(b"\x07").and(io::stdout().flush()),
+                FeedbackBackend::Speaker => {
+                    if let Some(speaker) = self.speaker.clone() {
+                        thread::spawn(move || beep(&speaker, change.tone()));
+                    }
+                    Ok(())
+                }
+                FeedbackBackend::Notification => {
+                    spawn(Command::new("notify-send").arg("remote-input").arg(format!(
+                        "{} {}.",
+                        self.device_name,
+                        change.name()
+                    )))
+                }
+            };
+            if let Err(error) = result {
+                warn!("[Feedback] {backend:?} failed: {error}.");
+            }
+        }
+        if let Some(command) = &self.command {
+            let result = spawn(
+                Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .env("REMOTE_INPUT_DEVICE", &self.device_name)
+                    .env("REMOTE_INPUT_STATE", change.name()),
+            );
+            if let Err(error) = result {
+                warn!("[Feedback] Failed to run \"{command}\": {error}.");
+            }
+        }
+    }
+}
+
+/// Start `command` and wait for it in another thread so that it does not linger as a zombie.
+fn spawn(command: &mut Command) -> io::Result<()> {
+    let mut child = command.spawn()?;
+    thread::spawn(move || child.wait());
+    Ok(())
+}
+
+/// Play a tone of `frequency` Hz for [`BEEP_DURATION`] with the device at `path`.
+fn beep(path: &PathBuf, frequency: i32) {
+    let result = Device::open(path).and_then(|mut speaker| {
+        let tone = SoundType::SND_TONE.0;
+        speaker.send_events(&[InputEvent::new(EventType::SOUND, tone, frequency)])?;
+        thread::sleep(BEEP_DURATION);
+        speaker.send_events(&[InputEvent::new(EventType::SOUND, tone, 0)])
+    });
+    if let Err(error) = result {
+        warn!("[Feedback] Failed to beep: {error}.");
+    }
+}
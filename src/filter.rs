@@ -0,0 +1,125 @@
+use crate::codes;
+use evdev::{EventType, InputEvent};
+use std::collections::HashSet;
+
+/// Decides which events are forwarded, by the names of their key code (e.g., "KEY_POWER") or event
+/// type (e.g., "EV_REL"). Synchronization events are always forwarded, so that reports stay complete.
+pub struct EventFilter {
+    /// The key codes and event types which are forwarded, or `None` to forward all which are not
+    /// blocked.
+    allowed: Option<(HashSet<u16>, HashSet<u16>)>,
+    /// The key codes and event types which are never forwarded.
+    blocked: (HashSet<u16>, HashSet<u16>),
+}
+
+impl EventFilter {
+    /// Create the filter forwarding only the keys and event types named in `allow` (if any) which
+    /// are not named in `block`. Fails with the first unknown name.
+    pub fn new(allow: Option<&[String]>, block: &[String]) -> Result<Self, String> {
+        Ok(Self {
+            allowed: allow.map(parse_names).transpose()?,
+            blocked: parse_names(block)?,
+        })
+    }
+
+    /// Whether `event` is forwarded.
+    pub fn forwards(&self, event: &InputEvent) -> bool {
+        event.event_type() == EventType::SYNCHRONIZATION
+            || (!matches(&self.blocked, event)
+                && self
+                    .allowed
+                    .as_ref()
+                    .is_none_or(|allowed| matches(allowed, event)))
+    }
+
+    /// Whether key events of `code` are forwarded.
+    pub fn forwards_key(&self, code: u16) -> bool {
+        self.forwards(&InputEvent::new(EventType::KEY, code, 1))
+    }
+}
+
+/// Whether `event` is a key event of one of the key codes or of one of the event types in `names`.
+fn matches((keys, event_types): &(HashSet<u16>, HashSet<u16>), event: &InputEvent) -> bool {
+    event_types.contains(&event.event_type().0)
+        || (event.event_type() == EventType::KEY && keys.contains(&event.code()))
+}
+
+/// Split `names` into key codes and event types.
+fn parse_names(names: &[String]) -> Result<(HashSet<u16>, HashSet<u16>), String> {
+    let (mut keys, mut event_types) = (HashSet::new(), HashSet::new());
+    for name in names {
+        if let Some(event_type) = codes::event_type_code(name) {
+            event_types.insert(event_type);
+        } else if let Some(code) = codes::key_code(name) {
+            keys.insert(code);
+        } else {
+            return Err(name.clone());
+        }
+    }
+    Ok((keys, event_types))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(ToString::to_string).collect()
+    }
+
+    fn key(code: evdev::Key) -> InputEvent {
+        InputEvent::new(EventType::KEY, code.code(), 1)
+    }
+
+    fn motion() -> InputEvent {
+        InputEvent::new(EventType::RELATIVE, evdev::RelativeAxisType::REL_X.0, 1)
+    }
+
+    fn sync() -> InputEvent {
+        InputEvent::new(EventType::SYNCHRONIZATION, 0, 0)
+    }
+
+    #[test]
+    fn forwards_only_allowed() {
+        let filter = EventFilter::new(Some(&names(&["KEY_A", "EV_REL"])), &[]).unwrap();
+        assert!(filter.forwards(&key(evdev::Key::KEY_A)));
+        assert!(filter.forwards(&motion()));
+        assert!(!filter.forwards(&key(evdev::Key::KEY_B)));
+        assert!(filter.forwards(&sync()));
+    }
+
+    #[test]
+    fn forwards_all_but_blocked() {
+        let filter = EventFilter::new(None, &names(&["KEY_POWER", "EV_REL"])).unwrap();
+        assert!(!filter.forwards_key(evdev::Key::KEY_POWER.code()));
+        assert!(!filter.forwards(&motion()));
+        assert!(filter.forwards(&key(evdev::Key::KEY_A)));
+        assert!(filter.forwards(&sync()));
+    }
+
+    #[test]
+    fn blocking_wins_over_allowing() {
+        let filter = EventFilter::new(Some(&names(&["EV_KEY"])), &names(&["KEY_POWER"])).unwrap();
+        assert!(filter.forwards(&key(evdev::Key::KEY_A)));
+        assert!(!filter.forwards(&key(evdev::Key::KEY_POWER)));
+        assert!(!filter.forwards(&motion()));
+    }
+
+    #[test]
+    fn synchronization_cannot_be_blocked() {
+        let filter = EventFilter::new(None, &names(&["EV_SYN"])).unwrap();
+        assert!(filter.forwards(&sync()));
+    }
+
+    #[test]
+    fn rejects_unknown_names() {
+        assert_eq!(
+            EventFilter::new(None, &names(&["KEY_A", "KEY_NOPE"])).err(),
+            Some("KEY_NOPE".to_string())
+        );
+        assert_eq!(
+            EventFilter::new(Some(&names(&["EV_NOPE"])), &[]).err(),
+            Some("EV_NOPE".to_string())
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use crate::protocol::{ForceFeedbackEffect, ForceFeedbackKind, Waveform};
+use evdev::{
+    Device, FFEffect, FFEffectData, FFEffectKind, FFEnvelope, FFReplay, FFTrigger, FFWaveform,
+};
+use std::collections::HashMap;
+use std::io;
+
+/// The force feedback effects uploaded to a device by clients, by their slot.
+/// Effects are removed from the device when they are erased or dropped.
+#[derive(Default)]
+pub struct ForceFeedback {
+    effects: HashMap<u8, FFEffect>,
+}
+
+impl ForceFeedback {
+    /// Upload `effect` to `device` in `slot`, replacing the effect already in it.
+    pub fn upload(
+        &mut self,
+        device: &mut Device,
+        slot: u8,
+        effect: ForceFeedbackEffect,
+    ) -> io::Result<()> {
+        let data = effect_data(effect);
+        if let Some(uploaded) = self.effects.get_mut(&slot) {
+            return uploaded.update(data);
+        }
+        self.effects.insert(slot, device.upload_ff_effect(data)?);
+        Ok(())
+    }
+
+    /// Play the effect in `slot` `count` times, or stop it if `count` is 0.
+    pub fn play(&mut self, slot: u8, count: i32) -> io::Result<()> {
+        let effect = self
+            .effects
+            .get_mut(&slot)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no effect in this slot"))?;
+        match count {
+            0 => effect.stop(),
+            count => effect.play(count),
+        }
+    }
+
+    /// Remove the effect in `slot` from the device.
+    pub fn erase(&mut self, slot: u8) {
+        self.effects.remove(&slot);
+    }
+
+    /// Forget every effect, e.g., after the device was removed.
+    pub fn clear(&mut self) {
+        self.effects.clear();
+    }
+}
+
+/// Convert `effect` to the representation of [`evdev`].
+fn effect_data(effect: ForceFeedbackEffect) -> FFEffectData {
+    let envelope = FFEnvelope {
+        attack_length: 0,
+        attack_level: 0,
+        fade_length: 0,
+        fade_level: 0,
+    };
+    let kind = match effect.kind {
+        ForceFeedbackKind::Rumble {
+            strong_magnitude,
+            weak_magnitude,
+        } => FFEffectKind::Rumble {
+            strong_magnitude,
+            weak_magnitude,
+        },
+        ForceFeedbackKind::Constant { level } => FFEffectKind::Constant { level, envelope },
+        ForceFeedbackKind::Periodic {
+            waveform,
+            period_millis,
+            magnitude,
+            offset,
+            phase,
+        } => FFEffectKind::Periodic {
+            waveform: match waveform {
+                Waveform::Square => FFWaveform::Square,
+                Waveform::Triangle => FFWaveform::Triangle,
+                Waveform::Sine => FFWaveform::Sine,
+                Waveform::SawUp => FFWaveform::SawUp,
+                Waveform::SawDown => FFWaveform::SawDown,
+            },
+            period: period_millis,
+            magnitude,
+            offset,
+            phase,
+            envelope,
+        },
+    };
+    FFEffectData {
+        direction: effect.direction,
+        trigger: FFTrigger::default(),
+        replay: FFReplay {
+            length: effect.length_millis,
+            delay: effect.delay_millis,
+        },
+        kind,
+    }
+}
@@ -0,0 +1,271 @@
+use crate::broadcast::Receiver;
+use crate::config::{ApiKey, ClientSlot, ServerConfig};
+use crate::error::Error;
+use crate::protocol;
+use crate::protocol::InputEventWrapper;
+use crate::server::{spawn_connection, EventBatch, EventBus};
+use remote_input_server::{RemoteInput, RemoteInputServer};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::metadata::MetadataValue;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::{Identity, Server, ServerTlsConfig};
+use tonic::{Request, Response, Status};
+use tracing::{info, warn};
+
+// The service generated by `build.rs`.
+include!(concat!(env!("OUT_DIR"), "/remote_input.RemoteInput.rs"));
+
+/// How many events are buffered for a client before the sender waits for it.
+const STREAM_CAPACITY: usize = 100;
+
+/// How often a stream checks whether its API key was revoked while no events arrive, if
+/// `disconnect_revoked_clients` is set.
+const REVOCATION_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The request of `StreamEvents`. It has no fields; the API key is sent as metadata.
+/// See `proto/remote_input.proto`.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct StreamEventsRequest {}
+
+/// The proto message equivalent of [`InputEventWrapper`].
+/// See `proto/remote_input.proto`.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct InputEvent {
+    #[prost(uint64, tag = "1")]
+    pub timestamp_seconds: u64,
+    #[prost(uint32, tag = "2")]
+    pub timestamp_nanos: u32,
+    #[prost(uint32, tag = "3")]
+    pub event_type: u32,
+    #[prost(uint32, tag = "4")]
+    pub code: u32,
+    #[prost(sint32, tag = "5")]
+    pub value: i32,
+}
+
+impl From<InputEventWrapper> for InputEvent {
+    fn from(event: InputEventWrapper) -> Self {
+        Self {
+            timestamp_seconds: event.timestamp.secs_since_epoch,
+            timestamp_nanos: event.timestamp.nanos_since_epoch,
+            event_type: event.event_type.into(),
+            code: event.code.into(),
+            value: event.value,
+        }
+    }
+}
+
+/// Serves `StreamEvents` calls by adding a receiver to `event_bus` for each one
+/// and forwarding its events with [`spawn_connection`], so that each call is counted
+/// against `max_connections` like a connection.
+struct RemoteInputService {
+    config: Arc<ServerConfig>,
+    /// Whether calls are answered with TLS, so that hashed API keys may be sent.
+    tls: bool,
+    device_name: String,
+    event_bus: EventBus,
+}
+
+#[tonic::async_trait]
+impl RemoteInput for RemoteInputService {
+    type StreamEventsStream = ReceiverStream<Result<InputEvent, Status>>;
+
+    async fn stream_events(
+        &self,
+        request: Request<StreamEventsRequest>,
+    ) -> Result<Response<Self::StreamEventsStream>, Status> {
+        let peer = match request.remote_addr() {
+            Some(address) => address.to_string(),
+            None => "UNKNOWN ADDRESS".to_string(),
+        };
+        let client = format!("gRPC Client {peer}");
+        if let Some(address) = request.remote_addr() {
+            if let Some(reason) = self.config.check_connection(address, "gRPC") {
+                warn!("[{client}] Rejected: {reason}.");
+                return Err(Status::permission_denied(reason));
+            }
+        }
+        let Some(connection_slot) = self.config.acquire_connection_slot() else {
+            warn!(
+                "[{client}] Rejected: {} connections are being handled.",
+                self.config.max_connections
+            );
+            return Err(Status::resource_exhausted("too many connections"));
+        };
+        info!("[{client}] Call established.");
+
+        // Validate the "api-key" metadata against `api_keys`.
+        let client_key = request
+            .metadata()
+            .get("api-key")
+            .map(|key| key.as_bytes())
+            .unwrap_or_default();
+        let address = request.remote_addr().map(|address| address.ip());
+        let mut api_key =
+            crate::server::find_api_key(client_key, self.tls, &self.config, &client, address).await;
+        // With TOTP, the "totp-code" metadata must hold the current code.
+        if let (Some(totp), Some(_)) = (&self.config.totp, &api_key) {
+            let code = request
+                .metadata()
+                .get("totp-code")
+                .map(|code| code.as_bytes())
+                .unwrap_or_default();
+            if !totp.verify(code) {
+                warn!("[{client}] Invalid TOTP code.");
+                api_key = None;
+            }
+        }
+        self.config
+            .record_authentication(&client, address, api_key.as_ref());
+        let Some(api_key) = api_key else {
+            warn!("[{client}] Invalid API key.");
+            return Err(Status::unauthenticated("invalid API key or TOTP code"));
+        };
+        info!("[{client}] Authenticated as \"{}\".", api_key.name);
+        let client = format!("{client} ({})", api_key.name);
+        let Some(slot) = self.config.acquire_client_slot() else {
+            warn!("[{client}] Rejected: server busy.");
+            return Err(Status::resource_exhausted("server busy"));
+        };
+        let config = Arc::clone(&self.config);
+
+        let receiver = self.event_bus.subscribe();
+        let (sender, stream) = mpsc::channel(STREAM_CAPACITY);
+        spawn_connection(peer, connection_slot, async move {
+            // The client is counted until the call ends.
+            forward_events(&client, &api_key, &config, &slot, receiver, &sender).await;
+        });
+
+        let mut response = Response::new(ReceiverStream::new(stream));
+        if let Ok(device_name) = MetadataValue::try_from(self.device_name.as_str()) {
+            response.metadata_mut().insert("device-name", device_name);
+        }
+        Ok(response)
+    }
+}
+
+/// Send events of the types allowed for `api_key` from `receiver` to `sender` until the client
+/// cancels the call or events can no longer be received from `receiver`.
+/// If `config.disconnect_revoked_clients` is set, the call also ends once `api_key` is revoked.
+/// Events are skipped while another client than `slot` is selected with a KVM hotkey.
+async fn forward_events(
+    client: &str,
+    api_key: &ApiKey,
+    config: &ServerConfig,
+    slot: &ClientSlot,
+    mut receiver: Receiver<EventBatch>,
+    sender: &mpsc::Sender<Result<InputEvent, Status>>,
+) {
+    loop {
+        if config.disconnect_revoked_clients && !config.api_keys.contains(api_key) {
+            info!("[{client}] API key was revoked. Ending call.");
+            let _ = sender
+                .send(Err(Status::unauthenticated("API key revoked")))
+                .await;
+            return;
+        }
+        let event = tokio::select! {
+            event = receiver.recv() => event,
+            () = sender.closed() => {
+                info!("[{client}] Call cancelled.");
+                return;
+            }
+            () = tokio::time::sleep(REVOCATION_POLL_INTERVAL), if config.disconnect_revoked_clients => continue,
+        };
+        match event {
+            Some(_) if !slot.receives() => {}
+            Some(events) => {
+                for event in protocol::split_batch(&events.events) {
+                    let event = match protocol::decode_event(event) {
+                        Ok(event) => event,
+                        Err(error) => {
+                            warn!("[{client}] Failed to deserialize event: {error}.");
+                            continue;
+                        }
+                    };
+                    if !api_key.permissions.allows_event_type(event.event_type) {
+                        continue;
+                    }
+                    if sender.send(Ok(event.into())).await.is_err() {
+                        info!("[{client}] Call cancelled.");
+                        return;
+                    }
+                }
+            }
+            None => {
+                info!("[{client}] Server is shutting down. Ending call.");
+                let _ = sender
+                    .send(Err(Status::unavailable("server shutting down")))
+                    .await;
+                return;
+            }
+        }
+    }
+}
+
+/// A gRPC server bound by [`bind`], to be started with [`serve`].
+pub struct Listener {
+    server: Server,
+    incoming: TcpIncoming,
+    tls: bool,
+}
+
+/// Bind the gRPC server to `address`. Calls are answered with TLS if `tls_identity` (a PEM encoded
+/// certificate chain and private key) is set.
+/// Must be called in the context of the tokio runtime serving the calls.
+pub fn bind(
+    address: SocketAddr,
+    tls_identity: Option<(Vec<u8>, Vec<u8>)>,
+) -> crate::error::Result<Listener> {
+    let mut server = Server::builder();
+    let tls = tls_identity.is_some();
+    if let Some((certificate, private_key)) = tls_identity {
+        server = server
+            .tls_config(
+                ServerTlsConfig::new().identity(Identity::from_pem(certificate, private_key)),
+            )
+            .map_err(|error| {
+                Error::Config(format!("unable to load gRPC TLS configuration: {error}"))
+            })?;
+    }
+    // The same options as `Server::serve`.
+    let incoming = TcpIncoming::new(address, false, None)
+        .map_err(|error| Error::bind("gRPC", &address.to_string())(io::Error::other(error)))?;
+    Ok(Listener {
+        server,
+        incoming,
+        tls,
+    })
+}
+
+/// Serve the `RemoteInput` gRPC service on `listener` forever.
+/// The initial response metadata of each call holds `device_name`.
+pub async fn serve(
+    listener: Listener,
+    config: Arc<ServerConfig>,
+    device_name: String,
+    event_bus: EventBus,
+) -> crate::error::Result<()> {
+    let Listener {
+        mut server,
+        incoming,
+        tls,
+    } = listener;
+    let service = RemoteInputService {
+        config,
+        tls,
+        device_name,
+        event_bus,
+    };
+    server
+        .add_service(RemoteInputServer::new(service))
+        .serve_with_incoming(incoming)
+        .await
+        .map_err(io::Error::other)
+        .map_err(Error::io("unable to serve gRPC"))
+}
@@ -0,0 +1,107 @@
+use crate::broadcast::Receiver;
+use crate::config::ServerConfig;
+use crate::server::EventBatch;
+use crate::{protocol, session, tls};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tracing::{info, warn};
+
+/// Handle a JSON lines debug connection, which may be wrapped in TLS.
+/// After receiving a newline terminated UTF-8 encoded string matching one of `config.api_keys`,
+/// send each event of a type permitted for that key from `receiver` as a JSON object followed by a newline until the client
+/// disconnects or events can no longer be received from `receiver`.
+/// If `config.disconnect_revoked_clients` is set, the connection is closed at the next event once
+/// the key is revoked.
+/// There is no handshake, so the stream can be consumed with `nc` and `jq`.
+/// Until the client is authenticated, the connection is closed if a read takes longer than
+/// `config.auth_timeout_millis`, and afterwards if sending events takes longer than `config.write_timeout_millis`.
+pub async fn handle_connection(
+    stream: tls::Stream,
+    config: &ServerConfig,
+    mut receiver: Receiver<EventBatch>,
+) {
+    let (client, ip_address) = session::connection_established("JSON Client", stream.peer_addr());
+    let mut buffer_reader = BufReader::new(stream);
+
+    // Receive a newline terminated UTF-8 encoded string from the client and validate it against `config.api_keys`.
+    // If `config.totp` is set, it must be followed by a line holding the current TOTP code.
+    let mut client_key = Vec::new();
+    let read = buffer_reader.read_until(b'\n', &mut client_key);
+    if let Err(error) = session::timeout(config.auth_timeout(), read).await {
+        warn!("[{client}] Failed to read bytes: {error}.");
+        return;
+    }
+    let mut api_key =
+        crate::server::find_api_key(&client_key, false, config, &client, ip_address).await;
+    if api_key.is_none() {
+        warn!("[{client}] Invalid API key.");
+    } else if let Some(totp) = &config.totp {
+        let mut code = String::new();
+        let read = buffer_reader.read_line(&mut code);
+        if session::timeout(config.auth_timeout(), read).await.is_err()
+            || !totp.verify(code.trim_end().as_bytes())
+        {
+            warn!("[{client}] Invalid TOTP code.");
+            api_key = None;
+        }
+    }
+    config.record_authentication(&client, ip_address, api_key.as_ref());
+    let Some(api_key) = api_key else {
+        return;
+    };
+    info!("[{client}] Authenticated as \"{}\".", api_key.name);
+    let client = format!("{client} ({})", api_key.name);
+    let mut stream = buffer_reader.into_inner();
+
+    // The client is counted until this function returns.
+    let Some(slot) = config.acquire_client_slot() else {
+        warn!("[{client}] Rejected: server busy.");
+        let write = stream.write_all(b"{\"error\":\"server busy\"}\n");
+        let _ = session::timeout(config.write_timeout(), write).await;
+        return;
+    };
+
+    // Transmit events received from `receiver` to the client, one JSON object per line.
+    loop {
+        let event = receiver.recv().await;
+        if config.disconnect_revoked_clients && !config.api_keys.contains(&api_key) {
+            info!("[{client}] API key was revoked. Disconnecting.");
+            return;
+        }
+        match event {
+            // Only the client selected with a KVM hotkey receives events, if one is selected.
+            Some(_) if !slot.receives() => {}
+            Some(events) => {
+                let mut lines = Vec::new();
+                for event in protocol::split_batch(&events.events) {
+                    let event = match protocol::decode_event(event) {
+                        Ok(event) => event,
+                        Err(error) => {
+                            warn!("[{client}] Failed to deserialize event: {error}.");
+                            continue;
+                        }
+                    };
+                    if !api_key.permissions.allows_event_type(event.event_type) {
+                        continue;
+                    }
+                    if let Err(error) = serde_json::to_writer(&mut lines, &event) {
+                        warn!("[{client}] Failed to serialize event: {error}.");
+                        continue;
+                    }
+                    lines.push(b'\n');
+                }
+                let write = async {
+                    stream.write_all(&lines).await?;
+                    stream.flush().await
+                };
+                if let Err(error) = session::timeout(config.write_timeout(), write).await {
+                    warn!("[{client}] Failed to send event: {error}.");
+                    return;
+                }
+            }
+            None => {
+                info!("[{client}] Server is shutting down. Disconnecting.");
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use crate::audit;
+use crate::protocol::Timestamp;
+use std::fs::OpenOptions;
+use std::io;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+/// Appends the latency of clients which answer pings to a file as CSV, one line per answered ping:
+/// the UNIX time in seconds, the client (e.g., "Client 127.0.0.1:50000"), the round trip and the
+/// estimated one-way delivery in milliseconds. Records nothing if no file was opened. Clones share
+/// the file.
+#[derive(Clone, Default)]
+pub struct LatencyLog(Option<Sender<String>>);
+
+impl LatencyLog {
+    /// Open the file at `path` for appending, creating it if it does not exist.
+    pub fn open(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        Ok(Self(Some(audit::spawn_writer(file, "Latency Log"))))
+    }
+
+    /// Log and append the latency of `client` measured with a ping it received at `received` (by its
+    /// clock), which was sent at `sent` and answered after `round_trip`.
+    pub fn record(&self, client: &str, round_trip: Duration, sent: Timestamp, received: Timestamp) {
+        let round_trip = round_trip.as_secs_f64() * 1000.0;
+        // The difference of two clocks, so it is only meaningful if they are synchronized.
+        let delivery =
+            (received.since_epoch().as_secs_f64() - sent.since_epoch().as_secs_f64()) * 1000.0;
+        debug!("[{client}] Round trip {round_trip:.3} ms, delivery {delivery:.3} ms.");
+        let Some(lines) = &self.0 else {
+            return;
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let _ = lines.send(format!(
+            "{:.3},{client},{round_trip:.3},{delivery:.3}\n",
+            now.as_secs_f64()
+        ));
+    }
+}
@@ -0,0 +1,114 @@
+//! Sends events from attached input devices over the network to compatible clients.
+//!
+//! The [`protocol`] module describes the wire format (events, framing and the handshake) and is
+//! available on every platform, so that clients can be written against it, as is the [`client`]
+//! receiving events from a server. The server itself ([`config`] and [`server`]) reads devices
+//! with evdev and is only available on Linux.
+//!
+//! A minimal server:
+//! ```no_run
+//! # #[cfg(target_os = "linux")]
+//! # {
+//! let data = std::fs::read_to_string("config.toml").unwrap();
+//! let config = remote_input::config::parse_config(&data).unwrap();
+//! remote_input::server::run(config, "config.toml".into()).unwrap();
+//! # }
+//! ```
+
+#[cfg(target_os = "linux")]
+mod as_hex;
+#[cfg(target_os = "linux")]
+mod audit;
+#[cfg(target_os = "linux")]
+mod auth_limiter;
+/// The channel carrying event batches from the devices to every connection, available on every
+/// platform.
+pub mod broadcast;
+/// Validation of a configuration beyond parsing it, for `remote-input check-config`.
+#[cfg(target_os = "linux")]
+pub mod check;
+/// A client receiving the events of a server, available on every platform.
+pub mod client;
+/// The names of Linux input event codes, available on every platform.
+pub mod codes;
+/// The configuration read from config.toml.
+#[cfg(target_os = "linux")]
+pub mod config;
+/// Running the server in the background with a PID file.
+#[cfg(target_os = "linux")]
+pub mod daemon;
+#[cfg(target_os = "linux")]
+mod dial_out;
+#[cfg(target_os = "linux")]
+mod encrypted;
+/// Errors which keep the server from starting.
+#[cfg(target_os = "linux")]
+pub mod error;
+#[cfg(target_os = "linux")]
+mod feedback;
+#[cfg(target_os = "linux")]
+mod filter;
+#[cfg(target_os = "linux")]
+mod force_feedback;
+#[cfg(all(target_os = "linux", feature = "grpc"))]
+mod grpc;
+#[cfg(target_os = "linux")]
+mod json_lines;
+#[cfg(target_os = "linux")]
+mod latency;
+/// Logging with `tracing`, configured in the `[log]` section of config.toml.
+#[cfg(target_os = "linux")]
+pub mod logging;
+#[cfg(target_os = "linux")]
+mod macros;
+#[cfg(all(target_os = "linux", feature = "mqtt"))]
+mod mqtt;
+#[cfg(target_os = "linux")]
+mod multicast;
+#[cfg(target_os = "linux")]
+mod multitouch;
+#[cfg(target_os = "linux")]
+mod noise;
+/// Diagnostics for devices which cannot be opened, and udev rules granting access to them.
+#[cfg(target_os = "linux")]
+pub mod permissions;
+/// The wire format: events, framing, the handshake and control messages.
+pub mod protocol;
+#[cfg(all(target_os = "linux", feature = "quic"))]
+mod quic;
+#[cfg(target_os = "linux")]
+mod recording;
+#[cfg(target_os = "linux")]
+mod replay;
+#[cfg(target_os = "linux")]
+mod rfcomm;
+#[cfg(target_os = "linux")]
+mod scaling;
+#[cfg(all(target_os = "linux", feature = "scripting"))]
+mod script;
+#[cfg(target_os = "linux")]
+mod secrets;
+#[cfg(all(target_os = "linux", feature = "serial"))]
+mod serial;
+/// Reading devices and serving their events to clients.
+#[cfg(target_os = "linux")]
+pub mod server;
+#[cfg(target_os = "linux")]
+mod session;
+#[cfg(target_os = "linux")]
+mod shutdown;
+#[cfg(target_os = "linux")]
+mod tap;
+/// A virtual keyboard typing a script, for developing clients.
+#[cfg(target_os = "linux")]
+pub mod test_device;
+#[cfg(target_os = "linux")]
+mod throttle;
+#[cfg(target_os = "linux")]
+mod tls;
+#[cfg(target_os = "linux")]
+mod totp;
+#[cfg(target_os = "linux")]
+mod uevent;
+#[cfg(target_os = "linux")]
+mod websocket;
@@ -0,0 +1,99 @@
+use crate::config::{LogConfig, LogFormat, LogOutput};
+use crate::error::{Error, Result};
+use std::ffi::CString;
+use std::fmt::{self, Write};
+use std::fs::File;
+use std::io::IsTerminal;
+use std::sync::Mutex;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+/// Log to the output of `config` with its filter.
+/// The `RUST_LOG` environment variable replaces `config.level` if it is set.
+pub fn init(config: &LogConfig) -> Result<()> {
+    let level = std::env::var(EnvFilter::DEFAULT_ENV).unwrap_or_else(|_| config.level.clone());
+    let filter = EnvFilter::try_new(&level)
+        .map_err(|error| Error::Config(format!("invalid log level \"{level}\": {error}")))?;
+    let registry = tracing_subscriber::registry().with(filter);
+    match config.output {
+        // Standard output is written in color if it is a terminal.
+        LogOutput::Stdout => {
+            let layer = tracing_subscriber::fmt::layer().with_ansi(std::io::stdout().is_terminal());
+            match config.format {
+                LogFormat::Text => registry.with(layer).try_init(),
+                LogFormat::Json => registry.with(layer.json()).try_init(),
+            }
+        }
+        LogOutput::File => {
+            let path = config.file.as_deref().ok_or_else(|| {
+                Error::Config("output = \"file\" requires the log file".to_string())
+            })?;
+            let file = File::options()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(Error::io(format!("unable to open log file \"{path}\"")))?;
+            let layer = tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(Mutex::new(file));
+            match config.format {
+                LogFormat::Text => registry.with(layer).try_init(),
+                LogFormat::Json => registry.with(layer.json()).try_init(),
+            }
+        }
+        LogOutput::Journald => {
+            let layer = tracing_journald::layer()
+                .map_err(Error::io("unable to connect to journald"))?
+                .with_syslog_identifier("remote-input".to_string());
+            registry.with(layer).try_init()
+        }
+        LogOutput::Syslog => registry.with(Syslog::open()).try_init(),
+    }
+    .map_err(|error| Error::Config(format!("unable to install logger: {error}")))
+}
+
+/// Sends events to syslog with the daemon facility and the priority of their level.
+struct Syslog;
+
+impl Syslog {
+    fn open() -> Self {
+        // SAFETY: The identifier is static, as `openlog` keeps the pointer.
+        unsafe { libc::openlog(c"remote-input".as_ptr(), libc::LOG_PID, libc::LOG_DAEMON) };
+        Self
+    }
+}
+
+impl<S: Subscriber> Layer<S> for Syslog {
+    fn on_event(&self, event: &Event<'_>, _context: Context<'_, S>) {
+        let priority = match *event.metadata().level() {
+            Level::ERROR => libc::LOG_ERR,
+            Level::WARN => libc::LOG_WARNING,
+            Level::INFO => libc::LOG_INFO,
+            Level::DEBUG | Level::TRACE => libc::LOG_DEBUG,
+        };
+        let mut message = Message(String::new());
+        event.record(&mut message);
+        // A zero byte would end the message early.
+        let Ok(message) = CString::new(message.0.replace('\0', "")) else {
+            return;
+        };
+        // SAFETY: The format consumes exactly one string argument.
+        unsafe { libc::syslog(priority, c"%s".as_ptr(), message.as_ptr()) };
+    }
+}
+
+/// The message of an event followed by its other fields as `name=value`.
+struct Message(String);
+
+impl Visit for Message {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.0, "{value:?}");
+        } else {
+            let _ = write!(self.0, " {}={value:?}", field.name());
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use crate::config::MacroConfig;
+use evdev::{EventType, InputEvent};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Expands the trigger keys of macros into their key events, which become due at the times given
+/// by the delays of their steps. Expansions are queued, so that a trigger pressed during an
+/// expansion starts after it, and [`Macros::due`] never blocks.
+pub struct Macros {
+    /// The key code, value and delay of each step, indexed by the code of the trigger key.
+    steps: HashMap<u16, Vec<(u16, i32, Duration)>>,
+    /// The key code and value of each event which is not due yet, and the time it is due.
+    scheduled: VecDeque<(Instant, u16, i32)>,
+}
+
+impl Macros {
+    pub fn new(macros: &[MacroConfig]) -> Self {
+        let steps = macros
+            .iter()
+            .map(|config| {
+                let steps = config
+                    .steps
+                    .iter()
+                    .map(|step| {
+                        (
+                            step.key.code(),
+                            step.value,
+                            Duration::from_millis(step.delay_millis),
+                        )
+                    })
+                    .collect();
+                (config.trigger.code(), steps)
+            })
+            .collect();
+        Self {
+            steps,
+            scheduled: VecDeque::new(),
+        }
+    }
+
+    /// The key codes sent by the macros.
+    pub fn keys(&self) -> Vec<u16> {
+        self.steps
+            .values()
+            .flatten()
+            .map(|&(code, _, _)| code)
+            .collect()
+    }
+
+    /// The codes of the trigger keys.
+    pub fn triggers(&self) -> impl Iterator<Item = u16> + '_ {
+        self.steps.keys().copied()
+    }
+
+    /// Whether `event` is a press, release or repeat of a trigger key. Presses schedule the steps
+    /// of its macro.
+    pub fn trigger(&mut self, event: &InputEvent) -> bool {
+        if event.event_type() != EventType::KEY {
+            return false;
+        }
+        let Some(steps) = self.steps.get(&event.code()) else {
+            return false;
+        };
+        if event.value() == 1 {
+            let mut time = self
+                .scheduled
+                .back()
+                .map_or(Instant::now(), |&(time, _, _)| time.max(Instant::now()));
+            for &(code, value, delay) in steps {
+                time += delay;
+                self.scheduled.push_back((time, code, value));
+            }
+        }
+        true
+    }
+
+    /// Remove and return the events which are due.
+    pub fn due(&mut self) -> Vec<InputEvent> {
+        let now = Instant::now();
+        let mut events = Vec::new();
+        while let Some(&(time, code, value)) = self.scheduled.front() {
+            if time > now {
+                break;
+            }
+            self.scheduled.pop_front();
+            events.push(InputEvent::new_now(EventType::KEY, code, value));
+        }
+        events
+    }
+
+    /// How long until the next event is due, or `None` if none is scheduled.
+    pub fn next_due(&self) -> Option<Duration> {
+        self.scheduled
+            .front()
+            .map(|&(time, _, _)| time.saturating_duration_since(Instant::now()))
+    }
+}
@@ -0,0 +1,78 @@
+use crate::broadcast::Receiver;
+use crate::config::ServerConfig;
+use crate::error::{Error, Result};
+use crate::protocol;
+use crate::server::EventBatch;
+use rumqttc::{Client, MqttOptions, QoS};
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+
+/// How many publishes are queued while the broker is unreachable before events are dropped.
+const QUEUE_CAPACITY: usize = 100;
+
+/// How long to wait before reconnecting after the connection to the broker fails.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// The topic events are published to when `mqtt_topic` is not configured.
+pub fn default_topic(device_name: &str) -> String {
+    format!("remote-input/{device_name}/events")
+}
+
+/// Publish events from `receiver` to the broker at `broker_address` (`host:port`) forever.
+/// Each event is published to `topic` as a JSON object with the fields of [`crate::protocol::InputEventWrapper`],
+/// the same as on the JSON lines endpoint (see [`crate::json_lines`]).
+/// Events are published with QoS 0 and dropped while the broker is unreachable so that
+/// `receiver` never falls behind the event bus.
+pub fn publish(
+    broker_address: &str,
+    topic: &str,
+    config: &ServerConfig,
+    mut receiver: Receiver<EventBatch>,
+) -> Result<()> {
+    let (host, port) = broker_address
+        .rsplit_once(':')
+        .and_then(|(host, port)| Some((host, port.parse().ok()?)))
+        .ok_or_else(|| Error::Config("mqtt_address must be host:port".to_string()))?;
+    let mut options = MqttOptions::new("remote-input", host, port);
+    if let Some(username) = &config.mqtt_username {
+        options.set_credentials(username, config.mqtt_password.clone().unwrap_or_default());
+    }
+    let (client, mut connection) = Client::new(options, QUEUE_CAPACITY);
+
+    // Drive the connection, which reconnects on the next iteration after an error.
+    let _ = thread::spawn(move || {
+        for notification in connection.iter() {
+            if let Err(error) = notification {
+                warn!("[MQTT] Connection failed: {error}.");
+                thread::sleep(RECONNECT_DELAY);
+            }
+        }
+    });
+
+    loop {
+        let Some(events) = receiver.blocking_recv() else {
+            warn!("[MQTT] Failed to receive event from bus: disconnected.");
+            return Ok(());
+        };
+        for event in protocol::split_batch(&events.events) {
+            let event = match protocol::decode_event(event) {
+                Ok(event) => event,
+                Err(error) => {
+                    warn!("[MQTT] Failed to deserialize event: {error}.");
+                    continue;
+                }
+            };
+            let payload = match serde_json::to_vec(&event) {
+                Ok(payload) => payload,
+                Err(error) => {
+                    warn!("[MQTT] Failed to serialize event: {error}.");
+                    continue;
+                }
+            };
+            if let Err(error) = client.try_publish(topic, QoS::AtMostOnce, false, payload) {
+                warn!("[MQTT] Dropped event: {error}.");
+            }
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use crate::broadcast::Receiver;
+use crate::error::{Error, Result};
+use crate::server::{EventBatch, EventBus};
+use ring::hmac;
+use std::net::{SocketAddr, UdpSocket};
+use tracing::warn;
+
+/// Bind the socket [`send_forever`] sends from to the multicast `group` (`address:port`), which is
+/// returned with it. `ttl` limits how many routers the packets may cross (1 keeps them on the
+/// local network).
+pub fn bind(group: &str, ttl: u32) -> Result<(UdpSocket, SocketAddr)> {
+    let group = group
+        .parse::<SocketAddr>()
+        .ok()
+        .filter(|group| group.ip().is_multicast())
+        .ok_or_else(|| {
+            Error::Config("multicast_address must be a multicast address:port".to_string())
+        })?;
+    let socket = match group {
+        SocketAddr::V4(_) => UdpSocket::bind("0.0.0.0:0"),
+        SocketAddr::V6(_) => UdpSocket::bind("[::]:0"),
+    }
+    .map_err(Error::io("unable to bind multicast socket"))?;
+    match group {
+        SocketAddr::V4(_) => socket.set_multicast_ttl_v4(ttl),
+        // The standard library has no setter for the IPv6 hop limit, which defaults to 1.
+        SocketAddr::V6(_) => Ok(()),
+    }
+    .map_err(Error::io("unable to set multicast ttl"))?;
+    Ok((socket, group))
+}
+
+/// Send every event batch from `event_bus` to the multicast `group` from `socket` forever.
+/// Each packet holds the batch sequence number as a big endian `u64`, the events exactly as they
+/// are sent over TCP (COBS encoded [`postcard`] messages, each terminated by a zero byte), and an
+/// HMAC-SHA256 tag of everything before it computed with `key`. Receivers must drop packets with
+/// an invalid tag and should drop packets whose sequence number is not larger than the last one.
+pub fn send_forever(socket: &UdpSocket, group: SocketAddr, key: &[u8], event_bus: &EventBus) {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+    let receiver = event_bus.subscribe();
+    send_events(socket, group, &key, receiver);
+}
+
+/// Send events from `receiver` to `group` until the event bus is disconnected.
+fn send_events(
+    socket: &UdpSocket,
+    group: SocketAddr,
+    key: &hmac::Key,
+    mut receiver: Receiver<EventBatch>,
+) {
+    let mut packet = Vec::new();
+    loop {
+        let Some(events) = receiver.blocking_recv() else {
+            warn!("[Multicast {group}] Failed to receive event from bus: disconnected.");
+            return;
+        };
+        packet.clear();
+        packet.extend_from_slice(&events.sequence.to_be_bytes());
+        packet.extend_from_slice(&events.events);
+        let tag = hmac::sign(key, &packet);
+        packet.extend_from_slice(tag.as_ref());
+        // Packets are sent best effort: a lost packet is only reported and never retried.
+        if let Err(error) = socket.send_to(&packet, group) {
+            warn!("[Multicast {group}] Failed to send event: {error}.");
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use evdev::{AbsoluteAxisType, Device, EventType, InputEvent};
+use std::io;
+use std::os::fd::AsRawFd;
+use tracing::warn;
+
+/// The first and last multi-touch axis codes after `ABS_MT_SLOT` (`ABS_MT_TOUCH_MAJOR` to `ABS_MT_TOOL_Y`).
+const MT_AXES: std::ops::RangeInclusive<u16> = 0x30..=0x3d;
+
+/// `EVIOCGMTSLOTS(len)` of `<linux/input.h>`, which reads the values of an axis for every slot.
+fn eviocgmtslots(len: usize) -> libc::c_ulong {
+    const IOC_READ: libc::c_ulong = 2;
+    (IOC_READ << 30) | ((len as libc::c_ulong) << 16) | ((b'E' as libc::c_ulong) << 8) | 0x0a
+}
+
+/// Tracks the multi-touch slots of a device using the type B protocol (`ABS_MT_SLOT`).
+///
+/// The kernel only reports `ABS_MT_SLOT` when the slot changes, so the events of a report depend on
+/// the reports before it. Clients which missed reports (e.g., because transmission was paused, they
+/// connected later or a batch was dropped) would apply updates to the wrong slot. Reports are made
+/// self-contained by selecting the slot before the first multi-touch event of each report, and
+/// [`MultiTouch::state`] restores the touches of every slot.
+pub struct MultiTouch {
+    /// The multi-touch axes of the device, except `ABS_MT_SLOT`.
+    axes: Vec<u16>,
+    /// The value of each axis in `axes` for each slot.
+    slots: Vec<Vec<i32>>,
+    /// The slot which multi-touch events currently update.
+    slot: usize,
+    /// Whether `slot` was selected in the current report.
+    slot_selected: bool,
+}
+
+impl MultiTouch {
+    /// Read the slots of `device`, or `None` if it does not track touches in slots.
+    pub fn new(device: &Device) -> Option<Self> {
+        let supported_axes = device.supported_absolute_axes()?;
+        if !supported_axes.contains(AbsoluteAxisType::ABS_MT_SLOT) {
+            return None;
+        }
+        let abs_state = device.get_abs_state().ok()?;
+        let slot_count = abs_state[AbsoluteAxisType::ABS_MT_SLOT.0 as usize].maximum as usize + 1;
+        let axes: Vec<u16> = supported_axes
+            .iter()
+            .map(|axis| axis.0)
+            .filter(|code| MT_AXES.contains(code))
+            .collect();
+        let mut slots = vec![vec![0; axes.len()]; slot_count];
+        for (index, &axis) in axes.iter().enumerate() {
+            match read_slot_values(device, axis, slot_count) {
+                Ok(values) => {
+                    for (slot, value) in values.into_iter().enumerate() {
+                        slots[slot][index] = value;
+                    }
+                }
+                Err(error) => {
+                    warn!("[Multi-Touch] Unable to read the slots of axis {axis}: {error}.")
+                }
+            }
+        }
+        Some(Self {
+            axes,
+            slots,
+            slot: abs_state[AbsoluteAxisType::ABS_MT_SLOT.0 as usize].value as usize,
+            slot_selected: false,
+        })
+    }
+
+    /// The number of slots, i.e., of touches tracked at once.
+    pub fn slot_count(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Track `event`, which is forwarded after the returned `ABS_MT_SLOT` event, if any.
+    /// A report which updates a slot without selecting it first has the slot selected.
+    pub fn track(&mut self, event: &InputEvent) -> Option<InputEvent> {
+        match event.event_type() {
+            EventType::SYNCHRONIZATION => {
+                self.slot_selected = false;
+                None
+            }
+            EventType::ABSOLUTE if event.code() == AbsoluteAxisType::ABS_MT_SLOT.0 => {
+                self.slot = event.value() as usize;
+                self.slot_selected = true;
+                None
+            }
+            EventType::ABSOLUTE => {
+                let index = self.axes.iter().position(|&axis| axis == event.code())?;
+                if let Some(values) = self.slots.get_mut(self.slot) {
+                    values[index] = event.value();
+                }
+                if self.slot_selected {
+                    return None;
+                }
+                self.slot_selected = true;
+                Some(slot_event(self.slot))
+            }
+            _ => None,
+        }
+    }
+
+    /// The events setting every slot to its current state, without the final `SYN_REPORT`.
+    /// Slots without a touch only have their `ABS_MT_TRACKING_ID` of -1 sent.
+    pub fn state(&self) -> Vec<InputEvent> {
+        let tracking_id = AbsoluteAxisType::ABS_MT_TRACKING_ID.0;
+        let mut events = Vec::new();
+        for (slot, values) in self.slots.iter().enumerate() {
+            events.push(slot_event(slot));
+            let touching = self
+                .axes
+                .iter()
+                .zip(values)
+                .any(|(&axis, &value)| axis == tracking_id && value != -1);
+            for (&axis, &value) in self.axes.iter().zip(values) {
+                if touching || axis == tracking_id {
+                    events.push(InputEvent::new_now(EventType::ABSOLUTE, axis, value));
+                }
+            }
+        }
+        events.push(slot_event(self.slot));
+        events
+    }
+}
+
+/// The event selecting `slot`.
+fn slot_event(slot: usize) -> InputEvent {
+    InputEvent::new_now(
+        EventType::ABSOLUTE,
+        AbsoluteAxisType::ABS_MT_SLOT.0,
+        slot as i32,
+    )
+}
+
+/// Read the value of `axis` in each of the `slot_count` slots of `device`.
+fn read_slot_values(device: &Device, axis: u16, slot_count: usize) -> io::Result<Vec<i32>> {
+    // The kernel fills in the values after the axis code.
+    let mut buffer = vec![0i32; slot_count + 1];
+    buffer[0] = axis as i32;
+    // SAFETY: `buffer` is writable for the length passed in the request.
+    let result = unsafe {
+        libc::ioctl(
+            device.as_raw_fd(),
+            eviocgmtslots(buffer.len() * std::mem::size_of::<i32>()),
+            buffer.as_mut_ptr(),
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    buffer.remove(0);
+    Ok(buffer)
+}
@@ -0,0 +1,251 @@
+use crate::as_hex;
+use crate::broadcast::Receiver;
+use crate::config::ServerConfig;
+use crate::protocol::ControlMessage;
+use crate::server::EventBatch;
+use crate::session::{self, TimeoutTransport, Transport};
+use snow::params::NoiseParams;
+use snow::resolvers::{CryptoResolver, DefaultResolver};
+use snow::TransportState;
+use std::io;
+use std::sync::mpsc::Sender;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::{info, warn};
+
+/// The Noise protocol spoken by clients. The client must know the server's static public key in advance (IK)
+/// and its own static public key is checked against the configured list of client keys.
+pub const NOISE_PARAMS: &str = "Noise_IK_25519_ChaChaPoly_BLAKE2s";
+
+/// The largest message permitted by the Noise specification.
+const MAX_MESSAGE_LEN: usize = 65535;
+
+/// Holds the server's static private key and the static public keys of authorized clients.
+pub struct NoiseConfig {
+    private_key: Vec<u8>,
+    client_keys: Vec<Vec<u8>>,
+}
+
+impl NoiseConfig {
+    /// Parse the hexadecimal server private key and client public keys from the configuration.
+    pub fn new(private_key: &str, client_keys: &[String]) -> Result<Self, String> {
+        let private_key = as_hex::from_hex(private_key)
+            .filter(|key| key.len() == 32)
+            .ok_or("noise_private_key must be 64 hexadecimal digits")?;
+        let client_keys = client_keys
+            .iter()
+            .map(|key| {
+                as_hex::from_hex(key)
+                    .filter(|key| key.len() == 32)
+                    .ok_or(format!(
+                        "noise client key \"{key}\" must be 64 hexadecimal digits"
+                    ))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            private_key,
+            client_keys,
+        })
+    }
+
+    /// The server's static public key, which clients must be given out of band.
+    pub fn public_key(&self) -> Vec<u8> {
+        let params: NoiseParams = NOISE_PARAMS.parse().unwrap();
+        let mut dh = DefaultResolver
+            .resolve_dh(&params.dh)
+            .expect("the default resolver supports 25519");
+        dh.set(&self.private_key);
+        dh.pubkey().to_vec()
+    }
+}
+
+/// Generate a new static keypair, returned as hexadecimal `(private_key, public_key)`.
+pub fn generate_keypair() -> (String, String) {
+    let keypair = snow::Builder::new(NOISE_PARAMS.parse().unwrap())
+        .generate_keypair()
+        .expect("unable to generate Noise keypair");
+    (
+        as_hex::as_hex(&keypair.private),
+        as_hex::as_hex(&keypair.public),
+    )
+}
+
+/// Read a message prefixed by its length as a big endian [`u16`].
+async fn read_message(stream: &mut TcpStream, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut len = [0u8; 2];
+    stream.read_exact(&mut len).await?;
+    let len = u16::from_be_bytes(len) as usize;
+    stream.read_exact(&mut buffer[..len]).await?;
+    Ok(len)
+}
+
+/// Write a message prefixed by its length as a big endian [`u16`].
+async fn write_message(stream: &mut TcpStream, message: &[u8]) -> io::Result<()> {
+    let mut buffer = Vec::with_capacity(2 + message.len());
+    buffer.extend_from_slice(&(message.len() as u16).to_be_bytes());
+    buffer.extend_from_slice(message);
+    stream.write_all(&buffer).await
+}
+
+/// A TCP stream carrying each frame in its own encrypted Noise message.
+struct NoiseTransport {
+    stream: TcpStream,
+    transport: TransportState,
+    message: Vec<u8>,
+    payload: Vec<u8>,
+    /// Bytes received from `stream` that are not yet part of a decrypted message.
+    incoming: Vec<u8>,
+}
+
+impl NoiseTransport {
+    /// Remove the first complete length prefixed message from `self.incoming` and decrypt it.
+    fn take_message(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.incoming.len() < 2 {
+            return Ok(None);
+        }
+        let len = u16::from_be_bytes([self.incoming[0], self.incoming[1]]) as usize;
+        if self.incoming.len() < 2 + len {
+            return Ok(None);
+        }
+        let message: Vec<u8> = self.incoming.drain(..2 + len).skip(2).collect();
+        let len = self
+            .transport
+            .read_message(&message, &mut self.payload)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        Ok(Some(self.payload[..len].to_vec()))
+    }
+
+    /// Append bytes from `stream` to `self.incoming`, returning an error at the end of the stream.
+    async fn fill(&mut self) -> io::Result<()> {
+        let mut chunk = [0u8; 4096];
+        match self.stream.read(&mut chunk).await? {
+            0 => Err(io::ErrorKind::UnexpectedEof.into()),
+            len => {
+                self.incoming.extend_from_slice(&chunk[..len]);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Transport for NoiseTransport {
+    async fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        let len = self
+            .transport
+            .write_message(frame, &mut self.message)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        write_message(&mut self.stream, &self.message[..len]).await
+    }
+
+    async fn recv(&mut self) -> io::Result<Vec<u8>> {
+        loop {
+            if let Some(frame) = self.take_message()? {
+                return Ok(frame);
+            }
+            self.fill().await?;
+        }
+    }
+
+    fn is_secure(&self) -> bool {
+        true
+    }
+}
+
+/// Handle a Noise encrypted TCP connection.
+/// Every Noise message in either direction is prefixed by its length as a big endian [`u16`].
+/// The client initiates a [`NOISE_PARAMS`] handshake which replaces the API key:
+/// it is only completed if the client's static public key is one of `config.client_keys`.
+/// Until then, the connection is closed if a read takes longer than `server_config.auth_timeout_millis`.
+/// Then perform the handshake and send events with [`session::run`], one encrypted message per frame.
+pub async fn handle_connection(
+    mut stream: TcpStream,
+    config: &NoiseConfig,
+    server_config: &ServerConfig,
+    receiver: Receiver<EventBatch>,
+    commands: &Sender<ControlMessage>,
+) {
+    let (client, _) = session::connection_established("Noise Client", stream.peer_addr());
+
+    let mut handshake = match snow::Builder::new(NOISE_PARAMS.parse().unwrap())
+        .local_private_key(&config.private_key)
+        .build_responder()
+    {
+        Ok(handshake) => handshake,
+        Err(error) => {
+            warn!("[{client}] Unable to start handshake: {error}.");
+            return;
+        }
+    };
+    let mut message = vec![0u8; MAX_MESSAGE_LEN];
+    let mut payload = vec![0u8; MAX_MESSAGE_LEN];
+
+    // -> e, es, s, ss
+    let read = read_message(&mut stream, &mut message);
+    let result = session::timeout(server_config.auth_timeout(), read)
+        .await
+        .and_then(|len| {
+            handshake
+                .read_message(&message[..len], &mut payload)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+        });
+    if let Err(error) = result {
+        warn!("[{client}] Handshake failed: {error}.");
+        return;
+    }
+
+    // Only respond to clients with an authorized static key.
+    let authorized = handshake
+        .get_remote_static()
+        .map(|key| {
+            config
+                .client_keys
+                .iter()
+                .any(|client_key| client_key == key)
+        })
+        .unwrap_or(false);
+    if !authorized {
+        warn!("[{client}] Unauthorized static key.");
+        server_config
+            .audit
+            .record(&client, "Authentication failed.");
+        return;
+    }
+
+    // <- e, ee, se
+    let result = match handshake
+        .write_message(&[], &mut message)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    {
+        Ok(len) => {
+            let write = write_message(&mut stream, &message[..len]);
+            session::timeout(server_config.write_timeout(), write).await
+        }
+        Err(error) => Err(error),
+    };
+    if let Err(error) = result {
+        warn!("[{client}] Handshake failed: {error}.");
+        return;
+    }
+    let transport = match handshake.into_transport_mode() {
+        Ok(transport) => transport,
+        Err(error) => {
+            warn!("[{client}] Handshake failed: {error}.");
+            return;
+        }
+    };
+    info!("[{client}] Authenticated.");
+    server_config.audit.record(&client, "Authenticated.");
+
+    let mut transport = TimeoutTransport::new(
+        NoiseTransport {
+            stream,
+            transport,
+            message,
+            payload,
+            incoming: Vec::new(),
+        },
+        server_config,
+    );
+    transport.read_timeout = None;
+    session::run(transport, &client, None, receiver, commands, server_config).await;
+}
@@ -0,0 +1,104 @@
+use crate::config::HardwareConfig;
+use crate::server::{parse_name_pattern, parse_vendor_product};
+use std::fs::{self, OpenOptions};
+use std::io;
+use tracing::warn;
+
+/// How to obtain access to input devices, printed after permission errors.
+const ACCESS_HELP: &str = "Input devices belong to root and the \"input\" group. Add the user to \
+    the group (`usermod -aG input <user>`, then log in again), run the server as root or install \
+    a udev rule granting access to the configured devices (`remote-input print-udev-rule`).";
+
+/// Explain why `error`, returned when opening or grabbing a device, occurred if the cause is
+/// likely a permission problem or another program holding a grab.
+pub fn explain_error(error: &io::Error, component: &str) {
+    if error.kind() == io::ErrorKind::PermissionDenied {
+        warn!("[{component}] Permission denied. {ACCESS_HELP}");
+    } else if error.raw_os_error() == Some(libc::EBUSY) {
+        warn!("[{component}] The device is grabbed by another program (e.g., another instance of this server or a virtual machine).");
+    }
+}
+
+/// Explain that the device may have been missed because `/dev/input` contains event devices this
+/// user cannot open, which evdev skips silently when enumerating devices.
+pub fn explain_unreadable_devices(component: &str) {
+    let Ok(entries) = fs::read_dir("/dev/input") else {
+        return;
+    };
+    let unreadable = entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("event"))
+        .filter(|entry| {
+            OpenOptions::new()
+                .read(true)
+                .open(entry.path())
+                .is_err_and(|error| error.kind() == io::ErrorKind::PermissionDenied)
+        })
+        .count();
+    if unreadable > 0 {
+        warn!(
+            "[{component}] {unreadable} input devices in /dev/input cannot be opened by this user and were not searched. {ACCESS_HELP}"
+        );
+    }
+}
+
+/// Print udev rules giving the "input" group access to every configured device, and to
+/// `/dev/uinput` if passthrough keys are configured.
+pub fn print_udev_rules(devices: &[HardwareConfig]) {
+    println!(
+        "# Save as /etc/udev/rules.d/60-remote-input.rules, add the user to the \"input\" group"
+    );
+    println!("# and run `udevadm control --reload && udevadm trigger`.");
+    for hardware in devices {
+        match udev_match(&hardware.name) {
+            Some(matches) => println!(
+                "SUBSYSTEM==\"input\", KERNEL==\"event*\", {matches}, GROUP=\"input\", MODE=\"0660\""
+            ),
+            None => println!(
+                "# \"{}\" cannot be expressed as a udev match; use its name, vendor:product ID or path instead.",
+                hardware.name
+            ),
+        }
+    }
+    if devices
+        .iter()
+        .any(|hardware| !hardware.passthrough.is_empty())
+    {
+        println!("SUBSYSTEM==\"misc\", KERNEL==\"uinput\", GROUP=\"input\", MODE=\"0660\"");
+    }
+}
+
+/// The udev attribute matches selecting the device `device_name` (see [`crate::server::find_device`]).
+fn udev_match(device_name: &str) -> Option<String> {
+    if device_name.starts_with("/dev/") {
+        // Resolve links such as /dev/input/by-id/* to the event device.
+        let path = fs::canonicalize(device_name).unwrap_or_else(|_| device_name.into());
+        let kernel = path.file_name()?.to_string_lossy().into_owned();
+        return Some(format!("KERNEL==\"{kernel}\""));
+    }
+    if let Some((vendor, product)) = parse_vendor_product(device_name) {
+        return Some(format!(
+            "ATTRS{{id/vendor}}==\"{vendor:04x}\", ATTRS{{id/product}}==\"{product:04x}\""
+        ));
+    }
+    if is_physical_path(device_name) {
+        return Some(format!("ATTRS{{phys}}==\"{device_name}\""));
+    }
+    // udev matches support the same * and ? wildcards as globs, but not regular expressions.
+    if device_name.starts_with('/') && parse_name_pattern(device_name).is_some() {
+        return None;
+    }
+    Some(format!("ATTRS{{name}}==\"{device_name}\""))
+}
+
+/// Whether an input device with the physical path `device_name` is present, which
+/// [`crate::server::find_device`] prefers over names. Sysfs is readable without access to the device.
+fn is_physical_path(device_name: &str) -> bool {
+    let Ok(entries) = fs::read_dir("/sys/class/input") else {
+        return false;
+    };
+    entries.filter_map(Result::ok).any(|entry| {
+        fs::read_to_string(entry.path().join("device/phys"))
+            .is_ok_and(|phys| phys.trim_end() == device_name)
+    })
+}
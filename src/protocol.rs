@@ -0,0 +1,793 @@
+use crate::codes;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+
+pub use remote_input_wire::{ClientHello, InputEventWrapper, ServerHello, Timestamp};
+
+/// Wrap an event read from a device.
+#[cfg(target_os = "linux")]
+pub fn wrap_event(input_event: evdev::InputEvent) -> InputEventWrapper {
+    InputEventWrapper {
+        timestamp: input_event.timestamp().into(),
+        event_type: input_event.event_type().0,
+        code: input_event.code(),
+        value: input_event.value(),
+    }
+}
+
+/// The newest protocol version spoken by this server.
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// The oldest protocol version still accepted from clients.
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
+
+/// Bit flags of optional protocol features.
+/// The server advertises the features it supports in [`ServerHello::features`] and the client
+/// requests a subset in [`ClientHello::features`].
+pub mod features {
+    /// The server sends a heartbeat (an empty message, which is never a valid event) whenever
+    /// the connection has been idle for the configured heartbeat interval.
+    pub const HEARTBEAT: u32 = 1 << 0;
+    /// The client answers every heartbeat with an empty message. The server closes the
+    /// connection if a heartbeat is not answered within the configured heartbeat timeout.
+    /// Only meaningful together with [`HEARTBEAT`].
+    pub const HEARTBEAT_PONG: u32 = 1 << 1;
+    /// After the handshake, frames in both directions hold the raw message prefixed by
+    /// its length as a big endian [`u16`] instead of being encoded by COBS.
+    /// See [`super::Framing::LengthPrefixed`].
+    pub const LENGTH_PREFIXED: u32 = 1 << 2;
+    /// Events are serialized with MessagePack (as a map with named fields) instead of [`postcard`].
+    /// See [`super::Encoding::MessagePack`].
+    pub const MESSAGE_PACK: u32 = 1 << 3;
+    /// After the handshake, everything the server sends is a single zstd stream which is
+    /// flushed after every frame. Frames sent by the client are not compressed.
+    /// See [`super::Compressor`].
+    pub const ZSTD: u32 = 1 << 4;
+    /// Events up to and including each `EV_SYN`/`SYN_REPORT` event are sent as one frame holding a
+    /// sequence of events instead of one frame per event. See [`super::reencode_batch`].
+    pub const BATCH: u32 = 1 << 5;
+    /// The client may send [`super::ControlMessage`]s, serialized with the negotiated encoding.
+    pub const CONTROL: u32 = 1 << 6;
+    /// Every event frame holds a `(sequence, event)` tuple (or `(sequence, events)` with [`BATCH`])
+    /// instead of the bare event. Sequence numbers increase by one for every batch, so a larger
+    /// increase indicates dropped events. See [`super::ControlMessage::Resync`].
+    pub const SEQUENCE: u32 = 1 << 7;
+    /// The server sends at most a window of event frames which the client has not acknowledged
+    /// with [`super::ControlMessage::Ack`]. Frames beyond that are queued (up to another window)
+    /// and then dropped according to the [`super::DropPolicy`].
+    pub const FLOW_CONTROL: u32 = 1 << 8;
+    /// Events are serialized with CBOR (as a map with named fields) instead of [`postcard`].
+    /// Not granted together with [`MESSAGE_PACK`]. See [`super::Encoding::Cbor`].
+    pub const CBOR: u32 = 1 << 9;
+    /// Right after the [`super::HandshakeResponse`], the server sends a [`super::SessionToken`] which
+    /// the client renews with [`super::ControlMessage::RenewSession`] before it expires.
+    /// Only offered if the server limits the lifetime of sessions.
+    pub const SESSION_TOKEN: u32 = 1 << 10;
+    /// After the [`super::HandshakeResponse`] (and [`super::SessionToken`], if negotiated), the server
+    /// sends a [`super::DeviceInfo`] describing the capabilities of each device, so that the client
+    /// can scale absolute values (e.g., touchscreen coordinates) or replicate the device.
+    pub const DEVICE_INFO: u32 = 1 << 11;
+    /// Every frame after the handshake holds a [`super::StreamFrame`]: events are tagged with the ID
+    /// of their device, and devices are announced when they are attached or removed, so that the
+    /// client can replicate each device separately.
+    pub const MULTIPLEX: u32 = 1 << 12;
+    /// The server periodically sends a [`super::StreamFrame::Ping`], which the client echoes with
+    /// [`super::ControlMessage::Pong`] so that the server can measure the latency of the connection.
+    /// Only granted together with [`MULTIPLEX`], whose frames tell pings from events.
+    pub const LATENCY: u32 = 1 << 13;
+    /// Events are sent as [`super::NamedEvent`]s, which also carry the symbolic names of their type
+    /// and code, instead of [`super::InputEventWrapper`]s.
+    pub const NAMES: u32 = 1 << 14;
+}
+
+/// How frames are delimited after the handshake. The handshake itself always uses [`Framing::Cobs`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Framing {
+    /// Each message is encoded by COBS and terminated by a zero byte.
+    Cobs,
+    /// Each message is prefixed by its length as a big endian [`u16`].
+    LengthPrefixed,
+}
+
+impl Framing {
+    /// The framing selected by the negotiated `features`.
+    pub fn from_features(features: u32) -> Self {
+        if features & features::LENGTH_PREFIXED != 0 {
+            Framing::LengthPrefixed
+        } else {
+            Framing::Cobs
+        }
+    }
+
+    /// Delimit `message` with this framing.
+    pub fn frame(self, message: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Framing::Cobs => {
+                let mut frame = vec![0; remote_input_wire::max_frame_len(message.len())];
+                let len = remote_input_wire::frame(message, &mut frame)
+                    .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?
+                    .len();
+                frame.truncate(len);
+                Ok(frame)
+            }
+            Framing::LengthPrefixed => {
+                let len = u16::try_from(message.len())
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "message too long"))?;
+                let mut frame = Vec::with_capacity(2 + message.len());
+                frame.extend_from_slice(&len.to_be_bytes());
+                frame.extend_from_slice(message);
+                Ok(frame)
+            }
+        }
+    }
+
+    /// Extract the message from a complete `frame`, including its delimiter.
+    pub fn unframe(self, frame: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Framing::Cobs => cobs::decode_vec(frame.strip_suffix(&[0x00]).unwrap_or(frame))
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, format!("{error:?}"))),
+            Framing::LengthPrefixed => Ok(frame.get(2..).unwrap_or_default().to_vec()),
+        }
+    }
+
+    /// The length of the first complete frame at the start of `buffer`, if there is one.
+    pub fn frame_len(self, buffer: &[u8]) -> Option<usize> {
+        match self {
+            Framing::Cobs => buffer
+                .iter()
+                .position(|&byte| byte == 0x00)
+                .map(|end| end + 1),
+            Framing::LengthPrefixed => {
+                let len = u16::from_be_bytes([*buffer.first()?, *buffer.get(1)?]) as usize;
+                (buffer.len() >= 2 + len).then_some(2 + len)
+            }
+        }
+    }
+}
+
+/// How events are serialized after the handshake.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Encoding {
+    Postcard,
+    MessagePack,
+    Cbor,
+}
+
+impl Encoding {
+    /// The encoding selected by the negotiated `features`.
+    pub fn from_features(features: u32) -> Self {
+        if features & features::MESSAGE_PACK != 0 {
+            Encoding::MessagePack
+        } else if features & features::CBOR != 0 {
+            Encoding::Cbor
+        } else {
+            Encoding::Postcard
+        }
+    }
+
+    /// Serialize `message` with this encoding.
+    pub fn serialize<T: Serialize>(self, message: &T) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Postcard => postcard::to_allocvec(message)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error)),
+            Encoding::MessagePack => rmp_serde::to_vec_named(message)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error)),
+            Encoding::Cbor => {
+                let mut buffer = Vec::new();
+                ciborium::into_writer(message, &mut buffer).map_err(|error| {
+                    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+                })?;
+                Ok(buffer)
+            }
+        }
+    }
+
+    /// Deserialize a `message` serialized with this encoding.
+    pub fn deserialize<T: for<'de> Deserialize<'de>>(self, message: &[u8]) -> io::Result<T> {
+        match self {
+            Encoding::Postcard => postcard::from_bytes(message)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error)),
+            Encoding::MessagePack => rmp_serde::from_slice(message)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error)),
+            Encoding::Cbor => ciborium::from_reader(message)
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error.to_string())),
+        }
+    }
+}
+
+/// Compresses frames sent to a client into a single zstd stream.
+pub struct Compressor {
+    encoder: zstd::stream::write::Encoder<'static, Vec<u8>>,
+}
+
+impl Compressor {
+    /// The zstd compression level. Frames are small, so a fast level compresses them as well as a slow one.
+    const LEVEL: i32 = 3;
+
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            encoder: zstd::stream::write::Encoder::new(Vec::new(), Self::LEVEL)?,
+        })
+    }
+
+    /// Compress `frame` and return everything the client needs to decompress it.
+    /// Earlier frames are used as context, so the output must be sent in order.
+    pub fn compress(&mut self, frame: &[u8]) -> io::Result<Vec<u8>> {
+        self.encoder.write_all(frame)?;
+        self.encoder.flush()?;
+        Ok(std::mem::take(self.encoder.get_mut()))
+    }
+}
+
+/// Split a batch from the event bus into its serialized events (COBS encoded [`postcard`] messages),
+/// each including its zero byte terminator.
+pub fn split_batch(batch: &[u8]) -> impl Iterator<Item = &[u8]> {
+    batch.split_inclusive(|&byte| byte == 0x00)
+}
+
+/// Deserialize an event from the event bus (a COBS encoded [`postcard`] message).
+pub fn decode_event(cobs_frame: &[u8]) -> io::Result<InputEventWrapper> {
+    remote_input_wire::decode_event(&mut cobs_frame.to_vec())
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+/// Convert a batch from the event bus into a single frame holding a sequence of its events
+/// with the given `encoding` and `framing`, preceded by `sequence` if set and tagged with `device` if set.
+/// The events are [`NamedEvent`]s if `names` is set.
+pub fn reencode_batch(
+    batch: &[u8],
+    encoding: Encoding,
+    framing: Framing,
+    sequence: Option<u64>,
+    device: Option<u16>,
+    names: bool,
+) -> io::Result<Vec<u8>> {
+    let events = split_batch(batch)
+        .map(decode_event)
+        .collect::<io::Result<Vec<_>>>()?;
+    if names {
+        let events: Vec<NamedEvent> = events.into_iter().map(NamedEvent::from).collect();
+        return serialize_frame(&events, encoding, framing, sequence, device);
+    }
+    serialize_frame(&events, encoding, framing, sequence, device)
+}
+
+/// Serialize `body` into a frame with the given `encoding` and `framing`, preceded by `sequence` if set
+/// and wrapped in a [`StreamFrame::Events`] of `device` if set.
+fn serialize_frame<T: Serialize>(
+    body: &T,
+    encoding: Encoding,
+    framing: Framing,
+    sequence: Option<u64>,
+    device: Option<u16>,
+) -> io::Result<Vec<u8>> {
+    let message = match (device, sequence) {
+        (None, None) => encoding.serialize(body)?,
+        (None, Some(sequence)) => encoding.serialize(&(sequence, body))?,
+        (Some(device), None) => encoding.serialize(&StreamFrame::Events {
+            device,
+            events: body,
+        })?,
+        (Some(device), Some(sequence)) => encoding.serialize(&StreamFrame::Events {
+            device,
+            events: (sequence, body),
+        })?,
+    };
+    framing.frame(&message)
+}
+
+/// Like [`reencode_batch`], but split the batch into as many frames as needed so that no frame is
+/// longer than `max_frame_size`, each holding consecutive events. Only a frame holding a single
+/// event may still be longer, which the caller must reject.
+pub fn reencode_batch_segmented(
+    batch: &[u8],
+    encoding: Encoding,
+    framing: Framing,
+    sequence: Option<u64>,
+    device: Option<u16>,
+    names: bool,
+    max_frame_size: usize,
+) -> io::Result<Vec<Vec<u8>>> {
+    let frame = reencode_batch(batch, encoding, framing, sequence, device, names)?;
+    let events: Vec<&[u8]> = split_batch(batch).collect();
+    if frame.len() <= max_frame_size || events.len() <= 1 {
+        return Ok(vec![frame]);
+    }
+    let (first, second) = events.split_at(events.len() / 2);
+    let mut frames = reencode_batch_segmented(
+        &first.concat(),
+        encoding,
+        framing,
+        sequence,
+        device,
+        names,
+        max_frame_size,
+    )?;
+    frames.extend(reencode_batch_segmented(
+        &second.concat(),
+        encoding,
+        framing,
+        sequence,
+        device,
+        names,
+        max_frame_size,
+    )?);
+    Ok(frames)
+}
+
+/// Convert a serialized event from the event bus (a COBS encoded [`postcard`] message)
+/// into a frame with the given `encoding` and `framing`, preceded by `sequence` if set and tagged
+/// with `device` if set. The event is a [`NamedEvent`] if `names` is set.
+pub fn reencode_event(
+    cobs_frame: &[u8],
+    encoding: Encoding,
+    framing: Framing,
+    sequence: Option<u64>,
+    device: Option<u16>,
+    names: bool,
+) -> io::Result<Vec<u8>> {
+    if names {
+        let event = NamedEvent::from(decode_event(cobs_frame)?);
+        return serialize_frame(&event, encoding, framing, sequence, device);
+    }
+    if sequence.is_some() || device.is_some() {
+        let event = decode_event(cobs_frame)?;
+        return serialize_frame(&event, encoding, framing, sequence, device);
+    }
+    match (encoding, framing) {
+        (Encoding::Postcard, Framing::Cobs) => Ok(cobs_frame.to_vec()),
+        (Encoding::Postcard, _) => {
+            let message = cobs::decode_vec(cobs_frame).map_err(|error| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("{error:?}"))
+            })?;
+            framing.frame(&message)
+        }
+        _ => framing.frame(&encoding.serialize(&decode_event(cobs_frame)?)?),
+    }
+}
+
+/// An event with the symbolic names of its type and code, sent instead of [`InputEventWrapper`] if
+/// [`features::NAMES`] was negotiated, for clients without tables of the codes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NamedEvent {
+    pub timestamp: Timestamp,
+    pub event_type: u16,
+    pub code: u16,
+    pub value: i32,
+    /// The name of the type (e.g., "EV_KEY"), or `None` if it is unknown.
+    pub type_name: Option<String>,
+    /// The name of the code (e.g., "KEY_ENTER"), or `None` if it is unknown.
+    pub code_name: Option<String>,
+}
+
+impl From<InputEventWrapper> for NamedEvent {
+    fn from(event: InputEventWrapper) -> Self {
+        Self {
+            timestamp: event.timestamp,
+            event_type: event.event_type,
+            code: event.code,
+            value: event.value,
+            type_name: codes::event_type_name(event.event_type).map(str::to_string),
+            code_name: codes::code_name(event.event_type, event.code).map(str::to_string),
+        }
+    }
+}
+
+/// Sent by the server in reply to [`ClientHello`].
+/// If the client is rejected, the server closes the connection after sending this.
+/// Serialized like [`remote_input_wire::HandshakeResponse`], which borrows the reason.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum HandshakeResponse {
+    Accepted { version: u16, features: u32 },
+    Rejected { reason: String },
+}
+
+/// Sent by the server right after [`HandshakeResponse::Accepted`] if [`features::SESSION_TOKEN`]
+/// was negotiated. The session ends `lifetime_secs` seconds after it started or was last renewed.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SessionToken {
+    pub token: [u8; 32],
+    pub lifetime_secs: u64,
+}
+
+/// The range and resolution of an absolute axis (`EV_ABS`) as reported by the kernel.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct AxisInfo {
+    /// The code of the axis (e.g., 0 for ABS_X).
+    pub code: u16,
+    pub minimum: i32,
+    pub maximum: i32,
+    /// Changes within `fuzz` are filtered out as noise.
+    pub fuzz: i32,
+    /// Values within `flat` of the center are reported as the center.
+    pub flat: i32,
+    /// Units per millimeter (or per radian for rotational axes), 0 if unknown.
+    pub resolution: i32,
+}
+
+/// The identity and capabilities of a device, e.g., to create a virtual device like it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct DeviceDescriptor {
+    pub name: String,
+    /// The bus (e.g., 3 for USB), vendor, product and version IDs.
+    pub bus_type: u16,
+    pub vendor: u16,
+    pub product: u16,
+    pub version: u16,
+    /// Bit `n` is set if the device emits events of type `n` (e.g., bit 1 for EV_KEY).
+    pub event_types: u32,
+    /// Bit `n % 8` of byte `n / 8` is set if the device has the key or button with the code `n`.
+    pub keys: Vec<u8>,
+    /// Bit `n` is set if the device has the relative axis with the code `n` (e.g., bit 0 for REL_X).
+    pub relative_axes: u16,
+    /// Every absolute axis of the device.
+    pub axes: Vec<AxisInfo>,
+    /// The number of multi-touch slots (touches tracked at once with `ABS_MT_SLOT`), 0 if the
+    /// device does not track touches in slots.
+    pub slots: u16,
+    /// The keyboard layout of the device from the configuration, if one is set.
+    pub keymap: Option<Keymap>,
+}
+
+/// The layout which the keys of a device are meant to be interpreted with, so that clients on
+/// systems with a different layout can produce the intended characters.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Keymap {
+    /// The XKB layout, variant and options (e.g., "de", "nodeadkeys" and "ctrl:nocaps"),
+    /// empty if not set.
+    pub layout: String,
+    pub variant: String,
+    pub options: String,
+    /// The X keysym produced by individual keys, overriding the layout.
+    pub keysyms: Vec<KeysymMapping>,
+}
+
+/// The X keysym (e.g., 0x79 for "y") produced by the key with the code `code`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct KeysymMapping {
+    pub code: u16,
+    pub keysym: u32,
+}
+
+/// A frame after the handshake if [`features::MULTIPLEX`] was negotiated. Devices are identified by
+/// their position in the configuration. Attached devices are announced right after the handshake.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum StreamFrame<T> {
+    /// What the frame would hold without [`features::MULTIPLEX`] (an event or a batch of events,
+    /// preceded by the sequence number with [`features::SEQUENCE`]), sent by `device`.
+    Events { device: u16, events: T },
+    /// `device` was attached. Its events follow.
+    Attached {
+        device: u16,
+        descriptor: DeviceDescriptor,
+    },
+    /// `device` was removed. Events of it which are still received are to be ignored.
+    Removed { device: u16 },
+    /// To be answered with [`ControlMessage::Pong`] right away. `sent` is the time of the server.
+    /// Only with [`features::LATENCY`].
+    Ping { id: u32, sent: Timestamp },
+    /// The server is shutting down and closes the connection after this frame.
+    Closing,
+}
+
+/// Sent by the server after the handshake if [`features::DEVICE_INFO`] was negotiated.
+/// Lists every configured device which is currently attached, in the order of the configuration.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DeviceInfo {
+    pub devices: Vec<DeviceDescriptor>,
+}
+
+/// Sent by a client which negotiated [`features::CONTROL`] to control the device.
+/// Clients share the device, so a control message affects every client.
+/// [`ControlMessage::Ack`], [`ControlMessage::FlowControl`], [`ControlMessage::RenewSession`] and
+/// [`ControlMessage::Pong`]
+/// only affect the sending client.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum ControlMessage {
+    /// Discard events until [`ControlMessage::Resume`], like pressing the pause key.
+    Pause,
+    /// Transmit events again.
+    Resume,
+    /// Grab the device, preventing its events from propagating, like pressing the escape key.
+    Grab,
+    /// Ungrab the device.
+    Ungrab,
+    /// Turn the LED with the code `led` (e.g., 0 for LED_NUML) on or off.
+    SetLed { led: u16, on: bool },
+    /// Send a key press for every key that is currently pressed, e.g., after a gap in the sequence numbers.
+    Resync,
+    /// Acknowledge that `frames` event frames were received. Only with [`features::FLOW_CONTROL`].
+    Ack { frames: u32 },
+    /// Change the flow control `window` and `policy` of this client. Only with [`features::FLOW_CONTROL`].
+    FlowControl { window: u32, policy: DropPolicy },
+    /// Extend the session by its lifetime. `mac` is HMAC-SHA256 of the [`SessionToken::token`] followed
+    /// by the number of earlier renewals as a big endian `u64`, keyed with the API key.
+    /// Only with [`features::SESSION_TOKEN`].
+    RenewSession { mac: [u8; 32] },
+    /// Upload `effect` to the force feedback slot `slot` of the device (e.g., a gamepad),
+    /// replacing the effect in that slot. The number of slots is limited by the device.
+    UploadEffect {
+        slot: u8,
+        effect: ForceFeedbackEffect,
+    },
+    /// Play the effect in `slot` `count` times. A `count` of 0 stops it.
+    PlayEffect { slot: u8, count: i32 },
+    /// Remove the effect in `slot` from the device.
+    EraseEffect { slot: u8 },
+    /// Answer the [`StreamFrame::Ping`] with the ID `id`, which was received at `received` (the
+    /// time of the client). Only with [`features::LATENCY`].
+    Pong { id: u32, received: Timestamp },
+}
+
+/// A force feedback effect uploaded with [`ControlMessage::UploadEffect`], like `struct ff_effect` of Linux.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct ForceFeedbackEffect {
+    /// The direction of the effect (0x4000 is down, 0x8000 is left, 0xC000 is up).
+    pub direction: u16,
+    /// How long the effect plays in milliseconds (0 plays it until it is stopped).
+    pub length_millis: u16,
+    /// How long to wait before playing the effect in milliseconds.
+    pub delay_millis: u16,
+    pub kind: ForceFeedbackKind,
+}
+
+/// The kinds of force feedback effects supported by most gamepads.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum ForceFeedbackKind {
+    /// Vibrate the heavy and the light motor.
+    Rumble {
+        strong_magnitude: u16,
+        weak_magnitude: u16,
+    },
+    /// A constant force.
+    Constant { level: i16 },
+    /// A force following `waveform`.
+    Periodic {
+        waveform: Waveform,
+        period_millis: u16,
+        magnitude: i16,
+        offset: i16,
+        phase: u16,
+    },
+}
+
+/// The waveform of a [`ForceFeedbackKind::Periodic`] effect.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sine,
+    SawUp,
+    SawDown,
+}
+
+/// What happens to event frames while the flow control window and the queue behind it are full.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Discard new frames (the default).
+    DropNewest,
+    /// Discard the oldest queued frames.
+    DropOldest,
+    /// Close the connection.
+    Disconnect,
+}
+
+/// Serialize `message` with [`postcard`] and encode it with COBS, the same way events are framed.
+pub fn encode<T: Serialize>(message: &T) -> postcard::Result<Vec<u8>> {
+    postcard::to_allocvec_cobs(message)
+}
+
+/// Decode a COBS encoded [`postcard`] message, including its zero byte terminator.
+pub fn decode<T: for<'de> Deserialize<'de>>(mut frame: Vec<u8>) -> postcard::Result<T> {
+    postcard::from_bytes_cobs(&mut frame)
+}
+
+/// Decide how to respond to `client_hello` given the `supported_features` of this server.
+pub fn negotiate(client_hello: &ClientHello, supported_features: u32) -> HandshakeResponse {
+    if client_hello.version < MIN_PROTOCOL_VERSION || client_hello.version > PROTOCOL_VERSION {
+        return HandshakeResponse::Rejected {
+            reason: format!(
+                "unsupported protocol version {} (supported: {MIN_PROTOCOL_VERSION} to {PROTOCOL_VERSION})",
+                client_hello.version
+            ),
+        };
+    }
+    let mut granted = client_hello.features & supported_features;
+    // Only one encoding can be used.
+    if granted & features::MESSAGE_PACK != 0 {
+        granted &= !features::CBOR;
+    }
+    // Pings can only be told from events in multiplexed frames.
+    if granted & features::MULTIPLEX == 0 {
+        granted &= !features::LATENCY;
+    }
+    HandshakeResponse::Accepted {
+        version: client_hello.version,
+        features: granted,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// The features granted to a client requesting `features` of the version [`PROTOCOL_VERSION`].
+    fn granted(features: u32, supported_features: u32) -> u32 {
+        let client_hello = ClientHello {
+            version: PROTOCOL_VERSION,
+            features,
+        };
+        match negotiate(&client_hello, supported_features) {
+            HandshakeResponse::Accepted { version, features } => {
+                assert_eq!(version, PROTOCOL_VERSION);
+                features
+            }
+            HandshakeResponse::Rejected { reason } => panic!("rejected: {reason}"),
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_versions() {
+        for version in [MIN_PROTOCOL_VERSION - 1, PROTOCOL_VERSION + 1] {
+            let client_hello = ClientHello {
+                version,
+                features: 0,
+            };
+            assert!(matches!(
+                negotiate(&client_hello, u32::MAX),
+                HandshakeResponse::Rejected { reason } if reason.contains("unsupported protocol version")
+            ));
+        }
+    }
+
+    #[test]
+    fn grants_features_both_support() {
+        let requested = features::HEARTBEAT | features::BATCH | features::ZSTD;
+        let supported = features::HEARTBEAT | features::ZSTD | features::CONTROL;
+        assert_eq!(
+            granted(requested, supported),
+            features::HEARTBEAT | features::ZSTD
+        );
+        assert_eq!(granted(0, supported), 0);
+        assert_eq!(granted(requested, 0), 0);
+    }
+
+    #[test]
+    fn grants_one_encoding() {
+        let requested = features::MESSAGE_PACK | features::CBOR;
+        assert_eq!(granted(requested, u32::MAX), features::MESSAGE_PACK);
+        assert_eq!(granted(requested, features::CBOR), features::CBOR);
+    }
+
+    #[test]
+    fn grants_latency_only_with_multiplex() {
+        let requested = features::LATENCY | features::BATCH;
+        assert_eq!(granted(requested, u32::MAX), features::BATCH);
+        let requested = features::LATENCY | features::MULTIPLEX;
+        assert_eq!(granted(requested, u32::MAX), requested);
+        assert_eq!(granted(requested, features::LATENCY), 0);
+    }
+
+    #[test]
+    fn frames_and_unframes_messages() {
+        let long = vec![7; u16::MAX as usize];
+        for framing in [Framing::Cobs, Framing::LengthPrefixed] {
+            for message in [&[][..], &[0, 1, 0, 2], &long] {
+                let frame = framing.frame(message).unwrap();
+                assert_eq!(framing.frame_len(&frame), Some(frame.len()));
+                assert_eq!(framing.unframe(&frame).unwrap(), message);
+            }
+        }
+    }
+
+    #[test]
+    fn frames_empty_message_as_heartbeat() {
+        assert_eq!(Framing::Cobs.frame(&[]).unwrap(), [0x01, 0x00]);
+        assert_eq!(Framing::LengthPrefixed.frame(&[]).unwrap(), [0x00, 0x00]);
+    }
+
+    #[test]
+    fn rejects_message_too_long_for_length_prefix() {
+        let message = vec![7; u16::MAX as usize + 1];
+        let error = Framing::LengthPrefixed.frame(&message).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn waits_for_complete_frame() {
+        let frame = Framing::LengthPrefixed.frame(&[1, 2, 3]).unwrap();
+        for len in 0..frame.len() {
+            assert_eq!(Framing::LengthPrefixed.frame_len(&frame[..len]), None);
+        }
+        // A length prefix longer than the data received so far.
+        assert_eq!(Framing::LengthPrefixed.frame_len(&[0xff, 0xff, 1, 2]), None);
+        assert_eq!(Framing::Cobs.frame_len(&[0x02, 0x05]), None);
+    }
+
+    /// `count` key events serialized like batches on the event bus.
+    fn batch(count: u16) -> (Vec<InputEventWrapper>, Vec<u8>) {
+        let events: Vec<_> = (0..count)
+            .map(|code| InputEventWrapper {
+                timestamp: Timestamp::from(Duration::from_millis(code.into())),
+                event_type: 1,
+                code,
+                value: 1,
+            })
+            .collect();
+        let batch = events
+            .iter()
+            .flat_map(|event| encode(event).unwrap())
+            .collect();
+        (events, batch)
+    }
+
+    /// The sequence numbers and events of postcard `frames` framed by COBS.
+    fn decode_frames(frames: &[Vec<u8>]) -> (Vec<u64>, Vec<InputEventWrapper>) {
+        let mut sequences = Vec::new();
+        let mut events = Vec::new();
+        for frame in frames {
+            let message = Framing::Cobs.unframe(frame).unwrap();
+            let (sequence, frame_events): (u64, Vec<InputEventWrapper>) =
+                postcard::from_bytes(&message).unwrap();
+            sequences.push(sequence);
+            events.extend(frame_events);
+        }
+        (sequences, events)
+    }
+
+    #[test]
+    fn splits_batch_into_frames_that_fit() {
+        let (events, batch) = batch(50);
+        let frames = reencode_batch_segmented(
+            &batch,
+            Encoding::Postcard,
+            Framing::Cobs,
+            Some(5),
+            None,
+            false,
+            100,
+        )
+        .unwrap();
+        assert!(frames.len() > 1);
+        assert!(frames.iter().all(|frame| frame.len() <= 100));
+        let (sequences, decoded) = decode_frames(&frames);
+        assert!(sequences.iter().all(|&sequence| sequence == 5));
+        assert_eq!(decoded, events);
+    }
+
+    #[test]
+    fn keeps_batch_that_fits_in_one_frame() {
+        let (events, batch) = batch(3);
+        let frames = reencode_batch_segmented(
+            &batch,
+            Encoding::Postcard,
+            Framing::Cobs,
+            Some(0),
+            None,
+            false,
+            4096,
+        )
+        .unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(decode_frames(&frames).1, events);
+    }
+
+    #[test]
+    fn keeps_single_event_longer_than_max_frame_size() {
+        let (events, batch) = batch(1);
+        let frames = reencode_batch_segmented(
+            &batch,
+            Encoding::Postcard,
+            Framing::Cobs,
+            Some(0),
+            None,
+            false,
+            4,
+        )
+        .unwrap();
+        assert_eq!(frames.len(), 1);
+        assert!(frames[0].len() > 4);
+        assert_eq!(decode_frames(&frames).1, events);
+    }
+}
@@ -0,0 +1,211 @@
+use crate::broadcast::Receiver;
+use crate::config::ServerConfig;
+use crate::error::{Error, Result};
+use crate::protocol::{ControlMessage, Framing};
+use crate::server::{spawn_connection, EventBatch, EventBus};
+use crate::session::{self, TimeoutTransport, Transport};
+use quinn::crypto::rustls::QuicServerConfig;
+use quinn::{Connection, Endpoint, Incoming, RecvStream, SendStream};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// The ALPN protocol identifier clients must offer during the QUIC handshake.
+pub const ALPN: &[u8] = b"remote-input";
+
+/// The longest frame accepted from a client (including the challenge response) until the handshake, in bytes.
+const MAX_FRAME_LEN: usize = 1024;
+
+/// Bind the QUIC endpoint [`serve`] accepts connections on to `address`.
+/// QUIC always uses TLS, so `tls_config` is required. Its certificate is shared with the TCP server.
+/// Must be called in the context of the tokio runtime serving the endpoint.
+pub fn bind(address: SocketAddr, tls_config: &rustls::ServerConfig) -> Result<Endpoint> {
+    let mut tls_config = tls_config.clone();
+    tls_config.alpn_protocols = vec![ALPN.to_vec()];
+    let crypto = QuicServerConfig::try_from(tls_config).map_err(|error| {
+        Error::Config(format!("TLS configuration is unsuitable for QUIC: {error}"))
+    })?;
+    Endpoint::server(quinn::ServerConfig::with_crypto(Arc::new(crypto)), address)
+        .map_err(Error::bind("QUIC", &address.to_string()))
+}
+
+/// Accept QUIC connections on `endpoint` until a shutdown is requested, adding a receiver to
+/// `event_bus` for each one and handling it in a task of its own with [`handle_connection`].
+pub async fn serve(
+    endpoint: Endpoint,
+    config: Arc<ServerConfig>,
+    event_bus: EventBus,
+    commands: Sender<ControlMessage>,
+) {
+    let endpoint_name = match endpoint.local_addr() {
+        Ok(address) => format!("QUIC {address}"),
+        Err(_) => "QUIC".to_string(),
+    };
+    while let Some(incoming) = endpoint.accept().await {
+        let address = incoming.remote_address();
+        if crate::shutdown::requested() {
+            info!("[Main] Stopped accepting QUIC connections.");
+            incoming.refuse();
+            return;
+        }
+        if let Some(reason) = config.check_connection(address, &endpoint_name) {
+            warn!("[Main] Rejected QUIC connection from {address}: {reason}.");
+            incoming.refuse();
+            continue;
+        }
+        let Some(slot) = config.acquire_connection_slot() else {
+            warn!(
+                "[Main] Rejected QUIC connection from {address}: {} connections are being handled.",
+                config.max_connections
+            );
+            incoming.refuse();
+            continue;
+        };
+        let config = Arc::clone(&config);
+        let commands = commands.clone();
+        let receiver = event_bus.subscribe();
+        spawn_connection(address.to_string(), slot, async move {
+            handle_connection(incoming, &config, receiver, &commands).await;
+        });
+    }
+}
+
+/// A QUIC connection carrying the authentication and handshake on a client initiated bidirectional
+/// stream and events on a dedicated server initiated unidirectional stream.
+/// Frames are delimited as on TCP.
+struct QuicTransport {
+    connection: Connection,
+    send: SendStream,
+    recv: RecvStream,
+    framing: Framing,
+    max_frame_size: usize,
+    /// Bytes received from `recv` that are not yet part of a returned frame.
+    buffer: Vec<u8>,
+    /// Opened when the first event is sent.
+    events: Option<SendStream>,
+}
+
+impl QuicTransport {
+    /// Remove the first complete frame from `self.buffer`.
+    fn take_frame(&mut self) -> Option<Vec<u8>> {
+        let len = self.framing.frame_len(&self.buffer)?;
+        Some(self.buffer.drain(..len).collect())
+    }
+}
+
+impl Transport for QuicTransport {
+    async fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        Ok(self.send.write_all(frame).await?)
+    }
+
+    async fn recv(&mut self) -> io::Result<Vec<u8>> {
+        loop {
+            if let Some(frame) = self.take_frame() {
+                return Ok(frame);
+            }
+            if self.buffer.len() > self.max_frame_size {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "frame exceeds maximum length",
+                ));
+            }
+            // Reading is cancel safe.
+            let mut chunk = [0u8; MAX_FRAME_LEN];
+            match self.recv.read(&mut chunk).await? {
+                Some(len) => self.buffer.extend_from_slice(&chunk[..len]),
+                None => return Err(io::ErrorKind::UnexpectedEof.into()),
+            }
+        }
+    }
+
+    async fn send_event(&mut self, frame: &[u8]) -> io::Result<()> {
+        let events = match &mut self.events {
+            Some(events) => events,
+            None => self
+                .events
+                .insert(self.connection.open_uni().await.map_err(io::Error::other)?),
+        };
+        Ok(events.write_all(frame).await?)
+    }
+
+    fn set_framing(&mut self, framing: Framing) {
+        self.framing = framing;
+    }
+
+    fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.max_frame_size = max_frame_size;
+    }
+
+    fn is_secure(&self) -> bool {
+        true
+    }
+}
+
+/// Handle a QUIC connection.
+/// The client opens a bidirectional stream by writing an empty frame and is then authenticated with
+/// [`session::authenticate`] using `config.api_keys`. Once authenticated, perform the handshake on that stream and send events with [`session::run`]
+/// on a unidirectional stream opened by the server. Until the client is authenticated, the connection
+/// is closed if opening the stream or a read takes longer than `config.auth_timeout_millis`.
+async fn handle_connection(
+    incoming: Incoming,
+    config: &ServerConfig,
+    receiver: Receiver<EventBatch>,
+    commands: &Sender<ControlMessage>,
+) {
+    let address = incoming.remote_address();
+    info!("[QUIC Client {address}] Connection established.");
+    let connection = match incoming.await {
+        Ok(connection) => connection,
+        Err(error) => {
+            warn!("[QUIC Client {address}] Handshake failed: {error}.");
+            return;
+        }
+    };
+    let accept = async { Ok(connection.accept_bi().await?) };
+    let (send, recv) = match session::timeout(config.auth_timeout(), accept).await {
+        Ok(streams) => streams,
+        Err(error) => {
+            warn!("[QUIC Client {address}] Failed to accept stream: {error}.");
+            return;
+        }
+    };
+    let mut transport = TimeoutTransport::new(
+        QuicTransport {
+            connection: connection.clone(),
+            send,
+            recv,
+            framing: Framing::Cobs,
+            max_frame_size: MAX_FRAME_LEN,
+            buffer: Vec::new(),
+            events: None,
+        },
+        config,
+    );
+
+    // The stream only becomes visible to the server once the client writes to it,
+    // so the client opens it with an empty frame which is otherwise ignored.
+    if let Err(error) = transport.recv().await {
+        warn!("[QUIC Client {address}] Failed to read opening frame: {error}.");
+        return;
+    }
+    let client = format!("QUIC Client {address}");
+    let authenticated =
+        session::accept_client(&mut transport, &client, Some(address.ip()), config).await;
+    let Some((api_key, _)) = authenticated else {
+        connection.close(0u32.into(), b"invalid api key");
+        return;
+    };
+
+    session::run(
+        transport,
+        &format!("QUIC Client {address} ({})", api_key.name),
+        Some(&api_key),
+        receiver,
+        commands,
+        config,
+    )
+    .await;
+    connection.close(0u32.into(), b"session ended");
+}
@@ -0,0 +1,57 @@
+use crate::broadcast::Receiver;
+use crate::error::{Error, Result};
+use crate::protocol::{self, InputEventWrapper};
+use crate::server::{EventBatch, EventBus};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use tracing::warn;
+
+/// Append every event sent on `event_bus` to the file at `path`, one JSON object per line like the
+/// JSON lines server, so that it can be replayed with `remote-input replay` or
+/// `remote-input-client --play`.
+pub fn record_forever(path: &str, event_bus: &EventBus) -> Result<()> {
+    let file = File::options()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(Error::io(format!("unable to open record_file {path}")))?;
+    let receiver = event_bus.subscribe();
+    record_events(BufWriter::new(file), receiver);
+    Ok(())
+}
+
+/// Write the events from `receiver` to `writer` until the event bus is disconnected.
+fn record_events(mut writer: BufWriter<File>, mut receiver: Receiver<EventBatch>) {
+    loop {
+        let Some(events) = receiver.blocking_recv() else {
+            warn!("[Recording] Failed to receive event from bus: disconnected.");
+            return;
+        };
+        for event in protocol::split_batch(&events.events) {
+            let result = protocol::decode_event(event).and_then(|event| {
+                serde_json::to_writer(&mut writer, &event)?;
+                writer.write_all(b"\n")
+            });
+            if let Err(error) = result {
+                warn!("[Recording] Failed to record event: {error}.");
+            }
+        }
+        if let Err(error) = writer.flush() {
+            warn!("[Recording] Failed to write recording: {error}.");
+        }
+    }
+}
+
+/// Read the events of the recording at `path`.
+pub fn read(path: &Path) -> io::Result<Vec<InputEventWrapper>> {
+    let mut events = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        events.push(serde_json::from_str(&line)?);
+    }
+    Ok(events)
+}
@@ -0,0 +1,46 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Remembers credentials which may only be used once until they expire.
+/// Clones share the remembered credentials.
+#[derive(Clone, Default)]
+pub struct ReplayCache(Arc<Mutex<HashMap<Vec<u8>, Instant>>>);
+
+impl ReplayCache {
+    /// Remember `credential` for `lifetime`. Returns `false` if it is already remembered, i.e., replayed.
+    pub fn insert(&self, credential: &[u8], lifetime: Duration) -> bool {
+        let now = Instant::now();
+        let mut credentials = self.0.lock().unwrap();
+        // Forget expired credentials so that the map stays small.
+        credentials.retain(|_, expires| *expires > now);
+        if credentials.contains_key(credential) {
+            return false;
+        }
+        credentials.insert(credential.to_vec(), now + lifetime);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn accepts_credential_once() {
+        let cache = ReplayCache::default();
+        assert!(cache.insert(b"credential", Duration::from_secs(60)));
+        assert!(!cache.insert(b"credential", Duration::from_secs(60)));
+        assert!(cache.clone().insert(b"another", Duration::from_secs(60)));
+        assert!(!cache.clone().insert(b"another", Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn forgets_credential_after_lifetime() {
+        let cache = ReplayCache::default();
+        assert!(cache.insert(b"credential", Duration::from_millis(10)));
+        thread::sleep(Duration::from_millis(20));
+        assert!(cache.insert(b"credential", Duration::from_millis(10)));
+    }
+}
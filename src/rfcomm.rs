@@ -0,0 +1,209 @@
+use crate::config::ServerConfig;
+use crate::protocol::ControlMessage;
+use crate::server::EventBus;
+use crate::tls;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::pin::Pin;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use tokio::io::unix::AsyncFd;
+use tokio::io::{AsyncRead, AsyncWrite, Interest, ReadBuf};
+use tracing::{info, warn};
+
+/// The Bluetooth protocol number of RFCOMM (from `<bluetooth/bluetooth.h>`).
+const BTPROTO_RFCOMM: libc::c_int = 3;
+
+/// `struct sockaddr_rc` from `<bluetooth/rfcomm.h>`.
+#[repr(C)]
+#[derive(Default)]
+struct SockaddrRc {
+    rc_family: libc::sa_family_t,
+    /// The Bluetooth device address in little endian byte order.
+    rc_bdaddr: [u8; 6],
+    rc_channel: u8,
+}
+
+/// Convert the return value of a libc call into an [`io::Result`].
+fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+/// A listening RFCOMM socket, registered with the tokio reactor.
+pub struct RfcommListener {
+    fd: AsyncFd<OwnedFd>,
+}
+
+impl RfcommListener {
+    /// Listen on RFCOMM `channel` (1 to 30) of every local Bluetooth adapter.
+    /// Must be called in the context of a tokio runtime.
+    pub fn bind(channel: u8) -> io::Result<Self> {
+        // SAFETY: `socket` has no memory safety preconditions. The returned descriptor is owned by `fd`.
+        let fd = unsafe {
+            OwnedFd::from_raw_fd(check(libc::socket(
+                libc::AF_BLUETOOTH,
+                libc::SOCK_STREAM | libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK,
+                BTPROTO_RFCOMM,
+            ))?)
+        };
+        let address = SockaddrRc {
+            rc_family: libc::AF_BLUETOOTH as libc::sa_family_t,
+            rc_bdaddr: [0; 6], // BDADDR_ANY
+            rc_channel: channel,
+        };
+        // SAFETY: `address` is a valid `sockaddr_rc` of the given length.
+        check(unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &address as *const SockaddrRc as *const libc::sockaddr,
+                std::mem::size_of::<SockaddrRc>() as libc::socklen_t,
+            )
+        })?;
+        // SAFETY: `fd` is a bound socket.
+        check(unsafe { libc::listen(fd.as_raw_fd(), 8) })?;
+        Ok(Self {
+            fd: AsyncFd::new(fd)?,
+        })
+    }
+
+    /// Wait for a client to connect.
+    pub async fn accept(&self) -> io::Result<RfcommStream> {
+        let mut address = SockaddrRc::default();
+        let fd = self
+            .fd
+            .async_io(Interest::READABLE, |fd| {
+                let mut len = std::mem::size_of::<SockaddrRc>() as libc::socklen_t;
+                // SAFETY: `address` and `len` describe a writable `sockaddr_rc`.
+                // The returned descriptor is owned by the new stream.
+                Ok(unsafe {
+                    OwnedFd::from_raw_fd(check(libc::accept4(
+                        fd.as_raw_fd(),
+                        &mut address as *mut SockaddrRc as *mut libc::sockaddr,
+                        &mut len,
+                        libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK,
+                    ))?)
+                })
+            })
+            .await?;
+        let bdaddr = address.rc_bdaddr;
+        Ok(RfcommStream {
+            file: AsyncFd::new(File::from(fd))?,
+            peer_address: format!(
+                "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
+                bdaddr[5], bdaddr[4], bdaddr[3], bdaddr[2], bdaddr[1], bdaddr[0]
+            ),
+        })
+    }
+}
+
+/// A connected RFCOMM socket, registered with the tokio reactor. Bytes are carried like a TCP stream.
+pub struct RfcommStream {
+    /// The non-blocking socket, which is read and written like a file.
+    file: AsyncFd<File>,
+    peer_address: String,
+}
+
+impl RfcommStream {
+    /// The Bluetooth device address of the client (e.g., "00:11:22:33:44:55").
+    pub fn peer_address(&self) -> &str {
+        &self.peer_address
+    }
+}
+
+impl AsyncRead for RfcommStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            let mut guard = ready!(self.file.poll_read_ready(cx))?;
+            let unfilled = buf.initialize_unfilled();
+            match guard.try_io(|file| file.get_ref().read(unfilled)) {
+                Ok(result) => {
+                    buf.advance(result?);
+                    return Poll::Ready(Ok(()));
+                }
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+impl AsyncWrite for RfcommStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            let mut guard = ready!(self.file.poll_write_ready(cx))?;
+            match guard.try_io(|file| file.get_ref().write(buf)) {
+                Ok(result) => return Poll::Ready(result),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // SAFETY: the descriptor is valid for the lifetime of `self`.
+        let result = check(unsafe { libc::shutdown(self.file.as_raw_fd(), libc::SHUT_WR) });
+        Poll::Ready(result.map(drop))
+    }
+}
+
+/// Accept RFCOMM connections from `listener` (bound to `channel`) until a shutdown is requested,
+/// adding a receiver to `event_bus` for each one and handling it in a task of its own like a TCP
+/// connection with [`crate::server::handle_connection`], so clients must answer the API key
+/// challenge and perform the handshake.
+pub async fn serve(
+    listener: RfcommListener,
+    channel: u8,
+    config: Arc<ServerConfig>,
+    event_bus: EventBus,
+    commands: Sender<ControlMessage>,
+) {
+    loop {
+        let result = listener.accept().await;
+        if crate::shutdown::requested() {
+            info!("[Main] Stopped accepting RFCOMM connections.");
+            return;
+        }
+        match result {
+            Ok(stream) => {
+                let stream = tls::Stream::Rfcomm(stream);
+                let Some(slot) = config.acquire_connection_slot() else {
+                    warn!(
+                        "[Main] Rejected RFCOMM connection from {}: {} connections are being handled.",
+                        stream.peer_name(),
+                        config.max_connections
+                    );
+                    continue;
+                };
+                config.audit.record(
+                    &stream.peer_name(),
+                    &format!("Accepted connection to RFCOMM channel {channel}."),
+                );
+                let config = Arc::clone(&config);
+                let commands = commands.clone();
+                let receiver = event_bus.subscribe();
+                crate::server::spawn_connection(stream.peer_name(), slot, async move {
+                    crate::server::handle_connection(stream, &config, receiver, &commands).await;
+                });
+            }
+            Err(error) => {
+                warn!("[Main] Unable to accept RFCOMM connection: {error}");
+            }
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use evdev::{EventType, InputEvent, RelativeAxisType};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Multiplies the values of relative axis events (e.g., mouse movement and wheels) by a factor per axis.
+/// The fractions lost by rounding are carried over to the next event of the same axis, so that slow
+/// movements scaled by less than one are not lost.
+pub struct RelativeScaling {
+    /// The factor and carried fraction of each scaled axis, indexed by its code.
+    axes: HashMap<u16, (f64, f64)>,
+}
+
+impl RelativeScaling {
+    /// Create the scaling for the factors `factors` named by their axis (e.g., "REL_X").
+    /// Fails with the name of the first unknown axis.
+    pub fn new(factors: &HashMap<String, f64>) -> Result<Self, String> {
+        let axes = factors
+            .iter()
+            .map(|(axis, &factor)| {
+                let axis = RelativeAxisType::from_str(axis).map_err(|_| axis.clone())?;
+                Ok((axis.0, (factor, 0.0)))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self { axes })
+    }
+
+    /// Scale `event` if it is a relative axis event with a factor. Returns `None` if the scaled
+    /// movement rounds to zero, which is then carried over to the next event of the axis.
+    pub fn scale(&mut self, event: InputEvent) -> Option<InputEvent> {
+        if event.event_type() != EventType::RELATIVE {
+            return Some(event);
+        }
+        let Some((factor, carried)) = self.axes.get_mut(&event.code()) else {
+            return Some(event);
+        };
+        let scaled = event.value() as f64 * *factor + *carried;
+        let value = scaled.round();
+        *carried = scaled - value;
+        if value == 0.0 {
+            return None;
+        }
+        let mut raw = *event.as_ref();
+        raw.value = value as i32;
+        Some(InputEvent::from(raw))
+    }
+}
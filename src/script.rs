@@ -0,0 +1,117 @@
+use crate::codes;
+use evdev::InputEvent;
+use rhai::{Array, CallFnOptions, Dynamic, Engine, Map, Scope, AST};
+use std::path::PathBuf;
+use tracing::warn;
+
+/// The most operations one call of `transform` may run, so that a script with an endless loop
+/// cannot stall its device.
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// A Rhai script transforming the events of a device. Its function `transform(event)` is called
+/// with every event as a map with the fields `type`, `code` and `value` and returns:
+/// - the event, changed or not, to forward it,
+/// - `()` to drop it,
+/// - or an array of events to forward instead of it (e.g., to synthesize further events).
+///
+/// `this` is a map which keeps its contents between calls, e.g., to track the state of keys.
+/// `key_code("KEY_A")` and `key_name(30)` convert between the names and codes of keys.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    state: Dynamic,
+}
+
+impl Script {
+    /// Compile the script at `path` and run its top-level statements.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.register_fn("key_code", |name: &str| {
+            codes::key_code(name).map_or(Dynamic::UNIT, |code| Dynamic::from_int(code.into()))
+        });
+        engine.register_fn("key_name", |code: i64| {
+            u16::try_from(code)
+                .ok()
+                .and_then(codes::key_name)
+                .map_or(Dynamic::UNIT, Dynamic::from)
+        });
+        let ast = engine
+            .compile_file(PathBuf::from(path))
+            .map_err(|error| error.to_string())?;
+        let mut scope = Scope::new();
+        engine
+            .run_ast_with_scope(&mut scope, &ast)
+            .map_err(|error| error.to_string())?;
+        Ok(Self {
+            engine,
+            ast,
+            scope,
+            state: Dynamic::from_map(Map::new()),
+        })
+    }
+
+    /// The events `transform` returns for `event`, which keep its time. If the script fails, the
+    /// error is printed and `event` is returned unchanged.
+    pub fn transform(&mut self, event: InputEvent) -> Vec<InputEvent> {
+        let mut map = Map::new();
+        map.insert(
+            "type".into(),
+            Dynamic::from_int(event.event_type().0.into()),
+        );
+        map.insert("code".into(), Dynamic::from_int(event.code().into()));
+        map.insert("value".into(), Dynamic::from_int(event.value().into()));
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut self.state);
+        let result = self
+            .engine
+            .call_fn_with_options::<Dynamic>(
+                options,
+                &mut self.scope,
+                &self.ast,
+                "transform",
+                (map,),
+            )
+            .map_err(|error| error.to_string())
+            .and_then(|result| {
+                if result.is_unit() {
+                    Ok(Vec::new())
+                } else if result.is_array() {
+                    result
+                        .cast::<Array>()
+                        .into_iter()
+                        .map(|item| to_event(item, &event))
+                        .collect()
+                } else {
+                    to_event(result, &event).map(|event| vec![event])
+                }
+            });
+        match result {
+            Ok(events) => events,
+            Err(error) => {
+                warn!("[Script] Unable to transform {event:?}: {error}.");
+                vec![event]
+            }
+        }
+    }
+}
+
+/// The event described by the map `value`, with the time of `original`.
+fn to_event(value: Dynamic, original: &InputEvent) -> Result<InputEvent, String> {
+    let type_name = value.type_name();
+    let map = value
+        .try_cast::<Map>()
+        .ok_or_else(|| format!("expected an event, (), or an array of events, got {type_name}"))?;
+    let field = |name: &str| {
+        map.get(name)
+            .and_then(|value| value.as_int().ok())
+            .ok_or_else(|| format!("event field {name} is missing or not an integer"))
+    };
+    let mut raw = *original.as_ref();
+    raw.type_ = u16::try_from(field("type")?).map_err(|error| error.to_string())?;
+    raw.code = u16::try_from(field("code")?).map_err(|error| error.to_string())?;
+    raw.value = i32::try_from(field("value")?).map_err(|error| error.to_string())?;
+    Ok(InputEvent::from(raw))
+}
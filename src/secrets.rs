@@ -0,0 +1,362 @@
+use crate::config::Config;
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::OnceLock;
+use toml::{Table, Value};
+
+/// The prefix of environment variables overriding configuration values.
+const OVERRIDE_PREFIX: &str = "REMOTE_INPUT_";
+
+/// The `key=value` overrides set with [`set_overrides`].
+static OVERRIDES: OnceLock<Vec<(String, String)>> = OnceLock::new();
+
+/// Parse the contents of a configuration file. If it sets `server.secrets_file`, the tables of that
+/// file are merged into it, its values replacing those of the configuration file. Then values are
+/// overridden by environment variables named like `REMOTE_INPUT_SERVER__ADDRESS` (the prefix and the
+/// upper case keys of `server.address` separated by `__`) and by [`set_overrides`], in that order.
+/// Finally every `${NAME}` in a string value is replaced with the environment variable `NAME`.
+/// Fails if the secrets file is readable by every user or an environment variable is not set.
+pub fn parse_config(data: &str) -> Result<Config, String> {
+    let mut config: Table = toml::from_str(data).map_err(|error| error.to_string())?;
+    if let Some(secrets_file) = secrets_file(&config) {
+        merge(&mut config, read_secrets_file(Path::new(&secrets_file))?);
+    }
+    override_values(
+        &mut config,
+        env::vars(),
+        OVERRIDES.get().map_or(&[], Vec::as_slice),
+    )?;
+    let mut config = Value::Table(config);
+    interpolate(&mut config)?;
+    config.try_into().map_err(|error| error.to_string())
+}
+
+/// Override configuration values with `key=value` pairs (e.g., `server.address=0.0.0.0:8650`)
+/// whenever a configuration is parsed, including when it is reloaded. Only the first call has an
+/// effect.
+pub fn set_overrides(overrides: &[String]) -> Result<(), String> {
+    let overrides = overrides
+        .iter()
+        .map(|pair| {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("override \"{pair}\" is not key=value"))?;
+            Ok((key.to_string(), value.to_string()))
+        })
+        .collect::<Result<_, String>>()?;
+    let _ = OVERRIDES.set(overrides);
+    Ok(())
+}
+
+/// Override the values of `config` with the environment `variables` named like
+/// `REMOTE_INPUT_SERVER__ADDRESS`, then with the `overrides` (see [`parse_config`]).
+fn override_values(
+    config: &mut Table,
+    variables: impl IntoIterator<Item = (String, String)>,
+    overrides: &[(String, String)],
+) -> Result<(), String> {
+    let mut variables: Vec<_> = variables
+        .into_iter()
+        .filter_map(|(name, value)| {
+            let key = name.strip_prefix(OVERRIDE_PREFIX)?;
+            // Other variables with the prefix, e.g., the client's REMOTE_INPUT_API_KEY, name no table.
+            key.contains("__")
+                .then(|| (key.to_lowercase().replace("__", "."), value))
+        })
+        .collect();
+    variables.sort();
+    for (key, value) in variables.iter().chain(overrides) {
+        set(config, key, value)?;
+    }
+    Ok(())
+}
+
+/// Set the value at the dotted `key` of `config`, creating missing tables. `value` is parsed as a
+/// TOML value (e.g., `8650`, `true` or `["a", "b"]`) and taken as a string if it is not one.
+fn set(config: &mut Table, key: &str, value: &str) -> Result<(), String> {
+    let mut names: Vec<_> = key.split('.').collect();
+    let name = names.pop().unwrap_or_default();
+    let mut table = config;
+    for table_name in names {
+        table = match table
+            .entry(table_name)
+            .or_insert_with(|| Value::Table(Table::new()))
+        {
+            Value::Table(table) => table,
+            _ => {
+                return Err(format!(
+                    "unable to override {key}: {table_name} is not a table"
+                ))
+            }
+        };
+    }
+    let value = toml::from_str::<Table>(&format!("value = {value}"))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(value.to_string()));
+    table.insert(name.to_string(), value);
+    Ok(())
+}
+
+/// The `server.secrets_file` set in `config`, if any.
+fn secrets_file(config: &Table) -> Option<String> {
+    config
+        .get("server")?
+        .get("secrets_file")?
+        .as_str()
+        .map(str::to_string)
+}
+
+/// Read the secrets file at `path`, refusing files which every user may read.
+fn read_secrets_file(path: &Path) -> Result<Table, String> {
+    let metadata = fs::metadata(path)
+        .map_err(|error| format!("unable to read secrets file {}: {error}", path.display()))?;
+    if metadata.permissions().mode() & 0o004 != 0 {
+        return Err(format!(
+            "secrets file {} is readable by every user (run chmod o-r)",
+            path.display()
+        ));
+    }
+    let data = fs::read_to_string(path)
+        .map_err(|error| format!("unable to read secrets file {}: {error}", path.display()))?;
+    toml::from_str(&data)
+        .map_err(|error| format!("unable to parse secrets file {}: {error}", path.display()))
+}
+
+/// Merge `secrets` into `config`. Tables are merged recursively and other values are replaced.
+fn merge(config: &mut Table, secrets: Table) {
+    for (name, value) in secrets {
+        match (config.get_mut(&name), value) {
+            (Some(Value::Table(table)), Value::Table(secrets)) => merge(table, secrets),
+            (_, value) => {
+                config.insert(name, value);
+            }
+        }
+    }
+}
+
+/// Replace every `${NAME}` in the string values of `value` with the environment variable `NAME`.
+fn interpolate(value: &mut Value) -> Result<(), String> {
+    match value {
+        Value::String(string) => {
+            let mut interpolated = String::with_capacity(string.len());
+            let mut rest = string.as_str();
+            while let Some(start) = rest.find("${") {
+                let end = rest[start..]
+                    .find('}')
+                    .ok_or_else(|| format!("unterminated ${{ in \"{string}\""))?;
+                let name = &rest[start + 2..start + end];
+                let variable = env::var(name)
+                    .map_err(|error| format!("environment variable {name}: {error}"))?;
+                interpolated.push_str(&rest[..start]);
+                interpolated.push_str(&variable);
+                rest = &rest[start + end + 1..];
+            }
+            interpolated.push_str(rest);
+            *string = interpolated;
+        }
+        Value::Array(values) => values.iter_mut().try_for_each(interpolate)?,
+        Value::Table(table) => table
+            .iter_mut()
+            .try_for_each(|(_, value)| interpolate(value))?,
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// Interpolate the string `value`.
+    fn interpolated(value: &str) -> Result<Value, String> {
+        let mut value = Value::String(value.to_string());
+        interpolate(&mut value)?;
+        Ok(value)
+    }
+
+    #[test]
+    fn interpolates_environment_variables() {
+        env::set_var("REMOTE_INPUT_TEST_USER", "user");
+        env::set_var("REMOTE_INPUT_TEST_PASSWORD", "pass");
+        assert_eq!(
+            interpolated("${REMOTE_INPUT_TEST_USER}:${REMOTE_INPUT_TEST_PASSWORD}@host"),
+            Ok(Value::String("user:pass@host".to_string()))
+        );
+        assert_eq!(
+            interpolated("${REMOTE_INPUT_TEST_USER}${REMOTE_INPUT_TEST_PASSWORD}"),
+            Ok(Value::String("userpass".to_string()))
+        );
+        assert_eq!(
+            interpolated("$REMOTE_INPUT_TEST_USER {REMOTE_INPUT_TEST_USER}"),
+            Ok(Value::String(
+                "$REMOTE_INPUT_TEST_USER {REMOTE_INPUT_TEST_USER}".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn interpolates_nested_values() {
+        env::set_var("REMOTE_INPUT_TEST_KEY", "key");
+        let mut value: Value = toml::from_str(
+            r#"
+server = { api_key = "${REMOTE_INPUT_TEST_KEY}", port = 8650 }
+keys = ["${REMOTE_INPUT_TEST_KEY}1", "${REMOTE_INPUT_TEST_KEY}2"]
+"#,
+        )
+        .unwrap();
+        interpolate(&mut value).unwrap();
+        assert_eq!(value["server"]["api_key"].as_str(), Some("key"));
+        assert_eq!(value["server"]["port"].as_integer(), Some(8650));
+        assert_eq!(value["keys"][1].as_str(), Some("key2"));
+    }
+
+    #[test]
+    fn rejects_unterminated_variable() {
+        let error = interpolated("${REMOTE_INPUT_TEST_USER").unwrap_err();
+        assert!(error.starts_with("unterminated ${"), "{error}");
+    }
+
+    #[test]
+    fn rejects_missing_variable() {
+        let error = interpolated("${REMOTE_INPUT_TEST_MISSING}").unwrap_err();
+        assert!(error.contains("REMOTE_INPUT_TEST_MISSING"), "{error}");
+    }
+
+    #[test]
+    fn merges_tables_recursively() {
+        let mut config: Table = toml::from_str(
+            r#"
+[server]
+address = "0.0.0.0:8650"
+api_key = "public"
+[server.limits]
+max = 1
+min = 0
+"#,
+        )
+        .unwrap();
+        let secrets = toml::from_str(
+            r#"
+[server]
+api_key = "secret"
+[server.limits]
+max = 2
+[mqtt]
+password = "secret"
+"#,
+        )
+        .unwrap();
+        merge(&mut config, secrets);
+        let expected: Table = toml::from_str(
+            r#"
+[server]
+address = "0.0.0.0:8650"
+api_key = "secret"
+[server.limits]
+max = 2
+min = 0
+[mqtt]
+password = "secret"
+"#,
+        )
+        .unwrap();
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn reads_secrets_file_unless_every_user_may_read_it() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "[server]\napi_key = \"secret\"").unwrap();
+        let set_mode = |mode| {
+            fs::set_permissions(file.path(), fs::Permissions::from_mode(mode)).unwrap();
+        };
+
+        set_mode(0o640);
+        let secrets = read_secrets_file(file.path()).unwrap();
+        assert_eq!(secrets["server"]["api_key"].as_str(), Some("secret"));
+
+        set_mode(0o604);
+        let error = read_secrets_file(file.path()).unwrap_err();
+        assert!(error.contains("readable by every user"), "{error}");
+    }
+
+    fn variable(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn overrides_keys_named_by_variables() {
+        let mut config = Table::new();
+        let variables = [
+            variable("REMOTE_INPUT_SERVER__ADDRESS", "0.0.0.0:8650"),
+            variable("REMOTE_INPUT_HARDWARE__DEVICES__NAME", "keyboard"),
+            variable("REMOTE_INPUT_API_KEY", "ignored"),
+            variable("SERVER__ADDRESS", "ignored"),
+        ];
+        override_values(&mut config, variables, &[]).unwrap();
+        let expected: Table = toml::from_str(
+            r#"
+server = { address = "0.0.0.0:8650" }
+hardware = { devices = { name = "keyboard" } }
+"#,
+        )
+        .unwrap();
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn parses_values_as_toml_or_strings() {
+        let mut config = Table::new();
+        for (key, value) in [
+            ("port", "8650"),
+            ("enabled", "true"),
+            ("names", r#"["a", "b"]"#),
+            ("quoted", r#""8650""#),
+            ("address", "0.0.0.0:8650"),
+            ("empty", ""),
+        ] {
+            set(&mut config, key, value).unwrap();
+        }
+        let expected: Table = toml::from_str(
+            r#"
+port = 8650
+enabled = true
+names = ["a", "b"]
+quoted = "8650"
+address = "0.0.0.0:8650"
+empty = ""
+"#,
+        )
+        .unwrap();
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn rejects_key_below_value() {
+        let mut config: Table = toml::from_str("[server]\naddress = \"0.0.0.0:8650\"").unwrap();
+        let error = set(&mut config, "server.address.port", "8650").unwrap_err();
+        assert_eq!(
+            error,
+            "unable to override server.address.port: address is not a table"
+        );
+    }
+
+    #[test]
+    fn overrides_file_with_variables_and_variables_with_flags() {
+        let address = |variables: &[(String, String)], overrides: &[(String, String)]| {
+            let mut config: Table = toml::from_str("[server]\naddress = \"file\"").unwrap();
+            override_values(&mut config, variables.to_vec(), overrides).unwrap();
+            config["server"]["address"].as_str().unwrap().to_string()
+        };
+        let variables = [variable("REMOTE_INPUT_SERVER__ADDRESS", "variable")];
+        let overrides = [variable("server.address", "flag")];
+        assert_eq!(address(&[], &[]), "file");
+        assert_eq!(address(&variables, &[]), "variable");
+        assert_eq!(address(&variables, &overrides), "flag");
+        assert_eq!(address(&[], &overrides), "flag");
+    }
+}
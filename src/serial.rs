@@ -0,0 +1,51 @@
+use crate::broadcast::Receiver;
+use crate::server::{EventBatch, EventBus};
+use std::io::Write;
+use std::thread;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// How long to wait before reopening the serial port after it could not be opened or written.
+const REOPEN_DELAY: Duration = Duration::from_secs(1);
+
+/// How long a write may block before the serial port is considered lost.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Write events to the serial port at `path` with `baud_rate` forever, reopening it if it is lost
+/// (e.g., a USB serial adapter is unplugged).
+/// Events are written exactly as they are broadcast: COBS encoded [`postcard`] messages, each
+/// terminated by a zero byte. There is no API key or handshake, so the port must only be
+/// connected to a trusted device such as a microcontroller.
+pub fn write_forever(path: &str, baud_rate: u32, event_bus: &EventBus) {
+    loop {
+        let mut port = match serialport::new(path, baud_rate)
+            .timeout(WRITE_TIMEOUT)
+            .open()
+        {
+            Ok(port) => port,
+            Err(error) => {
+                warn!("[Serial {path}] Unable to open port: {error}.");
+                thread::sleep(REOPEN_DELAY);
+                continue;
+            }
+        };
+        info!("[Serial {path}] Opened port at {baud_rate} baud.");
+        let receiver = event_bus.subscribe();
+        write_events(path, &mut port, receiver);
+        thread::sleep(REOPEN_DELAY);
+    }
+}
+
+/// Write events from `receiver` to `port` until writing fails.
+fn write_events(path: &str, port: &mut impl Write, mut receiver: Receiver<EventBatch>) {
+    loop {
+        let Some(events) = receiver.blocking_recv() else {
+            warn!("[Serial {path}] Failed to receive event from bus: disconnected.");
+            return;
+        };
+        if let Err(error) = port.write_all(&events.events).and_then(|_| port.flush()) {
+            warn!("[Serial {path}] Failed to write event: {error}.");
+            return;
+        }
+    }
+}
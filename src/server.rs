@@ -0,0 +1,2476 @@
+use crate::audit::AuditLog;
+use crate::broadcast::{self, Broadcast};
+use crate::check::Severity;
+use crate::config::{
+    ApiKey, ApiKeys, Autorepeat, ClientCount, ClientEvent, Config, ConnectionCount, ConnectionSlot,
+    HardwareConfig, HeldChord, KeymapConfig, LedFrame, ServerConfig,
+};
+use crate::error::{Error, Result};
+use crate::feedback::{Feedback, StateChange};
+use crate::filter::EventFilter;
+use crate::force_feedback::ForceFeedback;
+#[cfg(feature = "grpc")]
+use crate::grpc;
+use crate::latency::LatencyLog;
+use crate::macros::Macros;
+#[cfg(feature = "mqtt")]
+use crate::mqtt;
+use crate::multitouch::MultiTouch;
+use crate::protocol::{
+    self, AxisInfo, ControlMessage, DeviceDescriptor, InputEventWrapper, Keymap,
+};
+#[cfg(feature = "quic")]
+use crate::quic;
+use crate::scaling::RelativeScaling;
+#[cfg(feature = "scripting")]
+use crate::script::Script;
+#[cfg(feature = "serial")]
+use crate::serial;
+use crate::session::TimeoutTransport;
+use crate::tap::{DoubleTap, Tap};
+use crate::throttle::Throttle;
+use crate::uevent::UeventMonitor;
+use crate::{
+    as_hex, check, dial_out, encrypted, json_lines, multicast, noise, permissions, recording,
+    rfcomm, secrets, session, shutdown, tls, totp, websocket,
+};
+use evdev::uinput::{VirtualDevice, VirtualDeviceBuilder};
+use evdev::{AttributeSet, Device, EventType, InputEvent, Key, LedType, Synchronization};
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use std::{fs, mem, thread};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{error, info, trace, warn};
+
+/// Serialized events up to and including an `EV_SYN`/`SYN_REPORT` event, back to back.
+/// See [`device_listener`] for details.
+#[derive(Clone)]
+pub(crate) struct EventBatch {
+    /// Incremented for every batch, including batches missed by receivers which were full,
+    /// so that receivers can detect gaps.
+    pub(crate) sequence: u64,
+    /// The position of the device which emitted the events in the configuration.
+    pub(crate) device: u16,
+    /// Shared by every receiver and reused by the [`Serializer`] once all of them dropped it.
+    pub(crate) events: Arc<Vec<u8>>,
+}
+
+/// The bus carrying serialized events from [`device_listener`] to each connection handler.
+pub(crate) type EventBus = Arc<Broadcast<EventBatch>>;
+
+/// Iterate over enumerated devices and print information.
+pub fn list_devices() {
+    println!("path, name, physical_path, vendor:product");
+    for (path, device) in evdev::enumerate() {
+        println!(
+            "{}, {}, {}, {:04x}:{:04x}",
+            path.display(),
+            device.name().unwrap_or("[Unknown]"),
+            device.physical_path().unwrap_or("[Unknown]"),
+            device.input_id().vendor(),
+            device.input_id().product()
+        );
+    }
+}
+
+/// Parses a `vendor:product` pair of hexadecimal USB IDs (e.g., "046d:c31c").
+pub(crate) fn parse_vendor_product(device_name: &str) -> Option<(u16, u16)> {
+    let (vendor, product) = device_name.split_once(':')?;
+    if vendor.len() != 4 || product.len() != 4 {
+        return None;
+    }
+    Some((
+        u16::from_str_radix(vendor, 16).ok()?,
+        u16::from_str_radix(product, 16).ok()?,
+    ))
+}
+
+/// Parses a device name pattern: a regular expression between slashes (e.g., "/^Logitech .* Keyboard$/")
+/// or a glob in which `*` matches any characters and `?` matches one character (e.g., "*Logitech*Keyboard*").
+/// Returns `None` if `device_name` is neither.
+pub(crate) fn parse_name_pattern(
+    device_name: &str,
+) -> Option<std::result::Result<Regex, regex::Error>> {
+    if let Some(pattern) = device_name
+        .strip_prefix('/')
+        .and_then(|pattern| pattern.strip_suffix('/'))
+    {
+        return Some(Regex::new(pattern));
+    }
+    if !device_name.contains(['*', '?']) {
+        return None;
+    }
+    let pattern = regex::escape(device_name)
+        .replace("\\*", ".*")
+        .replace("\\?", ".");
+    Some(Regex::new(&format!("^{pattern}$")))
+}
+
+/// Finds the `Device` selected by `device_name`, which is either its `/dev/input/eventN` path,
+/// its `vendor:product` ID, its physical path, or (as a fallback) its name from `evdev::enumerate()`
+/// or a pattern matching its name (see [`parse_name_pattern`]).
+/// IDs, physical paths and names may be shared by several event nodes, of which the first one is used.
+/// If a pattern matches several devices, they are listed and the one with the lowest path is used.
+pub(crate) fn find_device(device_name: &String) -> Option<Device> {
+    if device_name.starts_with("/dev/") {
+        return Device::open(device_name).ok();
+    }
+    let mut devices: Vec<_> = evdev::enumerate().collect();
+    let vendor_product = parse_vendor_product(device_name);
+    let index = devices
+        .iter()
+        .position(|(_, device)| {
+            vendor_product.is_some_and(|(vendor, product)| {
+                device.input_id().vendor() == vendor && device.input_id().product() == product
+            })
+        })
+        .or_else(|| {
+            devices
+                .iter()
+                .position(|(_, device)| device.physical_path() == Some(device_name.as_str()))
+        })
+        .or_else(|| {
+            devices
+                .iter()
+                .position(|(_, device)| device.name() == Some(device_name.as_str()))
+        })
+        .or_else(|| find_device_by_pattern(&devices, device_name))?;
+    Some(devices.swap_remove(index).1)
+}
+
+/// The index of the device in `devices` with the lowest path whose name matches the pattern
+/// `device_name`, if it is one.
+fn find_device_by_pattern(devices: &[(PathBuf, Device)], device_name: &str) -> Option<usize> {
+    let pattern = match parse_name_pattern(device_name)? {
+        Ok(pattern) => pattern,
+        Err(error) => {
+            warn!("[Find Device] Invalid device name pattern \"{device_name}\": {error}.");
+            return None;
+        }
+    };
+    let mut candidates: Vec<usize> = (0..devices.len())
+        .filter(|&index| {
+            devices[index]
+                .1
+                .name()
+                .is_some_and(|name| pattern.is_match(name))
+        })
+        .collect();
+    candidates.sort_by(|&a, &b| devices[a].0.cmp(&devices[b].0));
+    if candidates.len() > 1 {
+        info!("[Find Device] \"{device_name}\" matches several devices:");
+        for &index in &candidates {
+            info!(
+                "[Find Device] {}, {}",
+                devices[index].0.display(),
+                devices[index].1.name().unwrap_or("[Unknown]")
+            );
+        }
+        info!(
+            "[Find Device] Using {}.",
+            devices[candidates[0]].0.display()
+        );
+    }
+    candidates.first().copied()
+}
+
+/// How often to search for a device which is not present if udev device events cannot be watched.
+const DEVICE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How often to search for a device which is not present in between udev device events, in case
+/// it becomes accessible without being added (e.g., after its permissions are changed).
+const DEVICE_RESCAN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Returns `true` if `error` means that the device was removed (e.g., the keyboard was unplugged).
+fn is_device_removed(error: &io::Error) -> bool {
+    error.raw_os_error() == Some(libc::ENODEV)
+}
+
+/// Finds the device selected by `device_name` (see [`find_device`]). If it is not present (e.g., it
+/// is on a USB hub which is enumerated late), permission errors are explained and it is waited for.
+fn open_device(device_name: &String, component: &str) -> Device {
+    if let Some(device) = find_device(device_name) {
+        return device;
+    }
+    if device_name.starts_with("/dev/") {
+        if let Err(error) = Device::open(device_name) {
+            warn!("[{component}] Unable to open \"{device_name}\": {error}.");
+            permissions::explain_error(&error, component);
+        }
+    } else {
+        permissions::explain_unreadable_devices(component);
+    }
+    info!("[{component}] Waiting for \"{device_name}\" to appear.");
+    let device = wait_for_device(device_name, component);
+    info!("[{component}] Device found.");
+    device
+}
+
+/// Wait for the device selected by `device_name` (see [`find_device`]) after it was removed so that it
+/// is used again once it is plugged back in.
+fn reattach_device(device_name: &String, component: &str) -> Device {
+    info!("[{component}] Device removed. Waiting for \"{device_name}\" to reappear.");
+    let device = wait_for_device(device_name, component);
+    info!("[{component}] Device reattached.");
+    device
+}
+
+/// Search for the device selected by `device_name` whenever udev reports a new input device
+/// until it is found. Falls back to polling if udev device events cannot be received.
+fn wait_for_device(device_name: &String, component: &str) -> Device {
+    let monitor = match UeventMonitor::open() {
+        Ok(monitor) => Some(monitor),
+        Err(error) => {
+            warn!("[{component}] Unable to watch udev device events: {error}. Polling instead.");
+            None
+        }
+    };
+    loop {
+        if let Some(device) = find_device(device_name) {
+            return device;
+        }
+        match &monitor {
+            Some(monitor) => {
+                if let Err(error) = monitor.wait_for_input_device(DEVICE_RESCAN_INTERVAL) {
+                    warn!("[{component}] Unable to receive udev device events: {error}.");
+                    thread::sleep(DEVICE_POLL_INTERVAL);
+                }
+            }
+            None => thread::sleep(DEVICE_POLL_INTERVAL),
+        }
+    }
+}
+
+/// The number of batch buffers each [`Serializer`] keeps for reuse. Receivers which keep up hold
+/// one or two batches at a time.
+const BATCH_POOL_SIZE: usize = 16;
+
+/// Passes the reports of a device to a [`Serializer`] thread of its own, so that serializing,
+/// logging and broadcasting events never hold up reading the device.
+/// Dropping it waits for the reports passed before to be broadcast.
+struct Broadcaster {
+    reports: Option<Sender<Vec<InputEvent>>>,
+    serializer: Option<JoinHandle<()>>,
+}
+
+impl Broadcaster {
+    fn new(
+        max_frame_size: usize,
+        sequence: Arc<Mutex<u64>>,
+        device: u16,
+        event_bus: EventBus,
+    ) -> Self {
+        let serializer = Serializer {
+            sequence,
+            device,
+            event_buffer: vec![0u8; max_frame_size],
+            pool: Vec::new(),
+        };
+        let (reports, receiver) = mpsc::channel();
+        let serializer = thread::spawn(move || serializer.run(&receiver, &event_bus));
+        Self {
+            reports: Some(reports),
+            serializer: Some(serializer),
+        }
+    }
+
+    /// Broadcast the events of `report` as one batch and clear it.
+    fn broadcast(&self, report: &mut Vec<InputEvent>) {
+        if let Some(reports) = &self.reports {
+            let _ = reports.send(mem::take(report));
+        }
+    }
+
+    /// Broadcast `events` followed by an `EV_SYN`/`SYN_REPORT` event as a report of their own.
+    fn broadcast_report(&self, events: impl IntoIterator<Item = InputEvent>) {
+        let mut report: Vec<_> = events.into_iter().collect();
+        report.push(InputEvent::new_now(
+            EventType::SYNCHRONIZATION,
+            Synchronization::SYN_REPORT.0,
+            0,
+        ));
+        self.broadcast(&mut report);
+    }
+}
+
+impl Drop for Broadcaster {
+    fn drop(&mut self) {
+        // The serializer returns once it received every report and the channel is closed.
+        self.reports = None;
+        if let Some(serializer) = self.serializer.take() {
+            let _ = serializer.join();
+        }
+    }
+}
+
+/// Serializes the reports passed by a [`Broadcaster`] into batches of at most `max_frame_size`
+/// bytes, then numbers and broadcasts them.
+struct Serializer {
+    /// The sequence number of the last batch, shared by the serializers of every device.
+    sequence: Arc<Mutex<u64>>,
+    /// The ID of the device, see [`EventBatch::device`].
+    device: u16,
+    /// Holds a serialized event. Events which do not fit are rejected.
+    event_buffer: Vec<u8>,
+    /// The buffers of batches broadcast before, see [`Serializer::share`].
+    pool: Vec<Arc<Vec<u8>>>,
+}
+
+impl Serializer {
+    /// Serialize and broadcast each report received from `reports` on `event_bus` until the
+    /// [`Broadcaster`] is dropped.
+    fn run(mut self, reports: &Receiver<Vec<InputEvent>>, event_bus: &EventBus) {
+        let mut batch = Vec::new();
+        let mut segments = Vec::new();
+        for report in reports {
+            for event in report {
+                self.append(event, &mut batch, &mut segments);
+            }
+            if !batch.is_empty() {
+                segments.push(self.share(&mut batch));
+            }
+            self.send(event_bus, &mut segments);
+        }
+    }
+
+    /// Serialize `event` and append it to `batch`. If `batch` would then be longer than
+    /// `max_frame_size`, the events already in it are first moved to `segments`.
+    fn append(&mut self, event: InputEvent, batch: &mut Vec<u8>, segments: &mut Vec<Arc<Vec<u8>>>) {
+        let serialized_event = match remote_input_wire::encode_event(
+            &protocol::wrap_event(event),
+            &mut self.event_buffer,
+        ) {
+            Err(error) => {
+                warn!("[Serializer] Failed to serialize event: {error}.");
+                return;
+            }
+            Ok(serialized_event) => serialized_event,
+        };
+        trace!(
+            "[Serializer] Serialized event: {}.",
+            as_hex::as_hex(serialized_event)
+        );
+        let len = serialized_event.len();
+        if !batch.is_empty() && batch.len() + len > self.event_buffer.len() {
+            segments.push(self.share(batch));
+        }
+        batch.extend_from_slice(&self.event_buffer[..len]);
+    }
+
+    /// Broadcast `segments` on `event_bus` as one batch with the next sequence number, which the
+    /// segments share, and clear them.
+    /// Receivers which fall behind miss batches on their own and notice the gap in the numbers.
+    fn send(&mut self, event_bus: &EventBus, segments: &mut Vec<Arc<Vec<u8>>>) {
+        if segments.is_empty() {
+            return;
+        }
+        // The sequence number stays locked until the batch is sent, so batches are broadcast in
+        // the order of their numbers.
+        let mut sequence = self.sequence.lock().unwrap();
+        *sequence += 1;
+        for events in segments.drain(..) {
+            event_bus.send(EventBatch {
+                sequence: *sequence,
+                device: self.device,
+                events,
+            });
+        }
+    }
+
+    /// Move the events of `batch` into a buffer shared with the receivers, leaving `batch` empty.
+    /// A pooled buffer which every receiver dropped is swapped with `batch`, so that neither the
+    /// events are copied nor memory is allocated once the pool holds enough buffers.
+    fn share(&mut self, batch: &mut Vec<u8>) -> Arc<Vec<u8>> {
+        match self
+            .pool
+            .iter_mut()
+            .position(|buffer| Arc::get_mut(buffer).is_some())
+        {
+            Some(index) => {
+                let buffer = &mut self.pool[index];
+                if let Some(events) = Arc::get_mut(buffer) {
+                    mem::swap(events, batch);
+                }
+                batch.clear();
+                Arc::clone(buffer)
+            }
+            None => {
+                let buffer = Arc::new(mem::take(batch));
+                if self.pool.len() < BATCH_POOL_SIZE {
+                    self.pool.push(Arc::clone(&buffer));
+                }
+                buffer
+            }
+        }
+    }
+}
+
+/// Broadcast a batch with a key press event for every key of `device` that is currently pressed,
+/// except `ignored_codes`, and the state of every multi-touch slot, so that clients which missed
+/// events can restore the state of the keys and touches. Keys are rewritten by `remap`, and events
+/// which `filter` does not forward are left out.
+fn resync(
+    device: &Device,
+    multi_touch: Option<&MultiTouch>,
+    ignored_codes: &[u16],
+    remap: &HashMap<u16, u16>,
+    filter: &EventFilter,
+    broadcaster: &Broadcaster,
+) {
+    let key_state = match device.get_key_state() {
+        Ok(key_state) => key_state,
+        Err(error) => {
+            warn!("[Device Listener] Unable to get key state: {error}.");
+            return;
+        }
+    };
+    info!("[Device Listener] Resynchronizing key state.");
+    let presses = key_state
+        .iter()
+        .filter(|key| !ignored_codes.contains(&key.code()))
+        .map(|key| remap_key(remap, key.code()))
+        .filter(|&code| filter.forwards_key(code))
+        .map(|code| InputEvent::new_now(EventType::KEY, code, 1));
+    let touches = multi_touch
+        .map(MultiTouch::state)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|event| filter.forwards(event));
+    broadcaster.broadcast_report(presses.chain(touches));
+}
+
+/// The client selected by the digit key `code` in KVM mode: its position in the order of connection
+/// (1 to 9 for KEY_1 to KEY_9), or 0 for every client (KEY_0).
+fn kvm_position(code: u16) -> Option<usize> {
+    match Key(code) {
+        Key::KEY_0 => Some(0),
+        _ if (Key::KEY_1.code()..=Key::KEY_9.code()).contains(&code) => {
+            Some(usize::from(code - Key::KEY_1.code()) + 1)
+        }
+        _ => None,
+    }
+}
+
+/// Broadcast the key events of single taps released by [`DoubleTap::flush`] like those of the
+/// device (rewritten by `remap` and unless `filter` does not forward them), each in a report of its
+/// own.
+fn forward_taps(
+    taps: Vec<InputEvent>,
+    remap: &HashMap<u16, u16>,
+    filter: &EventFilter,
+    pressed_keys: &mut HashSet<u16>,
+    broadcaster: &Broadcaster,
+) {
+    for tap in taps {
+        let tap = InputEvent::new_now(EventType::KEY, remap_key(remap, tap.code()), tap.value());
+        if filter.forwards(&tap) {
+            track_key(pressed_keys, &tap);
+            broadcaster.broadcast_report([tap]);
+        }
+    }
+}
+
+/// Record in `pressed` whether `event` pressed or released a key.
+fn track_key(pressed: &mut HashSet<u16>, event: &InputEvent) {
+    if event.event_type() == EventType::KEY {
+        match event.value() {
+            0 => pressed.remove(&event.code()),
+            _ => pressed.insert(event.code()),
+        };
+    }
+}
+
+/// The code the key `code` is forwarded as, according to `remap`.
+fn remap_key(remap: &HashMap<u16, u16>, code: u16) -> u16 {
+    remap.get(&code).copied().unwrap_or(code)
+}
+
+/// The keys of `device` which are currently pressed, as clients receive them: rewritten by `remap`,
+/// except `ignored_codes` and keys which `filter` does not forward.
+fn held_keys(
+    device: &Device,
+    ignored_codes: &[u16],
+    remap: &HashMap<u16, u16>,
+    filter: &EventFilter,
+) -> Option<HashSet<u16>> {
+    let key_state = match device.get_key_state() {
+        Ok(key_state) => key_state,
+        Err(error) => {
+            warn!("[Device Listener] Unable to get key state: {error}.");
+            return None;
+        }
+    };
+    let held = key_state
+        .iter()
+        .filter(|key| !ignored_codes.contains(&key.code()))
+        .map(|key| remap_key(remap, key.code()))
+        .filter(|&code| filter.forwards_key(code))
+        .collect();
+    Some(held)
+}
+
+/// Broadcast a report releasing the keys in `pressed` which are not in `held` and pressing the keys
+/// in `held` which are not in `pressed`, so that clients see the keys in `held` pressed (e.g.,
+/// modifiers held while the device was paused). `pressed` then holds the keys pressed on clients.
+fn synchronize_keys(
+    pressed: &mut HashSet<u16>,
+    held: HashSet<u16>,
+    event_bus: &EventBus,
+    broadcaster: &Broadcaster,
+) {
+    if !event_bus.has_receivers() {
+        pressed.clear();
+        return;
+    }
+    let releases = pressed
+        .difference(&held)
+        .map(|&code| InputEvent::new_now(EventType::KEY, code, 0));
+    let presses = held
+        .difference(pressed)
+        .map(|&code| InputEvent::new_now(EventType::KEY, code, 1));
+    let events: Vec<_> = releases.chain(presses).collect();
+    if !events.is_empty() {
+        info!("[Device Listener] Synchronizing {} keys.", events.len());
+        broadcaster.broadcast_report(events);
+    }
+    *pressed = held;
+}
+
+/// The descriptor of a device emitting the event types, keys and relative axes of `events`.
+fn describe_recording(events: &[InputEventWrapper]) -> DeviceDescriptor {
+    let mut descriptor = DeviceDescriptor {
+        name: "remote-input replay".to_string(),
+        bus_type: 0x06, // BUS_VIRTUAL
+        vendor: 0,
+        product: 0,
+        version: 0,
+        event_types: 0,
+        keys: vec![0u8; KEY_COUNT / 8],
+        relative_axes: 0,
+        slots: 0,
+        axes: Vec::new(),
+        keymap: None,
+    };
+    // Codes beyond those of the descriptor are left out.
+    for event in events {
+        descriptor.event_types |= 1u32.checked_shl(event.event_type.into()).unwrap_or(0);
+        match EventType(event.event_type) {
+            EventType::KEY if (event.code as usize) < KEY_COUNT => {
+                descriptor.keys[event.code as usize / 8] |= 1 << (event.code % 8);
+            }
+            EventType::RELATIVE => {
+                descriptor.relative_axes |= 1u16.checked_shl(event.code.into()).unwrap_or(0);
+            }
+            _ => {}
+        }
+    }
+    descriptor
+}
+
+/// Broadcast the events of a recording with their original timing whenever a client connects
+/// while it is not being replayed, in batches ending with `EV_SYN`/`SYN_REPORT` events like
+/// [`device_listener`].
+fn replay_forever(
+    events: &[InputEventWrapper],
+    broadcaster: Broadcaster,
+    client_events: Receiver<ClientEvent>,
+) {
+    let mut report = Vec::new();
+    loop {
+        // Wait for a client, forgetting the clients which connected during the last replay.
+        while !matches!(client_events.recv(), Ok(ClientEvent::Connected)) {}
+        while client_events.try_recv().is_ok() {}
+        info!("[Replay] Replaying {} events.", events.len());
+        let start = Instant::now();
+        let first = events.first().map(|event| event.timestamp.since_epoch());
+        for event in events {
+            let offset = first
+                .and_then(|first| event.timestamp.since_epoch().checked_sub(first))
+                .unwrap_or_default();
+            thread::sleep((start + offset).saturating_duration_since(Instant::now()));
+            report.push(InputEvent::new_now(
+                EventType(event.event_type),
+                event.code,
+                event.value,
+            ));
+            if event.event_type == EventType::SYNCHRONIZATION.0
+                && event.code == Synchronization::SYN_REPORT.0
+            {
+                broadcaster.broadcast(&mut report);
+            }
+        }
+        if !report.is_empty() {
+            broadcaster.broadcast(&mut report);
+        }
+        info!("[Replay] Finished replaying.");
+    }
+}
+
+/// Without the "scripting" feature, no scripts are loaded.
+#[cfg(not(feature = "scripting"))]
+pub(crate) enum Script {}
+
+#[cfg(not(feature = "scripting"))]
+impl Script {
+    fn transform(&mut self, _event: InputEvent) -> Vec<InputEvent> {
+        match *self {}
+    }
+}
+
+/// Load the script at `path` transforming the events of `device`.
+#[cfg(feature = "scripting")]
+pub(crate) fn load_script(path: &str, device: &str) -> Result<Script> {
+    Script::load(path)
+        .map_err(|error| Error::Config(format!("unable to load script of \"{device}\": {error}")))
+}
+
+/// Scripts cannot be ignored like other options of disabled features, since they may drop events
+/// which must not be forwarded.
+#[cfg(not(feature = "scripting"))]
+pub(crate) fn load_script(_path: &str, device: &str) -> Result<Script> {
+    Err(Error::Config(format!(
+        "the script of \"{device}\" requires the \"scripting\" feature"
+    )))
+}
+
+/// How long [`device_listener`] waits for input events before checking for control messages.
+const COMMAND_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long [`exit_on_shutdown`] waits for the device threads to release the keys pressed on clients,
+/// the devices and their LEDs.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long [`exit_on_shutdown`] waits for sessions to send the remaining events and close.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(1);
+
+/// How often [`exit_on_shutdown`] checks whether the device threads and sessions are done.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Wait up to `timeout` for `device` to have input events to fetch.
+/// Returns `false` if there are none yet or waiting failed (e.g., it was interrupted).
+fn wait_for_events(device: &Device, timeout: Duration) -> bool {
+    let mut poll_fd = libc::pollfd {
+        fd: device.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: `poll_fd` is a single valid `pollfd` for the duration of the call.
+    unsafe { libc::poll(&mut poll_fd, 1, timeout.as_millis() as libc::c_int) > 0 }
+}
+
+/// The number of key codes (`KEY_CNT` of Linux), which sizes the key bitmap of a [`DeviceDescriptor`].
+const KEY_COUNT: usize = 0x300;
+
+/// The identity and capabilities of `device`, with the configured `keymap`.
+fn read_device_descriptor(
+    device: &Device,
+    keymap: Option<Keymap>,
+    remap: &HashMap<u16, u16>,
+    filter: &EventFilter,
+) -> DeviceDescriptor {
+    let input_id = device.input_id();
+    let mut keys = vec![0u8; KEY_COUNT / 8];
+    for key in device.supported_keys().into_iter().flatten() {
+        let code = remap_key(remap, key.code());
+        if filter.forwards_key(code) {
+            keys[code as usize / 8] |= 1 << (code % 8);
+        }
+    }
+    DeviceDescriptor {
+        name: device.name().unwrap_or_default().to_string(),
+        bus_type: input_id.bus_type().0,
+        vendor: input_id.vendor(),
+        product: input_id.product(),
+        version: input_id.version(),
+        event_types: device
+            .supported_events()
+            .iter()
+            .fold(0, |types, event_type| types | 1 << event_type.0),
+        keys,
+        relative_axes: device
+            .supported_relative_axes()
+            .into_iter()
+            .flatten()
+            .fold(0, |axes, axis| axes | 1 << axis.0),
+        slots: MultiTouch::new(device).map_or(0, |multi_touch| multi_touch.slot_count() as u16),
+        axes: read_axes(device),
+        keymap,
+    }
+}
+
+/// The range and resolution of every absolute axis of `device`.
+fn read_axes(device: &Device) -> Vec<AxisInfo> {
+    let Some(supported_axes) = device.supported_absolute_axes() else {
+        return Vec::new();
+    };
+    let abs_state = match device.get_abs_state() {
+        Ok(abs_state) => abs_state,
+        Err(error) => {
+            warn!("[Device Listener] Unable to get absolute axes: {error}.");
+            return Vec::new();
+        }
+    };
+    supported_axes
+        .iter()
+        .map(|axis| {
+            let absinfo = abs_state[axis.0 as usize];
+            AxisInfo {
+                code: axis.0,
+                minimum: absinfo.minimum,
+                maximum: absinfo.maximum,
+                fuzz: absinfo.fuzz,
+                flat: absinfo.flat,
+                resolution: absinfo.resolution,
+            }
+        })
+        .collect()
+}
+
+/// Create a virtual device emitting `keys` to the local system, or `None` if there are no such keys.
+fn create_passthrough_device(keys: &[Key]) -> Option<VirtualDevice> {
+    if keys.is_empty() {
+        return None;
+    }
+    let mut key_set = AttributeSet::<Key>::new();
+    for &key in keys {
+        key_set.insert(key);
+    }
+    let result = VirtualDeviceBuilder::new()
+        .and_then(|builder| builder.name("remote-input passthrough").with_keys(&key_set))
+        .and_then(|builder| builder.build());
+    match result {
+        Ok(device) => Some(device),
+        Err(error) => {
+            warn!("[Device Listener] Unable to create passthrough device: {error}. Forwarding passthrough keys.");
+            None
+        }
+    }
+}
+
+/// Turn `led` of `device` on or off. Does nothing if `device` does not have that LED (e.g., a mouse).
+fn set_led(device: &mut Device, led: LedType, on: bool) -> io::Result<()> {
+    if !device
+        .supported_leds()
+        .is_some_and(|leds| leds.contains(led))
+    {
+        return Ok(());
+    }
+    device.send_events(&[InputEvent::new(EventType::LED, led.0, on as i32)])
+}
+
+/// Listens for input events from the device `hardware.name`, serializes them, and sends them through `event_bus`.
+/// The device is grabbed, preventing input events from propagating.
+/// When the key `hardware.escape` is pressed (while its modifiers are held if it is a chord), grab
+/// or ungrab the device. Otherwise, and for its modifiers, the keys are forwarded.
+/// If `hardware.double_tap_millis` is set, the escape and pause keys only toggle when tapped twice
+/// within it, and single taps are forwarded once it passed (or before the next key of the device).
+/// Grabbing, ungrabbing, pausing and unpausing are also shown by the backends in `hardware.feedback`.
+/// If the device is grabbed and not used for `hardware.idle_ungrab_minutes`, it is ungrabbed and paused.
+/// If `hardware.monitor` is set, the device is never grabbed and the escape key pauses and unpauses instead.
+/// When the key `hardware.pause` is pressed, discard events until it is pressed again.
+/// If `hardware.kvm` is set, pressing a digit key while the escape key is held selects the client
+/// receiving events in `clients` (see [`kvm_position`]) instead of toggling.
+/// [`ControlMessage`]s received from `commands` are applied within [`COMMAND_POLL_INTERVAL`].
+/// Grabbing, ungrabbing, pausing and unpausing are recorded in `audit`.
+/// Every event except the escape and pause keys is first transformed by `script` (if any), which may
+/// change, drop or add events.
+/// Relative axis events (e.g., mouse movement) are scaled by `relative_scaling`, and the movement
+/// on axes limited by `throttle` is summed up and forwarded at the end of reports at most at their
+/// maximum rate (or in a report of its own once the device stops moving).
+/// Keys in `hardware.remap` are rewritten to their target before being forwarded. The escape,
+/// pause and passthrough keys are those of the device, before remapping.
+/// At every grab, ungrab, pause and unpause, clients are sent the key presses and releases they
+/// missed (e.g., of modifiers held while pausing), so that no keys are stuck or missing.
+/// Pressing the trigger key of a macro in `hardware.macros` schedules its events, which are sent
+/// (each in its own report) when they are due without delaying the events of the device.
+/// Events which `filter` does not forward (e.g., blocked keys) are dropped after remapping, before
+/// they are serialized.
+/// The descriptor of the device is stored in `device_info` while it is attached.
+/// Keys in `hardware.passthrough` are not forwarded but emitted to the local system by a virtual
+/// (uinput) device while the device is grabbed, so that, e.g., volume keys keep working locally.
+/// Force feedback effects uploaded by clients are played on the device (e.g., a gamepad) and
+/// forgotten when it is removed.
+/// If the device is removed, wait for it to reappear, grab it again if it was grabbed and resynchronize.
+/// Once a shutdown is requested, the keys pressed on clients are released, the device is ungrabbed
+/// and its LED_SCROLLL and LED_CAPSL are reset before the listener returns.
+///
+/// Events are accumulated until an `EV_SYN`/`SYN_REPORT` event (which is included) and then passed to
+/// the [`Serializer`] of `broadcaster`, which converts them into [`InputEventWrapper`], serializes
+/// them with [`postcard`], encodes them with COBS and transmits them over `event_bus` as one
+/// [`EventBatch`], so that multi-axis updates arrive together.
+/// Batches longer than `max_frame_size` bytes are transmitted in segments with the same sequence number,
+/// and events which are longer on their own are rejected.
+/// Use [`protocol::split_batch`] to extract the serialized events (each terminated by 0x00) from a batch.
+#[allow(clippy::too_many_arguments)]
+fn device_listener(
+    hardware: &HardwareConfig,
+    broadcaster: Broadcaster,
+    mut relative_scaling: RelativeScaling,
+    mut throttle: Throttle,
+    filter: EventFilter,
+    mut script: Option<Script>,
+    device_info: Arc<RwLock<Option<DeviceDescriptor>>>,
+    event_bus: EventBus,
+    commands: Receiver<ControlMessage>,
+    audit: AuditLog,
+    clients: ClientCount,
+) {
+    let device_name = &hardware.name;
+    let escape_code = hardware.escape.key.code();
+    let pause_code = hardware.pause.code();
+    info!(
+        "[Device Listener] Searching for device \"{}\".",
+        device_name
+    );
+    let mut keyboard = open_device(device_name, "Device Listener");
+    let keymap = hardware.keymap.as_ref().map(KeymapConfig::keymap);
+    let remap: HashMap<u16, u16> = hardware
+        .remap
+        .iter()
+        .map(|(from, to)| (from.code(), to.code()))
+        .collect();
+    let mut macros = Macros::new(&hardware.macros);
+    let macro_keys = macros.keys();
+    // The descriptor of the device as clients see it, including the keys sent by macros.
+    let describe = |keyboard: &Device| {
+        let mut descriptor = read_device_descriptor(keyboard, keymap.clone(), &remap, &filter);
+        for &code in macro_keys.iter().filter(|&&code| filter.forwards_key(code)) {
+            descriptor.event_types |= 1 << EventType::KEY.0;
+            descriptor.keys[code as usize / 8] |= 1 << (code % 8);
+        }
+        descriptor
+    };
+    // Keys which are never forwarded as they are.
+    let ignored_codes: Vec<u16> = [escape_code, pause_code]
+        .into_iter()
+        .chain(hardware.passthrough.iter().map(|key| key.code()))
+        .chain(macros.triggers())
+        .collect();
+    *device_info.write().unwrap() = Some(describe(&keyboard));
+    let mut multi_touch = MultiTouch::new(&keyboard);
+
+    let mut grabbed = false; // Should reflect keyboard.raw.grabbed
+    let monitor = hardware.monitor; // Never grab the device. `escape_code` then pauses and unpauses.
+    let mut grab_target = !monitor; // The intended state of keyboard.raw.grabbed as controlled by pressing `escape_code`.
+    let mut pause = true; // Events are discarded when pause is true.
+    let mut pause_target = false; // The intended state of pause as controlled by pressing `pause_code`.
+    let idle_timeout = Duration::from_secs(hardware.idle_ungrab_minutes * 60);
+    let mut last_input = Instant::now(); // When the device was last used or grabbed.
+    let mut double_tap = (hardware.double_tap_millis > 0)
+        .then(|| DoubleTap::new(Duration::from_millis(hardware.double_tap_millis)));
+    let mut escape_chord = HeldChord::new(&hardware.escape); // Whether the escape chord is held.
+    let mut escape_used = false; // Whether a KVM hotkey was pressed while `escape_chord` is held, so that it does not toggle.
+    let mut kvm_keys = HashSet::new(); // The KVM hotkeys which are held.
+
+    let mut report = Vec::new(); // Holds the events to forward until the next SYN_REPORT.
+    let mut held_back = false; // Whether `throttle` held back events of the current report.
+    let mut pressed_keys = HashSet::new(); // The keys which were forwarded as pressed and not released yet.
+    let mut force_feedback = ForceFeedback::default();
+    let mut passthrough_device = create_passthrough_device(&hardware.passthrough);
+    let feedback = Feedback::new(
+        device_name,
+        &hardware.feedback,
+        hardware.feedback_command.as_deref(),
+    );
+
+    info!("[Device Listener] Listening for events.");
+    loop {
+        // Release the keys pressed on clients and the device before the server exits, so that
+        // neither keys on clients nor the device stay captured.
+        if shutdown::requested() {
+            synchronize_keys(&mut pressed_keys, HashSet::new(), &event_bus, &broadcaster);
+            if grabbed {
+                match keyboard.ungrab() {
+                    Ok(_) => {
+                        info!("[Device Listener] Ungrabbed device.");
+                        audit.record("Device Listener", "Ungrabbed device.");
+                    }
+                    Err(error) => warn!("[Device Listener] Unable to ungrab device: {error}."),
+                }
+            }
+            let leds = [(LedType::LED_SCROLLL, grabbed), (LedType::LED_CAPSL, pause)];
+            for (led, _) in leds.into_iter().filter(|&(_, on)| on) {
+                if let Err(error) = set_led(&mut keyboard, led, false) {
+                    warn!("[Device Listener] Unable to reset {led:?}: {error}.");
+                }
+            }
+            return;
+        }
+
+        // Apply control messages from clients.
+        while let Ok(command) = commands.try_recv() {
+            match command {
+                ControlMessage::Pause => pause_target = true,
+                ControlMessage::Resume => pause_target = false,
+                ControlMessage::Grab => grab_target = !monitor,
+                ControlMessage::Ungrab => grab_target = false,
+                ControlMessage::SetLed { led, on } => {
+                    if let Err(error) = set_led(&mut keyboard, LedType(led), on) {
+                        warn!("[Device Listener] Unable to set LED {led}: {error}.")
+                    }
+                }
+                // Handled by the client's session.
+                ControlMessage::Ack { .. }
+                | ControlMessage::FlowControl { .. }
+                | ControlMessage::RenewSession { .. }
+                | ControlMessage::Pong { .. } => {}
+                // Force feedback is only played by devices which support it.
+                ControlMessage::UploadEffect { .. }
+                | ControlMessage::PlayEffect { .. }
+                | ControlMessage::EraseEffect { .. }
+                    if keyboard.supported_ff().is_none() => {}
+                ControlMessage::UploadEffect { slot, effect } => {
+                    if let Err(error) = force_feedback.upload(&mut keyboard, slot, effect) {
+                        warn!("[Device Listener] Unable to upload effect {slot}: {error}.")
+                    }
+                }
+                ControlMessage::PlayEffect { slot, count } => {
+                    if let Err(error) = force_feedback.play(slot, count) {
+                        warn!("[Device Listener] Unable to play effect {slot}: {error}.")
+                    }
+                }
+                ControlMessage::EraseEffect { slot } => force_feedback.erase(slot),
+                ControlMessage::Resync => {
+                    if !pause {
+                        resync(
+                            &keyboard,
+                            multi_touch.as_ref(),
+                            &ignored_codes,
+                            &remap,
+                            &filter,
+                            &broadcaster,
+                        );
+                    }
+                }
+            }
+        }
+
+        // Release a device which was not used for `idle_timeout`, e.g., after walking away from it.
+        if grabbed && !idle_timeout.is_zero() && last_input.elapsed() >= idle_timeout {
+            let message = format!(
+                "No input for {} minutes. Ungrabbing and pausing.",
+                hardware.idle_ungrab_minutes
+            );
+            info!("[Device Listener] {message}");
+            audit.record("Device Listener", &message);
+            grab_target = false;
+            pause_target = true;
+            last_input = Instant::now();
+        }
+
+        // Grab and ungrab device as needed to reach `grab_target`.
+        // If that fails, prevent retrying by setting `grab_target` to `grabbed`.
+        // Send LED_SCROLLL events to display `grabbed`.
+        let (was_grabbed, was_paused) = (grabbed, pause);
+        if grabbed != grab_target {
+            if grab_target {
+                match keyboard.grab() {
+                    Ok(_) => {
+                        info!("[Device Listener] Grabbed device.");
+                        audit.record("Device Listener", "Grabbed device.");
+                        feedback.show(StateChange::Grabbed);
+                        last_input = Instant::now();
+                        if let Err(error) = set_led(&mut keyboard, LedType::LED_SCROLLL, true) {
+                            warn!("[Device Listener] Unable to set LED_SCROLLL: {error}.")
+                        };
+                        grabbed = true;
+                    }
+                    Err(error) => {
+                        warn!("[Device Listener] Unable to grab device: {error}.");
+                        permissions::explain_error(&error, "Device Listener");
+                        grab_target = false;
+                    }
+                }
+            } else {
+                match keyboard.ungrab() {
+                    Ok(_) => {
+                        info!("[Device Listener] Ungrabbed device.");
+                        audit.record("Device Listener", "Ungrabbed device.");
+                        feedback.show(StateChange::Ungrabbed);
+                        if let Err(error) = set_led(&mut keyboard, LedType::LED_SCROLLL, false) {
+                            warn!("[Device Listener] Unable to reset LED_SCROLLL: {error}.")
+                        };
+                        grabbed = false;
+                    }
+                    Err(error) => {
+                        warn!("[Device Listener] Unable to ungrab device: {error}.");
+                        grab_target = true;
+                    }
+                }
+            }
+        }
+
+        if pause != pause_target {
+            pause ^= true;
+            let message = format!(
+                "{} event transmission.",
+                if pause { "Paused" } else { "Unpaused" }
+            );
+            info!("[Device Listener] {message}");
+            audit.record("Device Listener", &message);
+            feedback.show(if pause {
+                StateChange::Paused
+            } else {
+                StateChange::Unpaused
+            });
+            if let Err(error) = set_led(&mut keyboard, LedType::LED_CAPSL, pause) {
+                warn!(
+                    "[Device Listener] Unable to {} LED_CAPSL: {error}.",
+                    if pause { "set" } else { "reset" }
+                )
+            };
+        }
+
+        // Bring the keys pressed on clients in line with the device at every grab, ungrab, pause
+        // and unpause: all keys are released while paused, and keys held are pressed otherwise.
+        if (was_grabbed, was_paused) != (grabbed, pause) {
+            let held = if pause {
+                Some(HashSet::new())
+            } else {
+                held_keys(&keyboard, &ignored_codes, &remap, &filter)
+            };
+            if let Some(held) = held {
+                synchronize_keys(&mut pressed_keys, held, &event_bus, &broadcaster);
+            }
+        }
+
+        // Send the events of macros which are due, each in a report of its own, the movement held
+        // back by `throttle` since the last report once its limits allow it and single taps of the
+        // escape and pause keys once their window for a double tap expired.
+        let due = macros.due();
+        let released = throttle.release();
+        let taps = double_tap
+            .as_mut()
+            .map(|double_tap| double_tap.flush(false))
+            .unwrap_or_default();
+        if (!due.is_empty() || !released.is_empty() || !taps.is_empty())
+            && !pause
+            && event_bus.has_receivers()
+        {
+            forward_taps(taps, &remap, &filter, &mut pressed_keys, &broadcaster);
+            for event in due.into_iter().filter(|event| filter.forwards(event)) {
+                track_key(&mut pressed_keys, &event);
+                broadcaster.broadcast_report([event]);
+            }
+            if !released.is_empty() {
+                broadcaster.broadcast_report(released);
+            }
+        }
+
+        // Wait for input events, but not so long that control messages, macro events or held back
+        // movement are delayed.
+        let timeout = [
+            macros.next_due(),
+            throttle.next_due(),
+            double_tap.as_ref().and_then(DoubleTap::next_due),
+        ]
+        .into_iter()
+        .flatten()
+        .fold(COMMAND_POLL_INTERVAL, Duration::min);
+        if !wait_for_events(&keyboard, timeout) {
+            continue;
+        }
+
+        // Process each input event in the kernel ring buffer.
+        let removed = match keyboard.fetch_events() {
+            Ok(events) => {
+                // Events are only forwarded while a client may receive them.
+                let forward = !pause && event_bus.has_receivers();
+                // Pass the events to the script, except LED events and the escape and pause keys.
+                let events = events.flat_map(|event| match script.as_mut() {
+                    Some(script)
+                        if event.event_type() != EventType::LED
+                            && !(event.event_type() == EventType::KEY
+                                && [escape_code, pause_code].contains(&event.code())) =>
+                    {
+                        script.transform(event)
+                    }
+                    _ => vec![event],
+                });
+                for event in events {
+                    // Ignore LED events, most are emitted from `blink_led`.
+                    if event.event_type() == EventType::LED {
+                        continue;
+                    }
+                    last_input = Instant::now();
+
+                    trace!("[Device Listener] Event: {event:?}");
+
+                    // Receive grab/ungrab and pause requests.
+                    // Absorb `escape_code` while it completes the escape chord and all `pause_code` key presses.
+                    if event.event_type() == EventType::KEY {
+                        // The modifiers of the escape chord are forwarded like any other key.
+                        let escape = escape_chord.key(event.code(), event.value());
+                        // Select the client receiving events with a digit key pressed while the escape key is held.
+                        if let Some(position) = kvm_position(event.code()).filter(|_| {
+                            hardware.kvm && escape_chord.is_pressed() && event.value() == 1
+                        }) {
+                            kvm_keys.insert(event.code());
+                            escape_used = true;
+                            if let Some(double_tap) = double_tap.as_mut() {
+                                double_tap.cancel(escape_code);
+                            }
+                            match (clients.select(position), position) {
+                                (true, 0) => {
+                                    info!("[Device Listener] Sending events to every client.")
+                                }
+                                (true, _) => info!(
+                                    "[Device Listener] Sending events to client {position} only."
+                                ),
+                                (false, _) => {
+                                    warn!("[Device Listener] No client {position} is connected.")
+                                }
+                            }
+                            continue;
+                        }
+                        if kvm_keys.contains(&event.code()) {
+                            if event.value() == 0 {
+                                kvm_keys.remove(&event.code());
+                            }
+                            continue;
+                        }
+                        // Forward a single tap held back by `double_tap` before the events of other keys.
+                        if let Some(double_tap) = double_tap.as_mut().filter(|double_tap| {
+                            double_tap
+                                .pending()
+                                .is_some_and(|code| code != event.code())
+                        }) {
+                            let taps = double_tap.flush(true);
+                            if forward {
+                                forward_taps(
+                                    taps,
+                                    &remap,
+                                    &filter,
+                                    &mut pressed_keys,
+                                    &broadcaster,
+                                );
+                            }
+                        }
+                        if escape || event.code() == pause_code {
+                            let mut used = false;
+                            if escape && event.value() == 0 {
+                                used = std::mem::take(&mut escape_used);
+                            }
+                            let tap = match double_tap.as_mut() {
+                                Some(double_tap) => double_tap.key(event.code(), event.value()),
+                                None if event.value() == 0 && !used => Tap::Toggle,
+                                None => Tap::Absorb,
+                            };
+                            match tap {
+                                Tap::Absorb => continue,
+                                Tap::Toggle => {
+                                    if escape && !monitor {
+                                        grab_target ^= true;
+                                    } else {
+                                        pause_target ^= true;
+                                    }
+                                    continue;
+                                }
+                                Tap::Forward => {}
+                            }
+                        }
+                    }
+
+                    // Emit passthrough keys locally instead of forwarding them.
+                    if let Some(passthrough_device) = passthrough_device.as_mut().filter(|_| {
+                        event.event_type() == EventType::KEY
+                            && hardware
+                                .passthrough
+                                .iter()
+                                .any(|key| key.code() == event.code())
+                    }) {
+                        if grabbed {
+                            if let Err(error) = passthrough_device.emit(&[event]) {
+                                warn!(
+                                    "[Device Listener] Unable to pass through {event:?}: {error}."
+                                );
+                            }
+                        }
+                        continue;
+                    }
+
+                    // Expand macros instead of forwarding their trigger keys.
+                    if macros.trigger(&event) {
+                        continue;
+                    }
+
+                    let Some(mut event) = relative_scaling.scale(event) else {
+                        continue;
+                    };
+
+                    // Rewrite remapped keys, keeping the time of the event.
+                    if event.event_type() == EventType::KEY {
+                        let mut raw = *event.as_ref();
+                        raw.code = remap_key(&remap, raw.code);
+                        event = InputEvent::from(raw);
+                    }
+
+                    // Drop events which are never forwarded before they are serialized.
+                    if !filter.forwards(&event) {
+                        continue;
+                    }
+
+                    // Hold back the movement of rate limited axes.
+                    if throttle.hold(&event) {
+                        held_back = true;
+                        continue;
+                    }
+
+                    // Suppress or convert autorepeat events.
+                    if event.event_type() == EventType::KEY && event.value() == 2 {
+                        match hardware.autorepeat {
+                            Autorepeat::Forward => {}
+                            Autorepeat::Suppress => continue,
+                            Autorepeat::PressRelease => {
+                                if forward {
+                                    report.push(InputEvent::new_now(
+                                        EventType::KEY,
+                                        event.code(),
+                                        0,
+                                    ));
+                                }
+                                event = InputEvent::new_now(EventType::KEY, event.code(), 1);
+                            }
+                        }
+                    }
+
+                    // Select the multi-touch slot in every report which updates one.
+                    let slot_event = multi_touch
+                        .as_mut()
+                        .and_then(|multi_touch| multi_touch.track(&event));
+
+                    // Add the movement held back by `throttle` to the end of the report once its
+                    // limits allow it. Reports of only held back events are not sent.
+                    let mut empty_report = false;
+                    if event.event_type() == EventType::SYNCHRONIZATION
+                        && event.code() == Synchronization::SYN_REPORT.0
+                    {
+                        let released = throttle.release();
+                        if forward {
+                            report.extend(released);
+                        }
+                        empty_report = std::mem::take(&mut held_back) && report.is_empty();
+                    }
+
+                    // Add the event to `report`.
+                    if forward && !empty_report {
+                        if let Some(slot_event) = slot_event.filter(|event| filter.forwards(event))
+                        {
+                            report.push(slot_event);
+                        }
+                        track_key(&mut pressed_keys, &event);
+                        report.push(event);
+                    }
+
+                    // Pass the report to the serializer at its end.
+                    if event.event_type() == EventType::SYNCHRONIZATION
+                        && event.code() == Synchronization::SYN_REPORT.0
+                        && !report.is_empty()
+                    {
+                        broadcaster.broadcast(&mut report);
+                    }
+                }
+                false
+            }
+            Err(error) if is_device_removed(&error) => true,
+            Err(error) => {
+                warn!("[Device Listener] Failed to fetch events: {error:?}.");
+                false
+            }
+        };
+        if removed {
+            audit.record("Device Listener", "Device removed.");
+            force_feedback.clear();
+            *device_info.write().unwrap() = None;
+            keyboard = reattach_device(device_name, "Device Listener");
+            *device_info.write().unwrap() = Some(describe(&keyboard));
+            multi_touch = MultiTouch::new(&keyboard);
+            audit.record("Device Listener", "Device reattached.");
+            // The new handle is not grabbed yet, and events of an incomplete report were lost.
+            grabbed = false;
+            report.clear();
+            if let Err(error) = set_led(&mut keyboard, LedType::LED_CAPSL, pause) {
+                warn!("[Device Listener] Unable to restore LED_CAPSL: {error}.")
+            };
+            if !pause {
+                resync(
+                    &keyboard,
+                    multi_touch.as_ref(),
+                    &ignored_codes,
+                    &remap,
+                    &filter,
+                    &broadcaster,
+                );
+            }
+        }
+    }
+}
+
+/// Indicate activity by playing the animation `hardware.led_pattern` on the keyboard LEDs over and over.
+/// When a [`ClientEvent`] is received from `client_events`, play `hardware.connect_led_pattern`,
+/// `hardware.disconnect_led_pattern` or the [`HardwareConfig::select_led_frames`] of the selected
+/// client once in between. LEDs which the device does not have are skipped.
+/// If the device is removed, wait for it to reappear. Once a shutdown is requested, the LEDs of
+/// the patterns are restored to their state before the first frame.
+fn blink_led(hardware: &HardwareConfig, client_events: Receiver<ClientEvent>) {
+    let device_name = &hardware.name;
+    info!("[Blink Led] Searching for device \"{}\".", device_name);
+    let mut keyboard = open_device(device_name, "Blink Led");
+
+    let frames = hardware.led_frames();
+    let connect_frames = hardware.connect_led_frames();
+    let disconnect_frames = hardware.disconnect_led_frames();
+    let has_led = |led: &LedType| {
+        keyboard
+            .supported_leds()
+            .is_some_and(|leds| leds.contains(*led))
+    };
+    if ![&frames, &connect_frames, &disconnect_frames]
+        .into_iter()
+        .flatten()
+        .any(|frame| frame.on.iter().chain(&frame.off).any(has_led))
+    {
+        info!("[Blink Led] The device has none of the LEDs of the patterns. Not blinking.");
+        return;
+    }
+    // The frame restoring the LEDs of the patterns (the select frames only use LED_NUML).
+    let initial_leds = keyboard.get_led_state().unwrap_or_default();
+    let mut restore = LedFrame {
+        on: Vec::new(),
+        off: Vec::new(),
+        millis: 0,
+    };
+    let leds = [&frames, &connect_frames, &disconnect_frames]
+        .into_iter()
+        .flatten()
+        .flat_map(|frame| frame.on.iter().chain(&frame.off))
+        .chain([&LedType::LED_NUML]);
+    for &led in leds {
+        if restore.on.contains(&led) || restore.off.contains(&led) {
+            continue;
+        }
+        if initial_leds.contains(led) {
+            restore.on.push(led);
+        } else {
+            restore.off.push(led);
+        }
+    }
+
+    info!("[Blink Led] Blinking Keyboard LEDs.");
+    loop {
+        for frame in &frames {
+            show_led_frame(&mut keyboard, device_name, frame);
+            // Wait for the duration of the frame, interrupted by client events and the shutdown.
+            let deadline = Instant::now() + Duration::from_millis(frame.millis);
+            while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+                if shutdown::requested() {
+                    info!("[Blink Led] Restoring LEDs.");
+                    show_led_frame(&mut keyboard, device_name, &restore);
+                    return;
+                }
+                let event = match client_events.recv_timeout(remaining.min(COMMAND_POLL_INTERVAL)) {
+                    Ok(event) => event,
+                    Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => {
+                        thread::sleep(remaining.min(COMMAND_POLL_INTERVAL));
+                        continue;
+                    }
+                };
+                let select_frames;
+                let event_frames = match event {
+                    ClientEvent::Connected => &connect_frames,
+                    ClientEvent::AllDisconnected => &disconnect_frames,
+                    ClientEvent::Selected(position) => {
+                        select_frames = hardware.select_led_frames(position);
+                        &select_frames
+                    }
+                };
+                for event_frame in event_frames.iter().take_while(|_| !shutdown::requested()) {
+                    show_led_frame(&mut keyboard, device_name, event_frame);
+                    thread::sleep(Duration::from_millis(event_frame.millis));
+                }
+                // Restore the LEDs of the interrupted frame.
+                show_led_frame(&mut keyboard, device_name, frame);
+            }
+        }
+    }
+}
+
+/// Turn the LEDs of `frame` on and off. If `keyboard` was removed, wait for `device_name` to reappear.
+fn show_led_frame(keyboard: &mut Device, device_name: &String, frame: &LedFrame) {
+    let leds = frame
+        .on
+        .iter()
+        .map(|&led| (led, true))
+        .chain(frame.off.iter().map(|&led| (led, false)));
+    for (led, on) in leds {
+        match set_led(keyboard, led, on) {
+            Ok(()) => {}
+            Err(error) if is_device_removed(&error) => {
+                *keyboard = reattach_device(device_name, "Blink Led");
+            }
+            Err(error) => warn!("[Blink Led] Unable to send LED event: {error}."),
+        }
+    }
+}
+
+/// Find the API key sent by `client` as `client_key`, without a trailing zero byte or line ending.
+/// Only used by transports which cannot perform [`session::authenticate`] (JSON lines and gRPC).
+/// `client_key` is either the key itself (see [`ApiKey::verify`]) or a timestamped key
+/// `<unix seconds>:<HMAC-SHA256(key, unix seconds) as hexadecimal digits>`, which is only accepted
+/// once and if the time is within `config.auth_max_age_secs` of the server's clock, so that a recorded
+/// timestamped key cannot be replayed. If `config.require_timestamped_keys` is set, keys themselves are rejected.
+/// Hashed keys are only accepted if the connection is `secure` (see [`ServerConfig::find_hashed_key`]).
+pub(crate) async fn find_api_key(
+    client_key: &[u8],
+    secure: bool,
+    config: &ServerConfig,
+    client: &str,
+    address: Option<IpAddr>,
+) -> Option<ApiKey> {
+    let client_key = client_key
+        .strip_suffix(b"\0")
+        .or_else(|| client_key.strip_suffix(b"\r\n"))
+        .or_else(|| client_key.strip_suffix(b"\n"))
+        .unwrap_or(client_key);
+    let timestamped = std::str::from_utf8(client_key)
+        .ok()
+        .and_then(|client_key| client_key.split_once(':'))
+        .and_then(|(timestamp, mac)| {
+            Some((
+                timestamp,
+                timestamp.parse::<u64>().ok()?,
+                as_hex::from_hex(mac)?,
+            ))
+        });
+    let Some((timestamp_digits, timestamp, mac)) = timestamped else {
+        if config.require_timestamped_keys {
+            warn!("[{client}] Rejected a key without timestamp.");
+            return None;
+        }
+        let api_key = config
+            .api_keys
+            .find(|api_key| !api_key.is_hashed() && api_key.verify(client_key));
+        return match api_key {
+            Some(api_key) => Some(api_key),
+            None => {
+                config
+                    .find_hashed_key(client_key, secure, client, address)
+                    .await
+            }
+        };
+    };
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    if now.abs_diff(timestamp) > config.auth_max_age_secs {
+        warn!("[{client}] Rejected a key with a stale timestamp ({timestamp}, now {now}).");
+        return None;
+    }
+    let api_key = config.api_keys.find(|api_key| {
+        api_key
+            .hmac_key()
+            .is_some_and(|key| ring::hmac::verify(&key, timestamp_digits.as_bytes(), &mac).is_ok())
+    })?;
+    // A timestamp is accepted until `auth_max_age_secs` after it, so remember the key as long.
+    if !config
+        .used_keys
+        .insert(&mac, Duration::from_secs(2 * config.auth_max_age_secs + 1))
+    {
+        warn!("[{client}] Rejected a replayed timestamped key.");
+        return None;
+    }
+    Some(api_key)
+}
+
+/// Handle a TCP connection, which may be wrapped in TLS.
+/// After authenticating the client with [`session::authenticate`] using `config.api_keys`
+/// (closing the connection if a read takes longer than `config.auth_timeout_millis` meanwhile),
+/// perform the handshake and send events with [`session::run`] as permitted for its key.
+/// Sends time out after [`ServerConfig::write_timeout`].
+pub(crate) async fn handle_connection(
+    stream: tls::Stream,
+    config: &ServerConfig,
+    receiver: broadcast::Receiver<EventBatch>,
+    commands: &Sender<ControlMessage>,
+) {
+    let address = stream.peer_name();
+    let ip_address = stream.peer_addr().ok().map(|address| address.ip());
+    info!("[Client {address}] Connection established.");
+    let client = format!("Client {address}");
+    let mut transport = TimeoutTransport::new(session::StreamTransport::new(stream), config);
+    let Some((api_key, _)) =
+        session::accept_client(&mut transport, &client, ip_address, config).await
+    else {
+        return;
+    };
+    session::run(
+        transport,
+        &format!("{client} ({})", api_key.name),
+        Some(&api_key),
+        receiver,
+        commands,
+        config,
+    )
+    .await;
+}
+
+/// Bind a TCP listener to `address`. If `only_v6` is set, an IPv6 listener does not also accept
+/// IPv4 connections, so that `[::]:PORT` can be bound together with `0.0.0.0:PORT`.
+fn bind_tcp_listener(address: &str, only_v6: bool) -> io::Result<std::net::TcpListener> {
+    let address = address.to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "address resolved to nothing")
+    })?;
+    let socket = socket2::Socket::new(
+        socket2::Domain::for_address(address),
+        socket2::Type::STREAM,
+        Some(socket2::Protocol::TCP),
+    )?;
+    if address.is_ipv6() {
+        socket.set_only_v6(only_v6)?;
+    }
+    // Like `std::net::TcpListener::bind`, allow binding while old connections are in TIME_WAIT.
+    socket.set_reuse_address(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(128)?;
+    Ok(socket.into())
+}
+
+/// Run `handler` for the connection from `peer` in a task of its own, counting the connection
+/// with `slot` until it returns. A panic of `handler` is logged and only ends this connection.
+/// Handlers are not queued: each starts at once, and callers reject connections beyond
+/// `max_connections` instead (see [`ServerConfig::acquire_connection_slot`]). On shutdown,
+/// [`exit_on_shutdown`] joins the handlers with [`ConnectionCount::join`].
+pub(crate) fn spawn_connection<F>(peer: String, slot: ConnectionSlot, handler: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    slot.spawn(async move {
+        // Spawned from the slot's task, `handler` runs on the same runtime.
+        let task = tokio::spawn(handler);
+        if let Err(error) = task.await {
+            let Ok(panic) = error.try_into_panic() else {
+                return;
+            };
+            let message = panic
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown panic");
+            error!("[Main] The connection from {peer} failed unexpectedly: {message}.");
+        }
+    });
+}
+
+/// Accept connections from `listener` until a shutdown is requested, adding a receiver to
+/// `event_bus` for each one and passing both to `handler` with [`spawn_connection`]. Connections
+/// rejected by [`ServerConfig::check_connection`], or beyond `max_connections`, are closed
+/// immediately.
+async fn accept_connections<F, H>(
+    listener: std::net::TcpListener,
+    config: &ServerConfig,
+    event_bus: &EventBus,
+    handler: F,
+) where
+    F: Fn(TcpStream, broadcast::Receiver<EventBatch>) -> H,
+    H: Future<Output = ()> + Send + 'static,
+{
+    let endpoint = match listener.local_addr() {
+        Ok(address) => address.to_string(),
+        Err(_) => "UNKNOWN ADDRESS".to_string(),
+    };
+    let listener = match listener
+        .set_nonblocking(true)
+        .and_then(|()| TcpListener::from_std(listener))
+    {
+        Ok(listener) => listener,
+        Err(error) => {
+            error!("[Main] Unable to listen on {endpoint}: {error}.");
+            return;
+        }
+    };
+    loop {
+        let result = tokio::select! {
+            result = listener.accept() => result,
+            () = shutdown::wait() => {
+                info!("[Main] Stopped accepting connections on {endpoint}.");
+                return;
+            }
+        };
+        match result {
+            Ok((stream, address)) => {
+                if let Some(reason) = config.check_connection(address, &endpoint) {
+                    warn!("[Main] Rejected connection from {address}: {reason}.");
+                    continue;
+                }
+                let Some(slot) = config.acquire_connection_slot() else {
+                    warn!(
+                        "[Main] Rejected connection from {address}: {} connections are being handled.",
+                        config.max_connections
+                    );
+                    continue;
+                };
+                let receiver = event_bus.subscribe();
+                spawn_connection(address.to_string(), slot, handler(stream, receiver));
+            }
+            Err(error) => {
+                warn!("[Main] Unable to accept connection: {error}");
+            }
+        }
+    }
+}
+
+/// Once SIGINT or SIGTERM is received, join the `device_threads` ([`device_listener`] and
+/// [`blink_led`]) within [`SHUTDOWN_TIMEOUT`], close `event_bus` so that sessions send their
+/// remaining events and tell their clients, join the handlers of the `connections` on `runtime`
+/// within [`SHUTDOWN_GRACE`] and exit.
+fn exit_on_shutdown(
+    device_threads: Vec<JoinHandle<()>>,
+    runtime: &tokio::runtime::Handle,
+    event_bus: &EventBus,
+    clients: &ClientCount,
+    connections: &ConnectionCount,
+) {
+    while !shutdown::requested() {
+        thread::sleep(COMMAND_POLL_INTERVAL);
+    }
+    info!("[Main] Shutting down.");
+    let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+    while device_threads.iter().any(|thread| !thread.is_finished()) && Instant::now() < deadline {
+        thread::sleep(SHUTDOWN_POLL_INTERVAL);
+    }
+    let (finished, unfinished): (Vec<_>, Vec<_>) = device_threads
+        .into_iter()
+        .partition(JoinHandle::is_finished);
+    for thread in finished {
+        let _ = thread.join();
+    }
+    if !unfinished.is_empty() {
+        warn!(
+            "[Main] {} device threads did not stop in time.",
+            unfinished.len()
+        );
+    }
+    event_bus.close();
+    let joined = runtime.block_on(async {
+        tokio::time::timeout(SHUTDOWN_GRACE, connections.join())
+            .await
+            .is_ok()
+    });
+    if clients.count() > 0 {
+        warn!(
+            "[Main] {} clients were not disconnected in time.",
+            clients.count()
+        );
+    }
+    if !joined {
+        warn!(
+            "[Main] {} connections were not closed in time.",
+            connections.count()
+        );
+    }
+    info!("[Main] Exiting.");
+    crate::daemon::remove_pid_file();
+    std::process::exit(0);
+}
+
+/// How often [`reload_api_keys`] checks whether the configuration file changed.
+const RELOAD_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Replace `api_keys` with the keys of the configuration file at `path` whenever it or `secrets_file`
+/// changes, so that keys can be added and revoked without restarting the server. Other settings are not reloaded.
+/// Clients authenticated with a revoked (or changed) key keep their session unless
+/// `disconnect_revoked_clients` is set.
+fn reload_api_keys(path: &Path, secrets_file: Option<&Path>, api_keys: &ApiKeys) {
+    let modified = || {
+        [Some(path), secrets_file].map(|path| {
+            fs::metadata(path?)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        })
+    };
+    let mut last_modified = modified();
+    loop {
+        thread::sleep(RELOAD_POLL_INTERVAL);
+        let current_modified = modified();
+        if current_modified == last_modified {
+            continue;
+        }
+        last_modified = current_modified;
+
+        let config = match fs::read_to_string(path)
+            .map_err(|error| error.to_string())
+            .and_then(|data| secrets::parse_config(&data))
+        {
+            Ok(config) => config,
+            Err(error) => {
+                warn!("[Main] Unable to reload configuration file: {error}.");
+                continue;
+            }
+        };
+        let new_keys = config.server.accepted_api_keys();
+        if new_keys.is_empty() {
+            warn!("[Main] Ignoring reloaded configuration file without api keys.");
+            continue;
+        }
+        if let Some(api_key) = new_keys.iter().find(|api_key| !api_key.has_valid_hash()) {
+            warn!(
+                "[Main] Ignoring reloaded configuration file: key_hash of api key \"{}\" is not an Argon2 hash.",
+                api_key.name
+            );
+            continue;
+        }
+        let old_keys = api_keys.replace(new_keys.clone());
+        for api_key in old_keys
+            .iter()
+            .filter(|api_key| !new_keys.contains(api_key))
+        {
+            info!("[Main] Revoked API key \"{}\".", api_key.name);
+        }
+        for api_key in new_keys
+            .iter()
+            .filter(|api_key| !old_keys.contains(api_key))
+        {
+            info!("[Main] Added API key \"{}\".", api_key.name);
+        }
+    }
+}
+
+/// Log the warnings [`check::validate`] finds in `config` and fail with its errors, so that the
+/// server refuses exactly the configurations `check-config` reports errors in.
+fn validate(config: &Config) -> Result<()> {
+    let mut errors = Vec::new();
+    for finding in check::validate(config) {
+        let problem = format!("{}: {}", finding.location, finding.message);
+        match finding.severity {
+            Severity::Warning => warn!("[Main] {problem}."),
+            Severity::Error => errors.push(problem),
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::Config(errors.join("; ")))
+    }
+}
+
+/// Serve the devices and clients configured in `config`, which was read from `config_file_path`
+/// (reloaded whenever its API keys change). Never returns unless every TCP listener fails.
+///
+/// # Errors
+///
+/// Fails if the configuration is invalid (see [`check::validate`]) or a listener cannot be bound.
+/// Both are checked before any device or listener is started. Every connection is served from the
+/// calling thread.
+pub fn run(mut config: Config, config_file_path: PathBuf) -> Result<()> {
+    validate(&config)?;
+    let api_keys = config.server.accepted_api_keys();
+    config.server.api_keys.replace(api_keys);
+
+    // Require TOTP codes if a secret is configured.
+    if let Some(totp_secret) = &config.server.totp_secret {
+        let totp = totp::Totp::new(totp_secret)
+            .ok_or_else(|| Error::Config("totp_secret must be base32 encoded".to_string()))?;
+        config.server.totp = Some(totp);
+    }
+
+    // Handle SIGINT and SIGTERM with [`exit_on_shutdown`].
+    shutdown::install().map_err(Error::io("unable to handle SIGINT and SIGTERM"))?;
+
+    // Build the event pipeline of every device, unless a recording is replayed instead.
+    let devices = match config.server.replay_file {
+        Some(_) => Vec::new(),
+        None => config.hardware.all_devices(),
+    };
+    let pipelines = devices
+        .into_iter()
+        .map(|hardware| -> Result<_> {
+            let relative_scaling =
+                RelativeScaling::new(&hardware.relative_scale).map_err(|axis| {
+                    Error::Config(format!(
+                        "unknown relative axis {axis} in relative_scale of \"{}\"",
+                        hardware.name
+                    ))
+                })?;
+            let filter =
+                EventFilter::new(hardware.allow.as_deref(), &hardware.block).map_err(|name| {
+                    Error::Config(format!(
+                        "unknown key or event type {name} in allow or block of \"{}\"",
+                        hardware.name
+                    ))
+                })?;
+            let script = hardware
+                .script
+                .as_deref()
+                .map(|path| {
+                    info!(
+                        "[Main] Loading script \"{path}\" for \"{}\".",
+                        hardware.name
+                    );
+                    load_script(path, &hardware.name)
+                })
+                .transpose()?;
+            let throttle = Throttle::new(&hardware.max_rate).map_err(|error| {
+                Error::Config(format!("{error} in max_rate of \"{}\"", hardware.name))
+            })?;
+            Ok((hardware, relative_scaling, filter, script, throttle))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // Read the recording to replay with [`replay_forever`].
+    let replay = config
+        .server
+        .replay_file
+        .as_ref()
+        .map(|replay_file| {
+            info!("[Main] Replaying \"{}\".", replay_file.display());
+            recording::read(replay_file).map_err(Error::io("unable to read recording"))
+        })
+        .transpose()?;
+
+    // Load the TLS certificate and private key if both are configured.
+    let tls_config = match (
+        &config.server.tls_certificate,
+        &config.server.tls_private_key,
+    ) {
+        (Some(certificate_path), Some(private_key_path)) => {
+            info!("[Main] Loading TLS certificate \"{certificate_path}\" and private key \"{private_key_path}\".");
+            Some(
+                tls::load_config(certificate_path, private_key_path)
+                    .map_err(Error::io("unable to load TLS configuration"))?,
+            )
+        }
+        (None, None) => None,
+        _ => {
+            return Err(Error::Config(
+                "tls_certificate and tls_private_key must be set together".to_string(),
+            ))
+        }
+    };
+
+    // Load the Noise static key if the Noise server is enabled.
+    let noise_config = config
+        .server
+        .noise_address
+        .as_ref()
+        .map(|_| {
+            let private_key = config.server.noise_private_key.as_deref().ok_or_else(|| {
+                Error::Config("noise_private_key must be set when noise_address is set".to_string())
+            })?;
+            let noise_config =
+                noise::NoiseConfig::new(private_key, &config.server.noise_client_keys).map_err(
+                    |error| Error::Config(format!("unable to load Noise configuration: {error}")),
+                )?;
+            info!(
+                "[Main] Noise public key: {}.",
+                as_hex::as_hex(&noise_config.public_key())
+            );
+            Ok(Arc::new(noise_config))
+        })
+        .transpose()?;
+
+    // Connections are accepted and their sockets registered on `runtime`, whose single event loop
+    // (epoll through mio) runs on this thread, so that idle connections and read-only subscribers
+    // cost no thread of their own. Each connection is handled in a task on `workers` instead, so
+    // that TLS, Noise and ChaCha20 crypto, compression and re-encoding never hold up the event
+    // loop. The QUIC and gRPC servers run on `workers` entirely, as their libraries encrypt in
+    // tasks of their own. Argon2 checks run on blocking threads (see [`ServerConfig::find_hashed_key`]).
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(Error::io("unable to start the async runtime"))?;
+    let workers = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .thread_name("remote-input-worker")
+        .build()
+        .map_err(Error::io("unable to start the async runtime"))?;
+    config.server.connections = ConnectionCount::new(workers.handle().clone());
+
+    // Bind every listener before anything is started, so that an address in use stops the server
+    // before it grabs a device.
+    let bind = |listener: &'static str, address: &Option<String>| {
+        address
+            .as_ref()
+            .map(|address| {
+                info!("[Main] Starting {listener} server on {address}.");
+                std::net::TcpListener::bind(address).map_err(Error::bind(listener, address))
+            })
+            .transpose()
+    };
+    let websocket_listener = bind("WebSocket", &config.server.websocket_address)?;
+    let json_lines_listener = bind("JSON lines", &config.server.json_lines_address)?;
+    let noise_listener = bind("Noise", &config.server.noise_address)?;
+    let encrypted_listener = bind("encrypted TCP", &config.server.encrypted_address)?;
+
+    #[cfg(feature = "quic")]
+    let quic_endpoint = config
+        .server
+        .quic_address
+        .as_ref()
+        .map(|quic_address| -> Result<_> {
+            info!("[Main] Starting QUIC server on {quic_address}.");
+            let quic_address = quic_address.parse().map_err(|error| {
+                Error::Config(format!(
+                    "unable to parse quic_address {quic_address}: {error}"
+                ))
+            })?;
+            let tls_config = tls_config.as_ref().ok_or_else(|| {
+                Error::Config(
+                    "quic_address requires tls_certificate and tls_private_key".to_string(),
+                )
+            })?;
+            let _workers = workers.enter();
+            quic::bind(quic_address, tls_config)
+        })
+        .transpose()?;
+    #[cfg(not(feature = "quic"))]
+    if let Some(quic_address) = &config.server.quic_address {
+        warn!(
+            "[Main] Ignoring quic_address {quic_address}: compiled without the \"quic\" feature."
+        );
+    }
+
+    #[cfg(feature = "grpc")]
+    let grpc_listener = config
+        .server
+        .grpc_address
+        .as_ref()
+        .map(|grpc_address| -> Result<_> {
+            info!("[Main] Starting gRPC server on {grpc_address}.");
+            let grpc_address = grpc_address.parse().map_err(|error| {
+                Error::Config(format!(
+                    "unable to parse grpc_address {grpc_address}: {error}"
+                ))
+            })?;
+            let tls_identity = tls_config
+                .as_ref()
+                .map(|_| -> Result<_> {
+                    Ok((
+                        fs::read(config.server.tls_certificate.as_ref().unwrap())
+                            .map_err(Error::io("unable to read TLS certificate"))?,
+                        fs::read(config.server.tls_private_key.as_ref().unwrap())
+                            .map_err(Error::io("unable to read TLS private key"))?,
+                    ))
+                })
+                .transpose()?;
+            let _workers = workers.enter();
+            grpc::bind(grpc_address, tls_identity)
+        })
+        .transpose()?;
+    #[cfg(not(feature = "grpc"))]
+    if let Some(grpc_address) = &config.server.grpc_address {
+        warn!(
+            "[Main] Ignoring grpc_address {grpc_address}: compiled without the \"grpc\" feature."
+        );
+    }
+
+    let rfcomm_listener = config
+        .server
+        .rfcomm_channel
+        .map(|channel| {
+            info!("[Main] Starting RFCOMM server on channel {channel}.");
+            let _runtime = runtime.enter();
+            rfcomm::RfcommListener::bind(channel)
+                .map(|listener| (listener, channel))
+                .map_err(Error::bind("RFCOMM", &format!("channel {channel}")))
+        })
+        .transpose()?;
+
+    let multicast_socket = config
+        .server
+        .multicast_address
+        .as_ref()
+        .map(|multicast_address| -> Result<_> {
+            info!("[Main] Sending events to multicast group {multicast_address}.");
+            let key = config
+                .server
+                .multicast_key
+                .clone()
+                .or_else(|| config.server.api_key.clone())
+                .ok_or_else(|| {
+                    Error::Config("multicast_key must be set without api_key".to_string())
+                })?;
+            let (socket, group) = multicast::bind(multicast_address, config.server.multicast_ttl)?;
+            Ok((socket, group, key))
+        })
+        .transpose()?;
+
+    let addresses = config.server.address.as_slice();
+    let tcp_listeners = addresses
+        .iter()
+        .map(|address| {
+            info!("[Main] Starting TCP server on {address}.");
+            bind_tcp_listener(address, addresses.len() > 1).map_err(Error::bind("TCP", address))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // Open the audit log if one is configured.
+    if let Some(audit_log) = &config.server.audit_log {
+        info!("[Main] Recording audit log in \"{audit_log}\".");
+        config.server.audit =
+            AuditLog::open(audit_log).map_err(Error::io("unable to open audit_log"))?;
+    }
+
+    // Open the latency log if one is configured.
+    if let Some(latency_file) = &config.server.latency_file {
+        info!("[Main] Recording latency in \"{latency_file}\".");
+        config.server.latency_log =
+            LatencyLog::open(latency_file).map_err(Error::io("unable to open latency_file"))?;
+    }
+
+    // Nothing can fail from here on. Spawn [`reload_api_keys`].
+    let api_keys = config.server.api_keys.clone();
+    let secrets_file = config.server.secrets_file.clone();
+    let _ = thread::spawn(move || {
+        reload_api_keys(
+            &config_file_path,
+            secrets_file.as_deref().map(Path::new),
+            &api_keys,
+        );
+    });
+
+    // `event_bus` is shared by every [`device_listener`], which sends events, and every connection,
+    // which subscribes to them without waiting for the device listeners.
+    let event_bus: EventBus = Arc::new(Broadcast::new(100));
+    // `commands` carries control messages from every client to each [`device_listener`].
+    let (commands, command_receiver) = mpsc::channel::<ControlMessage>();
+    let mut listener_commands = Vec::new();
+    let sequence = Arc::new(Mutex::new(0));
+
+    // Spawn [`blink_led`] and [`device_listener`] for every device.
+    let mut device_threads = Vec::new();
+    for (device, (hardware, relative_scaling, filter, script, throttle)) in
+        pipelines.into_iter().enumerate()
+    {
+        let blink_hardware = hardware.clone();
+        let client_events = config.server.clients.subscribe();
+        device_threads.push(thread::spawn(move || {
+            blink_led(&blink_hardware, client_events);
+        }));
+
+        let broadcaster = Broadcaster::new(
+            config.server.max_frame_size,
+            Arc::clone(&sequence),
+            device as u16,
+            Arc::clone(&event_bus),
+        );
+        let device_info = Arc::new(RwLock::new(None));
+        config.server.device_info.push(Arc::clone(&device_info));
+        let transmitter = Arc::clone(&event_bus);
+        let (sender, receiver) = mpsc::channel();
+        listener_commands.push(sender);
+        let audit = config.server.audit.clone();
+        let clients = config.server.clients.clone();
+        device_threads.push(thread::spawn(move || {
+            device_listener(
+                &hardware,
+                broadcaster,
+                relative_scaling,
+                throttle,
+                filter,
+                script,
+                device_info,
+                transmitter,
+                receiver,
+                audit,
+                clients,
+            );
+        }));
+    }
+
+    // Exit with [`exit_on_shutdown`] once the device threads released the devices.
+    let shutdown_runtime = workers.handle().clone();
+    let shutdown_bus = Arc::clone(&event_bus);
+    let shutdown_clients = config.server.clients.clone();
+    let shutdown_connections = config.server.connections.clone();
+    let _ = thread::spawn(move || {
+        exit_on_shutdown(
+            device_threads,
+            &shutdown_runtime,
+            &shutdown_bus,
+            &shutdown_clients,
+            &shutdown_connections,
+        );
+    });
+
+    // Replay the recording with [`replay_forever`] as device 0.
+    if let Some(events) = replay {
+        let broadcaster = Broadcaster::new(
+            config.server.max_frame_size,
+            Arc::clone(&sequence),
+            0,
+            Arc::clone(&event_bus),
+        );
+        let device_info = Arc::new(RwLock::new(Some(describe_recording(&events))));
+        config.server.device_info.push(device_info);
+        let client_events = config.server.clients.subscribe();
+        let _ = thread::spawn(move || {
+            replay_forever(&events, broadcaster, client_events);
+        });
+    }
+
+    // Record every event with [`recording::record_forever`].
+    if let Some(record_file) = &config.server.record_file {
+        info!("[Main] Recording events in \"{record_file}\".");
+        let record_file = record_file.clone();
+        let event_bus = Arc::clone(&event_bus);
+        let _ = thread::spawn(move || {
+            if let Err(error) = recording::record_forever(&record_file, &event_bus) {
+                error!("[Main] Stopped recording: {error}.");
+            }
+        });
+    }
+
+    // Forward every control message to each device.
+    let _ = thread::spawn(move || {
+        for command in command_receiver {
+            for sender in &listener_commands {
+                let _ = sender.send(command);
+            }
+        }
+    });
+
+    let server_config = Arc::new(config.server.clone());
+
+    // Accept WebSocket connections and handle each in a task of its own with [`websocket::handle_connection`].
+    if let Some(websocket_listener) = websocket_listener {
+        let server_config = Arc::clone(&server_config);
+        let tls_config = tls_config.clone();
+        let event_bus = Arc::clone(&event_bus);
+        let commands = commands.clone();
+        runtime.spawn(async move {
+            accept_connections(
+                websocket_listener,
+                &Arc::clone(&server_config),
+                &event_bus,
+                move |stream, receiver| {
+                    let server_config = Arc::clone(&server_config);
+                    let tls_config = tls_config.clone();
+                    let commands = commands.clone();
+                    async move {
+                        let auth_timeout = server_config.auth_timeout();
+                        match tls::Stream::accept(stream, tls_config.as_ref(), auth_timeout).await {
+                            Ok(stream) => {
+                                websocket::handle_connection(
+                                    stream,
+                                    &server_config,
+                                    receiver,
+                                    &commands,
+                                )
+                                .await;
+                            }
+                            Err(error) => warn!("[Main] Unable to start TLS session: {error}."),
+                        }
+                    }
+                },
+            )
+            .await;
+        });
+    }
+
+    // Accept JSON lines debug connections and handle each in a task of its own with [`json_lines::handle_connection`].
+    if let Some(json_lines_listener) = json_lines_listener {
+        let server_config = Arc::clone(&server_config);
+        let tls_config = tls_config.clone();
+        let event_bus = Arc::clone(&event_bus);
+        runtime.spawn(async move {
+            accept_connections(
+                json_lines_listener,
+                &Arc::clone(&server_config),
+                &event_bus,
+                move |stream, receiver| {
+                    let server_config = Arc::clone(&server_config);
+                    let tls_config = tls_config.clone();
+                    async move {
+                        let auth_timeout = server_config.auth_timeout();
+                        match tls::Stream::accept(stream, tls_config.as_ref(), auth_timeout).await {
+                            Ok(stream) => {
+                                json_lines::handle_connection(stream, &server_config, receiver)
+                                    .await;
+                            }
+                            Err(error) => warn!("[Main] Unable to start TLS session: {error}."),
+                        }
+                    }
+                },
+            )
+            .await;
+        });
+    }
+
+    // Accept QUIC connections and handle each in a task of its own with `quic::handle_connection`.
+    #[cfg(feature = "quic")]
+    if let Some(quic_endpoint) = quic_endpoint {
+        workers.spawn(quic::serve(
+            quic_endpoint,
+            Arc::clone(&server_config),
+            Arc::clone(&event_bus),
+            commands.clone(),
+        ));
+    }
+
+    // Serve gRPC calls with `grpc::serve`, forwarding the events of each in a task of its own.
+    #[cfg(feature = "grpc")]
+    if let Some(grpc_listener) = grpc_listener {
+        let server_config = Arc::clone(&server_config);
+        let device_name = config.hardware.name.clone();
+        let event_bus = Arc::clone(&event_bus);
+        workers.spawn(async move {
+            let result = grpc::serve(grpc_listener, server_config, device_name, event_bus).await;
+            if let Err(error) = result {
+                error!("[Main] Stopped the gRPC server: {error}.");
+            }
+        });
+    }
+
+    // Publish events to an MQTT broker with `mqtt::publish`.
+    if let Some(mqtt_address) = &config.server.mqtt_address {
+        #[cfg(feature = "mqtt")]
+        {
+            let topic = config
+                .server
+                .mqtt_topic
+                .clone()
+                .unwrap_or_else(|| mqtt::default_topic(&config.hardware.name));
+            info!("[Main] Publishing events to MQTT broker {mqtt_address} on topic \"{topic}\".");
+            let mqtt_address = mqtt_address.clone();
+            let server_config = Arc::clone(&server_config);
+            let receiver = event_bus.subscribe();
+            let _ = thread::spawn(move || {
+                if let Err(error) = mqtt::publish(&mqtt_address, &topic, &server_config, receiver) {
+                    error!("[Main] Stopped publishing to MQTT: {error}.");
+                }
+            });
+        }
+        #[cfg(not(feature = "mqtt"))]
+        warn!(
+            "[Main] Ignoring mqtt_address {mqtt_address}: compiled without the \"mqtt\" feature."
+        );
+    }
+
+    // Accept Noise connections and handle each in a task of its own with [`noise::handle_connection`].
+    if let (Some(noise_listener), Some(noise_config)) = (noise_listener, noise_config) {
+        let server_config = Arc::clone(&server_config);
+        let event_bus = Arc::clone(&event_bus);
+        let commands = commands.clone();
+        runtime.spawn(async move {
+            accept_connections(
+                noise_listener,
+                &Arc::clone(&server_config),
+                &event_bus,
+                move |stream, receiver| {
+                    let noise_config = Arc::clone(&noise_config);
+                    let server_config = Arc::clone(&server_config);
+                    let commands = commands.clone();
+                    async move {
+                        noise::handle_connection(
+                            stream,
+                            &noise_config,
+                            &server_config,
+                            receiver,
+                            &commands,
+                        )
+                        .await;
+                    }
+                },
+            )
+            .await;
+        });
+    }
+
+    // Accept encrypted plain TCP connections and handle each in a task of its own with [`encrypted::handle_connection`].
+    if let Some(encrypted_listener) = encrypted_listener {
+        let server_config = Arc::clone(&server_config);
+        let event_bus = Arc::clone(&event_bus);
+        let commands = commands.clone();
+        runtime.spawn(async move {
+            accept_connections(
+                encrypted_listener,
+                &Arc::clone(&server_config),
+                &event_bus,
+                move |stream, receiver| {
+                    let server_config = Arc::clone(&server_config);
+                    let commands = commands.clone();
+                    async move {
+                        encrypted::handle_connection(stream, &server_config, receiver, &commands)
+                            .await;
+                    }
+                },
+            )
+            .await;
+        });
+    }
+
+    // Accept Bluetooth RFCOMM connections and handle each in a task of its own with [`handle_connection`].
+    if let Some((rfcomm_listener, rfcomm_channel)) = rfcomm_listener {
+        runtime.spawn(rfcomm::serve(
+            rfcomm_listener,
+            rfcomm_channel,
+            Arc::clone(&server_config),
+            Arc::clone(&event_bus),
+            commands.clone(),
+        ));
+    }
+
+    // Send events to a multicast group with `multicast::send_forever`.
+    if let Some((socket, group, key)) = multicast_socket {
+        let event_bus = Arc::clone(&event_bus);
+        let _ = thread::spawn(move || {
+            multicast::send_forever(&socket, group, key.as_bytes(), &event_bus);
+        });
+    }
+
+    // Write events to a serial port with `serial::write_forever`.
+    if let Some(serial_port) = &config.server.serial_port {
+        #[cfg(feature = "serial")]
+        {
+            info!("[Main] Writing events to serial port {serial_port}.");
+            let serial_port = serial_port.clone();
+            let baud_rate = config.server.serial_baud_rate;
+            let event_bus = Arc::clone(&event_bus);
+            let _ = thread::spawn(move || {
+                serial::write_forever(&serial_port, baud_rate, &event_bus);
+            });
+        }
+        #[cfg(not(feature = "serial"))]
+        warn!(
+            "[Main] Ignoring serial_port {serial_port}: compiled without the \"serial\" feature."
+        );
+    }
+
+    // Connect to each client in `dial_out_addresses` with [`dial_out::connect_forever`].
+    for dial_out_address in &config.server.dial_out_addresses {
+        info!("[Main] Dialing out to {dial_out_address}.");
+        let dial_out_address = dial_out_address.clone();
+        let server_config = Arc::clone(&server_config);
+        let tls_config = tls_config.clone();
+        let event_bus = Arc::clone(&event_bus);
+        let commands = commands.clone();
+        runtime.spawn(async move {
+            dial_out::connect_forever(
+                &dial_out_address,
+                tls_config,
+                server_config,
+                &event_bus,
+                commands,
+            )
+            .await;
+        });
+    }
+
+    // Accept TCP requests on every address and handle each in a task of its own with [`handle_connection`].
+    let tcp_tasks: Vec<_> = tcp_listeners
+        .into_iter()
+        .map(|tcp_listener| {
+            let server_config = Arc::clone(&server_config);
+            let tls_config = tls_config.clone();
+            let commands = commands.clone();
+            let event_bus = Arc::clone(&event_bus);
+            runtime.spawn(async move {
+                accept_connections(
+                    tcp_listener,
+                    &Arc::clone(&server_config),
+                    &event_bus,
+                    move |stream, receiver| {
+                        let server_config = Arc::clone(&server_config);
+                        let tls_config = tls_config.clone();
+                        let commands = commands.clone();
+                        async move {
+                            let auth_timeout = server_config.auth_timeout();
+                            match tls::Stream::accept(stream, tls_config.as_ref(), auth_timeout)
+                                .await
+                            {
+                                Ok(stream) => {
+                                    handle_connection(stream, &server_config, receiver, &commands)
+                                        .await;
+                                }
+                                Err(error) => {
+                                    warn!("[Main] Unable to start TLS session: {error}.");
+                                }
+                            }
+                        }
+                    },
+                )
+                .await;
+            })
+        })
+        .collect();
+    runtime.block_on(async {
+        for tcp_task in tcp_tasks {
+            let _ = tcp_task.await;
+        }
+        // The listeners stop once a shutdown is requested, but the connections are still
+        // served until [`exit_on_shutdown`] exits.
+        if shutdown::requested() {
+            std::future::pending::<()>().await;
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    /// The key "secret" timestamped `offset_secs` after now, with a line ending like JSON lines.
+    fn timestamped_key(offset_secs: i64) -> Vec<u8> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let timestamp = (now.as_secs() as i64 + offset_secs).to_string();
+        let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, b"secret");
+        let mac = ring::hmac::sign(&key, timestamp.as_bytes());
+        format!("{timestamp}:{}\n", as_hex::as_hex(mac.as_ref())).into_bytes()
+    }
+
+    #[tokio::test]
+    async fn accepts_timestamped_key_once() {
+        let config = test_config().server;
+        let key = timestamped_key(0);
+        let api_key = find_api_key(&key, false, &config, "Test", None).await;
+        assert_eq!(api_key.unwrap().name, "default");
+        assert!(find_api_key(&key, false, &config, "Test", None)
+            .await
+            .is_none());
+        // Another timestamp is a new key.
+        assert!(
+            find_api_key(&timestamped_key(-1), false, &config, "Test", None)
+                .await
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_timestamp_outside_window() {
+        let config = test_config().server;
+        let max_age = config.auth_max_age_secs as i64;
+        for offset in [-max_age - 5, max_age + 5] {
+            let key = timestamped_key(offset);
+            assert!(find_api_key(&key, false, &config, "Test", None)
+                .await
+                .is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_plain_key_if_timestamps_are_required() {
+        let mut config = test_config().server;
+        config.require_timestamped_keys = false;
+        assert!(find_api_key(b"secret\n", false, &config, "Test", None)
+            .await
+            .is_some());
+        config.require_timestamped_keys = true;
+        assert!(find_api_key(b"secret\n", false, &config, "Test", None)
+            .await
+            .is_none());
+    }
+}
@@ -0,0 +1,1194 @@
+use crate::broadcast::Receiver;
+use crate::config::{ApiKey, ApiKeys, ClientSlot, Permissions, ServerConfig};
+use crate::protocol::{
+    self, features, ClientHello, Compressor, ControlMessage, DeviceDescriptor, DropPolicy,
+    Encoding, Framing, HandshakeResponse, ServerHello, SessionToken, StreamFrame, Timestamp,
+};
+use crate::server::EventBatch;
+use crate::tls;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::mpsc::Sender;
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+/// How often sessions of clients which negotiated [`features::MULTIPLEX`] check whether devices
+/// were attached or removed.
+const ANNOUNCEMENT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How often sessions check whether their API key was revoked if `disconnect_revoked_clients` is set.
+const REVOCATION_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A connection to a client which carries whole frames in both directions.
+/// Every transport (TCP, WebSocket, Noise, QUIC) implements this after its own authentication step
+/// so that the handshake and event stream are shared by all of them.
+pub trait Transport {
+    /// Send one frame to the client.
+    fn send(&mut self, frame: &[u8]) -> impl Future<Output = io::Result<()>> + Send;
+
+    /// Receive one frame from the client, including its delimiter (see [`Framing`]).
+    /// Cancelling it loses nothing: partially received frames are kept until a later call completes them.
+    fn recv(&mut self) -> impl Future<Output = io::Result<Vec<u8>>> + Send;
+
+    /// Send one event frame to the client.
+    /// Transports that carry events separately from the handshake override this.
+    fn send_event(&mut self, frame: &[u8]) -> impl Future<Output = io::Result<()>> + Send {
+        self.send(frame)
+    }
+
+    /// Delimit frames received after the handshake with `framing`.
+    /// Transports that carry each frame in its own message do not need to delimit frames.
+    fn set_framing(&mut self, _framing: Framing) {}
+
+    /// Fail to receive frames longer than `max_frame_size` instead of buffering them.
+    /// Transports that limit the size of their messages themselves do not need to check frames.
+    fn set_max_frame_size(&mut self, _max_frame_size: usize) {}
+
+    /// Returns `true` if the connection is encrypted and the server authenticated before the client
+    /// is (TLS, Noise, QUIC), so that clients of hashed API keys may send the key itself over it.
+    fn is_secure(&self) -> bool;
+}
+
+/// Fail with [`io::ErrorKind::TimedOut`] if `future` takes longer than `timeout`
+/// (or wait forever if it is `None`).
+pub async fn timeout<T>(
+    timeout: Option<Duration>,
+    future: impl Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, future)
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?,
+        None => future.await,
+    }
+}
+
+/// Fails sends and receives of a transport which take longer than its timeouts, like the timeouts
+/// of a blocking socket.
+pub struct TimeoutTransport<T> {
+    transport: T,
+    /// Set to `config.auth_timeout_millis` until the client is authenticated.
+    pub read_timeout: Option<Duration>,
+    pub write_timeout: Option<Duration>,
+}
+
+impl<T: Transport> TimeoutTransport<T> {
+    /// Wrap `transport` with the timeouts of `config` for a client which is not yet authenticated.
+    pub fn new(transport: T, config: &ServerConfig) -> Self {
+        Self {
+            transport,
+            read_timeout: config.auth_timeout(),
+            write_timeout: config.write_timeout(),
+        }
+    }
+
+    /// Return the wrapped transport.
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+}
+
+impl<T: Transport + Send> Transport for TimeoutTransport<T> {
+    async fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        timeout(self.write_timeout, self.transport.send(frame)).await
+    }
+
+    async fn recv(&mut self) -> io::Result<Vec<u8>> {
+        timeout(self.read_timeout, self.transport.recv()).await
+    }
+
+    async fn send_event(&mut self, frame: &[u8]) -> io::Result<()> {
+        timeout(self.write_timeout, self.transport.send_event(frame)).await
+    }
+
+    fn set_framing(&mut self, framing: Framing) {
+        self.transport.set_framing(framing);
+    }
+
+    fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.transport.set_max_frame_size(max_frame_size);
+    }
+
+    fn is_secure(&self) -> bool {
+        self.transport.is_secure()
+    }
+}
+
+/// A TCP stream, optionally wrapped in TLS, carrying frames back to back.
+pub struct StreamTransport {
+    stream: tls::Stream,
+    framing: Framing,
+    max_frame_size: Option<usize>,
+    /// Bytes received from `stream` that are not yet part of a returned frame.
+    incoming: Vec<u8>,
+}
+
+impl StreamTransport {
+    pub fn new(stream: tls::Stream) -> Self {
+        Self {
+            stream,
+            framing: Framing::Cobs,
+            max_frame_size: None,
+            incoming: Vec::new(),
+        }
+    }
+
+    /// Remove the first complete frame from `self.incoming`.
+    /// Returns an error if it is (or, while incomplete, already is) longer than `self.max_frame_size`.
+    fn take_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let len = self.framing.frame_len(&self.incoming);
+        if let Some(max_frame_size) = self.max_frame_size {
+            if len.unwrap_or(self.incoming.len()) > max_frame_size {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "frame exceeds max_frame_size",
+                ));
+            }
+        }
+        Ok(len.map(|len| self.incoming.drain(..len).collect()))
+    }
+
+    /// Append bytes from `stream` to `self.incoming`, returning an error at the end of the stream.
+    async fn fill(&mut self) -> io::Result<()> {
+        let mut chunk = [0u8; 4096];
+        match self.stream.read(&mut chunk).await? {
+            0 => Err(io::ErrorKind::UnexpectedEof.into()),
+            len => {
+                self.incoming.extend_from_slice(&chunk[..len]);
+                Ok(())
+            }
+        }
+    }
+
+    /// The underlying stream.
+    pub fn get_ref(&self) -> &tls::Stream {
+        &self.stream
+    }
+
+    /// Return the underlying stream and the bytes received from it that are not yet part of a returned frame.
+    pub fn into_inner(self) -> (tls::Stream, Vec<u8>) {
+        (self.stream, self.incoming)
+    }
+}
+
+impl Transport for StreamTransport {
+    async fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.stream.write_all(frame).await?;
+        self.stream.flush().await
+    }
+
+    async fn recv(&mut self) -> io::Result<Vec<u8>> {
+        loop {
+            if let Some(frame) = self.take_frame()? {
+                return Ok(frame);
+            }
+            self.fill().await?;
+        }
+    }
+
+    fn set_framing(&mut self, framing: Framing) {
+        self.framing = framing;
+    }
+
+    fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.max_frame_size = Some(max_frame_size);
+    }
+
+    fn is_secure(&self) -> bool {
+        matches!(self.get_ref(), tls::Stream::Tls(_))
+    }
+}
+
+/// The length of the random challenge sent by [`authenticate`], in bytes.
+pub const NONCE_LEN: usize = 32;
+
+/// Log that a `kind` client (e.g., "WebSocket Client") connected from `address`. Returns its name in
+/// log messages, e.g., "WebSocket Client 192.0.2.1:50000", and its IP address, if it is known.
+pub(crate) fn connection_established(
+    kind: &str,
+    address: io::Result<SocketAddr>,
+) -> (String, Option<IpAddr>) {
+    let (client, ip_address) = match address {
+        Ok(address) => (format!("{kind} {address}"), Some(address.ip())),
+        Err(_) => (format!("{kind} UNKNOWN ADDRESS"), None),
+    };
+    info!("[{client}] Connection established.");
+    (client, ip_address)
+}
+
+/// Authenticate `client` with [`authenticate`] and record the result with
+/// [`ServerConfig::record_authentication`]. Once the client is authenticated, reads no longer time
+/// out. Returns the key and the nonce, or `None` if authentication failed.
+pub(crate) async fn accept_client<T: Transport + Send>(
+    transport: &mut TimeoutTransport<T>,
+    client: &str,
+    address: Option<IpAddr>,
+    config: &ServerConfig,
+) -> Option<(ApiKey, [u8; NONCE_LEN])> {
+    let authenticated = authenticate(transport, client, address, config).await;
+    config.record_authentication(
+        client,
+        address,
+        authenticated.as_ref().map(|(api_key, _)| api_key),
+    );
+    if authenticated.is_some() {
+        transport.read_timeout = None;
+    }
+    authenticated
+}
+
+/// Authenticate a client without the API key ever being sent over the connection.
+/// The server sends a frame holding a random nonce of [`NONCE_LEN`] bytes and the client must reply
+/// with a frame holding HMAC-SHA256(key, nonce), both encoded by COBS, where key is one of `config.api_keys`.
+/// Every nonce is only used once, and replies arriving more than `config.auth_max_age_secs` after the
+/// nonce was sent are rejected, so a recorded HMAC can never open another session.
+/// Clients of hashed keys reply with the key itself instead, which does not depend on the nonce and is
+/// therefore only accepted if the transport [`Transport::is_secure`] (see [`ServerConfig::find_hashed_key`]).
+/// If `config.totp` is set, the client must then send a frame holding the current [`crate::totp::Totp`] code as ASCII digits,
+/// encoded by COBS.
+/// Returns the matching key and the nonce (e.g., to derive keys for the session from it), or `None`
+/// if a reply is wrong or late or the client disconnected.
+/// `address` is the IP address of the client, if it has one.
+pub async fn authenticate<T: Transport>(
+    transport: &mut T,
+    client: &str,
+    address: Option<IpAddr>,
+    config: &ServerConfig,
+) -> Option<(ApiKey, [u8; NONCE_LEN])> {
+    let mut nonce = [0u8; NONCE_LEN];
+    if SystemRandom::new().fill(&mut nonce).is_err() {
+        warn!("[{client}] Failed to generate challenge.");
+        return None;
+    }
+    let result = match Framing::Cobs.frame(&nonce) {
+        Ok(frame) => transport.send(&frame).await,
+        Err(error) => Err(error),
+    };
+    if let Err(error) = result {
+        warn!("[{client}] Failed to send challenge: {error}.");
+        return None;
+    }
+    let challenged = Instant::now();
+    let response = match transport
+        .recv()
+        .await
+        .and_then(|frame| Framing::Cobs.unframe(&frame))
+    {
+        Ok(response) => response,
+        Err(error) => {
+            warn!("[{client}] Failed to receive challenge response: {error}.");
+            return None;
+        }
+    };
+    if challenged.elapsed() > config.auth_max_age() {
+        warn!("[{client}] Challenge response arrived too late.");
+        return None;
+    }
+    let api_key = config.api_keys.find(|api_key| {
+        api_key
+            .hmac_key()
+            .is_some_and(|key| hmac::verify(&key, &nonce, &response).is_ok())
+    });
+    // Clients with a hashed key reply with the key itself instead (see `Secret::Hashed`).
+    let api_key = match api_key {
+        Some(api_key) => Some(api_key),
+        None => {
+            config
+                .find_hashed_key(&response, transport.is_secure(), client, address)
+                .await
+        }
+    };
+    let Some(api_key) = api_key else {
+        warn!("[{client}] Invalid API key.");
+        return None;
+    };
+    if let Some(totp) = &config.totp {
+        let code = match transport
+            .recv()
+            .await
+            .and_then(|frame| Framing::Cobs.unframe(&frame))
+        {
+            Ok(code) => code,
+            Err(error) => {
+                warn!("[{client}] Failed to receive TOTP code: {error}.");
+                return None;
+            }
+        };
+        if !totp.verify(&code) {
+            warn!("[{client}] Invalid TOTP code.");
+            return None;
+        }
+    }
+    info!("[{client}] Authenticated as \"{}\".", api_key.name);
+    Some((api_key, nonce))
+}
+
+/// Perform the protocol version handshake with an authenticated client.
+/// The server sends a [`ServerHello`], the client replies with a [`ClientHello`],
+/// and the server answers with a [`HandshakeResponse`], which rejects the client with `rejection` if set.
+/// Returns the negotiated `(version, features)` or `None` if the client was rejected or disconnected.
+/// `client` names the client in log messages (e.g., "Client 127.0.0.1:50000").
+pub async fn handshake<T: Transport>(
+    transport: &mut T,
+    client: &str,
+    supported_features: u32,
+    rejection: Option<&str>,
+) -> Option<(u16, u32)> {
+    let server_hello = ServerHello {
+        version: protocol::PROTOCOL_VERSION,
+        min_version: protocol::MIN_PROTOCOL_VERSION,
+        features: supported_features,
+    };
+    if let Err(error) = send_message(transport, &server_hello).await {
+        warn!("[{client}] Failed to send server hello: {error}.");
+        return None;
+    }
+
+    let client_hello: ClientHello = match transport.recv().await.and_then(|frame| {
+        protocol::decode(frame).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }) {
+        Ok(client_hello) => client_hello,
+        Err(error) => {
+            warn!("[{client}] Failed to receive client hello: {error}.");
+            return None;
+        }
+    };
+
+    let response = match rejection {
+        Some(reason) => HandshakeResponse::Rejected {
+            reason: reason.to_string(),
+        },
+        None => protocol::negotiate(&client_hello, supported_features),
+    };
+    if let Err(error) = send_message(transport, &response).await {
+        warn!("[{client}] Failed to send handshake response: {error}.");
+        return None;
+    }
+    match response {
+        HandshakeResponse::Accepted { version, features } => {
+            info!("[{client}] Negotiated protocol version {version} with features {features:#x}.");
+            Some((version, features))
+        }
+        HandshakeResponse::Rejected { reason } => {
+            warn!("[{client}] Rejected: {reason}.");
+            None
+        }
+    }
+}
+
+/// Send `message` encoded with [`protocol::encode`] as a frame of its own.
+async fn send_message<T: Transport>(
+    transport: &mut T,
+    message: &impl serde::Serialize,
+) -> io::Result<()> {
+    let frame = protocol::encode(message)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+    transport.send(&frame).await
+}
+
+/// Send `frame` as an event frame, compressing it first if `compressor` is set.
+async fn send_frame<T: Transport>(
+    transport: &mut T,
+    compressor: Option<&mut Compressor>,
+    frame: &[u8],
+) -> io::Result<()> {
+    match compressor {
+        Some(compressor) => transport.send_event(&compressor.compress(frame)?).await,
+        None => transport.send_event(frame).await,
+    }
+}
+
+/// Send a [`StreamFrame`] announcing each device which was attached or removed since it was recorded
+/// in `announced` (`None` if it was not attached), and record it. A device which was replaced (e.g.,
+/// reattached with another descriptor) is announced as removed and attached again.
+/// Announcements bypass flow control. Returns whether anything was sent.
+async fn send_announcements<T: Transport>(
+    transport: &mut T,
+    mut compressor: Option<&mut Compressor>,
+    config: &ServerConfig,
+    announced: &mut [Option<DeviceDescriptor>],
+    encoding: Encoding,
+    framing: Framing,
+) -> io::Result<bool> {
+    let mut sent = false;
+    for (device, (descriptor, announced)) in config.device_info.iter().zip(announced).enumerate() {
+        let descriptor = descriptor.read().unwrap().clone();
+        if descriptor == *announced {
+            continue;
+        }
+        let device = device as u16;
+        let mut messages = Vec::new();
+        if announced.is_some() {
+            messages.push(encoding.serialize(&StreamFrame::<()>::Removed { device })?);
+        }
+        if let Some(descriptor) = &descriptor {
+            messages.push(encoding.serialize(&StreamFrame::<()>::Attached {
+                device,
+                descriptor: descriptor.clone(),
+            })?);
+        }
+        for message in messages {
+            send_frame(
+                transport,
+                compressor.as_deref_mut(),
+                &framing.frame(&message)?,
+            )
+            .await?;
+            sent = true;
+        }
+        *announced = descriptor;
+    }
+    Ok(sent)
+}
+
+/// Event frames waiting for room in the window of a client which negotiated [`features::FLOW_CONTROL`].
+struct FlowControl {
+    /// The most frames sent but not yet acknowledged, which is also the most frames kept pending.
+    window: u32,
+    policy: DropPolicy,
+    in_flight: u32,
+    pending: VecDeque<Vec<u8>>,
+    dropped: u64,
+}
+
+impl FlowControl {
+    /// Queue `frame`, applying `self.policy` if the queue is full.
+    /// Returns `false` if the client must be disconnected.
+    fn push(&mut self, frame: Vec<u8>) -> bool {
+        if self.pending.len() >= self.window as usize {
+            match self.policy {
+                DropPolicy::DropNewest => {
+                    self.dropped += 1;
+                    return true;
+                }
+                DropPolicy::DropOldest => {
+                    self.pending.pop_front();
+                    self.dropped += 1;
+                }
+                DropPolicy::Disconnect => return false,
+            }
+        }
+        self.pending.push_back(frame);
+        true
+    }
+
+    /// Remove the next pending frame if the window has room for it.
+    fn pop(&mut self) -> Option<Vec<u8>> {
+        if self.in_flight >= self.window {
+            return None;
+        }
+        let frame = self.pending.pop_front()?;
+        self.in_flight += 1;
+        Some(frame)
+    }
+}
+
+/// A session which expires unless the client renews it with [`ControlMessage::RenewSession`].
+struct Session<'a> {
+    api_key: &'a ApiKey,
+    token: [u8; 32],
+    renewals: u64,
+    expires_at: Instant,
+}
+
+impl<'a> Session<'a> {
+    /// Start a session for `api_key` with a random token which expires after `lifetime`.
+    fn new(api_key: &'a ApiKey, lifetime: Duration) -> Option<Self> {
+        let mut token = [0u8; 32];
+        SystemRandom::new().fill(&mut token).ok()?;
+        Some(Self {
+            api_key,
+            token,
+            renewals: 0,
+            expires_at: Instant::now() + lifetime,
+        })
+    }
+
+    /// Extend the session by `lifetime` if `mac` is valid (see [`ControlMessage::RenewSession`])
+    /// and the API key is still one of `api_keys`. Returns `false` otherwise.
+    fn renew(&mut self, mac: &[u8], lifetime: Duration, api_keys: &ApiKeys) -> bool {
+        let Some(key) = self.api_key.hmac_key() else {
+            return false;
+        };
+        let mut message = self.token.to_vec();
+        message.extend_from_slice(&self.renewals.to_be_bytes());
+        if hmac::verify(&key, &message, mac).is_err() || !api_keys.contains(self.api_key) {
+            return false;
+        }
+        self.renewals += 1;
+        self.expires_at = Instant::now() + lifetime;
+        true
+    }
+}
+
+/// Handle a [`ControlMessage`] which the client sent: acknowledge frames and change the window of
+/// `flow_control`, renew `session`, record the latency of the answered `ping` or, if `control` was
+/// negotiated, forward it to `commands`. Returns `false` if renewing the session failed.
+#[allow(clippy::too_many_arguments)]
+fn handle_control_message(
+    message: ControlMessage,
+    client: &str,
+    control: bool,
+    flow_control: Option<&mut FlowControl>,
+    session: Option<&mut Session>,
+    session_lifetime: Duration,
+    ping: &mut Option<(u32, Instant, Timestamp)>,
+    commands: &Sender<ControlMessage>,
+    config: &ServerConfig,
+) -> bool {
+    match (message, flow_control) {
+        (ControlMessage::Ack { frames }, Some(flow_control)) => {
+            flow_control.in_flight = flow_control.in_flight.saturating_sub(frames);
+        }
+        (ControlMessage::FlowControl { window, policy }, Some(flow_control)) => {
+            debug!("[{client}] Flow control window {window} with policy {policy:?}.");
+            flow_control.window = window.max(1);
+            flow_control.policy = policy;
+        }
+        (ControlMessage::Ack { .. } | ControlMessage::FlowControl { .. }, None) => {
+            warn!("[{client}] Ignored {message:?}: flow control was not negotiated.");
+        }
+        (ControlMessage::RenewSession { mac }, _) => {
+            let Some(session) = session else {
+                warn!("[{client}] Ignored {message:?}: session tokens were not negotiated.");
+                return true;
+            };
+            if !session.renew(&mac, session_lifetime, &config.api_keys) {
+                return false;
+            }
+            info!("[{client}] Session renewed.");
+        }
+        (ControlMessage::Pong { id, received }, _) => {
+            match ping.filter(|&(ping_id, ..)| ping_id == id) {
+                Some((_, sent_at, sent)) => {
+                    config
+                        .latency_log
+                        .record(client, sent_at.elapsed(), sent, received);
+                    *ping = None;
+                }
+                None => warn!("[{client}] Ignored pong {id}: no such ping is unanswered."),
+            }
+        }
+        (command, _) if control => {
+            debug!("[{client}] Control message: {command:?}.");
+            config
+                .audit
+                .record(client, &format!("Control message: {command:?}."));
+            let _ = commands.send(command);
+        }
+        (command, _) => {
+            warn!("[{client}] Ignored {command:?}: control was not negotiated.");
+        }
+    }
+    true
+}
+
+/// Remove the events from `batch` (see [`protocol::split_batch`]) of types not allowed by `permissions`.
+fn filter_batch(batch: &[u8], permissions: &Permissions) -> io::Result<Vec<u8>> {
+    let mut permitted_events = Vec::with_capacity(batch.len());
+    for event in protocol::split_batch(batch) {
+        if permissions.allows_event_type(protocol::decode_event(event)?.event_type) {
+            permitted_events.extend_from_slice(event);
+        }
+    }
+    Ok(permitted_events)
+}
+
+/// Perform the handshake with an authenticated client, then send serialized events
+/// (see [`protocol::split_batch`]) from `receiver` until the client disconnects or
+/// events can no longer be received from `receiver`.
+/// See [`crate::server::device_listener`] for more details on the event serialization.
+/// Events are converted to the negotiated [`Encoding`] and [`Framing`] before they are sent,
+/// and compressed if the client requested [`features::ZSTD`]. If the client requested
+/// [`features::BATCH`], each batch of events is sent as one frame. Gaps in the sequence numbers
+/// of the batches are logged, and the numbers are sent if the client requested [`features::SEQUENCE`].
+/// Batches are split into frames of at most `config.max_frame_size` bytes and longer event frames are
+/// rejected. Clients sending longer frames are disconnected. Events carry the names of their type
+/// and code if the client requested [`features::NAMES`].
+///
+/// If the client requested [`features::HEARTBEAT`], a heartbeat is sent whenever no frame has been
+/// sent for `config.heartbeat_interval_millis`. If it also requested [`features::HEARTBEAT_PONG`],
+/// the connection is closed when a heartbeat is not answered within `config.heartbeat_timeout_millis`.
+///
+/// Only events of the types allowed by the permissions of `api_key` are sent. Clients authenticated
+/// without an API key (`None`) may receive and do everything. If `config.disconnect_revoked_clients`
+/// is set, the connection is closed within [`REVOCATION_POLL_INTERVAL`] once `api_key` is revoked.
+///
+/// If `config.session_token_lifetime_secs` is set, clients authenticated with an API key are disconnected
+/// once their session expires. Clients which requested [`features::SESSION_TOKEN`] receive a [`SessionToken`]
+/// and may extend the session with [`ControlMessage::RenewSession`] as long as their key is not revoked.
+///
+/// If the client requested [`features::DEVICE_INFO`], it receives the absolute axes of the devices after the handshake.
+/// If it requested [`features::MULTIPLEX`], events are tagged with the ID of their device, and the attached devices
+/// are announced after the handshake and whenever one is attached or removed.
+///
+/// Messages from the client (heartbeat answers, pongs and [`ControlMessage`]s) are handled as soon as
+/// they arrive. If the client requested [`features::CONTROL`], which is only offered if permitted,
+/// its [`ControlMessage`]s are forwarded to `commands`.
+///
+/// If the client requested [`features::FLOW_CONTROL`], at most `config.flow_control_window` event frames
+/// (or the window requested with [`ControlMessage::FlowControl`]) are sent without being acknowledged.
+/// Events keep being received from `receiver` meanwhile, so a slow client does not fill the bus.
+///
+/// If the client requested [`features::LATENCY`], it is pinged every `config.latency_interval_millis`
+/// and the latency of its answers is recorded in `config.latency_log`.
+pub async fn run<T: Transport>(
+    mut transport: T,
+    client: &str,
+    api_key: Option<&ApiKey>,
+    mut receiver: Receiver<EventBatch>,
+    commands: &Sender<ControlMessage>,
+    config: &ServerConfig,
+) {
+    let permissions = api_key.map_or(&Permissions::ALL, |api_key| &api_key.permissions);
+    let revocable_key = api_key.filter(|_| config.disconnect_revoked_clients);
+    let mut supported_features = 0;
+    if config.heartbeat_interval_millis > 0 {
+        supported_features |= features::HEARTBEAT | features::HEARTBEAT_PONG;
+    }
+    if config.latency_interval_millis > 0 {
+        supported_features |= features::LATENCY;
+    }
+    supported_features |= features::LENGTH_PREFIXED
+        | features::MESSAGE_PACK
+        | features::CBOR
+        | features::ZSTD
+        | features::BATCH
+        | features::SEQUENCE
+        | features::FLOW_CONTROL
+        | features::NAMES;
+    if permissions.control {
+        supported_features |= features::CONTROL;
+    }
+    supported_features |= features::DEVICE_INFO | features::MULTIPLEX;
+    let session_lifetime = Duration::from_secs(config.session_token_lifetime_secs);
+    let session_key = api_key.filter(|_| !session_lifetime.is_zero());
+    // Sessions of hashed keys cannot be renewed because the server cannot compute the MAC.
+    if session_key.is_some_and(|api_key| api_key.hmac_key().is_some()) {
+        supported_features |= features::SESSION_TOKEN;
+    }
+    // The client is counted until this function returns.
+    let slot = config.acquire_client_slot();
+    let rejection = slot.is_none().then_some("server busy");
+    let Some((_, features)) =
+        handshake(&mut transport, client, supported_features, rejection).await
+    else {
+        return;
+    };
+    let heartbeat = features & features::HEARTBEAT != 0;
+    let pong = heartbeat && features & features::HEARTBEAT_PONG != 0;
+    let control = features & features::CONTROL != 0;
+    let heartbeat_interval = Duration::from_millis(config.heartbeat_interval_millis);
+    let heartbeat_timeout = Duration::from_millis(config.heartbeat_timeout_millis);
+    let latency = features & features::LATENCY != 0;
+    let latency_interval = Duration::from_millis(config.latency_interval_millis);
+    let framing = Framing::from_features(features);
+    let encoding = Encoding::from_features(features);
+    let batch = features & features::BATCH != 0;
+    let sequence = features & features::SEQUENCE != 0;
+    let session_token = features & features::SESSION_TOKEN != 0;
+    let multiplex = features & features::MULTIPLEX != 0;
+    let names = features & features::NAMES != 0;
+    let mut session = match session_key {
+        Some(api_key) => match Session::new(api_key, session_lifetime) {
+            Some(session) => Some(session),
+            None => {
+                warn!("[{client}] Failed to generate session token.");
+                return;
+            }
+        },
+        None => None,
+    };
+    if let Some(session) = session.as_ref().filter(|_| session_token) {
+        let token = SessionToken {
+            token: session.token,
+            lifetime_secs: config.session_token_lifetime_secs,
+        };
+        if let Err(error) = send_message(&mut transport, &token).await {
+            warn!("[{client}] Failed to send session token: {error}.");
+            return;
+        }
+    }
+    if features & features::DEVICE_INFO != 0 {
+        if let Err(error) = send_message(&mut transport, &config.device_info()).await {
+            warn!("[{client}] Failed to send device info: {error}.");
+            return;
+        }
+    }
+    transport.set_framing(framing);
+    transport.set_max_frame_size(config.max_frame_size);
+    let heartbeat_frame = framing
+        .frame(&[])
+        .expect("an empty message fits in any frame");
+    let mut compressor = if features & features::ZSTD != 0 {
+        match Compressor::new() {
+            Ok(compressor) => Some(compressor),
+            Err(error) => {
+                warn!("[{client}] Failed to start compression: {error}.");
+                return;
+            }
+        }
+    } else {
+        None
+    };
+    let mut flow_control = (features & features::FLOW_CONTROL != 0).then(|| FlowControl {
+        window: config.flow_control_window.max(1),
+        policy: DropPolicy::DropNewest,
+        in_flight: 0,
+        pending: VecDeque::new(),
+        dropped: 0,
+    });
+
+    let mut announced = vec![None; config.device_info.len()];
+    if multiplex {
+        if let Err(error) = send_announcements(
+            &mut transport,
+            compressor.as_mut(),
+            config,
+            &mut announced,
+            encoding,
+            framing,
+        )
+        .await
+        {
+            warn!("[{client}] Failed to announce devices: {error}.");
+            return;
+        }
+    }
+
+    let mut last_sent = Instant::now();
+    let mut last_announced = Instant::now();
+    let mut awaiting_pong_since: Option<Instant> = None;
+    let mut last_ping = Instant::now();
+    // The ID, send time and timestamp of the unanswered ping.
+    let mut ping: Option<(u32, Instant, Timestamp)> = None;
+    let mut next_ping_id: u32 = 0;
+    let mut last_sequence: Option<u64> = None;
+    let mut missed = 0;
+    // Cleared once the client closed its side of the connection, after which it is only sent to.
+    let mut receiving = true;
+
+    // Transmit events received from `receiver` to the client and handle the messages it sends.
+    loop {
+        // Wait for an event or a message until the next heartbeat, heartbeat timeout, ping,
+        // announcement, revocation check or expiry is due.
+        let deadline = [
+            heartbeat.then(|| last_sent + heartbeat_interval),
+            awaiting_pong_since
+                .filter(|_| pong)
+                .map(|since| since + heartbeat_timeout),
+            latency.then(|| last_ping + latency_interval),
+            multiplex.then(|| last_announced + ANNOUNCEMENT_POLL_INTERVAL),
+            revocable_key.map(|_| Instant::now() + REVOCATION_POLL_INTERVAL),
+            session.as_ref().map(|session| session.expires_at),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        let mut frames = Vec::new();
+        tokio::select! {
+            event = receiver.recv() => {
+                // The bus is only closed when the server shuts down.
+                let Some(events) = event else {
+                    info!("[{client}] Server is shutting down. Disconnecting.");
+                    if multiplex {
+                        let result = match encoding
+                            .serialize(&StreamFrame::<()>::Closing)
+                            .and_then(|message| framing.frame(&message))
+                        {
+                            Ok(frame) => send_frame(&mut transport, compressor.as_mut(), &frame).await,
+                            Err(error) => Err(error),
+                        };
+                        if let Err(error) = result {
+                            warn!("[{client}] Failed to announce the shutdown: {error}.");
+                        }
+                    }
+                    return;
+                };
+                if let Some(last_sequence) = last_sequence {
+                    let gap = events.sequence.saturating_sub(last_sequence + 1);
+                    if gap > 0 {
+                        missed += gap;
+                        warn!("[{client}] Missed {gap} batches ({missed} missed in total).");
+                    }
+                }
+                last_sequence = Some(events.sequence);
+                let sequence = sequence.then_some(events.sequence);
+                let device = multiplex.then_some(events.device);
+                let permitted_events = match permissions.event_types {
+                    Some(_) => match filter_batch(&events.events, permissions) {
+                        Ok(permitted_events) => permitted_events.into(),
+                        Err(error) => {
+                            warn!("[{client}] Failed to deserialize event: {error}.");
+                            return;
+                        }
+                    },
+                    None => events.events,
+                };
+                // Only the client selected with a KVM hotkey receives events, if one is selected.
+                let selected = slot.as_ref().is_none_or(ClientSlot::receives);
+                let result = if permitted_events.is_empty() || !selected {
+                    Ok(())
+                } else if batch {
+                    protocol::reencode_batch_segmented(
+                        &permitted_events,
+                        encoding,
+                        framing,
+                        sequence,
+                        device,
+                        names,
+                        config.max_frame_size,
+                    )
+                    .map(|segments| frames.extend(segments))
+                } else {
+                    protocol::split_batch(&permitted_events).try_for_each(|event| {
+                        protocol::reencode_event(event, encoding, framing, sequence, device, names)
+                            .map(|frame| frames.push(frame))
+                    })
+                };
+                if let Err(error) = result {
+                    warn!("[{client}] Failed to serialize event: {error}.");
+                    return;
+                }
+                let len = frames.len();
+                frames.retain(|frame| frame.len() <= config.max_frame_size);
+                if frames.len() < len {
+                    warn!(
+                        "[{client}] Rejected {} event frames longer than {} bytes.",
+                        len - frames.len(),
+                        config.max_frame_size
+                    );
+                }
+            }
+            // Pongs and control messages are handled as soon as they arrive.
+            frame = transport.recv(), if receiving => {
+                match frame.and_then(|frame| framing.unframe(&frame)) {
+                    Ok(message) if message.is_empty() => awaiting_pong_since = None,
+                    Ok(message) => match encoding.deserialize::<ControlMessage>(&message) {
+                        Ok(message) => {
+                            let renewed = handle_control_message(
+                                message,
+                                client,
+                                control,
+                                flow_control.as_mut(),
+                                session.as_mut().filter(|_| session_token),
+                                session_lifetime,
+                                &mut ping,
+                                commands,
+                                config,
+                            );
+                            if !renewed {
+                                warn!("[{client}] Failed to renew session. Disconnecting.");
+                                return;
+                            }
+                        }
+                        Err(error) => warn!("[{client}] Invalid control message: {error}."),
+                    },
+                    Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => {
+                        debug!("[{client}] Client closed its side of the connection.");
+                        receiving = false;
+                    }
+                    Err(error) => {
+                        warn!("[{client}] Failed to receive message: {error}.");
+                        return;
+                    }
+                }
+            }
+            () = tokio::time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {}
+        }
+        // A pong may be missed while events are being sent, so the timeout is checked every time.
+        if awaiting_pong_since
+            .filter(|_| pong)
+            .is_some_and(|since| since.elapsed() >= heartbeat_timeout)
+        {
+            warn!("[{client}] Heartbeat timed out.");
+            return;
+        }
+        if let Some(api_key) = revocable_key {
+            if !config.api_keys.contains(api_key) {
+                info!("[{client}] API key was revoked. Disconnecting.");
+                return;
+            }
+        }
+        if session
+            .as_ref()
+            .is_some_and(|session| session.expires_at <= Instant::now())
+        {
+            info!("[{client}] Session expired. Disconnecting.");
+            return;
+        }
+        // Announce devices before their events.
+        if multiplex {
+            last_announced = Instant::now();
+            match send_announcements(
+                &mut transport,
+                compressor.as_mut(),
+                config,
+                &mut announced,
+                encoding,
+                framing,
+            )
+            .await
+            {
+                Ok(true) => last_sent = Instant::now(),
+                Ok(false) => {}
+                Err(error) => {
+                    warn!("[{client}] Failed to announce devices: {error}.");
+                    return;
+                }
+            }
+        }
+
+        // With flow control, queue the new frames and send as many pending frames as fit in the window.
+        if let Some(flow_control) = flow_control.as_mut() {
+            let dropped = flow_control.dropped;
+            for frame in frames.drain(..) {
+                if !flow_control.push(frame) {
+                    warn!("[{client}] Flow control window is full. Disconnecting.");
+                    return;
+                }
+            }
+            if flow_control.dropped > dropped {
+                warn!(
+                    "[{client}] Flow control window is full. Dropped {} frames ({} dropped in total).",
+                    flow_control.dropped - dropped,
+                    flow_control.dropped
+                );
+            }
+            frames.extend(std::iter::from_fn(|| flow_control.pop()));
+        }
+        for frame in &frames {
+            if let Err(error) = send_frame(&mut transport, compressor.as_mut(), frame).await {
+                warn!("[{client}] Failed to send event: {error}.");
+                return;
+            }
+        }
+        if !frames.is_empty() {
+            last_sent = Instant::now();
+        }
+
+        if heartbeat && frames.is_empty() && last_sent.elapsed() >= heartbeat_interval {
+            if let Err(error) =
+                send_frame(&mut transport, compressor.as_mut(), &heartbeat_frame).await
+            {
+                warn!("[{client}] Failed to send heartbeat: {error}.");
+                return;
+            }
+            last_sent = Instant::now();
+            awaiting_pong_since.get_or_insert(last_sent);
+        }
+
+        // Ping the client, replacing an unanswered ping.
+        if latency && last_ping.elapsed() >= latency_interval {
+            if let Some((id, ..)) = ping {
+                warn!("[{client}] Ping {id} was not answered.");
+            }
+            let (sent_at, sent) = (Instant::now(), Timestamp::from(SystemTime::now()));
+            let result = match encoding
+                .serialize(&StreamFrame::<()>::Ping {
+                    id: next_ping_id,
+                    sent,
+                })
+                .and_then(|message| framing.frame(&message))
+            {
+                Ok(frame) => send_frame(&mut transport, compressor.as_mut(), &frame).await,
+                Err(error) => Err(error),
+            };
+            if let Err(error) = result {
+                warn!("[{client}] Failed to send ping: {error}.");
+                return;
+            }
+            last_ping = sent_at;
+            last_sent = Instant::now();
+            ping = Some((next_ping_id, sent_at, sent));
+            next_ping_id = next_ping_id.wrapping_add(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+
+    /// A client answering the challenge of [`authenticate`] with `respond(nonce)`.
+    struct Responder<F> {
+        nonce: Vec<u8>,
+        respond: F,
+    }
+
+    impl<F: FnMut(&[u8]) -> Vec<u8> + Send> Transport for Responder<F> {
+        async fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+            self.nonce = Framing::Cobs.unframe(frame)?;
+            Ok(())
+        }
+
+        async fn recv(&mut self) -> io::Result<Vec<u8>> {
+            Framing::Cobs.frame(&(self.respond)(&self.nonce))
+        }
+
+        fn is_secure(&self) -> bool {
+            false
+        }
+    }
+
+    async fn respond(respond: impl FnMut(&[u8]) -> Vec<u8> + Send) -> Option<ApiKey> {
+        let mut transport = Responder {
+            nonce: Vec::new(),
+            respond,
+        };
+        authenticate(&mut transport, "Test", None, &test_config().server)
+            .await
+            .map(|(api_key, _)| api_key)
+    }
+
+    fn sign(key: &[u8], nonce: &[u8]) -> Vec<u8> {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+        hmac::sign(&key, nonce).as_ref().to_vec()
+    }
+
+    #[tokio::test]
+    async fn accepts_hmac_of_nonce() {
+        let api_key = respond(|nonce| sign(b"secret", nonce)).await.unwrap();
+        assert_eq!(api_key.name, "default");
+    }
+
+    #[tokio::test]
+    async fn returns_nonce_signed_by_client() {
+        let mut signed = Vec::new();
+        let mut transport = Responder {
+            nonce: Vec::new(),
+            respond: |nonce: &[u8]| {
+                signed = nonce.to_vec();
+                sign(b"secret", nonce)
+            },
+        };
+        let (_, nonce) = authenticate(&mut transport, "Test", None, &test_config().server)
+            .await
+            .unwrap();
+        assert_eq!(nonce.len(), NONCE_LEN);
+        assert_eq!(nonce.as_slice(), signed);
+    }
+
+    #[tokio::test]
+    async fn rejects_hmac_of_wrong_key() {
+        assert!(respond(|nonce| sign(b"secrets", nonce)).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn rejects_truncated_hmac() {
+        let response = respond(|nonce| {
+            let mut tag = sign(b"secret", nonce);
+            tag.pop();
+            tag
+        });
+        assert!(response.await.is_none());
+    }
+
+    #[tokio::test]
+    async fn rejects_prefix_of_key() {
+        // The plaintext key check used to accept every key starting with the configured one.
+        assert!(respond(|_| b"secret".to_vec()).await.is_none());
+        assert!(respond(|_| b"sec".to_vec()).await.is_none());
+        assert!(respond(|_| b"secret and more".to_vec()).await.is_none());
+    }
+
+    fn flow_control(window: u32, policy: DropPolicy) -> FlowControl {
+        FlowControl {
+            window,
+            policy,
+            in_flight: 0,
+            pending: VecDeque::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Handle `message` from a client which negotiated flow control.
+    fn handle(message: ControlMessage, flow_control: &mut FlowControl) {
+        let (commands, _) = std::sync::mpsc::channel();
+        assert!(handle_control_message(
+            message,
+            "Test",
+            true,
+            Some(flow_control),
+            None,
+            Duration::ZERO,
+            &mut None,
+            &commands,
+            &test_config().server,
+        ));
+    }
+
+    #[test]
+    fn sends_frames_until_window_is_full() {
+        let mut flow_control = flow_control(2, DropPolicy::DropNewest);
+        for frame in 0..2 {
+            assert!(flow_control.push(vec![frame]));
+        }
+        assert_eq!(flow_control.pop(), Some(vec![0]));
+        assert_eq!(flow_control.pop(), Some(vec![1]));
+        assert!(flow_control.push(vec![2]));
+        assert_eq!(flow_control.pop(), None);
+        assert_eq!(flow_control.pending.len(), 1);
+    }
+
+    #[test]
+    fn ack_advances_window() {
+        let mut flow_control = flow_control(2, DropPolicy::DropNewest);
+        for frame in 0..2 {
+            assert!(flow_control.push(vec![frame]));
+            flow_control.pop();
+        }
+        assert!(flow_control.push(vec![2]));
+        assert!(flow_control.push(vec![3]));
+        handle(ControlMessage::Ack { frames: 1 }, &mut flow_control);
+        assert_eq!(flow_control.pop(), Some(vec![2]));
+        assert_eq!(flow_control.pop(), None);
+        // Acknowledging more frames than are in flight does not open the window further.
+        handle(ControlMessage::Ack { frames: 10 }, &mut flow_control);
+        assert_eq!(flow_control.in_flight, 0);
+        assert_eq!(flow_control.pop(), Some(vec![3]));
+    }
+
+    #[test]
+    fn changes_window_and_policy() {
+        let mut flow_control = flow_control(1, DropPolicy::DropNewest);
+        let message = ControlMessage::FlowControl {
+            window: 0,
+            policy: DropPolicy::Disconnect,
+        };
+        handle(message, &mut flow_control);
+        assert_eq!(flow_control.window, 1);
+        assert_eq!(flow_control.policy, DropPolicy::Disconnect);
+    }
+
+    /// The pending frames and the number of dropped ones after pushing frames 0 to 4 to a window of 2.
+    fn overflow(policy: DropPolicy) -> Option<(Vec<Vec<u8>>, u64)> {
+        let mut flow_control = flow_control(2, policy);
+        for frame in 0..5 {
+            if !flow_control.push(vec![frame]) {
+                return None;
+            }
+        }
+        Some((flow_control.pending.into(), flow_control.dropped))
+    }
+
+    #[test]
+    fn applies_drop_policy_when_full() {
+        assert_eq!(
+            overflow(DropPolicy::DropOldest),
+            Some((vec![vec![3], vec![4]], 3))
+        );
+        assert_eq!(
+            overflow(DropPolicy::DropNewest),
+            Some((vec![vec![0], vec![1]], 3))
+        );
+        assert_eq!(overflow(DropPolicy::Disconnect), None);
+    }
+}
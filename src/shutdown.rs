@@ -0,0 +1,44 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Set when SIGINT or SIGTERM is received.
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request(_signal: libc::c_int) {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Handle SIGINT (e.g., Ctrl+C) and SIGTERM by requesting a shutdown (see [`requested`]) instead
+/// of terminating at once, so that the server can clean up before it exits.
+pub fn install() -> io::Result<()> {
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        // SAFETY: an all-zero `sigaction` is valid, and the handler only stores to an atomic, which
+        // is async-signal-safe. Interrupted system calls are restarted.
+        let result = unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = request as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigaction(signal, &action, std::ptr::null_mut())
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Whether SIGINT or SIGTERM was received.
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// How often [`wait`] checks whether a shutdown was requested.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Wait in an async task until SIGINT or SIGTERM is received.
+pub async fn wait() {
+    while !requested() {
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
@@ -0,0 +1,191 @@
+use evdev::{EventType, InputEvent};
+use std::time::{Duration, Instant};
+
+/// What to do with an event of a key which toggles something when it is tapped twice.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Tap {
+    /// Discard it. A single tap is forwarded by [`DoubleTap::flush`] instead.
+    Absorb,
+    /// Discard it and toggle: the key was released after it was pressed twice within the window.
+    Toggle,
+    /// Forward it, e.g., the release of a single tap which was forwarded while the key was held.
+    Forward,
+}
+
+/// Tells double taps of keys apart from single taps, which are held back until the window for the
+/// second tap expires and then forwarded, so that the keys remain usable.
+pub struct DoubleTap {
+    window: Duration,
+    /// The key tapped once, when it was pressed and whether it was released since.
+    pending: Option<(u16, Instant, bool)>,
+    /// The key pressed a second time, whose release toggles.
+    second: Option<u16>,
+    /// The key whose single tap was forwarded while it was held.
+    forwarded: Option<u16>,
+}
+
+impl DoubleTap {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            pending: None,
+            second: None,
+            forwarded: None,
+        }
+    }
+
+    /// Handle a press (value 1), release (0) or repeat (2) of the key `code`. Call
+    /// [`DoubleTap::flush`] before handling the events of other keys.
+    pub fn key(&mut self, code: u16, value: i32) -> Tap {
+        match value {
+            1 => match self.pending {
+                Some((pending, pressed, _))
+                    if pending == code && pressed.elapsed() < self.window =>
+                {
+                    self.pending = None;
+                    self.second = Some(code);
+                    Tap::Absorb
+                }
+                _ => {
+                    self.pending = Some((code, Instant::now(), false));
+                    Tap::Absorb
+                }
+            },
+            0 if self.second == Some(code) => {
+                self.second = None;
+                Tap::Toggle
+            }
+            0 if self.forwarded == Some(code) => {
+                self.forwarded = None;
+                Tap::Forward
+            }
+            0 => {
+                if let Some((pending, _, released)) = &mut self.pending {
+                    *released |= *pending == code;
+                }
+                Tap::Absorb
+            }
+            _ if self.forwarded == Some(code) => Tap::Forward,
+            _ => Tap::Absorb,
+        }
+    }
+
+    /// Remove and return the events of the single tap held back, if its window expired or `force`
+    /// is set (e.g., because another key was pressed): its press and, if the key was released
+    /// already, its release.
+    pub fn flush(&mut self, force: bool) -> Vec<InputEvent> {
+        let Some((code, pressed, released)) = self.pending else {
+            return Vec::new();
+        };
+        if !force && pressed.elapsed() < self.window {
+            return Vec::new();
+        }
+        self.pending = None;
+        let mut events = vec![InputEvent::new_now(EventType::KEY, code, 1)];
+        if released {
+            events.push(InputEvent::new_now(EventType::KEY, code, 0));
+        } else {
+            self.forwarded = Some(code);
+        }
+        events
+    }
+
+    /// Forget the taps of the key `code` which were not forwarded, e.g., because it was used together
+    /// with another key. Its release is then absorbed unless its press was forwarded.
+    pub fn cancel(&mut self, code: u16) {
+        if self.pending() == Some(code) {
+            self.pending = None;
+        }
+        if self.second == Some(code) {
+            self.second = None;
+        }
+    }
+
+    /// The key whose single tap is held back, if any.
+    pub fn pending(&self) -> Option<u16> {
+        self.pending.map(|(code, _, _)| code)
+    }
+
+    /// How long until the window of the single tap held back expires, or `None` if there is none.
+    pub fn next_due(&self) -> Option<Duration> {
+        self.pending
+            .map(|(_, pressed, _)| self.window.saturating_sub(pressed.elapsed()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_A: u16 = 30;
+    const KEY_B: u16 = 48;
+
+    /// The `(code, value)` of `events`.
+    fn keys(events: Vec<InputEvent>) -> Vec<(u16, i32)> {
+        events
+            .iter()
+            .map(|event| (event.code(), event.value()))
+            .collect()
+    }
+
+    #[test]
+    fn forwards_single_tap_after_window() {
+        let mut double_tap = DoubleTap::new(Duration::from_millis(10));
+        assert_eq!(double_tap.key(KEY_A, 1), Tap::Absorb);
+        assert_eq!(double_tap.key(KEY_A, 0), Tap::Absorb);
+        assert!(double_tap.flush(false).is_empty());
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(double_tap.next_due(), Some(Duration::ZERO));
+        assert_eq!(keys(double_tap.flush(false)), [(KEY_A, 1), (KEY_A, 0)]);
+        assert_eq!(double_tap.pending(), None);
+    }
+
+    #[test]
+    fn toggles_on_double_tap() {
+        let mut double_tap = DoubleTap::new(Duration::from_secs(60));
+        for value in [1, 0, 1] {
+            assert_eq!(double_tap.key(KEY_A, value), Tap::Absorb);
+        }
+        assert_eq!(double_tap.key(KEY_A, 0), Tap::Toggle);
+        assert!(double_tap.flush(true).is_empty());
+    }
+
+    #[test]
+    fn forwards_release_of_held_key() {
+        let mut double_tap = DoubleTap::new(Duration::ZERO);
+        assert_eq!(double_tap.key(KEY_A, 1), Tap::Absorb);
+        assert_eq!(keys(double_tap.flush(false)), [(KEY_A, 1)]);
+        assert_eq!(double_tap.key(KEY_A, 2), Tap::Forward);
+        assert_eq!(double_tap.key(KEY_A, 0), Tap::Forward);
+        assert_eq!(double_tap.key(KEY_A, 2), Tap::Absorb);
+    }
+
+    #[test]
+    fn forwards_single_tap_before_another_key() {
+        let mut double_tap = DoubleTap::new(Duration::from_secs(60));
+        double_tap.key(KEY_A, 1);
+        double_tap.key(KEY_A, 0);
+        assert_eq!(keys(double_tap.flush(true)), [(KEY_A, 1), (KEY_A, 0)]);
+        // A press within the window of the forwarded tap is a new first tap.
+        double_tap.key(KEY_A, 1);
+        assert_eq!(double_tap.key(KEY_A, 0), Tap::Absorb);
+        assert_eq!(double_tap.pending(), Some(KEY_A));
+    }
+
+    #[test]
+    fn cancels_taps_used_with_another_key() {
+        let mut double_tap = DoubleTap::new(Duration::from_secs(60));
+        double_tap.key(KEY_A, 1);
+        double_tap.cancel(KEY_A);
+        assert_eq!(double_tap.pending(), None);
+        assert_eq!(double_tap.key(KEY_A, 0), Tap::Absorb);
+        assert!(double_tap.flush(true).is_empty());
+
+        // A second press used with another key does not toggle.
+        double_tap.key(KEY_B, 1);
+        double_tap.key(KEY_B, 0);
+        double_tap.key(KEY_B, 1);
+        double_tap.cancel(KEY_B);
+        assert_eq!(double_tap.key(KEY_B, 0), Tap::Absorb);
+    }
+}
@@ -0,0 +1,150 @@
+use evdev::uinput::{VirtualDevice, VirtualDeviceBuilder};
+use evdev::{AttributeSet, EventType, InputEvent, Key};
+use std::io;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+
+/// The delay between two typed characters.
+const KEYSTROKE_INTERVAL: Duration = Duration::from_millis(150);
+/// The delay before the script is typed again.
+const SCRIPT_INTERVAL: Duration = Duration::from_secs(3);
+
+/// The script typed when none is given.
+pub const DEFAULT_SCRIPT: &str = "Hello, world!\n";
+
+const LETTERS: [Key; 26] = [
+    Key::KEY_A,
+    Key::KEY_B,
+    Key::KEY_C,
+    Key::KEY_D,
+    Key::KEY_E,
+    Key::KEY_F,
+    Key::KEY_G,
+    Key::KEY_H,
+    Key::KEY_I,
+    Key::KEY_J,
+    Key::KEY_K,
+    Key::KEY_L,
+    Key::KEY_M,
+    Key::KEY_N,
+    Key::KEY_O,
+    Key::KEY_P,
+    Key::KEY_Q,
+    Key::KEY_R,
+    Key::KEY_S,
+    Key::KEY_T,
+    Key::KEY_U,
+    Key::KEY_V,
+    Key::KEY_W,
+    Key::KEY_X,
+    Key::KEY_Y,
+    Key::KEY_Z,
+];
+
+const DIGITS: [Key; 10] = [
+    Key::KEY_0,
+    Key::KEY_1,
+    Key::KEY_2,
+    Key::KEY_3,
+    Key::KEY_4,
+    Key::KEY_5,
+    Key::KEY_6,
+    Key::KEY_7,
+    Key::KEY_8,
+    Key::KEY_9,
+];
+
+/// The key typing `character` on a US layout and whether shift is held, if there is one.
+fn key_for(character: char) -> Option<(Key, bool)> {
+    match character {
+        'a'..='z' => Some((LETTERS[character as usize - 'a' as usize], false)),
+        'A'..='Z' => Some((LETTERS[character as usize - 'A' as usize], true)),
+        '0'..='9' => Some((DIGITS[character as usize - '0' as usize], false)),
+        ' ' => Some((Key::KEY_SPACE, false)),
+        '\n' => Some((Key::KEY_ENTER, false)),
+        ',' => Some((Key::KEY_COMMA, false)),
+        '.' => Some((Key::KEY_DOT, false)),
+        '!' => Some((Key::KEY_1, true)),
+        '?' => Some((Key::KEY_SLASH, true)),
+        _ => None,
+    }
+}
+
+/// A virtual keyboard typing a script over and over, used in place of a real device by
+/// `remote-input test-device`.
+pub struct TestDevice {
+    device: VirtualDevice,
+    /// The keys and shift states typing the script.
+    keystrokes: Vec<(Key, bool)>,
+}
+
+impl TestDevice {
+    /// Create the virtual keyboard, which also has the keys `extra_keys` (e.g., the escape and
+    /// pause keys), and return it with its `/dev/input/eventN` path.
+    /// Characters of `script` which cannot be typed are skipped.
+    pub fn create(script: &str, extra_keys: &[Key]) -> io::Result<(Self, PathBuf)> {
+        let mut keys = AttributeSet::<Key>::new();
+        for &key in LETTERS.iter().chain(&DIGITS).chain(extra_keys) {
+            keys.insert(key);
+        }
+        for key in [
+            Key::KEY_LEFTSHIFT,
+            Key::KEY_SPACE,
+            Key::KEY_ENTER,
+            Key::KEY_COMMA,
+            Key::KEY_DOT,
+            Key::KEY_SLASH,
+        ] {
+            keys.insert(key);
+        }
+        let mut device = VirtualDeviceBuilder::new()?
+            .name("remote-input test device")
+            .with_keys(&keys)?
+            .build()?;
+        let path = device
+            .enumerate_dev_nodes_blocking()?
+            .filter_map(Result::ok)
+            .find(|path| {
+                path.file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with("event"))
+            })
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no event device node"))?;
+        let keystrokes = script.chars().filter_map(key_for).collect();
+        Ok((Self { device, keystrokes }, path))
+    }
+
+    /// Type the script forever.
+    pub fn run(mut self) {
+        loop {
+            thread::sleep(SCRIPT_INTERVAL);
+            if let Err(error) = self.type_script() {
+                warn!("[Test Device] Unable to type the script: {error}.");
+            }
+        }
+    }
+
+    /// Type the script once.
+    pub fn type_script(&mut self) -> io::Result<()> {
+        for &(key, shift) in &self.keystrokes {
+            type_key(&mut self.device, key, shift)?;
+            thread::sleep(KEYSTROKE_INTERVAL);
+        }
+        Ok(())
+    }
+}
+
+/// Press and release `key` on `device`, holding shift if `shift` is set.
+fn type_key(device: &mut VirtualDevice, key: Key, shift: bool) -> io::Result<()> {
+    let event = |key: Key, value| InputEvent::new(EventType::KEY, key.code(), value);
+    if shift {
+        device.emit(&[event(Key::KEY_LEFTSHIFT, 1)])?;
+    }
+    device.emit(&[event(key, 1)])?;
+    device.emit(&[event(key, 0)])?;
+    if shift {
+        device.emit(&[event(Key::KEY_LEFTSHIFT, 0)])?;
+    }
+    Ok(())
+}
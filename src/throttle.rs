@@ -0,0 +1,171 @@
+use evdev::{EventType, InputEvent, RelativeAxisType};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Limits how often relative axis events (e.g., mouse movement) are forwarded per axis, for mice with
+/// high polling rates. Movement between forwarded events is summed up instead of being lost.
+pub struct Throttle {
+    /// The state of each limited axis, indexed by its code.
+    axes: HashMap<u16, ThrottledAxis>,
+}
+
+struct ThrottledAxis {
+    /// The shortest time between two forwarded events.
+    interval: Duration,
+    /// When the last event was forwarded.
+    forwarded: Option<Instant>,
+    /// The movement which was held back since then.
+    pending: i32,
+}
+
+impl ThrottledAxis {
+    /// When the held back movement may be forwarded (`now` if no event was forwarded yet), or
+    /// `None` if there is none.
+    fn due(&self, now: Instant) -> Option<Instant> {
+        if self.pending == 0 {
+            return None;
+        }
+        Some(self.forwarded.map_or(now, |time| time + self.interval))
+    }
+}
+
+impl Throttle {
+    /// Create the limits for the maximum rates `rates` in events per second, named by their axis
+    /// (e.g., "REL_X"). Fails with a description of the first unknown axis or invalid rate.
+    pub fn new(rates: &HashMap<String, f64>) -> Result<Self, String> {
+        let axes = rates
+            .iter()
+            .map(|(axis, &rate)| {
+                let code = RelativeAxisType::from_str(axis)
+                    .map_err(|_| format!("unknown relative axis {axis}"))?
+                    .0;
+                if !(rate > 0.0 && rate.is_finite()) {
+                    return Err(format!("rate {rate} of {axis} is not positive"));
+                }
+                let interval = Duration::try_from_secs_f64(1.0 / rate)
+                    .map_err(|error| format!("rate {rate} of {axis} is too low: {error}"))?;
+                let axis_state = ThrottledAxis {
+                    interval,
+                    forwarded: None,
+                    pending: 0,
+                };
+                Ok((code, axis_state))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self { axes })
+    }
+
+    /// Hold back `event` if it is a relative axis event with a limit. Returns whether it was held
+    /// back; its movement is then forwarded by [`Throttle::release`].
+    pub fn hold(&mut self, event: &InputEvent) -> bool {
+        if event.event_type() != EventType::RELATIVE {
+            return false;
+        }
+        let Some(axis) = self.axes.get_mut(&event.code()) else {
+            return false;
+        };
+        axis.pending = axis.pending.saturating_add(event.value());
+        true
+    }
+
+    /// Remove and return the movement held back on the axes whose limits allow forwarding it now,
+    /// one event per axis.
+    pub fn release(&mut self) -> Vec<InputEvent> {
+        let now = Instant::now();
+        let mut events = Vec::new();
+        for (&code, axis) in &mut self.axes {
+            if axis.due(now).is_some_and(|due| due <= now) {
+                events.push(InputEvent::new_now(EventType::RELATIVE, code, axis.pending));
+                axis.pending = 0;
+                axis.forwarded = Some(now);
+            }
+        }
+        events
+    }
+
+    /// How long until held back movement may be forwarded, or `None` if there is none.
+    pub fn next_due(&self) -> Option<Duration> {
+        let now = Instant::now();
+        self.axes
+            .values()
+            .filter_map(|axis| axis.due(now))
+            .min()
+            .map(|due| due.saturating_duration_since(now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn relative(axis: RelativeAxisType, value: i32) -> InputEvent {
+        InputEvent::new(EventType::RELATIVE, axis.0, value)
+    }
+
+    /// The `(code, value)` of `events`, sorted by code.
+    fn movement(events: Vec<InputEvent>) -> Vec<(u16, i32)> {
+        let mut movement: Vec<_> = events
+            .iter()
+            .map(|event| (event.code(), event.value()))
+            .collect();
+        movement.sort();
+        movement
+    }
+
+    fn throttle(rates: &[(&str, f64)]) -> Result<Throttle, String> {
+        let rates = rates
+            .iter()
+            .map(|&(axis, rate)| (axis.to_string(), rate))
+            .collect();
+        Throttle::new(&rates)
+    }
+
+    #[test]
+    fn sums_movement_until_interval_elapsed() {
+        let mut throttle = throttle(&[("REL_X", 20.0), ("REL_Y", 20.0)]).unwrap();
+        let x = RelativeAxisType::REL_X;
+        let y = RelativeAxisType::REL_Y;
+        assert!(throttle.hold(&relative(x, 3)));
+        assert!(throttle.hold(&relative(x, 4)));
+        assert!(throttle.hold(&relative(y, -2)));
+        assert_eq!(movement(throttle.release()), [(x.0, 7), (y.0, -2)]);
+        assert_eq!(throttle.next_due(), None);
+
+        // The next movement waits for the interval of 50 ms.
+        assert!(throttle.hold(&relative(x, 1)));
+        assert!(throttle.hold(&relative(x, 1)));
+        assert!(throttle.release().is_empty());
+        assert!(throttle.next_due().unwrap() > Duration::from_millis(10));
+        std::thread::sleep(throttle.next_due().unwrap());
+        assert_eq!(movement(throttle.release()), [(x.0, 2)]);
+    }
+
+    #[test]
+    fn forwards_other_events() {
+        let mut throttle = throttle(&[("REL_X", 20.0)]).unwrap();
+        assert!(!throttle.hold(&relative(RelativeAxisType::REL_WHEEL, 1)));
+        assert!(!throttle.hold(&InputEvent::new(EventType::KEY, 30, 1)));
+        assert!(!throttle.hold(&InputEvent::new(EventType::SYNCHRONIZATION, 0, 0)));
+        assert!(throttle.release().is_empty());
+    }
+
+    #[test]
+    fn saturates_held_back_movement() {
+        let mut throttle = throttle(&[("REL_X", 20.0)]).unwrap();
+        throttle.hold(&relative(RelativeAxisType::REL_X, i32::MAX));
+        throttle.hold(&relative(RelativeAxisType::REL_X, 1));
+        assert_eq!(
+            movement(throttle.release()),
+            [(RelativeAxisType::REL_X.0, i32::MAX)]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_rates() {
+        assert!(throttle(&[("REL_Q", 20.0)]).is_err());
+        for rate in [0.0, -1.0, f64::NAN, f64::INFINITY, 1e-300] {
+            assert!(throttle(&[("REL_X", rate)]).is_err(), "rate {rate}");
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use crate::rfcomm::RfcommStream;
+use crate::session;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::ServerConfig;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+/// Build a rustls server configuration from a PEM encoded certificate chain and private key.
+pub fn load_config(
+    certificate_path: &str,
+    private_key_path: &str,
+) -> io::Result<Arc<ServerConfig>> {
+    let certificates = rustls_pemfile::certs(&mut BufReader::new(File::open(certificate_path)?))
+        .collect::<Result<Vec<CertificateDer<'static>>, _>>()?;
+    if certificates.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("no certificates found in \"{certificate_path}\""),
+        ));
+    }
+    let private_key: PrivateKeyDer<'static> =
+        rustls_pemfile::private_key(&mut BufReader::new(File::open(private_key_path)?))?
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("no private key found in \"{private_key_path}\""),
+                )
+            })?;
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certificates, private_key)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+    Ok(Arc::new(config))
+}
+
+/// A client connection which is either plaintext TCP, TLS over TCP, or Bluetooth RFCOMM.
+pub enum Stream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+    Rfcomm(RfcommStream),
+}
+
+impl Stream {
+    /// Wrap an accepted TCP stream, performing the TLS handshake if `config` is set. The handshake
+    /// fails if it takes longer than `timeout`.
+    pub async fn accept(
+        stream: TcpStream,
+        config: Option<&Arc<ServerConfig>>,
+        timeout: Option<Duration>,
+    ) -> io::Result<Self> {
+        match config {
+            None => Ok(Stream::Plain(stream)),
+            Some(config) => {
+                let acceptor = TlsAcceptor::from(Arc::clone(config));
+                let stream = session::timeout(timeout, acceptor.accept(stream)).await?;
+                Ok(Stream::Tls(Box::new(stream)))
+            }
+        }
+    }
+
+    /// The address of the remote end of the underlying TCP stream.
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Stream::Plain(stream) => stream.peer_addr(),
+            Stream::Tls(stream) => stream.get_ref().0.peer_addr(),
+            Stream::Rfcomm(_) => Err(io::ErrorKind::Unsupported.into()),
+        }
+    }
+
+    /// The address of the remote end for log messages, which is a Bluetooth device address for RFCOMM.
+    pub fn peer_name(&self) -> String {
+        match self {
+            Stream::Rfcomm(stream) => stream.peer_address().to_string(),
+            _ => match self.peer_addr() {
+                Ok(addr) => addr.to_string(),
+                Err(_) => "UNKNOWN ADDRESS".to_string(),
+            },
+        }
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+            Stream::Rfcomm(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+            Stream::Rfcomm(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+            Stream::Rfcomm(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+            Stream::Rfcomm(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
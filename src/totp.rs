@@ -0,0 +1,138 @@
+use ring::hmac;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The number of seconds for which a code is valid.
+const STEP_SECS: u64 = 30;
+
+/// The number of digits of a code.
+const DIGITS: u32 = 6;
+
+/// Time-based one-time passwords as described in RFC 6238 (HMAC-SHA1, 30 second steps, 6 digits),
+/// which authenticator apps generate from the same shared secret.
+#[derive(Clone)]
+pub struct Totp {
+    key: hmac::Key,
+}
+
+impl Totp {
+    /// Create a generator for the base32 encoded `secret` (RFC 4648, case insensitive, padding and
+    /// spaces ignored), the usual format of authenticator apps. Returns `None` if `secret` is not base32.
+    pub fn new(secret: &str) -> Option<Self> {
+        let mut secret_bytes = Vec::new();
+        let mut buffer = 0u32;
+        let mut bits = 0;
+        for character in secret
+            .chars()
+            .filter(|&character| character != ' ' && character != '=')
+        {
+            let value = match character.to_ascii_uppercase() {
+                character @ 'A'..='Z' => character as u32 - 'A' as u32,
+                character @ '2'..='7' => character as u32 - '2' as u32 + 26,
+                _ => return None,
+            };
+            buffer = (buffer << 5) | value;
+            bits += 5;
+            if bits >= 8 {
+                bits -= 8;
+                secret_bytes.push((buffer >> bits) as u8);
+            }
+        }
+        if secret_bytes.is_empty() {
+            return None;
+        }
+        Some(Self {
+            key: hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &secret_bytes),
+        })
+    }
+
+    /// Returns `true` if `code` (ASCII digits) is the code of the current step or the one before or
+    /// after it, which allows for clock skew and for codes entered just before they expire.
+    pub fn verify(&self, code: &[u8]) -> bool {
+        let Some(code) = std::str::from_utf8(code)
+            .ok()
+            .filter(|code| code.len() == DIGITS as usize)
+            .and_then(|code| code.parse::<u32>().ok())
+        else {
+            return false;
+        };
+        let step = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            / STEP_SECS;
+        [step.saturating_sub(1), step, step + 1]
+            .iter()
+            .any(|&step| self.code(step) == code)
+    }
+
+    /// The code for the time step `step` (RFC 4226 with the step as the counter).
+    fn code(&self, step: u64) -> u32 {
+        let tag = hmac::sign(&self.key, &step.to_be_bytes());
+        let tag = tag.as_ref();
+        let offset = (tag[tag.len() - 1] & 0x0f) as usize;
+        let truncated = u32::from_be_bytes([
+            tag[offset],
+            tag[offset + 1],
+            tag[offset + 2],
+            tag[offset + 3],
+        ]) & 0x7fff_ffff;
+        truncated % 10u32.pow(DIGITS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The ASCII secret "12345678901234567890" of the SHA-1 test vectors of RFC 6238, in base32.
+    const RFC_6238_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn matches_rfc_6238_vectors() {
+        let totp = Totp::new(RFC_6238_SECRET).unwrap();
+        // The last 6 of the 8 digits of the RFC.
+        for (time, code) in [
+            (59, 287082),
+            (1111111109, 81804),
+            (1111111111, 50471),
+            (1234567890, 5924),
+            (2000000000, 279037),
+            (20000000000, 353130),
+        ] {
+            assert_eq!(totp.code(time / STEP_SECS), code, "at {time}");
+        }
+    }
+
+    #[test]
+    fn decodes_base32_leniently() {
+        let expected = Totp::new(RFC_6238_SECRET).unwrap().code(1);
+        let totp = Totp::new("gezd gnbv gy3t qojq GEZD GNBV GY3T QOJQ").unwrap();
+        assert_eq!(totp.code(1), expected);
+
+        let padded = Totp::new("MZXW6===").unwrap();
+        assert_eq!(padded.code(1), Totp::new("mzxw6").unwrap().code(1));
+        assert_ne!(padded.code(1), Totp::new("MZXW6YQ=").unwrap().code(1));
+    }
+
+    #[test]
+    fn rejects_invalid_base32() {
+        assert!(Totp::new("").is_none());
+        assert!(Totp::new("====").is_none());
+        assert!(Totp::new("GEZDGNBV1").is_none());
+        assert!(Totp::new("GEZDGNBV8").is_none());
+    }
+
+    #[test]
+    fn verifies_current_code() {
+        let totp = Totp::new(RFC_6238_SECRET).unwrap();
+        let step = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            / STEP_SECS;
+        let code = format!("{:06}", totp.code(step));
+        assert!(totp.verify(code.as_bytes()));
+        assert!(!totp.verify(&code.as_bytes()[1..]));
+        assert!(!totp.verify(format!("{:06}", totp.code(step + 10)).as_bytes()));
+    }
+}
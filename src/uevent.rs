@@ -0,0 +1,91 @@
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::{Duration, Instant};
+
+/// The netlink multicast group of the device events udev sends once it has processed its rules
+/// (e.g., created the device node and set its permissions).
+const UDEV_MONITOR_GROUP: u32 = 2;
+
+/// Convert the return value of a libc call into an [`io::Result`].
+fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+/// Receives the device events udev broadcasts over netlink, like `udevadm monitor --udev`.
+pub struct UeventMonitor {
+    fd: OwnedFd,
+}
+
+impl UeventMonitor {
+    /// Subscribe to udev device events.
+    pub fn open() -> io::Result<Self> {
+        // SAFETY: `socket` has no memory safety preconditions. The returned descriptor is owned by `fd`.
+        let fd = unsafe {
+            OwnedFd::from_raw_fd(check(libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+                libc::NETLINK_KOBJECT_UEVENT,
+            ))?)
+        };
+        // SAFETY: `sockaddr_nl` is plain data for which all zeroes is valid.
+        let mut address: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        address.nl_groups = UDEV_MONITOR_GROUP;
+        // SAFETY: `address` is a valid `sockaddr_nl` of the given length.
+        check(unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        })?;
+        Ok(Self { fd })
+    }
+
+    /// Wait up to `timeout` for an input device to be added.
+    /// Returns `false` if none was added in time; other events are skipped.
+    pub fn wait_for_input_device(&self, timeout: Duration) -> io::Result<bool> {
+        let mut buffer = [0u8; 8192];
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let mut poll_fd = libc::pollfd {
+                fd: self.fd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: `poll_fd` is a single valid `pollfd` for the duration of the call.
+            if check(unsafe { libc::poll(&mut poll_fd, 1, remaining.as_millis() as libc::c_int) })?
+                == 0
+            {
+                return Ok(false);
+            }
+            // SAFETY: `buffer` is writable for its length.
+            let length = unsafe {
+                libc::recv(
+                    self.fd.as_raw_fd(),
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                    0,
+                )
+            };
+            if length < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if is_input_device_added(&buffer[..length as usize]) {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+/// Whether the event `message` reports an added input device. The message holds a header followed
+/// by `KEY=VALUE` properties, each terminated by a null byte.
+fn is_input_device_added(message: &[u8]) -> bool {
+    let properties: Vec<&[u8]> = message.split(|&byte| byte == 0).collect();
+    properties.contains(&&b"ACTION=add"[..]) && properties.contains(&&b"SUBSYSTEM=input"[..])
+}
@@ -0,0 +1,167 @@
+use crate::broadcast::Receiver;
+use crate::config::ServerConfig;
+use crate::protocol::ControlMessage;
+use crate::server::EventBatch;
+use crate::session::{self, TimeoutTransport, Transport};
+use crate::tls;
+use futures_util::{SinkExt, StreamExt};
+use std::io;
+use std::sync::mpsc::Sender;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use tracing::warn;
+
+/// A WebSocket connection carrying each frame in its own binary message.
+struct WebSocketTransport(WebSocketStream<tls::Stream>);
+
+impl WebSocketTransport {
+    /// Perform the opening handshake of the server.
+    async fn accept(stream: tls::Stream) -> io::Result<Self> {
+        tokio_tungstenite::accept_async(stream)
+            .await
+            .map(Self)
+            .map_err(io::Error::other)
+    }
+
+    /// Send a close frame, without waiting for the client to answer it.
+    async fn close(&mut self) -> io::Result<()> {
+        self.0.close(None).await.map_err(io::Error::other)
+    }
+}
+
+/// Each frame is carried in its own binary message.
+impl Transport for WebSocketTransport {
+    async fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.0
+            .send(Message::Binary(frame.to_vec()))
+            .await
+            .map_err(io::Error::other)
+    }
+
+    async fn recv(&mut self) -> io::Result<Vec<u8>> {
+        // The stream keeps partially received messages in its own buffer.
+        loop {
+            match self.0.next().await {
+                Some(Ok(Message::Binary(frame))) => return Ok(frame),
+                Some(Ok(Message::Text(frame))) => return Ok(frame.into_bytes()),
+                Some(Ok(Message::Close(_))) => return Err(io::ErrorKind::ConnectionAborted.into()),
+                Some(Ok(_)) => continue,
+                Some(Err(error)) => return Err(io::Error::other(error)),
+                None => return Err(io::ErrorKind::UnexpectedEof.into()),
+            }
+        }
+    }
+
+    fn is_secure(&self) -> bool {
+        matches!(self.0.get_ref(), tls::Stream::Tls(_))
+    }
+}
+
+/// Handle a WebSocket connection, which may be wrapped in TLS.
+/// After the opening handshake, authenticate the client with [`session::authenticate`] using
+/// `config.api_keys`, carrying the challenge and its response in binary messages. Until then,
+/// the connection is closed if the opening handshake or a read takes longer than `config.auth_timeout_millis`.
+/// Then perform the handshake and send events with [`session::run`], one binary message per frame.
+pub async fn handle_connection(
+    stream: tls::Stream,
+    config: &ServerConfig,
+    receiver: Receiver<EventBatch>,
+    commands: &Sender<ControlMessage>,
+) {
+    let (client, ip_address) =
+        session::connection_established("WebSocket Client", stream.peer_addr());
+
+    let accept = WebSocketTransport::accept(stream);
+    let websocket = match session::timeout(config.auth_timeout(), accept).await {
+        Ok(websocket) => websocket,
+        Err(error) => {
+            warn!("[{client}] Handshake failed: {error}.");
+            return;
+        }
+    };
+
+    // Control frames (ping, pong) sent before the challenge response are answered by tokio-tungstenite and skipped.
+    let mut transport = TimeoutTransport::new(websocket, config);
+    let Some((api_key, _)) =
+        session::accept_client(&mut transport, &client, ip_address, config).await
+    else {
+        let mut websocket = transport.into_inner();
+        let _ = session::timeout(config.write_timeout(), websocket.close()).await;
+        return;
+    };
+
+    session::run(
+        transport,
+        &format!("{client} ({})", api_key.name),
+        Some(&api_key),
+        receiver,
+        commands,
+        config,
+    )
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// A server transport after the opening handshake and the client end of its connection.
+    async fn connect() -> (WebSocketTransport, WebSocketStream<TcpStream>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/", listener.local_addr().unwrap());
+        let client = async {
+            let stream = TcpStream::connect(listener.local_addr().unwrap())
+                .await
+                .unwrap();
+            tokio_tungstenite::client_async(url, stream)
+                .await
+                .unwrap()
+                .0
+        };
+        let server = async {
+            let (stream, _) = listener.accept().await.unwrap();
+            WebSocketTransport::accept(tls::Stream::Plain(stream))
+                .await
+                .unwrap()
+        };
+        let (client, server) = tokio::join!(client, server);
+        (server, client)
+    }
+
+    #[tokio::test]
+    async fn carries_frames_in_messages() {
+        let (mut transport, mut client) = connect().await;
+        assert!(!transport.is_secure());
+        transport.send(b"frame").await.unwrap();
+        let message = client.next().await.unwrap().unwrap();
+        assert_eq!(message, Message::Binary(b"frame".to_vec()));
+
+        client
+            .send(Message::Binary(b"binary".to_vec()))
+            .await
+            .unwrap();
+        client.send(Message::Ping(Vec::new())).await.unwrap();
+        client
+            .send(Message::Text("text".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(transport.recv().await.unwrap(), b"binary");
+        assert_eq!(transport.recv().await.unwrap(), b"text");
+    }
+
+    #[tokio::test]
+    async fn fails_once_closed() {
+        let (mut transport, mut client) = connect().await;
+        client.close(None).await.unwrap();
+        let error = transport.recv().await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionAborted);
+
+        let (mut transport, mut client) = connect().await;
+        transport.close().await.unwrap();
+        assert!(matches!(
+            client.next().await,
+            Some(Ok(Message::Close(None)))
+        ));
+    }
+}
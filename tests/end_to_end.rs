@@ -0,0 +1,181 @@
+//! End-to-end tests of the event pipeline: the server runs on a loopback port, a [`Client`]
+//! connects to it and the events it decodes are compared with those injected, either from a
+//! recording replayed by the server or typed on a virtual uinput keyboard.
+#![cfg(target_os = "linux")]
+
+use remote_input::client::Client;
+use remote_input::protocol::{InputEventWrapper, Timestamp};
+use remote_input::test_device::TestDevice;
+use remote_input::{config, server};
+use std::fs::{self, File};
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const API_KEY: &str = "end-to-end test";
+
+/// Held by every test while it runs, so that [`serves_idle_subscribers_without_threads`] counts
+/// only the threads of its own server. The servers run until the test process exits.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+/// How long to wait for the server to start, the device to be attached and events to arrive.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const KEY_H: u16 = 35;
+const KEY_I: u16 = 23;
+const KEY_LEFTSHIFT: u16 = 42;
+
+/// The key events typing "Hi" as `(code, value)`.
+const TYPED: [(u16, i32); 6] = [
+    (KEY_LEFTSHIFT, 1),
+    (KEY_H, 1),
+    (KEY_H, 0),
+    (KEY_LEFTSHIFT, 0),
+    (KEY_I, 1),
+    (KEY_I, 0),
+];
+
+/// Run the server with the device `hardware_name` (or the recording `replay_file` instead) and the
+/// further `[server]` settings `settings` on a free loopback port and return its address.
+fn start_server(hardware_name: &str, replay_file: Option<PathBuf>, settings: &str) -> SocketAddr {
+    let address = TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .unwrap();
+    let data = format!(
+        r#"
+[hardware]
+name = "{hardware_name}"
+led_speed_millis = 3000
+escape = "KEY_SCROLLLOCK"
+pause = "KEY_PAUSE"
+
+[server]
+address = "{address}"
+api_key = "{API_KEY}"
+{settings}
+"#
+    );
+    let mut config = config::parse_config(&data).unwrap();
+    config.server.replay_file = replay_file;
+    // The server runs until the test process exits.
+    thread::spawn(move || server::run(config, PathBuf::from("end-to-end.toml")).unwrap());
+    address
+}
+
+/// Connect to the server at `address` once it accepts connections and describes a device.
+fn connect(address: SocketAddr) -> Client {
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        match Client::connect(address, API_KEY) {
+            Ok(client) if !client.devices().is_empty() => {
+                client.set_read_timeout(Some(TIMEOUT)).unwrap();
+                return client;
+            }
+            result if Instant::now() >= deadline => {
+                panic!("server did not describe a device: {:?}", result.err());
+            }
+            _ => thread::sleep(Duration::from_millis(50)),
+        }
+    }
+}
+
+/// Receive key events from `client` until there are `count`, as `(code, value)`.
+fn receive_keys(client: &mut Client, count: usize) -> Vec<(u16, i32)> {
+    let mut keys = Vec::new();
+    while keys.len() < count {
+        let events = client.receive().unwrap();
+        keys.extend(
+            events
+                .iter()
+                .filter(|event| event.event_type == EV_KEY)
+                .map(|event| (event.code, event.value)),
+        );
+    }
+    keys
+}
+
+/// Write a recording of [`TYPED`] named after `name` and return its path.
+fn write_recording(name: &str) -> PathBuf {
+    let recording = std::env::temp_dir().join(format!(
+        "remote-input-end-to-end-{name}-{}.jsonl",
+        std::process::id()
+    ));
+    let mut lines = String::new();
+    for (index, (code, value)) in TYPED.into_iter().enumerate() {
+        let timestamp = Timestamp::from(Duration::from_millis(index as u64));
+        for (event_type, code, value) in [(EV_KEY, code, value), (EV_SYN, 0, 0)] {
+            let event = InputEventWrapper {
+                timestamp,
+                event_type,
+                code,
+                value,
+            };
+            lines += &serde_json::to_string(&event).unwrap();
+            lines.push('\n');
+        }
+    }
+    fs::write(&recording, lines).unwrap();
+    recording
+}
+
+/// The number of threads of this process.
+fn thread_count() -> usize {
+    fs::read_dir("/proc/self/task").unwrap().count()
+}
+
+#[test]
+fn replays_recording_to_client() {
+    let _serial = SERIAL.lock().unwrap_or_else(|error| error.into_inner());
+    let recording = write_recording("replay");
+    let address = start_server("replay", Some(recording.clone()), "");
+    let mut client = connect(address);
+    let keys = receive_keys(&mut client, TYPED.len());
+    let _ = fs::remove_file(recording);
+    assert_eq!(keys, TYPED);
+}
+
+#[test]
+fn forwards_virtual_keyboard_to_client() {
+    // Creating virtual devices requires the uinput module and write access to it.
+    if let Err(error) = File::options().write(true).open("/dev/uinput") {
+        eprintln!("Skipping the virtual keyboard test: unable to open /dev/uinput: {error}.");
+        return;
+    }
+    let _serial = SERIAL.lock().unwrap_or_else(|error| error.into_inner());
+    let (mut device, path) = TestDevice::create("Hi", &[]).unwrap();
+    let address = start_server(&path.display().to_string(), None, "");
+    let mut client = connect(address);
+    device.type_script().unwrap();
+    assert_eq!(receive_keys(&mut client, TYPED.len()), TYPED);
+}
+
+#[test]
+fn serves_idle_subscribers_without_threads() {
+    const SUBSCRIBERS: usize = 300;
+    let _serial = SERIAL.lock().unwrap_or_else(|error| error.into_inner());
+    let recording = write_recording("idle");
+    let address = start_server(
+        "replay",
+        Some(recording.clone()),
+        &format!("max_clients = {0}\nmax_connections = {0}", SUBSCRIBERS + 1),
+    );
+    let mut first = connect(address);
+    receive_keys(&mut first, TYPED.len());
+    let threads = thread_count();
+
+    // Connections are served from the event loop thread and the workers, so subscribers which
+    // never read cost sockets and buffers, but no threads.
+    let subscribers: Vec<_> = (0..SUBSCRIBERS).map(|_| connect(address)).collect();
+    receive_keys(&mut first, TYPED.len());
+    let _ = fs::remove_file(recording);
+    assert!(
+        thread_count() <= threads + 2,
+        "{} threads after connecting {SUBSCRIBERS} subscribers to {threads}",
+        thread_count()
+    );
+    drop(subscribers);
+}
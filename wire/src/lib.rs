@@ -0,0 +1,289 @@
+//! The wire format of remote input events: the [`InputEventWrapper`] schema, the handshake messages
+//! and their COBS framing.
+//!
+//! The crate is `no_std` and never allocates, so that firmware and WASM clients share the encoder
+//! and decoder of the server instead of reimplementing them. The `std` feature converts
+//! [`Timestamp`]s to and from `std::time::SystemTime`.
+//!
+//! Events are [`postcard`] messages in COBS frames terminated by a zero byte:
+//! ```
+//! use remote_input_wire::{decode_event, encode_event, InputEventWrapper, Timestamp};
+//!
+//! let event = InputEventWrapper {
+//!     timestamp: Timestamp { secs_since_epoch: 1_718_000_000, nanos_since_epoch: 0 },
+//!     event_type: 1,
+//!     code: 30,
+//!     value: 1,
+//! };
+//! let mut buffer = [0; remote_input_wire::MAX_EVENT_FRAME_LEN];
+//! let frame = encode_event(&event, &mut buffer).unwrap();
+//! assert_eq!(decode_event(frame).unwrap(), event);
+//! ```
+
+#![no_std]
+
+#[cfg(feature = "std")]
+extern crate std;
+
+use core::time::Duration;
+use serde::{Deserialize, Serialize};
+
+pub use postcard::Error;
+
+/// The longest frame of an event, including its zero byte terminator.
+pub const MAX_EVENT_FRAME_LEN: usize = 28;
+
+/// The time of an event since the UNIX epoch, serialized like `std::time::SystemTime`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timestamp {
+    pub secs_since_epoch: u64,
+    pub nanos_since_epoch: u32,
+}
+
+impl Timestamp {
+    /// The time since the UNIX epoch.
+    pub fn since_epoch(self) -> Duration {
+        Duration::new(self.secs_since_epoch, self.nanos_since_epoch)
+    }
+}
+
+impl From<Duration> for Timestamp {
+    fn from(since_epoch: Duration) -> Self {
+        Self {
+            secs_since_epoch: since_epoch.as_secs(),
+            nanos_since_epoch: since_epoch.subsec_nanos(),
+        }
+    }
+}
+
+/// Times before the UNIX epoch are clamped to it.
+#[cfg(feature = "std")]
+impl From<std::time::SystemTime> for Timestamp {
+    fn from(time: std::time::SystemTime) -> Self {
+        time.duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .into()
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<Timestamp> for std::time::SystemTime {
+    fn from(timestamp: Timestamp) -> Self {
+        std::time::UNIX_EPOCH + timestamp.since_epoch()
+    }
+}
+
+/// Holds information about an input event. Serialized using postcard and sent to clients.
+/// Enum values can be found in <https://github.com/torvalds/linux/blob/master/include/uapi/linux/input-event-codes.h>
+/// Fields:
+/// - `timestamp`: the time of the event
+/// - `event_type`: the raw type (e.g., a key press)
+/// - `code`: the raw code (e.g., corresponding to a certain key)
+/// - `value`: the raw value (e.g., 1 for a key press and 0 for a key release)
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputEventWrapper {
+    pub timestamp: Timestamp,
+    pub event_type: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+/// Sent by the server as soon as the client is authenticated.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ServerHello {
+    pub version: u16,
+    pub min_version: u16,
+    pub features: u32,
+}
+
+/// Sent by the client in reply to [`ServerHello`].
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientHello {
+    pub version: u16,
+    pub features: u32,
+}
+
+/// Sent by the server in reply to [`ClientHello`], with a borrowed reason so that it is decoded
+/// without allocating. If the client is rejected, the server closes the connection after sending this.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HandshakeResponse<'a> {
+    Accepted { version: u16, features: u32 },
+    Rejected { reason: &'a str },
+}
+
+/// Encode `event` into a frame at the start of `buffer`, which needs at most
+/// [`MAX_EVENT_FRAME_LEN`] bytes.
+pub fn encode_event<'a>(
+    event: &InputEventWrapper,
+    buffer: &'a mut [u8],
+) -> postcard::Result<&'a mut [u8]> {
+    postcard::to_slice_cobs(event, buffer)
+}
+
+/// Decode the event in `frame` in place.
+pub fn decode_event(frame: &mut [u8]) -> postcard::Result<InputEventWrapper> {
+    postcard::from_bytes_cobs(frame)
+}
+
+/// The length of the first complete frame at the start of `buffer` (including its zero byte
+/// terminator), or `None` if `buffer` does not hold a complete frame yet.
+pub fn frame_len(buffer: &[u8]) -> Option<usize> {
+    buffer
+        .iter()
+        .position(|&byte| byte == 0x00)
+        .map(|end| end + 1)
+}
+
+/// The longest frame of a message of `message_len` bytes, including its zero byte terminator.
+pub const fn max_frame_len(message_len: usize) -> usize {
+    // COBS adds one byte per 254 bytes and one to start with.
+    message_len + message_len / 254 + 2
+}
+
+/// Encode `message` (e.g., the response to the authentication challenge) into a frame at the start
+/// of `buffer`, which needs at most [`max_frame_len`] bytes.
+pub fn frame<'a>(message: &[u8], buffer: &'a mut [u8]) -> postcard::Result<&'a mut [u8]> {
+    // An empty message (a heartbeat) is encoded as a single 0x01 instead of no bytes.
+    let len = if message.is_empty() {
+        *buffer.first_mut().ok_or(Error::SerializeBufferFull)? = 0x01;
+        1
+    } else {
+        cobs::try_encode(message, buffer).map_err(|_| Error::SerializeBufferFull)?
+    };
+    *buffer.get_mut(len).ok_or(Error::SerializeBufferFull)? = 0x00;
+    Ok(&mut buffer[..=len])
+}
+
+/// Decode `frame` (with or without its zero byte terminator) in place, returning the message.
+/// The message of a heartbeat is empty.
+pub fn unframe(frame: &mut [u8]) -> postcard::Result<&mut [u8]> {
+    let len = match frame.split_last() {
+        Some((0x00, _)) => frame.len() - 1,
+        _ => frame.len(),
+    };
+    let frame = &mut frame[..len];
+    let len = cobs::decode_in_place(frame).map_err(|_| Error::DeserializeBadEncoding)?;
+    Ok(&mut frame[..len])
+}
+
+/// Split the sequence number sent with the `SEQUENCE` feature off the start of `message`.
+pub fn take_sequence(message: &[u8]) -> postcard::Result<(u64, &[u8])> {
+    postcard::take_from_bytes(message)
+}
+
+/// The events of a batch message (sent with the `BATCH` feature), decoded one at a time.
+pub struct Batch<'a> {
+    remaining: usize,
+    message: &'a [u8],
+}
+
+impl<'a> Batch<'a> {
+    /// Read the number of events at the start of `message`.
+    pub fn new(message: &'a [u8]) -> postcard::Result<Self> {
+        let (remaining, message) = postcard::take_from_bytes(message)?;
+        Ok(Self { remaining, message })
+    }
+
+    /// The number of events which have not been decoded yet.
+    pub fn len(&self) -> usize {
+        self.remaining
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remaining == 0
+    }
+}
+
+impl Iterator for Batch<'_> {
+    type Item = postcard::Result<InputEventWrapper>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        match postcard::take_from_bytes(self.message) {
+            Ok((event, message)) => {
+                self.message = message;
+                Some(Ok(event))
+            }
+            Err(error) => {
+                self.remaining = 0;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The event with the longest encoding.
+    const LONGEST_EVENT: InputEventWrapper = InputEventWrapper {
+        timestamp: Timestamp {
+            secs_since_epoch: u64::MAX,
+            nanos_since_epoch: u32::MAX,
+        },
+        event_type: u16::MAX,
+        code: u16::MAX,
+        value: i32::MIN,
+    };
+
+    #[test]
+    fn frames_empty_message() {
+        let mut buffer = [0xff; max_frame_len(0)];
+        let frame = frame(&[], &mut buffer).unwrap();
+        assert_eq!(frame, [0x01, 0x00]);
+        assert_eq!(frame_len(frame), Some(2));
+        assert_eq!(unframe(frame).unwrap(), []);
+    }
+
+    #[test]
+    fn frames_messages_with_zeros_and_long_runs() {
+        let mut message = [0u8; 600];
+        for (index, byte) in message.iter_mut().enumerate() {
+            *byte = (index % 300) as u8;
+        }
+        let mut buffer = [0; max_frame_len(600)];
+        let frame = frame(&message, &mut buffer).unwrap();
+        assert_eq!(frame_len(frame), Some(frame.len()));
+        assert_eq!(frame.iter().filter(|&&byte| byte == 0).count(), 1);
+        assert_eq!(unframe(frame).unwrap(), message);
+    }
+
+    #[test]
+    fn unframes_without_terminator() {
+        let mut buffer = [0; max_frame_len(3)];
+        let len = frame(&[1, 0, 2], &mut buffer).unwrap().len();
+        assert_eq!(unframe(&mut buffer[..len - 1]).unwrap(), [1, 0, 2]);
+    }
+
+    #[test]
+    fn fails_to_frame_into_short_buffer() {
+        let mut buffer = [0; 3];
+        assert_eq!(
+            frame(&[1, 2, 3], &mut buffer),
+            Err(Error::SerializeBufferFull)
+        );
+        assert_eq!(frame(&[], &mut []), Err(Error::SerializeBufferFull));
+    }
+
+    #[test]
+    fn encodes_longest_event_in_max_event_frame_len() {
+        let mut buffer = [0; MAX_EVENT_FRAME_LEN];
+        let frame = encode_event(&LONGEST_EVENT, &mut buffer).unwrap();
+        assert_eq!(frame.len(), MAX_EVENT_FRAME_LEN);
+        assert_eq!(decode_event(frame).unwrap(), LONGEST_EVENT);
+
+        let mut buffer = [0; MAX_EVENT_FRAME_LEN - 1];
+        assert!(encode_event(&LONGEST_EVENT, &mut buffer).is_err());
+    }
+
+    #[test]
+    fn waits_for_terminator() {
+        assert_eq!(frame_len(&[]), None);
+        assert_eq!(frame_len(&[0x02, 0x05]), None);
+        assert_eq!(frame_len(&[0x02, 0x05, 0x00, 0x01]), Some(3));
+    }
+}